@@ -0,0 +1,680 @@
+// Generated by bolt-rendezvous-codegen from bolt-rendezvous-protocol. Do not edit.
+// Regenerate with: cargo run --features schema --bin bolt-rendezvous-codegen
+
+export const PROTOCOL_VERSION = 1;
+
+/**
+ * Messages sent from a client to the signaling server.
+ *
+ * Every message accepts an optional client-chosen `id`. The server echoes it on any `error` caused by the message (and on `signal_delivered` acks) so clients can correlate failures with in-flight requests.
+ *
+ * A connection may `register` several identities (see [`ServerLimits::max_identities`]). Messages that act as a peer take an optional `as` naming the identity; without it the first identity still registered acts.
+ */
+export type ClientMessage =
+  /**
+   * First message a client must send after connecting. Sent again on a registered connection, it adds another identity, answered with `identity_added`.
+   */
+  | {
+    type: "register";
+    /**
+     * See [`PeerData::capabilities`].
+     */
+    capabilities?: string[];
+    device_name: string;
+    device_type: DeviceType;
+    /**
+     * `false` keeps this peer out of the room's `peers` and room events. It still sees the room, and can be reached with `manual_signal` or by peers it has sent a `signal` to first.
+     */
+    discoverable?: boolean;
+    id?: string | null;
+    /**
+     * See [`PeerData::metadata`]. The keys `wt_url` and `wt_cert_hash` are aliases for those fields; the fields win if both are set.
+     */
+    metadata?: Record<string, string>;
+    peer_code: string;
+    /**
+     * Protocol version spoken by the client. Absent means [`MIN_PROTOCOL_VERSION`] unless a subprotocol was negotiated.
+     */
+    protocol_version?: number | null;
+    /**
+     * Join this named room instead of the effective-IP room. The first peer to use a name sets its secret; later peers must present the same `room_secret`.
+     */
+    room?: string | null;
+    /**
+     * Shared secret for `room`. Required with `room`.
+     */
+    room_secret?: string | null;
+    /**
+     * Initial presence subscription, so the first `peers` is already filtered and paged.
+     */
+    subscription?: Subscription | null;
+    wt_cert_hash?: string | null;
+    wt_url?: string | null;
+  }
+  /**
+   * Relay a WebRTC signaling payload to another peer.
+   *
+   * This path is room-scoped: the target must be in the sender's room (its effective-IP room, or the named room it registered with).
+   */
+  | {
+    type: "signal";
+    /**
+     * Request a `signal_delivered` ack once the payload has been handed to the target's connection.
+     */
+    ack?: boolean;
+    as?: string | null;
+    id?: string | null;
+    payload: unknown;
+    to: string;
+  }
+  /**
+   * Relay a WebRTC signaling payload to an exact peer code for explicit manual pairing. This does not add the peer to automatic discovery.
+   *
+   * Only relayed once the target has accepted a `pair_request` from this peer (or the two matched a PIN), until either disconnects.
+   */
+  | {
+    type: "manual_signal";
+    /**
+     * Request a `signal_delivered` ack once the payload has been handed to the target's connection.
+     */
+    ack?: boolean;
+    as?: string | null;
+    id?: string | null;
+    payload: unknown;
+    to: string;
+  }
+  /**
+   * Relay one signaling payload to several peers in the sender's room.
+   *
+   * Counts as a single message for rate limiting. The server answers with one `multicast_result` listing the outcome per target.
+   */
+  | {
+    type: "multicast_signal";
+    as?: string | null;
+    id?: string | null;
+    payload: unknown;
+    /**
+     * Target peer codes (at most `max_multicast_targets`).
+     */
+    to: string[];
+  }
+  /**
+   * Offer a short numeric PIN for ad-hoc pairing. When a second peer submits the same PIN before it expires, the server introduces the two with `pin_matched` and burns the PIN. The matched peers can then `signal` each other regardless of room.
+   */
+  | {
+    type: "match_pin";
+    as?: string | null;
+    id?: string | null;
+    pin: string;
+  }
+  /**
+   * Ask the peer with this exact code (in any room) for consent to `manual_signal`. The target receives `pair_request` with this peer's data and answers with `pair_accept` or `pair_reject`.
+   */
+  | {
+    type: "pair_request";
+    as?: string | null;
+    id?: string | null;
+    to: string;
+  }
+  /**
+   * Accept the pending `pair_request` from peer `to`.
+   */
+  | {
+    type: "pair_accept";
+    as?: string | null;
+    id?: string | null;
+    to: string;
+  }
+  /**
+   * Decline the pending `pair_request` from peer `to`.
+   */
+  | {
+    type: "pair_reject";
+    as?: string | null;
+    id?: string | null;
+    to: string;
+  }
+  /**
+   * Change this peer's advertised metadata without re-registering.
+   *
+   * Absent fields are left unchanged; `wt_url`/`wt_cert_hash` set to `null` are cleared, and `capabilities`/`metadata` replace the previous list or map. If anything changed, the room and the peer itself receive `peer_updated`.
+   */
+  | {
+    type: "update";
+    as?: string | null;
+    capabilities?: string[] | null;
+    device_name?: string | null;
+    id?: string | null;
+    metadata?: Record<string, string> | null;
+    wt_cert_hash?: string | null;
+    wt_url?: string | null;
+  }
+  /**
+   * Change this peer's presence. The room receives `presence_changed`.
+   */
+  | {
+    type: "set_presence";
+    as?: string | null;
+    id?: string | null;
+    presence: Presence;
+  }
+  /**
+   * Replace this peer's code in place. The room receives `peer_left` for the old code followed by `peer_joined` for the new one; the sender gets `code_rotated`. Pairing consent and PIN matches do not carry over.
+   */
+  | {
+    type: "rotate_code";
+    as?: string | null;
+    id?: string | null;
+    peer_code: string;
+  }
+  /**
+   * Leave the room but keep the connection open. The room receives `peer_left`; the client may `register` again afterwards. With `as` only that identity leaves; without it, all of them do.
+   */
+  | {
+    type: "leave";
+    as?: string | null;
+    id?: string | null;
+  }
+  /**
+   * Ask for the current peer list of the room.
+   *
+   * Every `peer_joined`, `peer_left`, `peer_updated` and `presence_changed` carries the room `version` it produced; versions of one room are consecutive, so a client that sees a jump has missed events. With `since` set to the last version it applied, the server answers with `peers_delta` if it still remembers every later change, otherwise (and without `since`) with a full `peers` snapshot. A recreated room continues above every version the emptied one reached, so a `since` from before gets a snapshot; the `peers` sent on registration is the baseline.
+   *
+   * With a [`Subscription::page_size`], snapshots are ordered by peer code and split into pages; `after` asks for the page following that code (the previous page's `next`).
+   */
+  | {
+    type: "list_peers";
+    after?: string | null;
+    as?: string | null;
+    id?: string | null;
+    since?: number | null;
+  }
+  /**
+   * Replace the presence subscription. Answered with the first page of a fresh `peers` snapshot under the new filters.
+   */
+  | {
+    type: "subscribe";
+    as?: string | null;
+    id?: string | null;
+    subscription: Subscription;
+  }
+  /**
+   * Keepalive ping from client. Prevents the idle timeout but does not count as activity for automatic `away`. Answered with `pong`.
+   */
+  | {
+    type: "ping";
+    id?: string | null;
+    /**
+     * Opaque value echoed in the `pong`, e.g. to match round trips.
+     */
+    nonce?: string | null;
+  };
+
+/**
+ * Outcome for one target of a `multicast_signal`.
+ */
+export interface DeliveryResult {
+  /**
+   * Why delivery failed (`not_found`, `peer_disconnected`, `invalid_peer_code`).
+   */
+  code?: ErrorCode | null;
+  /**
+   * Whether the payload was handed to the target's connection.
+   */
+  delivered: boolean;
+  /**
+   * Target peer code as sent by the client.
+   */
+  to: string;
+}
+
+/**
+ * Device type reported by connecting peers. Unrecognised values must be preserved, not rejected.
+ */
+export type DeviceType = "phone" | "tablet" | "laptop" | "desktop" | "tv" | "server" | "watch" | "vehicle" | (string & {});
+
+/**
+ * Machine-readable error category carried by [`ServerMessage::Error`].
+ *
+ * Serializes to `snake_case` strings (e.g. `"rate_limited"`). Codes added by newer servers deserialize as [`ErrorCode::Unknown`] so older clients can still fall back to the human-readable `message`.
+ */
+export type ErrorCode = "invalid_peer_code" | "invalid_field" | "rate_limited" | "room_full" | "room_limit" | "not_found" | "peer_disconnected" | "ambiguous" | "not_registered" | "already_registered" | "malformed" | "too_large" | "binary_rejected" | "unsupported_protocol_version" | "unauthorized" | "expired" | "consent_required" | "code_in_use" | "identity_limit" | "registration_timeout" | "unknown";
+
+/**
+ * JSON body of an HTTP response that refuses a connection. The same delay is sent in the `Retry-After` header.
+ */
+export interface HttpRejection {
+  message: string;
+  reason: RejectionReason;
+  /**
+   * Seconds the client should wait before connecting again.
+   */
+  retry_after_secs?: number | null;
+}
+
+/**
+ * Where the server took the client IP that chose an IP room from.
+ */
+export type IpSource = "socket" | "trusted_proxy" | "private_source_proxy" | "unknown";
+
+/**
+ * Public peer information broadcast to room members.
+ */
+export interface PeerData {
+  /**
+   * Features the peer supports, e.g. `webrtc`, `webtransport`, `zstd`, `bolt.v2`. The server only checks their size and spelling.
+   */
+  capabilities?: string[];
+  device_name: string;
+  device_type: DeviceType;
+  /**
+   * Free-form key/value pairs advertised by the peer. Always mirrors `wt_url` and `wt_cert_hash` under those keys.
+   */
+  metadata?: Record<string, string>;
+  peer_code: string;
+  /**
+   * Current availability (absent from servers that predate presence).
+   */
+  presence?: Presence;
+  /**
+   * WebTransport TLS certificate SHA-256 hash (hex). Required for browser serverCertificateHashes.
+   */
+  wt_cert_hash?: string | null;
+  /**
+   * WebTransport URL (e.g. "https://192.168.4.210:9948"). Optional — only desktop peers with WT enabled.
+   */
+  wt_url?: string | null;
+}
+
+/**
+ * Availability of a peer, shown to the rest of its room.
+ *
+ * Peers start out `available`. `busy` tells other peers not to send new offers. `away` is set by the peer itself or, when the server enables it, automatically after a quiet period.
+ */
+export type Presence = "available" | "busy" | "away" | "unknown";
+
+/**
+ * Why the server refused a connection before the WebSocket upgrade.
+ */
+export type RejectionReason = "server_full" | "too_many_connections" | "origin_not_allowed" | "upgrade_timeout" | "unknown";
+
+/**
+ * Room diagnostics: sent to each identity as `room_info` and served over HTTP at `/whoami`.
+ */
+export interface RoomInfo {
+  /**
+   * Salted hash of the room key, stable for the server's lifetime. Two peers see the same fingerprint exactly when they share a room; it does not reveal the IP address.
+   */
+  fingerprint: string;
+  /**
+   * Header the client IP was read from (`fly-client-ip` or `x-forwarded-for`) when a proxy source was used.
+   */
+  forwarded_header?: string | null;
+  ip_source: IpSource;
+  kind: RoomKind;
+  /**
+   * Discoverable peers currently in the room, the caller included if it is discoverable. Hidden peers are never counted.
+   */
+  size: number;
+}
+
+/**
+ * How the server classified the room a peer was placed in.
+ */
+export type RoomKind = "private" | "cgnat" | "public" | "named" | "unknown";
+
+/**
+ * Effective server limits advertised in [`ServerMessage::ServerInfo`].
+ *
+ * Clients should size messages and keepalives from these values instead of hardcoding them.
+ */
+export interface ServerLimits {
+  /**
+   * Seconds without client activity (other than `ping`) after which the server marks an `available` peer `away`. Absent when disabled.
+   */
+  auto_away_secs?: number | null;
+  /**
+   * Seconds without any client message before the server closes the socket.
+   */
+  idle_timeout_secs: number;
+  /**
+   * Maximum number of `capabilities` per peer (0 from servers that predate capabilities).
+   */
+  max_capabilities?: number;
+  /**
+   * Maximum length of `device_name` in bytes.
+   */
+  max_device_name_bytes: number;
+  /**
+   * Maximum number of identities one connection may register (0 from servers that predate multiple identities).
+   */
+  max_identities?: number;
+  /**
+   * Maximum size of a single WebSocket message in bytes.
+   */
+  max_message_bytes: number;
+  /**
+   * Maximum length of a client message `id` in bytes (0 from servers that predate message ids).
+   */
+  max_message_id_bytes?: number;
+  /**
+   * Maximum number of `metadata` entries per peer.
+   */
+  max_metadata_entries?: number;
+  /**
+   * Maximum length of a `metadata` value in bytes.
+   */
+  max_metadata_value_bytes?: number;
+  /**
+   * Maximum number of targets in one `multicast_signal` (0 from servers that predate multicast).
+   */
+  max_multicast_targets?: number;
+  /**
+   * Largest [`Subscription::page_size`] (0 from servers that predate subscriptions).
+   */
+  max_page_size?: number;
+  /**
+   * Maximum length of a peer code after hyphens are stripped.
+   */
+  max_peer_code_bytes: number;
+  /**
+   * Most room events one identity receives per second.
+   */
+  max_room_events_per_sec?: number;
+  /**
+   * Maximum messages per second per connection.
+   */
+  rate_limit_per_second: number;
+}
+
+/**
+ * Messages sent from the signaling server to clients.
+ */
+export type ServerMessage =
+  /**
+   * Negotiated protocol version and server parameters (sent on registration, before `peers`).
+   */
+  | {
+    type: "server_info";
+    /**
+     * Random identifier of the server process; changes on restart.
+     */
+    instance_id: string;
+    limits: ServerLimits;
+    protocol_version: number;
+    server_version: string;
+  }
+  /**
+   * Full list of the other peers in the room, sent on registration and as a snapshot answer to `list_peers`.
+   */
+  | {
+    type: "peers";
+    /**
+     * The `list_peers` `after` this page follows; absent on a first page.
+     */
+    after?: string | null;
+    id?: string | null;
+    /**
+     * Set when more peers follow: pass it as `after` for the next page.
+     */
+    next?: string | null;
+    peers: PeerData[];
+    /**
+     * Room version the list reflects (absent from servers that predate room versions).
+     */
+    version?: number | null;
+  }
+  /**
+   * Answer to `list_peers` when the server still remembers every change after `since`: the net effect of those changes.
+   */
+  | {
+    type: "peers_delta";
+    id?: string | null;
+    /**
+     * Peers that joined or changed, with their current data.
+     */
+    peers: PeerData[];
+    /**
+     * Codes of peers that left.
+     */
+    removed: string[];
+    since: number;
+    version: number;
+  }
+  /**
+   * Room events for this connection were dropped to stay within its event rate. Sent once per second at most; after `retry_after_ms` a `list_peers` with `since` catches up.
+   */
+  | {
+    type: "presence_throttled";
+    retry_after_ms: number;
+  }
+  /**
+   * Which room an identity was placed in and why; sent after its `peers` or `identity_added`.
+   */
+  | {
+    type: "room_info";
+    peer_code: string;
+    room: RoomInfo;
+  }
+  /**
+   * A new peer joined the room.
+   */
+  | {
+    type: "peer_joined";
+    peer: PeerData;
+    /**
+     * Room version after this change; see [`ClientMessage::ListPeers`].
+     */
+    version?: number | null;
+  }
+  /**
+   * A peer left the room.
+   */
+  | {
+    type: "peer_left";
+    peer_code: string;
+    /**
+     * Room version after this change.
+     */
+    version?: number | null;
+  }
+  /**
+   * A peer in the room changed its metadata (see `update`). `peer` is the complete new state. Sent to the whole room, including the peer itself.
+   */
+  | {
+    type: "peer_updated";
+    peer: PeerData;
+    /**
+     * Room version after this change.
+     */
+    version?: number | null;
+  }
+  /**
+   * A peer in the room changed presence, either via `set_presence` or automatically. Sent to the whole room, including the peer itself.
+   */
+  | {
+    type: "presence_changed";
+    /**
+     * `true` when the server set the state (automatic `away`, or the return to `available` on the next activity).
+     */
+    automatic?: boolean;
+    peer_code: string;
+    presence: Presence;
+    /**
+     * Room version after this change.
+     */
+    version?: number | null;
+  }
+  /**
+   * Relayed signaling payload from another peer.
+   */
+  | {
+    type: "signal";
+    from: string;
+    payload: unknown;
+    /**
+     * The receiving identity's peer code.
+     */
+    to?: string | null;
+  }
+  /**
+   * The `match_pin` was accepted and is waiting for a second peer.
+   */
+  | {
+    type: "pin_waiting";
+    /**
+     * Milliseconds until the PIN expires unmatched.
+     */
+    expires_in_ms: number;
+    id?: string | null;
+  }
+  /**
+   * Another peer submitted the same PIN. `id` is that of this client's own `match_pin`.
+   */
+  | {
+    type: "pin_matched";
+    id?: string | null;
+    peer: PeerData;
+  }
+  /**
+   * Another peer asks for consent to `manual_signal` this peer. Answer with `pair_accept` or `pair_reject` naming `peer.peer_code`.
+   */
+  | {
+    type: "pair_request";
+    peer: PeerData;
+    /**
+     * The receiving identity's peer code.
+     */
+    to?: string | null;
+  }
+  /**
+   * The target of this peer's `pair_request` accepted; `manual_signal` to it is now relayed.
+   */
+  | {
+    type: "pair_accepted";
+    peer: PeerData;
+  }
+  /**
+   * The target of this peer's `pair_request` declined.
+   */
+  | {
+    type: "pair_rejected";
+    peer_code: string;
+  }
+  /**
+   * Per-target outcome of a `multicast_signal`, in request order.
+   */
+  | {
+    type: "multicast_result";
+    id?: string | null;
+    results: DeliveryResult[];
+  }
+  /**
+   * A `register` on an already registered connection succeeded. `peers` lists the new identity's room, like `peers` after the first registration.
+   */
+  | {
+    type: "identity_added";
+    id?: string | null;
+    /**
+     * Cursor for the next page of `peers`, as in `peers`; request it with `list_peers` `as` this identity.
+     */
+    next?: string | null;
+    peer_code: string;
+    peers: PeerData[];
+    /**
+     * Room version `peers` reflects, as in `peers`.
+     */
+    version?: number | null;
+  }
+  /**
+   * This peer's `rotate_code` succeeded; `peer_code` is the new (normalized) code.
+   */
+  | {
+    type: "code_rotated";
+    id?: string | null;
+    peer_code: string;
+    /**
+     * Room version after the rotation. It stands for two changes, the `peer_left` of the old code and the `peer_joined` of the new one, which the other peers see. Absent for a hidden peer or when the code did not change.
+     */
+    version?: number | null;
+  }
+  /**
+   * Acknowledges a `signal` or `manual_signal` sent with `ack: true`: the payload has been handed to the target's connection. This does not confirm that the target processed it.
+   */
+  | {
+    type: "signal_delivered";
+    id?: string | null;
+    /**
+     * Peer code the payload was relayed to.
+     */
+    to: string;
+  }
+  /**
+   * Reply to `ping`. The timestamps are taken when the server handles the ping, so a client can estimate round-trip time and its clock offset from the server.
+   */
+  | {
+    type: "pong";
+    id?: string | null;
+    /**
+     * Milliseconds on a monotonic clock that starts with the server process. Unaffected by wall-clock adjustments; compare only values from the same `instance_id`.
+     */
+    monotonic_ms: number;
+    /**
+     * The `nonce` from the `ping`.
+     */
+    nonce?: string | null;
+    /**
+     * Server wall-clock time, milliseconds since the Unix epoch.
+     */
+    server_time_ms: number;
+  }
+  /**
+   * Error response for invalid or malformed messages.
+   *
+   * `message` is the human-readable text older clients match on; `code` and the optional details are the machine-readable equivalent.
+   */
+  | {
+    type: "error";
+    code?: ErrorCode | null;
+    /**
+     * Name of the offending request field (e.g. `"peer_code"`, `"to"`).
+     */
+    field?: string | null;
+    /**
+     * `id` of the client message that caused this error, if it had one.
+     */
+    id?: string | null;
+    message: string;
+    /**
+     * Milliseconds the client should wait before retrying.
+     */
+    retry_after_ms?: number | null;
+  };
+
+/**
+ * Which peers a client wants to hear about, and how much at a time.
+ *
+ * Set with `register` or `subscribe`. Filters narrow `peers`, `peers_delta` and room events to matching peers; all filters must match. The defaults (all empty) keep everything.
+ */
+export interface Subscription {
+  /**
+   * In peer lists, only peers that joined, changed or updated their presence within this many seconds. Room events are recent by nature and are not held back by it.
+   */
+  active_within_secs?: number | null;
+  /**
+   * Only peers advertising every one of these capabilities.
+   */
+  capabilities?: string[];
+  /**
+   * Only peers of one of these device types.
+   */
+  device_types?: DeviceType[];
+  /**
+   * Most room events delivered per second. Capped by [`ServerLimits::max_room_events_per_sec`], which also applies without a subscription.
+   */
+  max_events_per_sec?: number | null;
+  /**
+   * Most peers per `peers` message; further pages are requested with `list_peers` and `after`. Capped by [`ServerLimits::max_page_size`].
+   */
+  page_size?: number | null;
+}
@@ -0,0 +1,59 @@
+//! Write the generated JSON Schema and TypeScript definitions for the
+//! rendezvous protocol.
+//!
+//! Usage:
+//!
+//! ```text
+//! cargo run --features schema --bin bolt-rendezvous-codegen [-- --check] [--out DIR]
+//! ```
+//!
+//! Without `--out`, files are written to this crate's `generated/` directory.
+//! With `--check`, nothing is written and the process exits non-zero if any
+//! file on disk differs from the freshly generated output.
+
+use std::path::PathBuf;
+
+use bolt_rendezvous_protocol::schema::{
+    json_schema_string, typescript, SCHEMA_FILE, TYPESCRIPT_FILE,
+};
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let check = args.iter().any(|a| a == "--check");
+    let out_dir = args
+        .iter()
+        .position(|a| a == "--out")
+        .and_then(|i| args.get(i + 1))
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("generated"));
+
+    let files = [
+        (SCHEMA_FILE, json_schema_string()),
+        (TYPESCRIPT_FILE, typescript()),
+    ];
+
+    let mut stale = false;
+    for (name, contents) in files {
+        let path = out_dir.join(name);
+        if check {
+            let on_disk = std::fs::read_to_string(&path).unwrap_or_default();
+            if on_disk != contents {
+                eprintln!("stale: {}", path.display());
+                stale = true;
+            }
+            continue;
+        }
+        if let Err(e) =
+            std::fs::create_dir_all(&out_dir).and_then(|_| std::fs::write(&path, contents))
+        {
+            eprintln!("failed to write {}: {e}", path.display());
+            std::process::exit(1);
+        }
+        println!("wrote {}", path.display());
+    }
+
+    if stale {
+        eprintln!("generated files are out of date; rerun without --check");
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,1217 @@
+//! Async WebSocket client for the rendezvous server (feature `client`).
+//!
+//! [`RendezvousClient`] owns one logical registration. It connects, sends
+//! `register`, keeps the connection alive with `ping` before the server's idle
+//! timeout, and reconnects with exponential backoff after a disconnect,
+//! registering the same peer code again. The current room membership is
+//! exposed as a [`watch`] channel; every other server message is delivered as
+//! a [`ClientEvent`].
+//!
+//! ```rust,no_run
+//! use bolt_rendezvous_protocol::client::{ClientConfig, ClientEvent, RendezvousClient};
+//! use bolt_rendezvous_protocol::{DeviceType, ServerMessage};
+//!
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let config = ClientConfig::new("ws://127.0.0.1:3001", "ABCDEF", "Laptop", DeviceType::Laptop);
+//! let mut client = RendezvousClient::connect(config).await?;
+//! println!("peers: {:?}", client.peers().borrow().clone());
+//! while let Some(event) = client.next_event().await {
+//!     if let ClientEvent::Message(ServerMessage::Signal { from, payload, .. }) = event {
+//!         client.send_signal(&from, payload)?;
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, watch};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::{header, HeaderValue};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use crate::{
+    subprotocol, ClientMessage, DeviceType, Encoding, ErrorCode, HttpRejection, PeerData,
+    ServerLimits, ServerMessage, Subscription, PROTOCOL_VERSION,
+};
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Default interval between keepalive pings. Shortened automatically to half
+/// of the server's advertised idle timeout if that is smaller.
+pub const DEFAULT_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Default first reconnect delay.
+pub const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Default upper bound for the reconnect delay.
+pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Connection and registration settings for [`RendezvousClient`].
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    /// Server URL, e.g. `ws://192.168.1.10:3001`.
+    pub url: String,
+    /// Peer code registered on every (re)connect.
+    pub peer_code: String,
+    pub device_name: String,
+    pub device_type: DeviceType,
+    pub wt_url: Option<String>,
+    pub wt_cert_hash: Option<String>,
+    pub capabilities: Vec<String>,
+    pub metadata: BTreeMap<String, String>,
+    /// Named room and its secret; `None` joins the effective-IP room.
+    pub room: Option<(String, String)>,
+    /// Whether other peers in the room see this peer (default `true`).
+    pub discoverable: bool,
+    /// Presence filters, page size and event rate; `None` takes everything.
+    pub subscription: Option<Subscription>,
+    /// Wire encoding requested through the WebSocket subprotocol.
+    pub encoding: Encoding,
+    pub keepalive_interval: Duration,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl ClientConfig {
+    /// Create a config with default keepalive, backoff and JSON encoding.
+    pub fn new(
+        url: impl Into<String>,
+        peer_code: impl Into<String>,
+        device_name: impl Into<String>,
+        device_type: DeviceType,
+    ) -> Self {
+        Self {
+            url: url.into(),
+            peer_code: peer_code.into(),
+            device_name: device_name.into(),
+            device_type,
+            wt_url: None,
+            wt_cert_hash: None,
+            capabilities: Vec::new(),
+            metadata: BTreeMap::new(),
+            room: None,
+            discoverable: true,
+            subscription: None,
+            encoding: Encoding::Json,
+            keepalive_interval: DEFAULT_KEEPALIVE_INTERVAL,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+        }
+    }
+
+    /// Advertise a WebTransport endpoint in the registration.
+    pub fn with_webtransport(
+        mut self,
+        url: impl Into<String>,
+        cert_hash: impl Into<String>,
+    ) -> Self {
+        self.wt_url = Some(url.into());
+        self.wt_cert_hash = Some(cert_hash.into());
+        self
+    }
+
+    /// Advertise feature capabilities in the registration.
+    pub fn with_capabilities<I, S>(mut self, capabilities: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.capabilities = capabilities.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Advertise one metadata entry in the registration.
+    pub fn with_metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    /// With `false`, register without appearing in the room's peer list or
+    /// events; see [`ClientMessage::Register`].
+    pub fn with_discoverable(mut self, discoverable: bool) -> Self {
+        self.discoverable = discoverable;
+        self
+    }
+
+    /// Only track peers matching `subscription`, in pages of its size. The
+    /// peer list watch fetches every page.
+    pub fn with_subscription(mut self, subscription: Subscription) -> Self {
+        self.subscription = Some(subscription);
+        self
+    }
+
+    /// Join a named room with its shared secret instead of the IP room.
+    pub fn with_room(mut self, name: impl Into<String>, secret: impl Into<String>) -> Self {
+        self.room = Some((name.into(), secret.into()));
+        self
+    }
+
+    /// Select the wire encoding.
+    pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Set the keepalive ping interval.
+    pub fn with_keepalive_interval(mut self, interval: Duration) -> Self {
+        self.keepalive_interval = interval;
+        self
+    }
+
+    /// Set the reconnect backoff bounds.
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Fold an `update` or `subscribe` message into the values used for
+    /// re-registration.
+    fn apply_update(&mut self, msg: &ClientMessage) {
+        if let ClientMessage::Subscribe { subscription, .. } = msg {
+            self.subscription = Some(subscription.clone());
+        }
+        if let ClientMessage::Update {
+            device_name,
+            wt_url,
+            wt_cert_hash,
+            capabilities,
+            metadata,
+            ..
+        } = msg
+        {
+            if let Some(name) = device_name {
+                self.device_name = name.clone();
+            }
+            if let Some(url) = wt_url {
+                self.wt_url = url.clone();
+            }
+            if let Some(hash) = wt_cert_hash {
+                self.wt_cert_hash = hash.clone();
+            }
+            if let Some(capabilities) = capabilities {
+                self.capabilities = capabilities.clone();
+            }
+            if let Some(metadata) = metadata {
+                self.metadata = metadata.clone();
+            }
+        }
+    }
+
+    fn register_message(&self) -> ClientMessage {
+        ClientMessage::Register {
+            peer_code: self.peer_code.clone(),
+            device_name: self.device_name.clone(),
+            device_type: self.device_type.clone(),
+            wt_url: self.wt_url.clone(),
+            wt_cert_hash: self.wt_cert_hash.clone(),
+            protocol_version: Some(PROTOCOL_VERSION),
+            room: self.room.as_ref().map(|(name, _)| name.clone()),
+            room_secret: self.room.as_ref().map(|(_, secret)| secret.clone()),
+            capabilities: self.capabilities.clone(),
+            metadata: self.metadata.clone(),
+            discoverable: self.discoverable,
+            subscription: self.subscription.clone(),
+            id: None,
+        }
+    }
+}
+
+/// Events delivered by [`RendezvousClient::next_event`].
+#[derive(Debug, Clone)]
+pub enum ClientEvent {
+    /// Registration succeeded, initially or after a reconnect.
+    Registered {
+        /// Limits from `server_info` (absent on servers that predate it).
+        limits: Option<ServerLimits>,
+        instance_id: Option<String>,
+    },
+    /// A server message other than the peer-list bookkeeping handled by
+    /// [`RendezvousClient::peers`] (which are still forwarded here too).
+    Message(ServerMessage),
+    /// The connection dropped; the client is reconnecting.
+    Disconnected,
+    /// The server rejected the registration permanently; no more reconnects.
+    Closed { reason: String },
+}
+
+/// Errors returned by [`RendezvousClient`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientError {
+    /// The WebSocket connection could not be established.
+    Connect(String),
+    /// The server answered the upgrade with an HTTP error, e.g. 503 at its
+    /// connection limit. Reconnects wait at least `retry_after` (capped at
+    /// the configured maximum backoff).
+    Refused {
+        status: u16,
+        retry_after: Option<Duration>,
+        rejection: Option<HttpRejection>,
+    },
+    /// The server refused the registration.
+    Rejected {
+        code: Option<ErrorCode>,
+        message: String,
+    },
+    /// The client is between connections.
+    NotConnected,
+    /// The background task has stopped.
+    Closed,
+}
+
+impl std::fmt::Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientError::Connect(e) => write!(f, "connect failed: {e}"),
+            ClientError::Refused {
+                status,
+                rejection: Some(rejection),
+                ..
+            } => write!(f, "connection refused ({status}): {}", rejection.message),
+            ClientError::Refused { status, .. } => write!(f, "connection refused ({status})"),
+            ClientError::Rejected { message, .. } => write!(f, "registration rejected: {message}"),
+            ClientError::NotConnected => write!(f, "not connected"),
+            ClientError::Closed => write!(f, "client closed"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+/// Typed client for the rendezvous protocol with keepalive and reconnect.
+///
+/// Dropping the client closes the connection and stops reconnecting.
+pub struct RendezvousClient {
+    commands: mpsc::UnboundedSender<ClientMessage>,
+    events: mpsc::UnboundedReceiver<ClientEvent>,
+    peers: watch::Receiver<Vec<PeerData>>,
+    connected: Arc<AtomicBool>,
+}
+
+impl RendezvousClient {
+    /// Connect and register. Returns once the first registration has been
+    /// accepted; later disconnects are retried in the background.
+    pub async fn connect(config: ClientConfig) -> Result<Self, ClientError> {
+        let (commands, command_rx) = mpsc::unbounded_channel();
+        let (event_tx, events) = mpsc::unbounded_channel();
+        let (peers_tx, peers) = watch::channel(Vec::new());
+        let connected = Arc::new(AtomicBool::new(false));
+
+        let session = open_session(&config, &event_tx, &peers_tx).await?;
+        connected.store(true, Ordering::Release);
+
+        let worker = Worker {
+            config,
+            commands: command_rx,
+            events: event_tx,
+            peers: peers_tx,
+            connected: connected.clone(),
+        };
+        tokio::spawn(worker.run(session));
+
+        Ok(Self {
+            commands,
+            events,
+            peers,
+            connected,
+        })
+    }
+
+    /// Current room membership, updated from `peers`, `peer_joined` and
+    /// `peer_left`. Cleared while disconnected.
+    pub fn peers(&self) -> watch::Receiver<Vec<PeerData>> {
+        self.peers.clone()
+    }
+
+    /// Whether the client is currently registered.
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Acquire)
+    }
+
+    /// Wait for the next event. Returns `None` once the client has stopped.
+    pub async fn next_event(&mut self) -> Option<ClientEvent> {
+        self.events.recv().await
+    }
+
+    /// Relay a signaling payload to a peer in the same room.
+    ///
+    /// Use [`send`](Self::send) with an `id` and `ack: true` to correlate
+    /// errors and delivery acks with individual messages.
+    pub fn send_signal(&self, to: &str, payload: serde_json::Value) -> Result<(), ClientError> {
+        self.send(ClientMessage::Signal {
+            to: to.to_string(),
+            payload,
+            id: None,
+            identity: None,
+            ack: false,
+        })
+    }
+
+    /// Relay a signaling payload to an exact peer code (manual pairing).
+    pub fn send_manual_signal(
+        &self,
+        to: &str,
+        payload: serde_json::Value,
+    ) -> Result<(), ClientError> {
+        self.send(ClientMessage::ManualSignal {
+            to: to.to_string(),
+            payload,
+            id: None,
+            identity: None,
+            ack: false,
+        })
+    }
+
+    /// Relay one payload to several peers in the same room. The server
+    /// answers with a single `multicast_result` event.
+    pub fn send_multicast_signal(
+        &self,
+        to: Vec<String>,
+        payload: serde_json::Value,
+    ) -> Result<(), ClientError> {
+        self.send(ClientMessage::MulticastSignal {
+            to,
+            payload,
+            id: None,
+            identity: None,
+        })
+    }
+
+    /// Offer a pairing PIN. Expect `pin_waiting`, then `pin_matched` once
+    /// another peer submits the same PIN, or an `expired` error.
+    pub fn match_pin(&self, pin: &str) -> Result<(), ClientError> {
+        self.send(ClientMessage::MatchPin {
+            pin: pin.to_string(),
+            id: None,
+            identity: None,
+        })
+    }
+
+    /// Ask peer `to` for consent to `manual_signal` it.
+    pub fn pair_request(&self, to: &str) -> Result<(), ClientError> {
+        self.send(ClientMessage::PairRequest {
+            to: to.to_string(),
+            id: None,
+            identity: None,
+        })
+    }
+
+    /// Answer a `pair_request` from peer `from`.
+    pub fn answer_pair_request(&self, from: &str, accept: bool) -> Result<(), ClientError> {
+        let to = from.to_string();
+        self.send(if accept {
+            ClientMessage::PairAccept {
+                to,
+                id: None,
+                identity: None,
+            }
+        } else {
+            ClientMessage::PairReject {
+                to,
+                id: None,
+                identity: None,
+            }
+        })
+    }
+
+    /// Switch to a new peer code without reconnecting. Once the server
+    /// confirms with `code_rotated`, reconnects register the new code.
+    pub fn rotate_code(&self, peer_code: &str) -> Result<(), ClientError> {
+        self.send(ClientMessage::RotateCode {
+            peer_code: peer_code.to_string(),
+            id: None,
+            identity: None,
+        })
+    }
+
+    /// Change the advertised device name and/or WebTransport endpoint.
+    ///
+    /// `None` leaves a field unchanged; `Some(None)` clears a WebTransport
+    /// field. The new values are also used when re-registering after a
+    /// reconnect.
+    pub fn update(
+        &self,
+        device_name: Option<String>,
+        wt_url: Option<Option<String>>,
+        wt_cert_hash: Option<Option<String>>,
+    ) -> Result<(), ClientError> {
+        self.send(ClientMessage::Update {
+            device_name,
+            wt_url,
+            wt_cert_hash,
+            capabilities: None,
+            metadata: None,
+            id: None,
+            identity: None,
+        })
+    }
+
+    /// Set this peer's presence (`available`, `busy`, `away`).
+    pub fn set_presence(&self, presence: crate::Presence) -> Result<(), ClientError> {
+        self.send(ClientMessage::SetPresence {
+            presence,
+            id: None,
+            identity: None,
+        })
+    }
+
+    /// Ask for a fresh `peers` snapshot, which replaces the peer list.
+    /// Missed room events are repaired automatically.
+    pub fn list_peers(&self) -> Result<(), ClientError> {
+        self.send(ClientMessage::ListPeers {
+            since: None,
+            after: None,
+            id: None,
+            identity: None,
+        })
+    }
+
+    /// Replace the presence subscription; the peer list is rebuilt from the
+    /// server's answer and the subscription is kept across reconnects.
+    pub fn subscribe(&self, subscription: Subscription) -> Result<(), ClientError> {
+        self.send(ClientMessage::Subscribe {
+            subscription,
+            id: None,
+            identity: None,
+        })
+    }
+
+    /// Send a `ping` carrying `nonce`. The server answers with a `pong`
+    /// event echoing it, for round-trip and clock-offset measurement.
+    pub fn ping(&self, nonce: &str) -> Result<(), ClientError> {
+        self.send(ClientMessage::Ping {
+            nonce: Some(nonce.to_string()),
+            id: None,
+        })
+    }
+
+    /// Send any client message on the current connection.
+    ///
+    /// Messages are not queued across reconnects: while disconnected this
+    /// returns [`ClientError::NotConnected`].
+    pub fn send(&self, msg: ClientMessage) -> Result<(), ClientError> {
+        if !self.is_connected() {
+            return Err(ClientError::NotConnected);
+        }
+        self.commands.send(msg).map_err(|_| ClientError::Closed)
+    }
+}
+
+/// Background task owning the socket between reconnects.
+struct Worker {
+    config: ClientConfig,
+    commands: mpsc::UnboundedReceiver<ClientMessage>,
+    events: mpsc::UnboundedSender<ClientEvent>,
+    peers: watch::Sender<Vec<PeerData>>,
+    connected: Arc<AtomicBool>,
+}
+
+/// A registered connection plus the keepalive interval derived from
+/// `server_info`.
+struct Session {
+    ws: WsStream,
+    keepalive: Duration,
+    view: RoomView,
+}
+
+/// Bookkeeping for the peer list of one session.
+#[derive(Debug, Default)]
+struct RoomView {
+    /// Room version the peer list reflects, once known.
+    version: Option<u64>,
+    /// The subscription filters peers, so skipped versions are expected and
+    /// do not trigger a resync.
+    filtered: bool,
+}
+
+impl RoomView {
+    fn new(subscription: Option<&Subscription>) -> Self {
+        Self {
+            version: None,
+            filtered: subscription.is_some_and(Subscription::is_filtered),
+        }
+    }
+}
+
+/// Why a session ended.
+enum SessionEnd {
+    /// The socket closed or failed; reconnect.
+    Disconnected,
+    /// The client handle was dropped; stop.
+    Shutdown,
+}
+
+impl Worker {
+    async fn run(mut self, mut session: Session) {
+        loop {
+            let end = self.run_session(session).await;
+            self.connected.store(false, Ordering::Release);
+            self.peers.send_replace(Vec::new());
+            if matches!(end, SessionEnd::Shutdown) {
+                return;
+            }
+            let _ = self.events.send(ClientEvent::Disconnected);
+
+            session = match self.reconnect().await {
+                Some(session) => session,
+                None => return,
+            };
+            self.connected.store(true, Ordering::Release);
+        }
+    }
+
+    /// Reconnect with exponential backoff. Returns `None` if the client was
+    /// dropped or the server rejected the registration permanently.
+    async fn reconnect(&mut self) -> Option<Session> {
+        let mut attempt = 0;
+        let mut retry_after = Duration::ZERO;
+        loop {
+            let delay = backoff_delay(
+                self.config.initial_backoff,
+                self.config.max_backoff,
+                attempt,
+            )
+            .max(retry_after.min(self.config.max_backoff));
+            attempt += 1;
+
+            // Sleep, but stop promptly if the handle is dropped. Commands
+            // issued while disconnected are discarded.
+            let sleep = tokio::time::sleep(delay);
+            tokio::pin!(sleep);
+            loop {
+                tokio::select! {
+                    _ = &mut sleep => break,
+                    cmd = self.commands.recv() => {
+                        cmd?;
+                    }
+                }
+            }
+
+            match open_session(&self.config, &self.events, &self.peers).await {
+                Ok(session) => return Some(session),
+                Err(ClientError::Rejected { message, .. }) => {
+                    let _ = self.events.send(ClientEvent::Closed { reason: message });
+                    return None;
+                }
+                Err(ClientError::Refused {
+                    retry_after: Some(after),
+                    ..
+                }) => retry_after = after,
+                Err(_) => retry_after = Duration::ZERO,
+            }
+        }
+    }
+
+    async fn run_session(&mut self, session: Session) -> SessionEnd {
+        let Session {
+            mut ws,
+            keepalive,
+            mut view,
+        } = session;
+        let mut ticker =
+            tokio::time::interval_at(tokio::time::Instant::now() + keepalive, keepalive);
+        // Catch-up after the server dropped room events for our event rate.
+        let mut resync_at: Option<tokio::time::Instant> = None;
+
+        loop {
+            tokio::select! {
+                frame = ws.next() => {
+                    match frame {
+                        Some(Ok(frame)) => {
+                            if let Some(msg) = decode_frame(&frame, self.config.encoding) {
+                                // Re-register under the rotated code after a reconnect.
+                                if let ServerMessage::CodeRotated { peer_code, .. } = &msg {
+                                    self.config.peer_code = peer_code.clone();
+                                }
+                                if let ServerMessage::PresenceThrottled { retry_after_ms } = &msg {
+                                    resync_at.get_or_insert(
+                                        tokio::time::Instant::now() + Duration::from_millis(*retry_after_ms),
+                                    );
+                                }
+                                let follow_up = handle_server_message(msg, &self.events, &self.peers, &mut view)
+                                    // While throttled, skipped versions are expected; the
+                                    // timer resyncs once.
+                                    .filter(|list| resync_at.is_none() || !matches!(list, ClientMessage::ListPeers { since: Some(_), .. }));
+                                if let Some(list) = follow_up {
+                                    if send_message(&mut ws, &list, self.config.encoding).await.is_err() {
+                                        return SessionEnd::Disconnected;
+                                    }
+                                }
+                            }
+                        }
+                        Some(Err(_)) | None => return SessionEnd::Disconnected,
+                    }
+                }
+                cmd = self.commands.recv() => {
+                    let Some(cmd) = cmd else {
+                        let _ = ws.close(None).await;
+                        return SessionEnd::Shutdown;
+                    };
+                    // Keep metadata updates across reconnects.
+                    self.config.apply_update(&cmd);
+                    view.filtered = self.config.subscription.as_ref().is_some_and(Subscription::is_filtered);
+                    if send_message(&mut ws, &cmd, self.config.encoding).await.is_err() {
+                        return SessionEnd::Disconnected;
+                    }
+                    // Any outbound message resets the server's idle timer.
+                    ticker.reset();
+                }
+                _ = tokio::time::sleep_until(resync_at.unwrap_or_else(tokio::time::Instant::now)), if resync_at.is_some() => {
+                    resync_at = None;
+                    let list = ClientMessage::ListPeers { since: view.version, after: None, id: None, identity: None };
+                    if send_message(&mut ws, &list, self.config.encoding).await.is_err() {
+                        return SessionEnd::Disconnected;
+                    }
+                }
+                _ = ticker.tick() => {
+                    if send_message(&mut ws, &ClientMessage::Ping { nonce: None, id: None }, self.config.encoding).await.is_err() {
+                        return SessionEnd::Disconnected;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Delay before reconnect attempt `attempt` (0-based): `initial * 2^attempt`,
+/// capped at `max`.
+pub fn backoff_delay(initial: Duration, max: Duration, attempt: u32) -> Duration {
+    let factor = 1u32.checked_shl(attempt.min(31)).unwrap_or(u32::MAX);
+    initial.saturating_mul(factor).min(max)
+}
+
+/// Connect, register, and wait for the server to accept the registration.
+async fn open_session(
+    config: &ClientConfig,
+    events: &mpsc::UnboundedSender<ClientEvent>,
+    peers: &watch::Sender<Vec<PeerData>>,
+) -> Result<Session, ClientError> {
+    let mut request = config
+        .url
+        .as_str()
+        .into_client_request()
+        .map_err(|e| ClientError::Connect(e.to_string()))?;
+    let token = subprotocol(PROTOCOL_VERSION, config.encoding);
+    let value = HeaderValue::from_str(&token).map_err(|e| ClientError::Connect(e.to_string()))?;
+    request
+        .headers_mut()
+        .insert(header::SEC_WEBSOCKET_PROTOCOL, value);
+
+    let (mut ws, _response) = tokio_tungstenite::connect_async(request)
+        .await
+        .map_err(connect_error)?;
+
+    send_message(&mut ws, &config.register_message(), config.encoding)
+        .await
+        .map_err(|e| ClientError::Connect(e.to_string()))?;
+
+    // The server answers a successful registration with `server_info`
+    // (current servers) or directly with `peers` (older servers).
+    let mut view = RoomView::new(config.subscription.as_ref());
+    loop {
+        let frame = match ws.next().await {
+            Some(Ok(frame)) => frame,
+            Some(Err(e)) => return Err(ClientError::Connect(e.to_string())),
+            None => return Err(ClientError::Connect("closed during registration".into())),
+        };
+        let Some(msg) = decode_frame(&frame, config.encoding) else {
+            continue;
+        };
+        match msg {
+            ServerMessage::ServerInfo {
+                ref limits,
+                ref instance_id,
+                ..
+            } => {
+                let keepalive = keepalive_for(config.keepalive_interval, Some(limits));
+                let _ = events.send(ClientEvent::Registered {
+                    limits: Some(limits.clone()),
+                    instance_id: Some(instance_id.clone()),
+                });
+                handle_server_message(msg, events, peers, &mut view);
+                return Ok(Session {
+                    ws,
+                    keepalive,
+                    view,
+                });
+            }
+            ServerMessage::Peers { .. } => {
+                let _ = events.send(ClientEvent::Registered {
+                    limits: None,
+                    instance_id: None,
+                });
+                handle_server_message(msg, events, peers, &mut view);
+                let keepalive = keepalive_for(config.keepalive_interval, None);
+                return Ok(Session {
+                    ws,
+                    keepalive,
+                    view,
+                });
+            }
+            ServerMessage::Error { code, message, .. } if is_fatal(code) => {
+                return Err(ClientError::Rejected { code, message });
+            }
+            other => {
+                handle_server_message(other, events, peers, &mut view);
+            }
+        }
+    }
+}
+
+/// Registration errors that will not succeed on retry.
+fn is_fatal(code: Option<ErrorCode>) -> bool {
+    matches!(
+        code,
+        Some(
+            ErrorCode::InvalidPeerCode
+                | ErrorCode::InvalidField
+                | ErrorCode::UnsupportedProtocolVersion
+                | ErrorCode::Unauthorized
+        )
+    )
+}
+
+/// Keepalive interval: the configured value, shortened to half the server's
+/// idle timeout when that is smaller.
+fn keepalive_for(configured: Duration, limits: Option<&ServerLimits>) -> Duration {
+    let interval = match limits {
+        Some(limits) if limits.idle_timeout_secs > 0 => {
+            configured.min(Duration::from_secs(limits.idle_timeout_secs) / 2)
+        }
+        _ => configured,
+    };
+    interval.max(Duration::from_secs(1))
+}
+
+/// Apply peer-list bookkeeping and forward the message as an event.
+///
+/// `view.version` tracks the room version the list reflects. Events the last
+/// snapshot already covers are not applied again. Returns the `list_peers`
+/// to send next, if any: a resync after a skipped version, or the next page
+/// of a paged snapshot.
+fn handle_server_message(
+    msg: ServerMessage,
+    events: &mpsc::UnboundedSender<ClientEvent>,
+    peers: &watch::Sender<Vec<PeerData>>,
+    view: &mut RoomView,
+) -> Option<ClientMessage> {
+    let list_peers = |since: Option<u64>, after: Option<String>| ClientMessage::ListPeers {
+        since,
+        after,
+        id: None,
+        identity: None,
+    };
+    let mut follow_up = None;
+    let mut apply = true;
+    // `code_rotated` stands for this client's own leave and join, which the
+    // server sends only to the rest of the room.
+    if let ServerMessage::CodeRotated {
+        version: Some(version),
+        ..
+    } = &msg
+    {
+        if view.version == version.checked_sub(2) {
+            view.version = Some(*version);
+        }
+    }
+    if let Some(version) = msg.room_version() {
+        match view.version {
+            Some(current) if version <= current => apply = false,
+            Some(current) if version > current + 1 && !view.filtered => {
+                follow_up = Some(list_peers(Some(current), None));
+            }
+            _ => {}
+        }
+        if apply {
+            view.version = Some(version);
+        }
+    }
+
+    match &msg {
+        _ if !apply => {}
+        ServerMessage::Peers {
+            peers: list,
+            version,
+            after,
+            next,
+            ..
+        } => {
+            if after.is_none() {
+                peers.send_replace(list.clone());
+                // Events that arrived before the snapshot may be newer than it.
+                if let (Some(snapshot), Some(current)) = (*version, view.version) {
+                    if current > snapshot && !view.filtered {
+                        follow_up = Some(list_peers(Some(snapshot), None));
+                    }
+                }
+            } else {
+                // A later page: events since the first page may already
+                // have added some of these peers.
+                peers.send_modify(|current| {
+                    current.retain(|p| !list.iter().any(|l| l.peer_code == p.peer_code));
+                    current.extend(list.iter().cloned());
+                });
+            }
+            if version.is_some() {
+                view.version = *version;
+            }
+            if next.is_some() {
+                follow_up = Some(list_peers(None, next.clone()));
+            }
+        }
+        // The server sends the answer in order with room events, so it is
+        // never older than an applied event unless a newer answer came first.
+        ServerMessage::PeersDelta {
+            version,
+            peers: changed,
+            removed,
+            ..
+        } if view.version.is_none_or(|current| *version >= current) => {
+            peers.send_modify(|list| {
+                list.retain(|p| {
+                    !removed.contains(&p.peer_code)
+                        && !changed.iter().any(|c| c.peer_code == p.peer_code)
+                });
+                list.extend(changed.iter().cloned());
+            });
+            view.version = Some(*version);
+        }
+        ServerMessage::PeerJoined { peer, .. } => {
+            peers.send_modify(|list| {
+                list.retain(|p| p.peer_code != peer.peer_code);
+                list.push(peer.clone());
+            });
+        }
+        ServerMessage::PeerLeft { peer_code, .. } => {
+            peers.send_modify(|list| list.retain(|p| &p.peer_code != peer_code));
+        }
+        ServerMessage::PresenceChanged {
+            peer_code,
+            presence,
+            ..
+        } => {
+            peers.send_modify(|list| {
+                if let Some(existing) = list.iter_mut().find(|p| &p.peer_code == peer_code) {
+                    existing.presence = *presence;
+                }
+            });
+        }
+        ServerMessage::PeerUpdated { peer, .. } => {
+            peers.send_modify(|list| {
+                if let Some(existing) = list.iter_mut().find(|p| p.peer_code == peer.peer_code) {
+                    *existing = peer.clone();
+                }
+            });
+        }
+        // Answer to the worker's own keepalive ping.
+        ServerMessage::Pong {
+            nonce: None,
+            id: None,
+            ..
+        } => return follow_up,
+        _ => {}
+    }
+    let _ = events.send(ClientEvent::Message(msg));
+    follow_up
+}
+
+fn decode_frame(frame: &Message, encoding: Encoding) -> Option<ServerMessage> {
+    match frame {
+        Message::Text(text) => Encoding::Json.decode(text.as_bytes()).ok(),
+        Message::Binary(data) => encoding.decode(data).ok(),
+        _ => None,
+    }
+}
+
+async fn send_message(
+    ws: &mut WsStream,
+    msg: &ClientMessage,
+    encoding: Encoding,
+) -> Result<(), ClientError> {
+    let bytes = encoding
+        .encode(msg)
+        .map_err(|e| ClientError::Connect(e.to_string()))?;
+    let frame = if encoding.is_binary() {
+        Message::Binary(bytes)
+    } else {
+        Message::Text(String::from_utf8_lossy(&bytes).into_owned())
+    };
+    ws.send(frame)
+        .await
+        .map_err(|e| ClientError::Connect(e.to_string()))
+}
+
+/// Map a failed upgrade to [`ClientError`], keeping the `Retry-After` and
+/// [`HttpRejection`] body of an HTTP error response.
+fn connect_error(e: tokio_tungstenite::tungstenite::Error) -> ClientError {
+    match e {
+        tokio_tungstenite::tungstenite::Error::Http(response) => {
+            let retry_after = response
+                .headers()
+                .get(header::RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .map(Duration::from_secs);
+            let rejection = response
+                .body()
+                .as_deref()
+                .and_then(|body| serde_json::from_slice(body).ok());
+            ClientError::Refused {
+                status: response.status().as_u16(),
+                retry_after,
+                rejection,
+            }
+        }
+        e => ClientError::Connect(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_and_caps() {
+        let initial = Duration::from_millis(500);
+        let max = Duration::from_secs(30);
+        assert_eq!(backoff_delay(initial, max, 0), Duration::from_millis(500));
+        assert_eq!(backoff_delay(initial, max, 1), Duration::from_secs(1));
+        assert_eq!(backoff_delay(initial, max, 3), Duration::from_secs(4));
+        assert_eq!(backoff_delay(initial, max, 10), max);
+        assert_eq!(backoff_delay(initial, max, u32::MAX), max);
+    }
+
+    #[test]
+    fn keepalive_stays_below_server_idle_timeout() {
+        let limits = ServerLimits {
+            max_message_bytes: 1_048_576,
+            rate_limit_per_second: 50,
+            idle_timeout_secs: 300,
+            max_peer_code_bytes: 16,
+            max_device_name_bytes: 256,
+            max_message_id_bytes: 64,
+            max_multicast_targets: 16,
+            max_identities: 8,
+            auto_away_secs: None,
+            max_capabilities: 32,
+            max_metadata_entries: 16,
+            max_metadata_value_bytes: 256,
+            max_page_size: 256,
+            max_room_events_per_sec: 20,
+        };
+        assert_eq!(
+            keepalive_for(Duration::from_secs(60), Some(&limits)),
+            Duration::from_secs(60)
+        );
+        assert_eq!(
+            keepalive_for(Duration::from_secs(600), Some(&limits)),
+            Duration::from_secs(150)
+        );
+        assert_eq!(
+            keepalive_for(Duration::from_secs(600), None),
+            Duration::from_secs(600)
+        );
+    }
+
+    #[test]
+    fn peer_list_tracks_join_and_leave() {
+        let (events, mut event_rx) = mpsc::unbounded_channel();
+        let (peers, peer_rx) = watch::channel(Vec::new());
+        let peer = |code: &str| PeerData {
+            peer_code: code.into(),
+            device_name: "Device".into(),
+            device_type: DeviceType::Desktop,
+            wt_url: None,
+            wt_cert_hash: None,
+            presence: crate::Presence::Available,
+            capabilities: vec![],
+            metadata: BTreeMap::new(),
+        };
+        let mut view = RoomView::default();
+
+        handle_server_message(
+            ServerMessage::Peers {
+                peers: vec![peer("AAA")],
+                version: None,
+                id: None,
+                after: None,
+                next: None,
+            },
+            &events,
+            &peers,
+            &mut view,
+        );
+        handle_server_message(
+            ServerMessage::PeerJoined {
+                peer: peer("BBB"),
+                version: None,
+            },
+            &events,
+            &peers,
+            &mut view,
+        );
+        let mut renamed = peer("BBB");
+        renamed.device_name = "Renamed".into();
+        handle_server_message(
+            ServerMessage::PeerUpdated {
+                peer: renamed,
+                version: None,
+            },
+            &events,
+            &peers,
+            &mut view,
+        );
+        handle_server_message(
+            ServerMessage::PeerLeft {
+                peer_code: "AAA".into(),
+                version: None,
+            },
+            &events,
+            &peers,
+            &mut view,
+        );
+
+        let codes: Vec<String> = peer_rx
+            .borrow()
+            .iter()
+            .map(|p| p.peer_code.clone())
+            .collect();
+        assert_eq!(codes, vec!["BBB".to_string()]);
+        assert_eq!(peer_rx.borrow()[0].device_name, "Renamed");
+        // Every message is still forwarded as an event.
+        let mut forwarded = 0;
+        while event_rx.try_recv().is_ok() {
+            forwarded += 1;
+        }
+        assert_eq!(forwarded, 4);
+    }
+
+    #[test]
+    fn room_versions_skip_stale_events_and_request_resync() {
+        let (events, _event_rx) = mpsc::unbounded_channel();
+        let (peers, peer_rx) = watch::channel(Vec::new());
+        let peer = |code: &str| PeerData {
+            peer_code: code.into(),
+            device_name: "Device".into(),
+            device_type: DeviceType::Desktop,
+            wt_url: None,
+            wt_cert_hash: None,
+            presence: crate::Presence::Available,
+            capabilities: vec![],
+            metadata: BTreeMap::new(),
+        };
+        let codes = || -> Vec<String> {
+            peer_rx
+                .borrow()
+                .iter()
+                .map(|p: &PeerData| p.peer_code.clone())
+                .collect()
+        };
+        let since = |follow_up: Option<ClientMessage>| match follow_up {
+            Some(ClientMessage::ListPeers { since, .. }) => since,
+            _ => None,
+        };
+        let mut view = RoomView::default();
+
+        // A join that raced ahead of the snapshot is newer than it.
+        let early = ServerMessage::PeerJoined {
+            peer: peer("BBB"),
+            version: Some(4),
+        };
+        assert_eq!(
+            since(handle_server_message(early, &events, &peers, &mut view)),
+            None
+        );
+        let snapshot = ServerMessage::Peers {
+            peers: vec![peer("AAA")],
+            version: Some(3),
+            id: None,
+            after: None,
+            next: None,
+        };
+        assert_eq!(
+            since(handle_server_message(snapshot, &events, &peers, &mut view)),
+            Some(3)
+        );
+        assert_eq!(view.version, Some(3));
+
+        // Already covered by the snapshot: not applied again.
+        let stale = ServerMessage::PeerLeft {
+            peer_code: "AAA".into(),
+            version: Some(3),
+        };
+        assert_eq!(
+            since(handle_server_message(stale, &events, &peers, &mut view)),
+            None
+        );
+        assert_eq!(codes(), vec!["AAA".to_string()]);
+
+        // Version 5 after 3: version 4 was missed.
+        let after_gap = ServerMessage::PeerJoined {
+            peer: peer("CCC"),
+            version: Some(5),
+        };
+        assert_eq!(
+            since(handle_server_message(after_gap, &events, &peers, &mut view)),
+            Some(3)
+        );
+        let delta = ServerMessage::PeersDelta {
+            since: 3,
+            version: 5,
+            peers: vec![peer("BBB"), peer("CCC")],
+            removed: vec!["AAA".into()],
+            id: None,
+        };
+        assert_eq!(
+            since(handle_server_message(delta, &events, &peers, &mut view)),
+            None
+        );
+        assert_eq!(codes(), vec!["BBB".to_string(), "CCC".to_string()]);
+        assert_eq!(view.version, Some(5));
+    }
+
+    #[test]
+    fn updates_carry_over_to_reregistration() {
+        let mut config = ClientConfig::new("ws://x", "ABC", "Old", DeviceType::Desktop)
+            .with_webtransport("https://old", "old-hash");
+        config.apply_update(&ClientMessage::Update {
+            device_name: Some("New".into()),
+            wt_url: Some(None),
+            wt_cert_hash: None,
+            id: None,
+            identity: None,
+            capabilities: None,
+            metadata: None,
+        });
+        match config.register_message() {
+            ClientMessage::Register {
+                device_name,
+                wt_url,
+                wt_cert_hash,
+                ..
+            } => {
+                assert_eq!(device_name, "New");
+                assert_eq!(wt_url, None);
+                assert_eq!(wt_cert_hash.as_deref(), Some("old-hash"));
+            }
+            other => panic!("expected Register, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn only_validation_errors_are_fatal() {
+        assert!(is_fatal(Some(ErrorCode::InvalidPeerCode)));
+        assert!(is_fatal(Some(ErrorCode::UnsupportedProtocolVersion)));
+        assert!(!is_fatal(Some(ErrorCode::RateLimited)));
+        assert!(!is_fatal(Some(ErrorCode::RoomFull)));
+        assert!(!is_fatal(None));
+    }
+}
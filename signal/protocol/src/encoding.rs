@@ -0,0 +1,190 @@
+//! Wire encodings for rendezvous messages.
+//!
+//! JSON over WebSocket text frames is the default and is always available.
+//! With the `cbor` feature, messages can also be exchanged as CBOR over
+//! binary frames. The encoding is chosen per connection through the
+//! WebSocket subprotocol: `bolt-rendezvous.v{N}` selects JSON and
+//! `bolt-rendezvous.v{N}.cbor` selects CBOR.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Message encoding used on a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Encoding {
+    /// JSON in WebSocket text frames.
+    #[default]
+    Json,
+    /// CBOR (RFC 8949) in WebSocket binary frames.
+    #[cfg(feature = "cbor")]
+    Cbor,
+}
+
+/// Error produced when a message cannot be encoded or decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodecError(String);
+
+impl std::fmt::Display for CodecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+impl Encoding {
+    /// Subprotocol suffix after the version number (empty for JSON).
+    pub fn suffix(self) -> &'static str {
+        match self {
+            Encoding::Json => "",
+            #[cfg(feature = "cbor")]
+            Encoding::Cbor => ".cbor",
+        }
+    }
+
+    /// Parse a subprotocol suffix (the part after `v{N}`).
+    pub fn from_suffix(suffix: &str) -> Option<Self> {
+        match suffix {
+            "" | ".json" => Some(Encoding::Json),
+            #[cfg(feature = "cbor")]
+            ".cbor" => Some(Encoding::Cbor),
+            _ => None,
+        }
+    }
+
+    /// Whether this encoding travels in WebSocket binary frames.
+    pub fn is_binary(self) -> bool {
+        match self {
+            Encoding::Json => false,
+            #[cfg(feature = "cbor")]
+            Encoding::Cbor => true,
+        }
+    }
+
+    /// Serialize a message.
+    pub fn encode<T: Serialize>(self, msg: &T) -> Result<Vec<u8>, CodecError> {
+        match self {
+            Encoding::Json => serde_json::to_vec(msg).map_err(|e| CodecError(e.to_string())),
+            #[cfg(feature = "cbor")]
+            Encoding::Cbor => {
+                let mut buf = Vec::new();
+                ciborium::into_writer(msg, &mut buf).map_err(|e| CodecError(e.to_string()))?;
+                Ok(buf)
+            }
+        }
+    }
+
+    /// Deserialize a message.
+    pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, CodecError> {
+        match self {
+            Encoding::Json => serde_json::from_slice(bytes).map_err(|e| CodecError(e.to_string())),
+            #[cfg(feature = "cbor")]
+            Encoding::Cbor => ciborium::from_reader(bytes).map_err(|e| CodecError(e.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ClientMessage;
+    use serde_json::json;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn json_roundtrip() {
+        let msg = ClientMessage::Signal {
+            to: "XYZ789".into(),
+            payload: json!({"sdp": "offer-data"}),
+            id: None,
+            ack: false,
+            identity: None,
+        };
+        let bytes = Encoding::Json.encode(&msg).unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&bytes).unwrap(),
+            serde_json::to_value(&msg).unwrap()
+        );
+        let decoded: ClientMessage = Encoding::Json.decode(&bytes).unwrap();
+        assert!(matches!(decoded, ClientMessage::Signal { .. }));
+    }
+
+    #[test]
+    fn suffix_roundtrip() {
+        assert_eq!(Encoding::from_suffix(""), Some(Encoding::Json));
+        assert_eq!(Encoding::from_suffix(".json"), Some(Encoding::Json));
+        assert_eq!(Encoding::from_suffix(".xml"), None);
+        assert_eq!(Encoding::Json.suffix(), "");
+        assert!(!Encoding::Json.is_binary());
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn cbor_roundtrip_preserves_wire_shape() {
+        let msg = ClientMessage::Register {
+            peer_code: "ABC123".into(),
+            device_name: "Daemon".into(),
+            device_type: crate::DeviceType::Desktop,
+            wt_url: None,
+            wt_cert_hash: None,
+            protocol_version: Some(1),
+            room: None,
+            room_secret: None,
+            id: Some("r1".into()),
+            capabilities: vec![],
+            metadata: BTreeMap::new(),
+            discoverable: true,
+            subscription: None,
+        };
+        let bytes = Encoding::Cbor.encode(&msg).unwrap();
+        let decoded: ClientMessage = Encoding::Cbor.decode(&bytes).unwrap();
+        assert_eq!(
+            serde_json::to_value(&decoded).unwrap(),
+            serde_json::to_value(&msg).unwrap()
+        );
+        assert_eq!(Encoding::from_suffix(".cbor"), Some(Encoding::Cbor));
+        assert!(Encoding::Cbor.is_binary());
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn cbor_carries_json_payloads() {
+        let msg = crate::ServerMessage::Signal {
+            from: "alice".into(),
+            payload: json!({"candidate": "a=1", "sdpMLineIndex": 0, "nested": [true, null]}),
+            to: None,
+        };
+        let bytes = Encoding::Cbor.encode(&msg).unwrap();
+        let decoded: crate::ServerMessage = Encoding::Cbor.decode(&bytes).unwrap();
+        assert_eq!(
+            serde_json::to_value(&decoded).unwrap(),
+            serde_json::to_value(&msg).unwrap()
+        );
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn cbor_preserves_unknown_device_types() {
+        let peer = crate::PeerData {
+            peer_code: "ABC".into(),
+            device_name: "Box".into(),
+            device_type: crate::DeviceType::Unknown("fridge".into()),
+            wt_url: None,
+            wt_cert_hash: None,
+            presence: crate::Presence::Available,
+            capabilities: vec![],
+            metadata: BTreeMap::new(),
+        };
+        let bytes = Encoding::Cbor.encode(&peer).unwrap();
+        let decoded: crate::PeerData = Encoding::Cbor.decode(&bytes).unwrap();
+        assert_eq!(decoded.device_type, peer.device_type);
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn cbor_rejects_garbage() {
+        assert!(Encoding::Cbor
+            .decode::<ClientMessage>(&[0xff, 0x00])
+            .is_err());
+    }
+}
@@ -9,27 +9,236 @@
 //! # Wire Format
 //!
 //! Client-to-server messages use `snake_case` type tags:
-//! - `register`, `signal`, `manual_signal`, `ping`
+//! - `register`, `signal`, `manual_signal`, `multicast_signal`, `match_pin`,
+//!   `pair_request`, `pair_accept`, `pair_reject`, `update`, `set_presence`,
+//!   `rotate_code`, `leave`, `list_peers`, `subscribe`, `ping`
 //!
 //! Server-to-client messages use `snake_case` type tags:
-//! - `peers`, `peer_joined`, `peer_left`, `signal`, `error`
+//! - `server_info`, `peers`, `peers_delta`, `peer_joined`, `peer_left`,
+//!   `peer_updated`, `presence_changed`, `presence_throttled`, `signal`,
+//!   `multicast_result`, `pin_waiting`, `pin_matched`, `pair_request`,
+//!   `pair_accepted`, `pair_rejected`, `code_rotated`, `identity_added`,
+//!   `signal_delivered`, `room_info`, `pong`, `error`
+//!
+//! `error` messages carry a human-readable `message` plus an optional
+//! machine-readable [`ErrorCode`] and details (`field`, `retry_after_ms`).
+//!
+//! # Versioning
+//!
+//! Clients declare the protocol version they speak either by offering the
+//! WebSocket subprotocol `bolt-rendezvous.v{N}` (see [`subprotocol`]) or by
+//! setting `protocol_version` on `register`. Clients that do neither are
+//! treated as [`MIN_PROTOCOL_VERSION`]. After a successful registration the
+//! server answers with `server_info` before the initial `peers` list.
+//!
+//! # Encodings
+//!
+//! JSON is the default. With the `cbor` feature, the subprotocol
+//! `bolt-rendezvous.v{N}.cbor` selects CBOR over binary frames instead; see
+//! [`Encoding`].
+//!
+//! # Client
+//!
+//! With the `client` feature, [`client::RendezvousClient`] implements the
+//! connect/register/keepalive/reconnect cycle for Rust consumers.
+//!
+//! # Generated Definitions
+//!
+//! With the `schema` feature, [`schema`] renders these types as a JSON Schema
+//! document and a TypeScript declaration module. The checked-in copies under
+//! `generated/` are refreshed with
+//! `cargo run --features schema --bin bolt-rendezvous-codegen`.
+
+#[cfg(feature = "client")]
+pub mod client;
+mod encoding;
+#[cfg(feature = "schema")]
+pub mod schema;
+
+pub use encoding::{CodecError, Encoding};
+
+use std::collections::BTreeMap;
 
 use serde::{Deserialize, Serialize};
 
+/// Protocol version implemented by this crate.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Oldest protocol version a server built from this crate accepts.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Prefix of the WebSocket subprotocol token. The full token is
+/// `bolt-rendezvous.v{N}` plus an optional encoding suffix, e.g.
+/// `bolt-rendezvous.v1` or `bolt-rendezvous.v1.cbor`.
+pub const SUBPROTOCOL_PREFIX: &str = "bolt-rendezvous.v";
+
+/// Build the WebSocket subprotocol token for a protocol version and encoding.
+pub fn subprotocol(version: u32, encoding: Encoding) -> String {
+    format!("{SUBPROTOCOL_PREFIX}{version}{}", encoding.suffix())
+}
+
+/// Parse a WebSocket subprotocol token of the form
+/// `bolt-rendezvous.v{N}[.{encoding}]`.
+///
+/// Returns `None` for tokens that do not belong to this protocol or name an
+/// encoding this build does not support.
+pub fn parse_subprotocol(token: &str) -> Option<(u32, Encoding)> {
+    let rest = token.trim().strip_prefix(SUBPROTOCOL_PREFIX)?;
+    let digits = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    if digits == 0 {
+        return None;
+    }
+    let (version, suffix) = rest.split_at(digits);
+    Some((version.parse().ok()?, Encoding::from_suffix(suffix)?))
+}
+
 /// Device type reported by connecting peers.
 ///
-/// Serializes to lowercase strings: `"phone"`, `"tablet"`, `"laptop"`, `"desktop"`.
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
+/// Serializes to lowercase strings: `"phone"`, `"tablet"`, `"laptop"`,
+/// `"desktop"`, `"tv"`, `"server"`, `"watch"`, `"vehicle"`. `"headless"` is
+/// accepted as an alias for `"server"`. Any other string deserializes as
+/// [`DeviceType::Unknown`] and serializes back unchanged, so kinds added by
+/// newer peers pass through older servers and clients intact.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum DeviceType {
     Phone,
     Tablet,
     Laptop,
     Desktop,
+    /// Television or set-top box.
+    Tv,
+    /// Headless machine (home server, NAS, daemon).
+    Server,
+    Watch,
+    Vehicle,
+    /// A device kind this build does not know about, kept verbatim.
+    Unknown(String),
+}
+
+impl DeviceType {
+    /// Every device kind this build recognises, in wire form.
+    pub const KNOWN: &'static [&'static str] = &[
+        "phone", "tablet", "laptop", "desktop", "tv", "server", "watch", "vehicle",
+    ];
+
+    /// Wire representation.
+    pub fn as_str(&self) -> &str {
+        match self {
+            DeviceType::Phone => "phone",
+            DeviceType::Tablet => "tablet",
+            DeviceType::Laptop => "laptop",
+            DeviceType::Desktop => "desktop",
+            DeviceType::Tv => "tv",
+            DeviceType::Server => "server",
+            DeviceType::Watch => "watch",
+            DeviceType::Vehicle => "vehicle",
+            DeviceType::Unknown(other) => other,
+        }
+    }
+
+    /// Whether this is a kind this build does not recognise.
+    pub fn is_unknown(&self) -> bool {
+        matches!(self, DeviceType::Unknown(_))
+    }
+}
+
+impl From<&str> for DeviceType {
+    fn from(value: &str) -> Self {
+        match value {
+            "phone" => DeviceType::Phone,
+            "tablet" => DeviceType::Tablet,
+            "laptop" => DeviceType::Laptop,
+            "desktop" => DeviceType::Desktop,
+            "tv" => DeviceType::Tv,
+            "server" | "headless" => DeviceType::Server,
+            "watch" => DeviceType::Watch,
+            "vehicle" => DeviceType::Vehicle,
+            other => DeviceType::Unknown(other.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for DeviceType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for DeviceType {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for DeviceType {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = std::borrow::Cow::<'de, str>::deserialize(deserializer)?;
+        Ok(DeviceType::from(value.as_ref()))
+    }
+}
+
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for DeviceType {
+    fn schema_name() -> String {
+        "DeviceType".into()
+    }
+
+    fn json_schema(_gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        use schemars::schema::{InstanceType, Metadata, SchemaObject, SubschemaValidation};
+
+        let known = SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            enum_values: Some(DeviceType::KNOWN.iter().map(|&k| k.into()).collect()),
+            ..Default::default()
+        };
+        let other = SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            ..Default::default()
+        };
+        SchemaObject {
+            metadata: Some(Box::new(Metadata {
+                description: Some(
+                    "Device type reported by connecting peers. Unrecognised values must be \
+                     preserved, not rejected."
+                        .into(),
+                ),
+                ..Default::default()
+            })),
+            subschemas: Some(Box::new(SubschemaValidation {
+                any_of: Some(vec![known.into(), other.into()]),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+/// Availability of a peer, shown to the rest of its room.
+///
+/// Peers start out `available`. `busy` tells other peers not to send new
+/// offers. `away` is set by the peer itself or, when the server enables it,
+/// automatically after a quiet period.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum Presence {
+    #[default]
+    Available,
+    /// Occupied (e.g. in a transfer); not accepting new sessions.
+    Busy,
+    /// Not at the device.
+    Away,
+    /// A state this client does not know about; treat like `available`.
+    #[serde(other)]
+    Unknown,
 }
 
 /// Public peer information broadcast to room members.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct PeerData {
     pub peer_code: String,
     pub device_name: String,
@@ -40,6 +249,57 @@ pub struct PeerData {
     /// WebTransport TLS certificate SHA-256 hash (hex). Required for browser serverCertificateHashes.
     #[serde(skip_serializing_if = "Option::is_none", default)]
     pub wt_cert_hash: Option<String>,
+    /// Current availability (absent from servers that predate presence).
+    #[serde(default)]
+    pub presence: Presence,
+    /// Features the peer supports, e.g. `webrtc`, `webtransport`, `zstd`,
+    /// `bolt.v2`. The server only checks their size and spelling.
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub capabilities: Vec<String>,
+    /// Free-form key/value pairs advertised by the peer. Always mirrors
+    /// `wt_url` and `wt_cert_hash` under those keys.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// Which peers a client wants to hear about, and how much at a time.
+///
+/// Set with `register` or `subscribe`. Filters narrow `peers`, `peers_delta`
+/// and room events to matching peers; all filters must match. The defaults
+/// (all empty) keep everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Subscription {
+    /// Only peers of one of these device types.
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub device_types: Vec<DeviceType>,
+    /// Only peers advertising every one of these capabilities.
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub capabilities: Vec<String>,
+    /// In peer lists, only peers that joined, changed or updated their
+    /// presence within this many seconds. Room events are recent by nature
+    /// and are not held back by it.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub active_within_secs: Option<u64>,
+    /// Most peers per `peers` message; further pages are requested with
+    /// `list_peers` and `after`. Capped by [`ServerLimits::max_page_size`].
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub page_size: Option<u32>,
+    /// Most room events delivered per second. Capped by
+    /// [`ServerLimits::max_room_events_per_sec`], which also applies without
+    /// a subscription.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub max_events_per_sec: Option<u32>,
+}
+
+impl Subscription {
+    /// Whether the subscription narrows which peers are reported, so room
+    /// versions are expected to skip.
+    pub fn is_filtered(&self) -> bool {
+        !self.device_types.is_empty()
+            || !self.capabilities.is_empty()
+            || self.active_within_secs.is_some()
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -47,10 +307,22 @@ pub struct PeerData {
 // ---------------------------------------------------------------------------
 
 /// Messages sent from a client to the signaling server.
+///
+/// Every message accepts an optional client-chosen `id`. The server echoes it
+/// on any `error` caused by the message (and on `signal_delivered` acks) so
+/// clients can correlate failures with in-flight requests.
+///
+/// A connection may `register` several identities (see
+/// [`ServerLimits::max_identities`]). Messages that act as a peer take an
+/// optional `as` naming the identity; without it the first identity still
+/// registered acts.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum ClientMessage {
-    /// First message a client must send after connecting.
+    /// First message a client must send after connecting. Sent again on a
+    /// registered connection, it adds another identity, answered with
+    /// `identity_added`.
     Register {
         peer_code: String,
         device_name: String,
@@ -59,46 +331,755 @@ pub enum ClientMessage {
         wt_url: Option<String>,
         #[serde(skip_serializing_if = "Option::is_none", default)]
         wt_cert_hash: Option<String>,
+        /// Protocol version spoken by the client. Absent means
+        /// [`MIN_PROTOCOL_VERSION`] unless a subprotocol was negotiated.
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        protocol_version: Option<u32>,
+        /// Join this named room instead of the effective-IP room. The first
+        /// peer to use a name sets its secret; later peers must present the
+        /// same `room_secret`.
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        room: Option<String>,
+        /// Shared secret for `room`. Required with `room`.
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        room_secret: Option<String>,
+        /// See [`PeerData::capabilities`].
+        #[serde(skip_serializing_if = "Vec::is_empty", default)]
+        capabilities: Vec<String>,
+        /// See [`PeerData::metadata`]. The keys `wt_url` and `wt_cert_hash`
+        /// are aliases for those fields; the fields win if both are set.
+        #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+        metadata: BTreeMap<String, String>,
+        /// `false` keeps this peer out of the room's `peers` and room events.
+        /// It still sees the room, and can be reached with `manual_signal` or
+        /// by peers it has sent a `signal` to first.
+        #[serde(skip_serializing_if = "is_true", default = "default_true")]
+        discoverable: bool,
+        /// Initial presence subscription, so the first `peers` is already
+        /// filtered and paged.
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        subscription: Option<Subscription>,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        id: Option<String>,
     },
     /// Relay a WebRTC signaling payload to another peer.
     ///
-    /// This path is room-scoped: the target must be in the sender's effective-IP
-    /// room.
+    /// This path is room-scoped: the target must be in the sender's room
+    /// (its effective-IP room, or the named room it registered with).
     Signal {
         to: String,
         payload: serde_json::Value,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        id: Option<String>,
+        /// Request a `signal_delivered` ack once the payload has been handed
+        /// to the target's connection.
+        #[serde(skip_serializing_if = "is_false", default)]
+        ack: bool,
+        #[serde(rename = "as", skip_serializing_if = "Option::is_none", default)]
+        identity: Option<String>,
     },
     /// Relay a WebRTC signaling payload to an exact peer code for explicit
     /// manual pairing. This does not add the peer to automatic discovery.
+    ///
+    /// Only relayed once the target has accepted a `pair_request` from this
+    /// peer (or the two matched a PIN), until either disconnects.
     ManualSignal {
         to: String,
         payload: serde_json::Value,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        id: Option<String>,
+        /// Request a `signal_delivered` ack once the payload has been handed
+        /// to the target's connection.
+        #[serde(skip_serializing_if = "is_false", default)]
+        ack: bool,
+        #[serde(rename = "as", skip_serializing_if = "Option::is_none", default)]
+        identity: Option<String>,
+    },
+    /// Relay one signaling payload to several peers in the sender's room.
+    ///
+    /// Counts as a single message for rate limiting. The server answers with
+    /// one `multicast_result` listing the outcome per target.
+    MulticastSignal {
+        /// Target peer codes (at most `max_multicast_targets`).
+        to: Vec<String>,
+        payload: serde_json::Value,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        id: Option<String>,
+        #[serde(rename = "as", skip_serializing_if = "Option::is_none", default)]
+        identity: Option<String>,
+    },
+    /// Offer a short numeric PIN for ad-hoc pairing. When a second peer
+    /// submits the same PIN before it expires, the server introduces the two
+    /// with `pin_matched` and burns the PIN. The matched peers can then
+    /// `signal` each other regardless of room.
+    MatchPin {
+        pin: String,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        id: Option<String>,
+        #[serde(rename = "as", skip_serializing_if = "Option::is_none", default)]
+        identity: Option<String>,
+    },
+    /// Ask the peer with this exact code (in any room) for consent to
+    /// `manual_signal`. The target receives `pair_request` with this peer's
+    /// data and answers with `pair_accept` or `pair_reject`.
+    PairRequest {
+        to: String,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        id: Option<String>,
+        #[serde(rename = "as", skip_serializing_if = "Option::is_none", default)]
+        identity: Option<String>,
+    },
+    /// Accept the pending `pair_request` from peer `to`.
+    PairAccept {
+        to: String,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        id: Option<String>,
+        #[serde(rename = "as", skip_serializing_if = "Option::is_none", default)]
+        identity: Option<String>,
+    },
+    /// Decline the pending `pair_request` from peer `to`.
+    PairReject {
+        to: String,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        id: Option<String>,
+        #[serde(rename = "as", skip_serializing_if = "Option::is_none", default)]
+        identity: Option<String>,
+    },
+    /// Change this peer's advertised metadata without re-registering.
+    ///
+    /// Absent fields are left unchanged; `wt_url`/`wt_cert_hash` set to `null`
+    /// are cleared, and `capabilities`/`metadata` replace the previous list
+    /// or map. If anything changed, the room and the peer itself receive
+    /// `peer_updated`.
+    Update {
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        device_name: Option<String>,
+        #[serde(
+            skip_serializing_if = "Option::is_none",
+            default,
+            deserialize_with = "present"
+        )]
+        wt_url: Option<Option<String>>,
+        #[serde(
+            skip_serializing_if = "Option::is_none",
+            default,
+            deserialize_with = "present"
+        )]
+        wt_cert_hash: Option<Option<String>>,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        capabilities: Option<Vec<String>>,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        metadata: Option<BTreeMap<String, String>>,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        id: Option<String>,
+        #[serde(rename = "as", skip_serializing_if = "Option::is_none", default)]
+        identity: Option<String>,
+    },
+    /// Change this peer's presence. The room receives `presence_changed`.
+    SetPresence {
+        presence: Presence,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        id: Option<String>,
+        #[serde(rename = "as", skip_serializing_if = "Option::is_none", default)]
+        identity: Option<String>,
+    },
+    /// Replace this peer's code in place. The room receives `peer_left` for
+    /// the old code followed by `peer_joined` for the new one; the sender gets
+    /// `code_rotated`. Pairing consent and PIN matches do not carry over.
+    RotateCode {
+        peer_code: String,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        id: Option<String>,
+        #[serde(rename = "as", skip_serializing_if = "Option::is_none", default)]
+        identity: Option<String>,
+    },
+    /// Leave the room but keep the connection open. The room receives
+    /// `peer_left`; the client may `register` again afterwards. With `as`
+    /// only that identity leaves; without it, all of them do.
+    Leave {
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        id: Option<String>,
+        #[serde(rename = "as", skip_serializing_if = "Option::is_none", default)]
+        identity: Option<String>,
+    },
+    /// Ask for the current peer list of the room.
+    ///
+    /// Every `peer_joined`, `peer_left`, `peer_updated` and
+    /// `presence_changed` carries the room `version` it produced; versions of
+    /// one room are consecutive, so a client that sees a jump has missed
+    /// events. With `since` set to the last version it applied, the server
+    /// answers with `peers_delta` if it still remembers every later change,
+    /// otherwise (and without `since`) with a full `peers` snapshot.
+    /// A recreated room continues above every version the emptied one
+    /// reached, so a `since` from before gets a snapshot; the `peers` sent on
+    /// registration is the baseline.
+    ///
+    /// With a [`Subscription::page_size`], snapshots are ordered by peer code
+    /// and split into pages; `after` asks for the page following that code
+    /// (the previous page's `next`).
+    ListPeers {
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        since: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        after: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        id: Option<String>,
+        #[serde(rename = "as", skip_serializing_if = "Option::is_none", default)]
+        identity: Option<String>,
+    },
+    /// Replace the presence subscription. Answered with the first page of a
+    /// fresh `peers` snapshot under the new filters.
+    Subscribe {
+        subscription: Subscription,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        id: Option<String>,
+        #[serde(rename = "as", skip_serializing_if = "Option::is_none", default)]
+        identity: Option<String>,
     },
-    /// Keepalive ping from client (no-op, just prevents idle timeout).
-    Ping,
+    /// Keepalive ping from client. Prevents the idle timeout but does not
+    /// count as activity for automatic `away`. Answered with `pong`.
+    Ping {
+        /// Opaque value echoed in the `pong`, e.g. to match round trips.
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        nonce: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        id: Option<String>,
+    },
+}
+
+impl ClientMessage {
+    /// The client-supplied correlation id, if any.
+    pub fn id(&self) -> Option<&str> {
+        match self {
+            ClientMessage::Register { id, .. }
+            | ClientMessage::Signal { id, .. }
+            | ClientMessage::ManualSignal { id, .. }
+            | ClientMessage::MulticastSignal { id, .. }
+            | ClientMessage::MatchPin { id, .. }
+            | ClientMessage::PairRequest { id, .. }
+            | ClientMessage::PairAccept { id, .. }
+            | ClientMessage::PairReject { id, .. }
+            | ClientMessage::Update { id, .. }
+            | ClientMessage::SetPresence { id, .. }
+            | ClientMessage::RotateCode { id, .. }
+            | ClientMessage::Leave { id, .. }
+            | ClientMessage::ListPeers { id, .. }
+            | ClientMessage::Subscribe { id, .. }
+            | ClientMessage::Ping { id, .. } => id.as_deref(),
+        }
+    }
+
+    /// The identity named by `as`, if any.
+    pub fn identity(&self) -> Option<&str> {
+        match self {
+            ClientMessage::Signal { identity, .. }
+            | ClientMessage::ManualSignal { identity, .. }
+            | ClientMessage::MulticastSignal { identity, .. }
+            | ClientMessage::MatchPin { identity, .. }
+            | ClientMessage::PairRequest { identity, .. }
+            | ClientMessage::PairAccept { identity, .. }
+            | ClientMessage::PairReject { identity, .. }
+            | ClientMessage::Update { identity, .. }
+            | ClientMessage::SetPresence { identity, .. }
+            | ClientMessage::RotateCode { identity, .. }
+            | ClientMessage::Leave { identity, .. }
+            | ClientMessage::ListPeers { identity, .. }
+            | ClientMessage::Subscribe { identity, .. } => identity.as_deref(),
+            ClientMessage::Register { .. } | ClientMessage::Ping { .. } => None,
+        }
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+fn is_true(value: &bool) -> bool {
+    *value
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Deserialize a field that is present in the message (possibly as `null`)
+/// as `Some(..)`, so patch-style messages can tell "clear" from "unchanged".
+fn present<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
 }
 
 // ---------------------------------------------------------------------------
 // Server -> Client messages
 // ---------------------------------------------------------------------------
 
+/// Machine-readable error category carried by [`ServerMessage::Error`].
+///
+/// Serializes to `snake_case` strings (e.g. `"rate_limited"`). Codes added by
+/// newer servers deserialize as [`ErrorCode::Unknown`] so older clients can
+/// still fall back to the human-readable `message`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// A peer code (own or target) failed validation.
+    InvalidPeerCode,
+    /// A field other than a peer code failed validation.
+    InvalidField,
+    /// Per-connection message rate exceeded.
+    RateLimited,
+    /// The room has reached its peer limit.
+    RoomFull,
+    /// The server has reached its room limit.
+    RoomLimit,
+    /// The target peer is not registered (or not visible to the sender).
+    NotFound,
+    /// The target peer disconnected while the message was being relayed.
+    PeerDisconnected,
+    /// A manual peer code matches peers in more than one room.
+    Ambiguous,
+    /// The message requires a prior `register`.
+    NotRegistered,
+    /// `register` was sent on an already-registered connection.
+    AlreadyRegistered,
+    /// The message could not be parsed.
+    Malformed,
+    /// The message exceeds the size limit.
+    TooLarge,
+    /// Binary WebSocket frames are not accepted.
+    BinaryRejected,
+    /// The client's protocol version is not supported.
+    UnsupportedProtocolVersion,
+    /// The `room_secret` does not match the named room's secret.
+    Unauthorized,
+    /// A PIN expired without a match, or was already used.
+    Expired,
+    /// `manual_signal` to a peer that has not accepted a `pair_request`.
+    ConsentRequired,
+    /// `rotate_code` to a code another peer in the room is using.
+    CodeInUse,
+    /// The connection already holds `max_identities` identities.
+    IdentityLimit,
+    /// No `register` arrived within the server's registration deadline; the
+    /// connection is closed.
+    RegistrationTimeout,
+    /// A code this client does not know about.
+    #[serde(other)]
+    Unknown,
+}
+
+/// Effective server limits advertised in [`ServerMessage::ServerInfo`].
+///
+/// Clients should size messages and keepalives from these values instead of
+/// hardcoding them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ServerLimits {
+    /// Maximum size of a single WebSocket message in bytes.
+    pub max_message_bytes: u64,
+    /// Maximum messages per second per connection.
+    pub rate_limit_per_second: u32,
+    /// Seconds without any client message before the server closes the socket.
+    pub idle_timeout_secs: u64,
+    /// Maximum length of a peer code after hyphens are stripped.
+    pub max_peer_code_bytes: u32,
+    /// Maximum length of `device_name` in bytes.
+    pub max_device_name_bytes: u32,
+    /// Maximum length of a client message `id` in bytes (0 from servers that
+    /// predate message ids).
+    #[serde(default)]
+    pub max_message_id_bytes: u32,
+    /// Maximum number of targets in one `multicast_signal` (0 from servers
+    /// that predate multicast).
+    #[serde(default)]
+    pub max_multicast_targets: u32,
+    /// Maximum number of identities one connection may register (0 from
+    /// servers that predate multiple identities).
+    #[serde(default)]
+    pub max_identities: u32,
+    /// Maximum number of `capabilities` per peer (0 from servers that
+    /// predate capabilities).
+    #[serde(default)]
+    pub max_capabilities: u32,
+    /// Maximum number of `metadata` entries per peer.
+    #[serde(default)]
+    pub max_metadata_entries: u32,
+    /// Maximum length of a `metadata` value in bytes.
+    #[serde(default)]
+    pub max_metadata_value_bytes: u32,
+    /// Largest [`Subscription::page_size`] (0 from servers that predate
+    /// subscriptions).
+    #[serde(default)]
+    pub max_page_size: u32,
+    /// Most room events one identity receives per second.
+    #[serde(default)]
+    pub max_room_events_per_sec: u32,
+    /// Seconds without client activity (other than `ping`) after which the
+    /// server marks an `available` peer `away`. Absent when disabled.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub auto_away_secs: Option<u64>,
+}
+
+/// How the server classified the room a peer was placed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum RoomKind {
+    /// Private, loopback or link-local address; all such peers share the
+    /// server's `local` room.
+    Private,
+    /// Carrier-grade NAT / shared address space (`100.64.0.0/10`, also used
+    /// by Tailscale); shares the `local` room with private addresses.
+    Cgnat,
+    /// A public IP address: everyone behind it shares the room.
+    Public,
+    /// A named room joined with its secret.
+    Named,
+    /// A kind this client does not know about.
+    #[serde(other)]
+    Unknown,
+}
+
+/// Where the server took the client IP that chose an IP room from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum IpSource {
+    /// The TCP peer address; forwarded headers were absent or not trusted.
+    Socket,
+    /// A forwarded header from a configured trusted proxy.
+    TrustedProxy,
+    /// A forwarded header from a proxy connecting from a private address
+    /// (a PaaS edge such as Fly.io).
+    PrivateSourceProxy,
+    /// A source this client does not know about.
+    #[serde(other)]
+    Unknown,
+}
+
+/// Room diagnostics: sent to each identity as `room_info` and served over
+/// HTTP at `/whoami`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RoomInfo {
+    pub kind: RoomKind,
+    /// Salted hash of the room key, stable for the server's lifetime. Two
+    /// peers see the same fingerprint exactly when they share a room; it
+    /// does not reveal the IP address.
+    pub fingerprint: String,
+    /// Discoverable peers currently in the room, the caller included if it
+    /// is discoverable. Hidden peers are never counted.
+    pub size: u32,
+    pub ip_source: IpSource,
+    /// Header the client IP was read from (`fly-client-ip` or
+    /// `x-forwarded-for`) when a proxy source was used.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub forwarded_header: Option<String>,
+}
+
+/// Why the server refused a connection before the WebSocket upgrade.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum RejectionReason {
+    /// The server is at its connection limit (HTTP 503).
+    ServerFull,
+    /// The client IP holds too many connections (HTTP 429).
+    TooManyConnections,
+    /// The page's `Origin` is not allowed to connect (HTTP 403). Retrying
+    /// does not help.
+    OriginNotAllowed,
+    /// The WebSocket upgrade did not finish within the server's deadline
+    /// (HTTP 408).
+    UpgradeTimeout,
+    /// A reason this client does not know about.
+    #[serde(other)]
+    Unknown,
+}
+
+/// JSON body of an HTTP response that refuses a connection. The same delay
+/// is sent in the `Retry-After` header.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct HttpRejection {
+    pub reason: RejectionReason,
+    pub message: String,
+    /// Seconds the client should wait before connecting again.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub retry_after_secs: Option<u64>,
+}
+
+/// Outcome for one target of a `multicast_signal`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DeliveryResult {
+    /// Target peer code as sent by the client.
+    pub to: String,
+    /// Whether the payload was handed to the target's connection.
+    pub delivered: bool,
+    /// Why delivery failed (`not_found`, `peer_disconnected`,
+    /// `invalid_peer_code`).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub code: Option<ErrorCode>,
+}
+
 /// Messages sent from the signaling server to clients.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum ServerMessage {
-    /// Full list of peers currently in the same IP room (sent on registration).
-    Peers { peers: Vec<PeerData> },
-    /// A new peer joined the IP room.
-    PeerJoined { peer: PeerData },
-    /// A peer left the IP room.
-    PeerLeft { peer_code: String },
+    /// Negotiated protocol version and server parameters (sent on registration,
+    /// before `peers`).
+    ServerInfo {
+        protocol_version: u32,
+        server_version: String,
+        /// Random identifier of the server process; changes on restart.
+        instance_id: String,
+        limits: ServerLimits,
+    },
+    /// Full list of the other peers in the room, sent on registration and
+    /// as a snapshot answer to `list_peers`.
+    Peers {
+        peers: Vec<PeerData>,
+        /// Room version the list reflects (absent from servers that predate
+        /// room versions).
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        version: Option<u64>,
+        /// The `list_peers` `after` this page follows; absent on a first page.
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        after: Option<String>,
+        /// Set when more peers follow: pass it as `after` for the next page.
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        next: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        id: Option<String>,
+    },
+    /// Answer to `list_peers` when the server still remembers every change
+    /// after `since`: the net effect of those changes.
+    PeersDelta {
+        since: u64,
+        version: u64,
+        /// Peers that joined or changed, with their current data.
+        peers: Vec<PeerData>,
+        /// Codes of peers that left.
+        removed: Vec<String>,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        id: Option<String>,
+    },
+    /// Room events for this connection were dropped to stay within its
+    /// event rate. Sent once per second at most; after `retry_after_ms` a
+    /// `list_peers` with `since` catches up.
+    PresenceThrottled { retry_after_ms: u64 },
+    /// Which room an identity was placed in and why; sent after its
+    /// `peers` or `identity_added`.
+    RoomInfo { peer_code: String, room: RoomInfo },
+    /// A new peer joined the room.
+    PeerJoined {
+        peer: PeerData,
+        /// Room version after this change; see [`ClientMessage::ListPeers`].
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        version: Option<u64>,
+    },
+    /// A peer left the room.
+    PeerLeft {
+        peer_code: String,
+        /// Room version after this change.
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        version: Option<u64>,
+    },
+    /// A peer in the room changed its metadata (see `update`). `peer` is
+    /// the complete new state. Sent to the whole room, including the peer
+    /// itself.
+    PeerUpdated {
+        peer: PeerData,
+        /// Room version after this change.
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        version: Option<u64>,
+    },
+    /// A peer in the room changed presence, either via `set_presence` or
+    /// automatically. Sent to the whole room, including the peer itself.
+    PresenceChanged {
+        peer_code: String,
+        presence: Presence,
+        /// `true` when the server set the state (automatic `away`, or the
+        /// return to `available` on the next activity).
+        #[serde(skip_serializing_if = "is_false", default)]
+        automatic: bool,
+        /// Room version after this change.
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        version: Option<u64>,
+    },
     /// Relayed signaling payload from another peer.
     Signal {
         from: String,
         payload: serde_json::Value,
+        /// The receiving identity's peer code.
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        to: Option<String>,
+    },
+    /// The `match_pin` was accepted and is waiting for a second peer.
+    PinWaiting {
+        /// Milliseconds until the PIN expires unmatched.
+        expires_in_ms: u64,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        id: Option<String>,
+    },
+    /// Another peer submitted the same PIN. `id` is that of this client's own
+    /// `match_pin`.
+    PinMatched {
+        peer: PeerData,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        id: Option<String>,
+    },
+    /// Another peer asks for consent to `manual_signal` this peer. Answer
+    /// with `pair_accept` or `pair_reject` naming `peer.peer_code`.
+    PairRequest {
+        peer: PeerData,
+        /// The receiving identity's peer code.
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        to: Option<String>,
+    },
+    /// The target of this peer's `pair_request` accepted; `manual_signal` to
+    /// it is now relayed.
+    PairAccepted { peer: PeerData },
+    /// The target of this peer's `pair_request` declined.
+    PairRejected { peer_code: String },
+    /// Per-target outcome of a `multicast_signal`, in request order.
+    MulticastResult {
+        results: Vec<DeliveryResult>,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        id: Option<String>,
+    },
+    /// A `register` on an already registered connection succeeded. `peers`
+    /// lists the new identity's room, like `peers` after the first
+    /// registration.
+    IdentityAdded {
+        peer_code: String,
+        peers: Vec<PeerData>,
+        /// Room version `peers` reflects, as in `peers`.
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        version: Option<u64>,
+        /// Cursor for the next page of `peers`, as in `peers`; request it
+        /// with `list_peers` `as` this identity.
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        next: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        id: Option<String>,
+    },
+    /// This peer's `rotate_code` succeeded; `peer_code` is the new
+    /// (normalized) code.
+    CodeRotated {
+        peer_code: String,
+        /// Room version after the rotation. It stands for two changes, the
+        /// `peer_left` of the old code and the `peer_joined` of the new one,
+        /// which the other peers see. Absent for a hidden peer or when the
+        /// code did not change.
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        version: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        id: Option<String>,
+    },
+    /// Acknowledges a `signal` or `manual_signal` sent with `ack: true`: the
+    /// payload has been handed to the target's connection. This does not
+    /// confirm that the target processed it.
+    SignalDelivered {
+        /// Peer code the payload was relayed to.
+        to: String,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        id: Option<String>,
+    },
+    /// Reply to `ping`. The timestamps are taken when the server handles the
+    /// ping, so a client can estimate round-trip time and its clock offset
+    /// from the server.
+    Pong {
+        /// The `nonce` from the `ping`.
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        nonce: Option<String>,
+        /// Server wall-clock time, milliseconds since the Unix epoch.
+        server_time_ms: u64,
+        /// Milliseconds on a monotonic clock that starts with the server
+        /// process. Unaffected by wall-clock adjustments; compare only values
+        /// from the same `instance_id`.
+        monotonic_ms: u64,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        id: Option<String>,
     },
     /// Error response for invalid or malformed messages.
-    Error { message: String },
+    ///
+    /// `message` is the human-readable text older clients match on; `code`
+    /// and the optional details are the machine-readable equivalent.
+    Error {
+        message: String,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        code: Option<ErrorCode>,
+        /// Name of the offending request field (e.g. `"peer_code"`, `"to"`).
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        field: Option<String>,
+        /// Milliseconds the client should wait before retrying.
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        retry_after_ms: Option<u64>,
+        /// `id` of the client message that caused this error, if it had one.
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        id: Option<String>,
+    },
+}
+
+impl ServerMessage {
+    /// The room version stamped on a room event, if any.
+    pub fn room_version(&self) -> Option<u64> {
+        match self {
+            ServerMessage::PeerJoined { version, .. }
+            | ServerMessage::PeerLeft { version, .. }
+            | ServerMessage::PeerUpdated { version, .. }
+            | ServerMessage::PresenceChanged { version, .. } => *version,
+            _ => None,
+        }
+    }
+
+    /// Build an `error` message with a machine-readable code.
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        ServerMessage::Error {
+            message: message.into(),
+            code: Some(code),
+            field: None,
+            retry_after_ms: None,
+            id: None,
+        }
+    }
+
+    /// Build an `error` message that names the offending request field.
+    pub fn field_error(code: ErrorCode, field: &str, message: impl Into<String>) -> Self {
+        ServerMessage::Error {
+            message: message.into(),
+            code: Some(code),
+            field: Some(field.to_string()),
+            retry_after_ms: None,
+            id: None,
+        }
+    }
+
+    /// Build a `rate_limited` error with a retry hint.
+    pub fn rate_limited(retry_after_ms: u64) -> Self {
+        ServerMessage::Error {
+            message: "rate limited".into(),
+            code: Some(ErrorCode::RateLimited),
+            field: None,
+            retry_after_ms: Some(retry_after_ms),
+            id: None,
+        }
+    }
+
+    /// Attach the correlation id of the triggering client message to an
+    /// `error`. Other messages are returned unchanged.
+    pub fn with_id(mut self, request_id: Option<String>) -> Self {
+        if let ServerMessage::Error { id, .. } = &mut self {
+            *id = request_id;
+        }
+        self
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -124,6 +1105,16 @@ mod tests {
             peer_code: "ABC123".into(),
             device_name: "iPhone 15".into(),
             device_type: DeviceType::Phone,
+            wt_url: None,
+            wt_cert_hash: None,
+            protocol_version: None,
+            room: None,
+            room_secret: None,
+            id: None,
+            capabilities: vec![],
+            metadata: BTreeMap::new(),
+            discoverable: true,
+            subscription: None,
         };
         assert_wire_eq(
             &msg,
@@ -137,45 +1128,323 @@ mod tests {
     }
 
     #[test]
-    fn wire_client_signal() {
+    fn wire_client_register_with_version() {
+        let msg = ClientMessage::Register {
+            peer_code: "ABC123".into(),
+            device_name: "iPhone 15".into(),
+            device_type: DeviceType::Phone,
+            wt_url: None,
+            wt_cert_hash: None,
+            protocol_version: Some(1),
+            room: None,
+            room_secret: None,
+            id: None,
+            capabilities: vec![],
+            metadata: BTreeMap::new(),
+            discoverable: true,
+            subscription: None,
+        };
+        assert_wire_eq(
+            &msg,
+            json!({
+                "type": "register",
+                "peer_code": "ABC123",
+                "device_name": "iPhone 15",
+                "device_type": "phone",
+                "protocol_version": 1
+            }),
+        );
+    }
+
+    #[test]
+    fn wire_client_register_named_room() {
+        let msg = ClientMessage::Register {
+            peer_code: "ABC123".into(),
+            device_name: "iPhone 15".into(),
+            device_type: DeviceType::Phone,
+            wt_url: None,
+            wt_cert_hash: None,
+            protocol_version: None,
+            room: Some("design-team".into()),
+            room_secret: Some("correct horse".into()),
+            id: None,
+            capabilities: vec![],
+            metadata: BTreeMap::new(),
+            discoverable: true,
+            subscription: None,
+        };
+        assert_wire_eq(
+            &msg,
+            json!({
+                "type": "register",
+                "peer_code": "ABC123",
+                "device_name": "iPhone 15",
+                "device_type": "phone",
+                "room": "design-team",
+                "room_secret": "correct horse"
+            }),
+        );
+    }
+
+    #[test]
+    fn wire_client_signal() {
+        let msg = ClientMessage::Signal {
+            to: "XYZ789".into(),
+            payload: json!({"sdp": "offer-data"}),
+            id: None,
+            ack: false,
+            identity: None,
+        };
+        assert_wire_eq(
+            &msg,
+            json!({
+                "type": "signal",
+                "to": "XYZ789",
+                "payload": {"sdp": "offer-data"}
+            }),
+        );
+    }
+
+    #[test]
+    fn wire_client_manual_signal() {
+        let msg = ClientMessage::ManualSignal {
+            to: "XYZ789".into(),
+            payload: json!({"sdp": "offer-data"}),
+            id: None,
+            ack: false,
+            identity: None,
+        };
+        assert_wire_eq(
+            &msg,
+            json!({
+                "type": "manual_signal",
+                "to": "XYZ789",
+                "payload": {"sdp": "offer-data"}
+            }),
+        );
+    }
+
+    #[test]
+    fn wire_client_ping() {
+        let msg = ClientMessage::Ping {
+            nonce: None,
+            id: None,
+        };
+        assert_wire_eq(&msg, json!({"type": "ping"}));
+
+        let msg = ClientMessage::Ping {
+            nonce: Some("n-1".into()),
+            id: None,
+        };
+        assert_wire_eq(&msg, json!({"type": "ping", "nonce": "n-1"}));
+    }
+
+    #[test]
+    fn wire_client_update() {
+        let msg = ClientMessage::Update {
+            device_name: Some("Studio Mac".into()),
+            wt_url: None,
+            wt_cert_hash: Some(Some("ab12".into())),
+            id: None,
+            identity: None,
+            capabilities: None,
+            metadata: None,
+        };
+        assert_wire_eq(
+            &msg,
+            json!({
+                "type": "update",
+                "device_name": "Studio Mac",
+                "wt_cert_hash": "ab12"
+            }),
+        );
+    }
+
+    #[test]
+    fn deserialize_client_update_distinguishes_null_from_absent() {
+        let json = r#"{"type":"update","wt_url":null}"#;
+        match serde_json::from_str::<ClientMessage>(json).unwrap() {
+            ClientMessage::Update {
+                device_name,
+                wt_url,
+                wt_cert_hash,
+                ..
+            } => {
+                assert_eq!(device_name, None);
+                assert_eq!(wt_url, Some(None), "null clears");
+                assert_eq!(wt_cert_hash, None, "absent leaves unchanged");
+            }
+            other => panic!("expected Update, got {other:?}"),
+        }
+        // The explicit null survives a round trip.
+        let msg: ClientMessage = serde_json::from_str(json).unwrap();
+        assert_wire_eq(&msg, json!({"type": "update", "wt_url": null}));
+    }
+
+    #[test]
+    fn wire_client_set_presence() {
+        let msg = ClientMessage::SetPresence {
+            presence: Presence::Busy,
+            id: None,
+            identity: None,
+        };
+        assert_wire_eq(&msg, json!({"type": "set_presence", "presence": "busy"}));
+    }
+
+    #[test]
+    fn wire_server_presence_changed() {
+        let msg = ServerMessage::PresenceChanged {
+            peer_code: "ABC".into(),
+            presence: Presence::Away,
+            automatic: true,
+            version: None,
+        };
+        assert_wire_eq(
+            &msg,
+            json!({
+                "type": "presence_changed",
+                "peer_code": "ABC",
+                "presence": "away",
+                "automatic": true
+            }),
+        );
+    }
+
+    #[test]
+    fn presence_is_forward_compatible() {
+        let peer: PeerData = serde_json::from_value(json!({
+            "peer_code": "A",
+            "device_name": "d",
+            "device_type": "phone"
+        }))
+        .unwrap();
+        assert_eq!(peer.presence, Presence::Available);
+        let state: Presence = serde_json::from_value(json!("do_not_disturb")).unwrap();
+        assert_eq!(state, Presence::Unknown);
+    }
+
+    #[test]
+    fn wire_client_multicast_signal() {
+        let msg = ClientMessage::MulticastSignal {
+            to: vec!["AAA".into(), "BBB".into()],
+            payload: json!({"sdp": "offer"}),
+            id: Some("m1".into()),
+            identity: None,
+        };
+        assert_wire_eq(
+            &msg,
+            json!({
+                "type": "multicast_signal",
+                "to": ["AAA", "BBB"],
+                "payload": {"sdp": "offer"},
+                "id": "m1"
+            }),
+        );
+    }
+
+    #[test]
+    fn wire_server_multicast_result() {
+        let msg = ServerMessage::MulticastResult {
+            results: vec![
+                DeliveryResult {
+                    to: "AAA".into(),
+                    delivered: true,
+                    code: None,
+                },
+                DeliveryResult {
+                    to: "BBB".into(),
+                    delivered: false,
+                    code: Some(ErrorCode::NotFound),
+                },
+            ],
+            id: Some("m1".into()),
+        };
+        assert_wire_eq(
+            &msg,
+            json!({
+                "type": "multicast_result",
+                "results": [
+                    {"to": "AAA", "delivered": true},
+                    {"to": "BBB", "delivered": false, "code": "not_found"}
+                ],
+                "id": "m1"
+            }),
+        );
+    }
+
+    #[test]
+    fn wire_client_signal_with_id_and_ack() {
         let msg = ClientMessage::Signal {
             to: "XYZ789".into(),
-            payload: json!({"sdp": "offer-data"}),
+            payload: json!({"candidate": "c1"}),
+            id: Some("m-7".into()),
+            ack: true,
+            identity: None,
         };
         assert_wire_eq(
             &msg,
             json!({
                 "type": "signal",
                 "to": "XYZ789",
-                "payload": {"sdp": "offer-data"}
+                "payload": {"candidate": "c1"},
+                "id": "m-7",
+                "ack": true
             }),
         );
+        assert_eq!(msg.id(), Some("m-7"));
     }
 
+    // ── ServerMessage wire compatibility ─────────────────────
+
     #[test]
-    fn wire_client_manual_signal() {
-        let msg = ClientMessage::ManualSignal {
-            to: "XYZ789".into(),
-            payload: json!({"sdp": "offer-data"}),
+    fn wire_server_info() {
+        let msg = ServerMessage::ServerInfo {
+            protocol_version: 1,
+            server_version: "0.1.1".into(),
+            instance_id: "abc".into(),
+            limits: ServerLimits {
+                max_message_bytes: 1_048_576,
+                rate_limit_per_second: 50,
+                idle_timeout_secs: 300,
+                max_peer_code_bytes: 16,
+                max_device_name_bytes: 256,
+                max_message_id_bytes: 64,
+                max_multicast_targets: 16,
+                max_identities: 8,
+                auto_away_secs: None,
+                max_capabilities: 32,
+                max_metadata_entries: 16,
+                max_metadata_value_bytes: 256,
+                max_page_size: 256,
+                max_room_events_per_sec: 20,
+            },
         };
         assert_wire_eq(
             &msg,
             json!({
-                "type": "manual_signal",
-                "to": "XYZ789",
-                "payload": {"sdp": "offer-data"}
+                "type": "server_info",
+                "protocol_version": 1,
+                "server_version": "0.1.1",
+                "instance_id": "abc",
+                "limits": {
+                    "max_message_bytes": 1_048_576,
+                    "rate_limit_per_second": 50,
+                    "idle_timeout_secs": 300,
+                    "max_peer_code_bytes": 16,
+                    "max_device_name_bytes": 256,
+                    "max_message_id_bytes": 64,
+                    "max_multicast_targets": 16,
+                    "max_identities": 8,
+                    "max_capabilities": 32,
+                    "max_metadata_entries": 16,
+                    "max_metadata_value_bytes": 256,
+                    "max_page_size": 256,
+                    "max_room_events_per_sec": 20
+                }
             }),
         );
     }
 
-    #[test]
-    fn wire_client_ping() {
-        let msg = ClientMessage::Ping;
-        assert_wire_eq(&msg, json!({"type": "ping"}));
-    }
-
-    // ── ServerMessage wire compatibility ─────────────────────
-
     #[test]
     fn wire_server_peers() {
         let msg = ServerMessage::Peers {
@@ -185,7 +1454,14 @@ mod tests {
                 device_type: DeviceType::Laptop,
                 wt_url: None,
                 wt_cert_hash: None,
+                presence: Presence::Available,
+                capabilities: vec![],
+                metadata: BTreeMap::new(),
             }],
+            version: None,
+            id: None,
+            after: None,
+            next: None,
         };
         assert_wire_eq(
             &msg,
@@ -194,7 +1470,8 @@ mod tests {
                 "peers": [{
                     "peer_code": "ABC123",
                     "device_name": "MacBook",
-                    "device_type": "laptop"
+                    "device_type": "laptop",
+                    "presence": "available"
                 }]
             }),
         );
@@ -209,7 +1486,11 @@ mod tests {
                 device_type: DeviceType::Tablet,
                 wt_url: None,
                 wt_cert_hash: None,
+                presence: Presence::Available,
+                capabilities: vec![],
+                metadata: BTreeMap::new(),
             },
+            version: None,
         };
         assert_wire_eq(
             &msg,
@@ -218,7 +1499,8 @@ mod tests {
                 "peer": {
                     "peer_code": "DEF456",
                     "device_name": "iPad",
-                    "device_type": "tablet"
+                    "device_type": "tablet",
+                    "presence": "available"
                 }
             }),
         );
@@ -228,6 +1510,7 @@ mod tests {
     fn wire_server_peer_left() {
         let msg = ServerMessage::PeerLeft {
             peer_code: "ABC123".into(),
+            version: None,
         };
         assert_wire_eq(
             &msg,
@@ -243,6 +1526,7 @@ mod tests {
         let msg = ServerMessage::Signal {
             from: "ABC123".into(),
             payload: json!({"sdp": "offer-data"}),
+            to: None,
         };
         assert_wire_eq(
             &msg,
@@ -258,6 +1542,10 @@ mod tests {
     fn wire_server_error() {
         let msg = ServerMessage::Error {
             message: "bad request".into(),
+            code: None,
+            field: None,
+            retry_after_ms: None,
+            id: None,
         };
         assert_wire_eq(
             &msg,
@@ -268,6 +1556,414 @@ mod tests {
         );
     }
 
+    #[test]
+    fn wire_server_error_with_code() {
+        let msg = ServerMessage::field_error(
+            ErrorCode::InvalidPeerCode,
+            "to",
+            "invalid_peer_code: Target peer code cannot be empty",
+        );
+        assert_wire_eq(
+            &msg,
+            json!({
+                "type": "error",
+                "message": "invalid_peer_code: Target peer code cannot be empty",
+                "code": "invalid_peer_code",
+                "field": "to"
+            }),
+        );
+    }
+
+    #[test]
+    fn wire_server_error_rate_limited() {
+        assert_wire_eq(
+            &ServerMessage::rate_limited(250),
+            json!({
+                "type": "error",
+                "message": "rate limited",
+                "code": "rate_limited",
+                "retry_after_ms": 250
+            }),
+        );
+    }
+
+    #[test]
+    fn wire_server_error_echoes_id() {
+        let msg = ServerMessage::error(ErrorCode::NotFound, "peer 'X' not found")
+            .with_id(Some("m-7".into()));
+        assert_wire_eq(
+            &msg,
+            json!({
+                "type": "error",
+                "message": "peer 'X' not found",
+                "code": "not_found",
+                "id": "m-7"
+            }),
+        );
+    }
+
+    #[test]
+    fn wire_server_signal_delivered() {
+        let msg = ServerMessage::SignalDelivered {
+            to: "XYZ789".into(),
+            id: Some("m-7".into()),
+        };
+        assert_wire_eq(
+            &msg,
+            json!({
+                "type": "signal_delivered",
+                "to": "XYZ789",
+                "id": "m-7"
+            }),
+        );
+    }
+
+    #[test]
+    fn wire_pin_matching() {
+        let msg = ClientMessage::MatchPin {
+            pin: "042137".into(),
+            id: Some("p1".into()),
+            identity: None,
+        };
+        assert_wire_eq(
+            &msg,
+            json!({"type": "match_pin", "pin": "042137", "id": "p1"}),
+        );
+        let msg = ServerMessage::PinWaiting {
+            expires_in_ms: 60_000,
+            id: Some("p1".into()),
+        };
+        assert_wire_eq(
+            &msg,
+            json!({"type": "pin_waiting", "expires_in_ms": 60_000, "id": "p1"}),
+        );
+        let msg = ServerMessage::PinMatched {
+            peer: PeerData {
+                peer_code: "ABC".into(),
+                device_name: "Box".into(),
+                device_type: DeviceType::Desktop,
+                wt_url: None,
+                wt_cert_hash: None,
+                presence: Presence::Available,
+                capabilities: vec![],
+                metadata: BTreeMap::new(),
+            },
+            id: None,
+        };
+        assert_wire_eq(
+            &msg,
+            json!({
+                "type": "pin_matched",
+                "peer": {
+                    "peer_code": "ABC",
+                    "device_name": "Box",
+                    "device_type": "desktop",
+                    "presence": "available"
+                }
+            }),
+        );
+    }
+
+    #[test]
+    fn wire_pairing_consent() {
+        let msg = ClientMessage::PairRequest {
+            to: "XYZ789".into(),
+            id: None,
+            identity: None,
+        };
+        assert_wire_eq(&msg, json!({"type": "pair_request", "to": "XYZ789"}));
+        let msg = ClientMessage::PairAccept {
+            to: "ABC123".into(),
+            id: None,
+            identity: None,
+        };
+        assert_wire_eq(&msg, json!({"type": "pair_accept", "to": "ABC123"}));
+        let msg = ClientMessage::PairReject {
+            to: "ABC123".into(),
+            id: None,
+            identity: None,
+        };
+        assert_wire_eq(&msg, json!({"type": "pair_reject", "to": "ABC123"}));
+        let msg = ServerMessage::PairRejected {
+            peer_code: "XYZ789".into(),
+        };
+        assert_wire_eq(
+            &msg,
+            json!({"type": "pair_rejected", "peer_code": "XYZ789"}),
+        );
+    }
+
+    #[test]
+    fn wire_leave_and_rotate() {
+        assert_wire_eq(
+            &ClientMessage::Leave {
+                id: None,
+                identity: None,
+            },
+            json!({"type": "leave"}),
+        );
+        let msg = ClientMessage::RotateCode {
+            peer_code: "NEW123".into(),
+            id: Some("r1".into()),
+            identity: None,
+        };
+        assert_wire_eq(
+            &msg,
+            json!({"type": "rotate_code", "peer_code": "NEW123", "id": "r1"}),
+        );
+        let msg = ServerMessage::CodeRotated {
+            peer_code: "NEW123".into(),
+            version: Some(7),
+            id: Some("r1".into()),
+        };
+        assert_wire_eq(
+            &msg,
+            json!({"type": "code_rotated", "peer_code": "NEW123", "version": 7, "id": "r1"}),
+        );
+    }
+
+    #[test]
+    fn wire_capabilities_and_metadata() {
+        let json = r#"{"type":"register","peer_code":"ABC","device_name":"d","device_type":"desktop","capabilities":["webrtc","zstd"],"metadata":{"bolt.inbox":"1"}}"#;
+        let msg: ClientMessage = serde_json::from_str(json).unwrap();
+        match &msg {
+            ClientMessage::Register {
+                capabilities,
+                metadata,
+                ..
+            } => {
+                assert_eq!(capabilities, &["webrtc", "zstd"]);
+                assert_eq!(metadata.get("bolt.inbox").map(String::as_str), Some("1"));
+            }
+            _ => panic!("expected Register"),
+        }
+        let msg = ClientMessage::Update {
+            device_name: None,
+            wt_url: None,
+            wt_cert_hash: None,
+            capabilities: Some(vec![]),
+            metadata: None,
+            id: None,
+            identity: None,
+        };
+        assert_wire_eq(&msg, json!({"type": "update", "capabilities": []}));
+        let peer = PeerData {
+            peer_code: "ABC".into(),
+            device_name: "d".into(),
+            device_type: DeviceType::Desktop,
+            wt_url: None,
+            wt_cert_hash: None,
+            presence: Presence::Available,
+            capabilities: vec!["webrtc".into()],
+            metadata: BTreeMap::from([("k".to_string(), "v".to_string())]),
+        };
+        assert_wire_eq(
+            &peer,
+            json!({
+                "peer_code": "ABC",
+                "device_name": "d",
+                "device_type": "desktop",
+                "presence": "available",
+                "capabilities": ["webrtc"],
+                "metadata": {"k": "v"}
+            }),
+        );
+    }
+
+    #[test]
+    fn wire_discoverable_defaults_to_true() {
+        let json =
+            r#"{"type":"register","peer_code":"ABC","device_name":"d","device_type":"desktop"}"#;
+        let msg: ClientMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(
+            msg,
+            ClientMessage::Register {
+                discoverable: true,
+                ..
+            }
+        ));
+        // Only the non-default value goes on the wire.
+        assert!(!serde_json::to_string(&msg)
+            .unwrap()
+            .contains("discoverable"));
+        let json = r#"{"type":"register","peer_code":"ABC","device_name":"d","device_type":"desktop","discoverable":false}"#;
+        let msg: ClientMessage = serde_json::from_str(json).unwrap();
+        assert!(serde_json::to_string(&msg)
+            .unwrap()
+            .contains(r#""discoverable":false"#));
+    }
+
+    #[test]
+    fn wire_subscription() {
+        let msg = ClientMessage::Subscribe {
+            subscription: Subscription {
+                device_types: vec![DeviceType::Phone],
+                page_size: Some(50),
+                ..Default::default()
+            },
+            id: None,
+            identity: None,
+        };
+        assert_wire_eq(
+            &msg,
+            json!({"type": "subscribe", "subscription": {"device_types": ["phone"], "page_size": 50}}),
+        );
+        let msg = ServerMessage::Peers {
+            peers: vec![],
+            version: Some(3),
+            after: Some("BBB".into()),
+            next: Some("DDD".into()),
+            id: None,
+        };
+        assert_wire_eq(
+            &msg,
+            json!({"type": "peers", "peers": [], "version": 3, "after": "BBB", "next": "DDD"}),
+        );
+        assert_wire_eq(
+            &ServerMessage::PresenceThrottled {
+                retry_after_ms: 250,
+            },
+            json!({"type": "presence_throttled", "retry_after_ms": 250}),
+        );
+    }
+
+    #[test]
+    fn http_rejection_round_trips() {
+        let rejection = HttpRejection {
+            reason: RejectionReason::ServerFull,
+            message: "server is at its connection limit".into(),
+            retry_after_secs: Some(5),
+        };
+        let json = serde_json::to_value(&rejection).unwrap();
+        assert_eq!(
+            json,
+            json!({"reason": "server_full", "message": "server is at its connection limit",
+                   "retry_after_secs": 5})
+        );
+        assert_eq!(
+            serde_json::from_value::<HttpRejection>(json).unwrap(),
+            rejection
+        );
+        let unknown: HttpRejection =
+            serde_json::from_str(r#"{"reason":"maintenance","message":"x"}"#).unwrap();
+        assert_eq!(unknown.reason, RejectionReason::Unknown);
+        assert_eq!(unknown.retry_after_secs, None);
+    }
+
+    #[test]
+    fn wire_room_info() {
+        let msg = ServerMessage::RoomInfo {
+            peer_code: "ABC".into(),
+            room: RoomInfo {
+                kind: RoomKind::Cgnat,
+                fingerprint: "00ff00ff00ff00ff".into(),
+                size: 3,
+                ip_source: IpSource::PrivateSourceProxy,
+                forwarded_header: Some("fly-client-ip".into()),
+            },
+        };
+        assert_wire_eq(
+            &msg,
+            json!({"type": "room_info", "peer_code": "ABC", "room": {
+                "kind": "cgnat", "fingerprint": "00ff00ff00ff00ff", "size": 3,
+                "ip_source": "private_source_proxy", "forwarded_header": "fly-client-ip"
+            }}),
+        );
+        let room: RoomInfo = serde_json::from_str(
+            r#"{"kind":"mesh","fingerprint":"x","size":1,"ip_source":"relay"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            (room.kind, room.ip_source),
+            (RoomKind::Unknown, IpSource::Unknown)
+        );
+    }
+
+    #[test]
+    fn wire_room_versions() {
+        let msg: ClientMessage =
+            serde_json::from_str(r#"{"type":"list_peers","since":7,"id":"r1"}"#).unwrap();
+        assert!(matches!(
+            msg,
+            ClientMessage::ListPeers { since: Some(7), .. }
+        ));
+        assert_eq!(msg.id(), Some("r1"));
+        let msg = ServerMessage::PeerLeft {
+            peer_code: "ABC".into(),
+            version: Some(8),
+        };
+        assert_eq!(msg.room_version(), Some(8));
+        assert_wire_eq(
+            &msg,
+            json!({"type": "peer_left", "peer_code": "ABC", "version": 8}),
+        );
+        let msg = ServerMessage::PeersDelta {
+            since: 7,
+            version: 8,
+            peers: vec![],
+            removed: vec!["ABC".into()],
+            id: Some("r1".into()),
+        };
+        assert_wire_eq(
+            &msg,
+            json!({"type": "peers_delta", "since": 7, "version": 8, "peers": [], "removed": ["ABC"], "id": "r1"}),
+        );
+        // Servers without room versions.
+        let msg: ServerMessage =
+            serde_json::from_str(r#"{"type":"peer_left","peer_code":"ABC"}"#).unwrap();
+        assert_eq!(msg.room_version(), None);
+    }
+
+    #[test]
+    fn wire_multiple_identities() {
+        let json = r#"{"type":"signal","to":"BOB","payload":{},"as":"INBOX"}"#;
+        let msg: ClientMessage = serde_json::from_str(json).unwrap();
+        assert_eq!(msg.identity(), Some("INBOX"));
+        assert_wire_eq(
+            &msg,
+            json!({"type": "signal", "to": "BOB", "payload": {}, "as": "INBOX"}),
+        );
+        let msg = ServerMessage::Signal {
+            from: "BOB".into(),
+            payload: json!({}),
+            to: Some("INBOX".into()),
+        };
+        assert_wire_eq(
+            &msg,
+            json!({"type": "signal", "from": "BOB", "payload": {}, "to": "INBOX"}),
+        );
+        let msg = ServerMessage::IdentityAdded {
+            peer_code: "INBOX".into(),
+            peers: vec![],
+            id: Some("r2".into()),
+            version: None,
+            next: None,
+        };
+        assert_wire_eq(
+            &msg,
+            json!({"type": "identity_added", "peer_code": "INBOX", "peers": [], "id": "r2"}),
+        );
+    }
+
+    #[test]
+    fn wire_server_pong() {
+        let msg = ServerMessage::Pong {
+            nonce: Some("n-1".into()),
+            server_time_ms: 1_700_000_000_000,
+            monotonic_ms: 4_321,
+            id: None,
+        };
+        assert_wire_eq(
+            &msg,
+            json!({
+                "type": "pong",
+                "nonce": "n-1",
+                "server_time_ms": 1_700_000_000_000u64,
+                "monotonic_ms": 4_321
+            }),
+        );
+    }
+
     // ── Deserialization roundtrip tests ──────────────────────
 
     #[test]
@@ -294,7 +1990,7 @@ mod tests {
         let json = r#"{"type":"signal","to":"XYZ789","payload":{"sdp":"..."}}"#;
         let msg: ClientMessage = serde_json::from_str(json).unwrap();
         match msg {
-            ClientMessage::Signal { to, payload } => {
+            ClientMessage::Signal { to, payload, .. } => {
                 assert_eq!(to, "XYZ789");
                 assert!(payload.get("sdp").is_some());
             }
@@ -307,7 +2003,7 @@ mod tests {
         let json = r#"{"type":"manual_signal","to":"XYZ789","payload":{"sdp":"..."}}"#;
         let msg: ClientMessage = serde_json::from_str(json).unwrap();
         match msg {
-            ClientMessage::ManualSignal { to, payload } => {
+            ClientMessage::ManualSignal { to, payload, .. } => {
                 assert_eq!(to, "XYZ789");
                 assert!(payload.get("sdp").is_some());
             }
@@ -320,7 +2016,7 @@ mod tests {
         let json = r#"{"type":"peers","peers":[{"peer_code":"ABC123","device_name":"test","device_type":"desktop"}]}"#;
         let msg: ServerMessage = serde_json::from_str(json).unwrap();
         match msg {
-            ServerMessage::Peers { peers } => {
+            ServerMessage::Peers { peers, .. } => {
                 assert_eq!(peers.len(), 1);
                 assert_eq!(peers[0].peer_code, "ABC123");
             }
@@ -333,9 +2029,10 @@ mod tests {
         let json = r#"{"type":"signal","from":"alice","payload":{"sdp":"offer-data"}}"#;
         let msg: ServerMessage = serde_json::from_str(json).unwrap();
         match msg {
-            ServerMessage::Signal { from, payload } => {
+            ServerMessage::Signal { from, payload, to } => {
                 assert_eq!(from, "alice");
                 assert_eq!(payload, json!({"sdp": "offer-data"}));
+                assert_eq!(to, None);
             }
             _ => panic!("expected Signal"),
         }
@@ -346,11 +2043,52 @@ mod tests {
         let json = r#"{"type":"error","message":"peer not found"}"#;
         let msg: ServerMessage = serde_json::from_str(json).unwrap();
         match msg {
-            ServerMessage::Error { message } => assert_eq!(message, "peer not found"),
+            ServerMessage::Error { message, code, .. } => {
+                assert_eq!(message, "peer not found");
+                assert_eq!(code, None);
+            }
+            _ => panic!("expected Error"),
+        }
+    }
+
+    #[test]
+    fn deserialize_server_error_unknown_code() {
+        let json = r#"{"type":"error","message":"later","code":"some_future_code"}"#;
+        let msg: ServerMessage = serde_json::from_str(json).unwrap();
+        match msg {
+            ServerMessage::Error { code, .. } => assert_eq!(code, Some(ErrorCode::Unknown)),
             _ => panic!("expected Error"),
         }
     }
 
+    #[test]
+    fn error_code_roundtrip() {
+        for (code, expected_str) in [
+            (ErrorCode::InvalidPeerCode, "invalid_peer_code"),
+            (ErrorCode::RateLimited, "rate_limited"),
+            (ErrorCode::RoomFull, "room_full"),
+            (ErrorCode::RoomLimit, "room_limit"),
+            (ErrorCode::NotFound, "not_found"),
+            (ErrorCode::Ambiguous, "ambiguous"),
+            (ErrorCode::NotRegistered, "not_registered"),
+            (ErrorCode::AlreadyRegistered, "already_registered"),
+            (ErrorCode::Malformed, "malformed"),
+            (ErrorCode::TooLarge, "too_large"),
+            (ErrorCode::BinaryRejected, "binary_rejected"),
+            (ErrorCode::Unauthorized, "unauthorized"),
+            (ErrorCode::Expired, "expired"),
+            (ErrorCode::ConsentRequired, "consent_required"),
+            (ErrorCode::CodeInUse, "code_in_use"),
+            (ErrorCode::IdentityLimit, "identity_limit"),
+            (ErrorCode::RegistrationTimeout, "registration_timeout"),
+        ] {
+            let json = serde_json::to_value(code).unwrap();
+            assert_eq!(json, json!(expected_str));
+            let decoded: ErrorCode = serde_json::from_value(json).unwrap();
+            assert_eq!(decoded, code);
+        }
+    }
+
     // ── DeviceType serde ─────────────────────────────────────
 
     #[test]
@@ -360,6 +2098,10 @@ mod tests {
             (DeviceType::Tablet, "tablet"),
             (DeviceType::Laptop, "laptop"),
             (DeviceType::Desktop, "desktop"),
+            (DeviceType::Tv, "tv"),
+            (DeviceType::Server, "server"),
+            (DeviceType::Watch, "watch"),
+            (DeviceType::Vehicle, "vehicle"),
         ] {
             let json = serde_json::to_value(&variant).unwrap();
             assert_eq!(json, json!(expected_str));
@@ -368,6 +2110,70 @@ mod tests {
         }
     }
 
+    #[test]
+    fn device_type_headless_alias() {
+        let decoded: DeviceType = serde_json::from_value(json!("headless")).unwrap();
+        assert_eq!(decoded, DeviceType::Server);
+        assert_eq!(serde_json::to_value(&decoded).unwrap(), json!("server"));
+    }
+
+    #[test]
+    fn device_type_unknown_roundtrips() {
+        let decoded: DeviceType = serde_json::from_value(json!("fridge")).unwrap();
+        assert_eq!(decoded, DeviceType::Unknown("fridge".into()));
+        assert!(decoded.is_unknown());
+        assert_eq!(serde_json::to_value(&decoded).unwrap(), json!("fridge"));
+
+        // A register from a newer client is no longer rejected as malformed.
+        let json = r#"{"type":"register","peer_code":"ABC","device_name":"Car","device_type":"hovercraft"}"#;
+        let msg: ClientMessage = serde_json::from_str(json).unwrap();
+        match msg {
+            ClientMessage::Register { device_type, .. } => {
+                assert_eq!(device_type.as_str(), "hovercraft")
+            }
+            _ => panic!("expected Register"),
+        }
+    }
+
+    #[test]
+    fn device_type_rejects_non_strings() {
+        assert!(serde_json::from_value::<DeviceType>(json!(3)).is_err());
+    }
+
+    // ── Subprotocol tokens ───────────────────────────────────
+
+    #[test]
+    fn subprotocol_roundtrip() {
+        assert_eq!(subprotocol(1, Encoding::Json), "bolt-rendezvous.v1");
+        assert_eq!(
+            parse_subprotocol("bolt-rendezvous.v1"),
+            Some((1, Encoding::Json))
+        );
+        assert_eq!(
+            parse_subprotocol(" bolt-rendezvous.v12 "),
+            Some((12, Encoding::Json))
+        );
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn subprotocol_cbor_suffix() {
+        assert_eq!(subprotocol(1, Encoding::Cbor), "bolt-rendezvous.v1.cbor");
+        assert_eq!(
+            parse_subprotocol("bolt-rendezvous.v1.cbor"),
+            Some((1, Encoding::Cbor))
+        );
+    }
+
+    #[test]
+    fn subprotocol_rejects_foreign_tokens() {
+        assert_eq!(parse_subprotocol("graphql-ws"), None);
+        assert_eq!(parse_subprotocol("bolt-rendezvous.v"), None);
+        assert_eq!(parse_subprotocol("bolt-rendezvous.v1x"), None);
+        assert_eq!(parse_subprotocol("bolt-rendezvous.v-1"), None);
+        assert_eq!(parse_subprotocol("bolt-rendezvous.v1.xml"), None);
+    }
+
     // ── Clone ────────────────────────────────────────────────
 
     #[test]
@@ -375,6 +2181,7 @@ mod tests {
         let msg = ServerMessage::Signal {
             from: "peer1".into(),
             payload: json!({"key": "value"}),
+            to: None,
         };
         let cloned = msg.clone();
         let orig_val = serde_json::to_value(&msg).unwrap();
@@ -382,6 +2189,37 @@ mod tests {
         assert_eq!(orig_val, clone_val);
     }
 
+    #[test]
+    fn older_payloads_still_deserialize() {
+        let ping: ClientMessage = serde_json::from_str(r#"{"type":"ping"}"#).unwrap();
+        assert!(matches!(
+            ping,
+            ClientMessage::Ping {
+                nonce: None,
+                id: None
+            }
+        ));
+        let signal: ClientMessage =
+            serde_json::from_str(r#"{"type":"signal","to":"X","payload":null}"#).unwrap();
+        assert!(matches!(
+            signal,
+            ClientMessage::Signal {
+                id: None,
+                ack: false,
+                ..
+            }
+        ));
+        let limits: ServerLimits = serde_json::from_value(json!({
+            "max_message_bytes": 1,
+            "rate_limit_per_second": 1,
+            "idle_timeout_secs": 1,
+            "max_peer_code_bytes": 1,
+            "max_device_name_bytes": 1
+        }))
+        .unwrap();
+        assert_eq!(limits.max_message_id_bytes, 0);
+    }
+
     #[test]
     fn client_message_clone() {
         let msg = ClientMessage::Register {
@@ -390,6 +2228,14 @@ mod tests {
             device_type: DeviceType::Desktop,
             wt_url: None,
             wt_cert_hash: None,
+            protocol_version: Some(PROTOCOL_VERSION),
+            room: None,
+            room_secret: None,
+            id: None,
+            capabilities: vec![],
+            metadata: BTreeMap::new(),
+            discoverable: true,
+            subscription: None,
         };
         let cloned = msg.clone();
         let orig_val = serde_json::to_value(&msg).unwrap();
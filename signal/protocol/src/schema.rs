@@ -0,0 +1,371 @@
+//! JSON Schema and TypeScript generation for the protocol types.
+//!
+//! The Rust types in this crate are the single source of truth. This module
+//! renders them as a JSON Schema (draft-07) document and as a TypeScript
+//! declaration module so the web client can consume the same definitions.
+//! Run `cargo run --features schema --bin bolt-rendezvous-codegen` to
+//! refresh the checked-in copies under `generated/`; the
+//! `generated_files_are_up_to_date` test fails when they are stale.
+
+use schemars::gen::SchemaSettings;
+use serde_json::{Map, Value};
+
+use crate::{
+    ClientMessage, DeviceType, ErrorCode, HttpRejection, PeerData, RoomInfo, ServerLimits,
+    ServerMessage,
+};
+
+/// File name of the generated JSON Schema, relative to `generated/`.
+pub const SCHEMA_FILE: &str = "rendezvous.schema.json";
+
+/// File name of the generated TypeScript declarations, relative to `generated/`.
+pub const TYPESCRIPT_FILE: &str = "rendezvous.d.ts";
+
+/// Header placed at the top of every generated TypeScript file.
+const TS_HEADER: &str = "\
+// Generated by bolt-rendezvous-codegen from bolt-rendezvous-protocol. Do not edit.
+// Regenerate with: cargo run --features schema --bin bolt-rendezvous-codegen
+";
+
+/// Build the JSON Schema document covering every protocol type.
+///
+/// The document has no root type; each message type is a named entry under
+/// `definitions`.
+pub fn json_schema() -> Value {
+    let mut gen = SchemaSettings::draft07().into_generator();
+    // Registering the top-level types pulls in everything they reference.
+    gen.subschema_for::<ClientMessage>();
+    gen.subschema_for::<ServerMessage>();
+    gen.subschema_for::<PeerData>();
+    gen.subschema_for::<DeviceType>();
+    gen.subschema_for::<ErrorCode>();
+    gen.subschema_for::<ServerLimits>();
+    gen.subschema_for::<RoomInfo>();
+    gen.subschema_for::<HttpRejection>();
+
+    let definitions: Map<String, Value> = gen
+        .take_definitions()
+        .into_iter()
+        .map(|(name, schema)| (name, serde_json::to_value(schema).unwrap_or(Value::Null)))
+        .collect();
+
+    let mut root = Map::new();
+    root.insert(
+        "$schema".into(),
+        Value::from("http://json-schema.org/draft-07/schema#"),
+    );
+    root.insert("title".into(), Value::from("bolt-rendezvous-protocol"));
+    root.insert(
+        "version".into(),
+        Value::from(crate::PROTOCOL_VERSION.to_string()),
+    );
+    root.insert("definitions".into(), Value::Object(definitions));
+    Value::Object(root)
+}
+
+/// Render the JSON Schema document as pretty-printed JSON with a trailing newline.
+pub fn json_schema_string() -> String {
+    let mut out = serde_json::to_string_pretty(&json_schema()).unwrap_or_default();
+    out.push('\n');
+    out
+}
+
+/// Render the TypeScript declaration module.
+pub fn typescript() -> String {
+    let schema = json_schema();
+    let mut out = String::from(TS_HEADER);
+    out.push_str(&format!(
+        "\nexport const PROTOCOL_VERSION = {};\n",
+        crate::PROTOCOL_VERSION
+    ));
+    if let Some(definitions) = schema.get("definitions").and_then(Value::as_object) {
+        for (name, def) in definitions {
+            out.push('\n');
+            push_doc(&mut out, def, "");
+            if is_plain_object(def) {
+                out.push_str(&format!("export interface {name} {}\n", ts_object(def, "")));
+            } else {
+                let ty = ts_type(def, "");
+                let sep = if ty.starts_with('\n') { "" } else { " " };
+                out.push_str(&format!("export type {name} ={sep}{ty};\n"));
+            }
+        }
+    }
+    out
+}
+
+/// Whether a schema is an object with named properties (rendered as an
+/// `interface` rather than a type alias).
+fn is_plain_object(schema: &Value) -> bool {
+    schema.get("properties").is_some()
+        && schema.get("oneOf").is_none()
+        && schema.get("anyOf").is_none()
+}
+
+/// Append a JSDoc comment built from the schema's `description`, if any.
+fn push_doc(out: &mut String, schema: &Value, indent: &str) {
+    let Some(description) = schema.get("description").and_then(Value::as_str) else {
+        return;
+    };
+    out.push_str(indent);
+    out.push_str("/**\n");
+    for line in description.lines() {
+        out.push_str(indent);
+        if line.is_empty() {
+            out.push_str(" *\n");
+        } else {
+            out.push_str(" * ");
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    out.push_str(indent);
+    out.push_str(" */\n");
+}
+
+/// Render a schema as a TypeScript type expression.
+fn ts_type(schema: &Value, indent: &str) -> String {
+    let obj = match schema {
+        Value::Bool(true) => return "unknown".into(),
+        Value::Bool(false) => return "never".into(),
+        Value::Object(obj) => obj,
+        _ => return "unknown".into(),
+    };
+
+    if let Some(reference) = obj.get("$ref").and_then(Value::as_str) {
+        return reference
+            .rsplit('/')
+            .next()
+            .unwrap_or(reference)
+            .to_string();
+    }
+    if let Some(values) = obj.get("enum").and_then(Value::as_array) {
+        return union(values.iter().map(ts_literal).collect());
+    }
+    if let Some(value) = obj.get("const") {
+        return ts_literal(value);
+    }
+    for key in ["oneOf", "anyOf"] {
+        if let Some(variants) = obj.get(key).and_then(Value::as_array) {
+            let members: Vec<String> = variants.iter().map(|v| ts_type(v, indent)).collect();
+            if members.len() > 1 && members.iter().all(|m| m.starts_with('{')) {
+                // Tagged unions: one documented variant per line.
+                let inner = format!("{indent}  ");
+                let mut out = String::new();
+                for (variant, member) in variants.iter().zip(members) {
+                    out.push('\n');
+                    push_doc(&mut out, variant, &inner);
+                    out.push_str(&inner);
+                    out.push_str("| ");
+                    out.push_str(&member.replace('\n', "\n  "));
+                }
+                return out;
+            }
+            return union(members);
+        }
+    }
+    if let Some(all) = obj.get("allOf").and_then(Value::as_array) {
+        return all
+            .iter()
+            .map(|v| ts_type(v, indent))
+            .collect::<Vec<_>>()
+            .join(" & ");
+    }
+
+    match obj.get("type") {
+        Some(Value::String(ty)) => ts_primitive(ty, obj, indent),
+        Some(Value::Array(types)) => union(
+            types
+                .iter()
+                .filter_map(Value::as_str)
+                .map(|ty| ts_primitive(ty, obj, indent))
+                .collect(),
+        ),
+        _ => "unknown".into(),
+    }
+}
+
+/// Render a single JSON Schema primitive type.
+fn ts_primitive(ty: &str, obj: &Map<String, Value>, indent: &str) -> String {
+    match ty {
+        "string" => "string".into(),
+        "integer" | "number" => "number".into(),
+        "boolean" => "boolean".into(),
+        "null" => "null".into(),
+        "array" => {
+            let item = obj
+                .get("items")
+                .map(|items| ts_type(items, indent))
+                .unwrap_or_else(|| "unknown".into());
+            if item.contains(' ') {
+                format!("Array<{item}>")
+            } else {
+                format!("{item}[]")
+            }
+        }
+        "object" => {
+            if obj.contains_key("properties") {
+                ts_object(&Value::Object(obj.clone()), indent)
+            } else {
+                let value = obj
+                    .get("additionalProperties")
+                    .map(|v| ts_type(v, indent))
+                    .unwrap_or_else(|| "unknown".into());
+                format!("Record<string, {value}>")
+            }
+        }
+        _ => "unknown".into(),
+    }
+}
+
+/// Render an object schema as a TypeScript object type literal.
+fn ts_object(schema: &Value, indent: &str) -> String {
+    let required: Vec<&str> = schema
+        .get("required")
+        .and_then(Value::as_array)
+        .map(|r| r.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+    let Some(properties) = schema.get("properties").and_then(Value::as_object) else {
+        return "{}".into();
+    };
+
+    // Put the `type` discriminator first so tagged unions read naturally.
+    let mut names: Vec<&String> = properties.keys().collect();
+    names.sort_by_key(|name| *name != "type");
+
+    let inner = format!("{indent}  ");
+    let mut out = String::from("{\n");
+    for name in names {
+        let prop = &properties[name];
+        push_doc(&mut out, prop, &inner);
+        let optional = if required.contains(&name.as_str()) {
+            ""
+        } else {
+            "?"
+        };
+        out.push_str(&format!(
+            "{inner}{name}{optional}: {};\n",
+            ts_type(prop, &inner)
+        ));
+    }
+    out.push_str(indent);
+    out.push('}');
+    out
+}
+
+/// Render a JSON value as a TypeScript literal type.
+fn ts_literal(value: &Value) -> String {
+    match value {
+        Value::String(s) => format!("{s:?}"),
+        Value::Null => "null".into(),
+        other => other.to_string(),
+    }
+}
+
+/// Join type expressions into a union, dropping duplicates.
+///
+/// Open string enums (literals plus `string`) keep their literals visible to
+/// editors by rendering the catch-all as `(string & {})`.
+fn union(members: Vec<String>) -> String {
+    let mut unique: Vec<String> = Vec::new();
+    for member in members {
+        if !unique.contains(&member) {
+            unique.push(member);
+        }
+    }
+    if unique.iter().any(|m| m.starts_with('"')) {
+        for member in &mut unique {
+            if member == "string" {
+                *member = "(string & {})".into();
+            }
+        }
+    }
+    match unique.len() {
+        0 => "never".into(),
+        _ => unique.join(" | "),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn generated_path(file: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("generated")
+            .join(file)
+    }
+
+    #[test]
+    fn generated_files_are_up_to_date() {
+        for (file, expected) in [
+            (SCHEMA_FILE, json_schema_string()),
+            (TYPESCRIPT_FILE, typescript()),
+        ] {
+            let on_disk = std::fs::read_to_string(generated_path(file)).unwrap_or_default();
+            assert!(
+                on_disk == expected,
+                "generated/{file} is stale; run `cargo run --features schema --bin bolt-rendezvous-codegen`"
+            );
+        }
+    }
+
+    #[test]
+    fn schema_defines_every_message_type() {
+        let schema = json_schema();
+        let definitions = schema["definitions"].as_object().unwrap();
+        for name in [
+            "ClientMessage",
+            "ServerMessage",
+            "PeerData",
+            "DeviceType",
+            "ErrorCode",
+            "ServerLimits",
+            "RoomInfo",
+            "HttpRejection",
+        ] {
+            assert!(definitions.contains_key(name), "missing definition {name}");
+        }
+    }
+
+    #[test]
+    fn typescript_renders_tagged_unions() {
+        let ts = typescript();
+        assert!(ts.contains("export type ClientMessage ="));
+        assert!(ts.contains("type: \"register\";"));
+        assert!(ts.contains("type: \"peer_joined\";"));
+        assert!(ts.contains("export interface PeerData {"));
+        assert!(ts.contains("wt_url?: string | null;"));
+        assert!(ts.contains("export type DeviceType = \"phone\" | \"tablet\""));
+        assert!(ts.contains("| \"vehicle\" | (string & {});"));
+    }
+
+    #[test]
+    fn ts_type_handles_basic_schemas() {
+        use serde_json::json;
+        assert_eq!(ts_type(&json!(true), ""), "unknown");
+        assert_eq!(
+            ts_type(&json!({"$ref": "#/definitions/PeerData"}), ""),
+            "PeerData"
+        );
+        assert_eq!(
+            ts_type(
+                &json!({"type": "array", "items": {"$ref": "#/definitions/PeerData"}}),
+                ""
+            ),
+            "PeerData[]"
+        );
+        assert_eq!(
+            ts_type(&json!({"type": ["integer", "null"]}), ""),
+            "number | null"
+        );
+        assert_eq!(
+            ts_type(
+                &json!({"type": "object", "additionalProperties": {"type": "string"}}),
+                ""
+            ),
+            "Record<string, string>"
+        );
+    }
+}
@@ -0,0 +1,315 @@
+//! Per-source-IP admission limits.
+//!
+//! [`IpLimiter`] caps how many WebSocket connections one address may hold at
+//! once (checked when the TCP connection is accepted, and again during the
+//! handshake for a client IP forwarded by a proxy) and how many
+//! registrations it may make per minute (checked on `register`, additional
+//! identities and `rotate_code`, each of which the room sees as a join).
+//! IPv6 addresses are grouped by /64 (see [`limit_key`]). Rejections are
+//! counted so that operators can tell limits are being hit.
+
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use dashmap::DashMap;
+use tokio::time::Instant;
+use tracing::warn;
+
+/// Default concurrent WebSocket connections per source IP.
+pub const DEFAULT_MAX_CONNECTIONS_PER_IP: usize = 32;
+
+/// Default registrations (`register`, added identities, `rotate_code`) per
+/// client IP per minute.
+pub const DEFAULT_MAX_REGISTRATIONS_PER_MINUTE: u32 = 60;
+
+/// Length of the registration counting window.
+pub const REGISTRATION_WINDOW: Duration = Duration::from_secs(60);
+
+/// Tracked addresses beyond which idle entries are pruned.
+const PRUNE_THRESHOLD: usize = 4096;
+
+/// Minimum time between two prunes, so a large table is scanned at most
+/// this often rather than on every call.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Key that per-IP limits are counted under. IPv6 hosts usually control a
+/// whole /64, so IPv6 addresses are grouped by their /64 prefix;
+/// IPv4-mapped IPv6 addresses count as the IPv4 address. Anything that does
+/// not parse as an IP is used as is.
+pub fn limit_key(ip: &str) -> String {
+    match ip.parse::<IpAddr>() {
+        Ok(IpAddr::V4(v4)) => v4.to_string(),
+        Ok(IpAddr::V6(v6)) => match v6.to_ipv4_mapped() {
+            Some(v4) => v4.to_string(),
+            None => {
+                let s = v6.segments();
+                format!("{:x}:{:x}:{:x}:{:x}::/64", s[0], s[1], s[2], s[3])
+            }
+        },
+        Err(_) => ip.to_string(),
+    }
+}
+
+struct IpState {
+    connections: usize,
+    window_start: Instant,
+    registrations: u32,
+}
+
+impl IpState {
+    fn new(now: Instant) -> Self {
+        Self {
+            connections: 0,
+            window_start: now,
+            registrations: 0,
+        }
+    }
+
+    /// Whether the entry carries no information worth keeping.
+    fn is_idle(&self, now: Instant) -> bool {
+        self.connections == 0
+            && (self.registrations == 0
+                || now.duration_since(self.window_start) >= REGISTRATION_WINDOW)
+    }
+}
+
+/// Shared per-IP limit state. All methods are safe to call concurrently.
+pub struct IpLimiter {
+    max_connections: usize,
+    max_registrations: u32,
+    state: DashMap<String, IpState>,
+    next_prune: Mutex<Instant>,
+    rejected_connections: AtomicU64,
+    rejected_registrations: AtomicU64,
+}
+
+impl IpLimiter {
+    /// Create a limiter. A limit of 0 disables that check.
+    pub fn new(max_connections: usize, max_registrations: u32) -> Self {
+        Self {
+            max_connections,
+            max_registrations,
+            state: DashMap::new(),
+            next_prune: Mutex::new(Instant::now()),
+            rejected_connections: AtomicU64::new(0),
+            rejected_registrations: AtomicU64::new(0),
+        }
+    }
+
+    /// Concurrent connections allowed per IP (0 = unlimited).
+    pub fn max_connections(&self) -> usize {
+        self.max_connections
+    }
+
+    /// Registrations allowed per IP per [`REGISTRATION_WINDOW`] (0 = unlimited).
+    pub fn max_registrations(&self) -> u32 {
+        self.max_registrations
+    }
+
+    /// Connections refused because their IP was at the connection cap.
+    pub fn rejected_connections(&self) -> u64 {
+        self.rejected_connections.load(Ordering::Relaxed)
+    }
+
+    /// Registrations refused because their IP was over the per-minute limit.
+    pub fn rejected_registrations(&self) -> u64 {
+        self.rejected_registrations.load(Ordering::Relaxed)
+    }
+
+    /// Number of connections `ip` (or its /64) currently holds.
+    pub fn connections(&self, ip: &str) -> usize {
+        self.state
+            .get(&limit_key(ip))
+            .map_or(0, |state| state.connections)
+    }
+
+    /// Take a connection slot for `ip`, released when the returned guard is
+    /// dropped. `None` if `ip` already holds `max_connections`.
+    pub fn try_connect(self: &Arc<Self>, ip: &str) -> Option<IpConnectionGuard> {
+        let key = limit_key(ip);
+        let now = Instant::now();
+        self.prune(now);
+        let mut state = self
+            .state
+            .entry(key.clone())
+            .or_insert_with(|| IpState::new(now));
+        if self.max_connections > 0 && state.connections >= self.max_connections {
+            drop(state);
+            self.rejected_connections.fetch_add(1, Ordering::Relaxed);
+            warn!(ip = %ip, max = self.max_connections, "per-IP connection limit reached");
+            return None;
+        }
+        state.connections += 1;
+        Some(IpConnectionGuard {
+            limiter: self.clone(),
+            key,
+        })
+    }
+
+    /// Count one registration against `ip` (a client IP as used for room
+    /// keys). On refusal, returns how long until the window resets.
+    pub fn try_register(&self, ip: &str) -> Result<(), Duration> {
+        if self.max_registrations == 0 {
+            return Ok(());
+        }
+        let now = Instant::now();
+        self.prune(now);
+        let mut state = self
+            .state
+            .entry(limit_key(ip))
+            .or_insert_with(|| IpState::new(now));
+        let elapsed = now.duration_since(state.window_start);
+        if elapsed >= REGISTRATION_WINDOW {
+            state.window_start = now;
+            state.registrations = 0;
+        }
+        if state.registrations >= self.max_registrations {
+            let retry_after =
+                REGISTRATION_WINDOW.saturating_sub(now.duration_since(state.window_start));
+            drop(state);
+            self.rejected_registrations.fetch_add(1, Ordering::Relaxed);
+            warn!(ip = %ip, max = self.max_registrations, "per-IP registration limit reached");
+            return Err(retry_after);
+        }
+        state.registrations += 1;
+        Ok(())
+    }
+
+    /// Drop idle entries once the table is large, at most every
+    /// [`PRUNE_INTERVAL`]. Callers that lose the race skip the scan.
+    fn prune(&self, now: Instant) {
+        if self.state.len() < PRUNE_THRESHOLD {
+            return;
+        }
+        let Ok(mut next_prune) = self.next_prune.try_lock() else {
+            return;
+        };
+        if now < *next_prune {
+            return;
+        }
+        *next_prune = now + PRUNE_INTERVAL;
+        drop(next_prune);
+        self.state.retain(|_, state| !state.is_idle(now));
+    }
+
+    fn release(&self, key: &str) {
+        let now = Instant::now();
+        self.state.remove_if_mut(key, |_, state| {
+            state.connections = state.connections.saturating_sub(1);
+            state.is_idle(now)
+        });
+    }
+}
+
+impl Default for IpLimiter {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_MAX_CONNECTIONS_PER_IP,
+            DEFAULT_MAX_REGISTRATIONS_PER_MINUTE,
+        )
+    }
+}
+
+/// RAII guard for one connection slot of an IP, see [`IpLimiter::try_connect`].
+pub struct IpConnectionGuard {
+    limiter: Arc<IpLimiter>,
+    key: String,
+}
+
+impl Drop for IpConnectionGuard {
+    fn drop(&mut self) {
+        self.limiter.release(&self.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IP: &str = "203.0.113.7";
+    const OTHER: &str = "203.0.113.8";
+
+    #[test]
+    fn connection_cap_is_per_ip_and_released_on_drop() {
+        let limiter = Arc::new(IpLimiter::new(2, 0));
+        let a = limiter.try_connect(IP).unwrap();
+        let _b = limiter.try_connect(IP).unwrap();
+        assert!(limiter.try_connect(IP).is_none());
+        assert_eq!(limiter.rejected_connections(), 1);
+        // Another address is unaffected.
+        assert!(limiter.try_connect(OTHER).is_some());
+
+        drop(a);
+        assert_eq!(limiter.connections(IP), 1);
+        assert!(limiter.try_connect(IP).is_some());
+    }
+
+    #[test]
+    fn idle_entries_are_removed_when_the_last_connection_closes() {
+        let limiter = Arc::new(IpLimiter::new(2, 0));
+        drop(limiter.try_connect(IP).unwrap());
+        assert!(limiter.state.is_empty());
+    }
+
+    #[test]
+    fn registrations_are_limited_per_window() {
+        let limiter = IpLimiter::new(0, 2);
+        assert!(limiter.try_register(IP).is_ok());
+        assert!(limiter.try_register(IP).is_ok());
+        let retry = limiter.try_register(IP).unwrap_err();
+        assert!(retry > Duration::ZERO && retry <= REGISTRATION_WINDOW);
+        assert_eq!(limiter.rejected_registrations(), 1);
+        assert!(limiter.try_register(OTHER).is_ok());
+
+        // A new window starts once the old one has elapsed.
+        limiter.state.get_mut(IP).unwrap().window_start -= REGISTRATION_WINDOW;
+        assert!(limiter.try_register(IP).is_ok());
+    }
+
+    #[test]
+    fn ipv6_addresses_share_their_64() {
+        assert_eq!(limit_key("2001:db8:1:2::5"), "2001:db8:1:2::/64");
+        assert_eq!(
+            limit_key("2001:db8:1:2:aaaa:bbbb:cccc:dddd"),
+            "2001:db8:1:2::/64"
+        );
+        assert_eq!(limit_key("::ffff:203.0.113.7"), IP);
+
+        let limiter = Arc::new(IpLimiter::new(1, 0));
+        let _held = limiter.try_connect("2001:db8:1:2::5").unwrap();
+        assert!(limiter.try_connect("2001:db8:1:2::6").is_none());
+        assert!(limiter.try_connect("2001:db8:1:3::5").is_some());
+    }
+
+    #[test]
+    fn pruning_is_rate_limited() {
+        let limiter = IpLimiter::new(0, 1);
+        for i in 0..PRUNE_THRESHOLD {
+            limiter
+                .state
+                .insert(format!("idle-{i}"), IpState::new(Instant::now()));
+        }
+        // All entries are idle, but the first prune only happens when the
+        // table is large and the deadline has passed.
+        assert!(limiter.try_register(IP).is_ok());
+        assert_eq!(limiter.state.len(), 1);
+        for i in 0..PRUNE_THRESHOLD {
+            limiter
+                .state
+                .insert(format!("idle-{i}"), IpState::new(Instant::now()));
+        }
+        assert!(limiter.try_register(OTHER).is_ok());
+        assert_eq!(limiter.state.len(), PRUNE_THRESHOLD + 2);
+    }
+
+    #[test]
+    fn zero_disables_both_limits() {
+        let limiter = Arc::new(IpLimiter::new(0, 0));
+        let _guards: Vec<_> = (0..100).map(|_| limiter.try_connect(IP).unwrap()).collect();
+        for _ in 0..100 {
+            assert!(limiter.try_register(IP).is_ok());
+        }
+    }
+}
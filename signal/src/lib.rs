@@ -19,24 +19,41 @@
 //! }
 //! ```
 
+pub mod ip_limit;
+pub mod origin;
+pub mod pin;
 pub mod protocol;
 pub mod room;
 pub mod server;
 
 use std::net::{IpAddr, SocketAddr};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
-use tokio::net::TcpListener;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::tungstenite::http::StatusCode;
 use tracing::{error, info, warn};
 
+use ip_limit::IpLimiter;
+use pin::PinMatcher;
+use protocol::RejectionReason;
 use room::RoomManager;
-use server::handle_connection;
+use server::{handle_connection, reject_connection, ConnectionConfig};
 
 /// Default maximum concurrent WebSocket connections.
 /// Fail-closed: once this limit is reached, new connections receive HTTP 503.
 pub const DEFAULT_MAX_WS_CONNECTIONS: usize = 256;
 
+/// Refused connections answered with an HTTP response at once. Beyond this
+/// the TCP stream is dropped, so a connection flood cannot turn the
+/// rejection path itself into a resource sink.
+pub const MAX_PENDING_REJECTIONS: usize = 64;
+
+/// How often [`SignalingServer`] logs its rejection counters (only when they
+/// changed).
+pub const REJECTION_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
 /// RAII guard that decrements the active connection counter on drop.
 ///
 /// Created by [`SignalingServer`] when a connection slot is acquired.
@@ -55,22 +72,49 @@ impl Drop for ConnectionGuard {
 /// A WebSocket signaling server for LocalBolt P2P file transfer.
 ///
 /// The server listens for incoming WebSocket connections, groups peers by their
-/// originating IP address, and relays WebRTC signaling messages between peers
-/// in the same IP room.
+/// originating IP address (or a named room joined with a shared secret), and
+/// relays WebRTC signaling messages between peers in the same room.
 ///
 /// ## Connection Limit (AC-22)
 ///
 /// The server enforces a hard concurrent WebSocket connection limit
 /// (default: [`DEFAULT_MAX_WS_CONNECTIONS`]). When the limit is reached,
-/// new TCP connections are accepted but immediately closed — the connection
-/// slot is never consumed. Configure via [`with_max_connections`](Self::with_max_connections)
-/// or the `MAX_WS_CONNECTIONS` environment variable at startup.
+/// new TCP connections are answered `503 Service Unavailable` with a
+/// `Retry-After` header and an [`HttpRejection`](protocol::HttpRejection)
+/// JSON body, without consuming a connection slot. At most
+/// [`MAX_PENDING_REJECTIONS`] such answers are in flight; beyond that the
+/// stream is dropped. Configure via [`with_max_connections`](Self::with_max_connections)
+/// or the `MAX_WS_CONNECTIONS` environment variable at startup. Each
+/// connection uses one file descriptor, and a second one while its upgrade
+/// is in progress (see [`server::DEFAULT_UPGRADE_TIMEOUT`]); size the
+/// process's descriptor limit for that.
+///
+/// ## Per-IP Limits
+///
+/// One address may hold at most
+/// [`ip_limit::DEFAULT_MAX_CONNECTIONS_PER_IP`] connections; further ones are
+/// answered `429 Too Many Requests`. The cap is checked at accept time,
+/// except for trusted proxies and private addresses: those may carry many
+/// clients (a PaaS edge connects from private addresses), so the client IP
+/// they forward is capped during the handshake instead. Each client IP may
+/// also register (including added identities and code
+/// rotations) at most [`ip_limit::DEFAULT_MAX_REGISTRATIONS_PER_MINUTE`]
+/// times a minute; excess registrations get a `rate_limited` error. IPv6
+/// addresses are counted per /64. Rejections are counted (see
+/// [`rejected_ip_connections`](Self::rejected_ip_connections)) and logged
+/// every [`REJECTION_LOG_INTERVAL`]. Configure via
+/// [`with_max_connections_per_ip`](Self::with_max_connections_per_ip) and
+/// [`with_max_registrations_per_minute`](Self::with_max_registrations_per_minute).
 pub struct SignalingServer {
     addr: SocketAddr,
     room_manager: Arc<RoomManager>,
-    trusted_proxies: Arc<Vec<IpAddr>>,
+    pins: Arc<PinMatcher>,
+    ip_limiter: Arc<IpLimiter>,
+    connection_config: ConnectionConfig,
     max_connections: usize,
     active_connections: Arc<AtomicUsize>,
+    pending_rejections: Arc<AtomicUsize>,
+    rejected_connections: Arc<AtomicU64>,
 }
 
 impl SignalingServer {
@@ -82,9 +126,13 @@ impl SignalingServer {
         Self {
             addr,
             room_manager: Arc::new(RoomManager::new()),
-            trusted_proxies: Arc::new(Vec::new()),
+            pins: Arc::new(PinMatcher::new()),
+            ip_limiter: Arc::new(IpLimiter::default()),
+            connection_config: ConnectionConfig::new(),
             max_connections: DEFAULT_MAX_WS_CONNECTIONS,
             active_connections: Arc::new(AtomicUsize::new(0)),
+            pending_rejections: Arc::new(AtomicUsize::new(0)),
+            rejected_connections: Arc::new(AtomicU64::new(0)),
         }
     }
 
@@ -92,42 +140,93 @@ impl SignalingServer {
     /// will be honored for room assignment. Addresses not in this list
     /// always use the socket address (fail-closed).
     pub fn with_trusted_proxies(mut self, proxies: Vec<IpAddr>) -> Self {
-        self.trusted_proxies = Arc::new(proxies);
+        self.connection_config.trusted_proxies = proxies;
+        self
+    }
+
+    /// Restrict which `Origin` headers may open a WebSocket. The default
+    /// accepts any origin; see [`origin::OriginPolicy`].
+    pub fn with_origin_policy(mut self, policy: origin::OriginPolicy) -> Self {
+        self.connection_config.origin_policy = policy;
+        self
+    }
+
+    /// Mark peers `away` after `after` without client activity (messages
+    /// other than `ping`). Capped at [`server::MAX_AUTO_AWAY`]; zero disables
+    /// automatic away (the default).
+    pub fn with_auto_away(mut self, after: Duration) -> Self {
+        self.connection_config.auto_away =
+            (!after.is_zero()).then(|| after.min(server::MAX_AUTO_AWAY));
+        self
+    }
+
+    /// Deadline for a new connection's first bytes; see
+    /// [`server::DEFAULT_PEEK_TIMEOUT`].
+    pub fn with_peek_timeout(mut self, timeout: Duration) -> Self {
+        self.connection_config.peek_timeout = timeout;
+        self
+    }
+
+    /// Deadline for the WebSocket upgrade handshake; see
+    /// [`server::DEFAULT_UPGRADE_TIMEOUT`].
+    pub fn with_upgrade_timeout(mut self, timeout: Duration) -> Self {
+        self.connection_config.upgrade_timeout = timeout;
+        self
+    }
+
+    /// Deadline from the upgrade to the first `register`; see
+    /// [`server::DEFAULT_REGISTER_TIMEOUT`].
+    pub fn with_register_timeout(mut self, timeout: Duration) -> Self {
+        self.connection_config.register_timeout = timeout;
         self
     }
 
     /// Set the maximum number of concurrent WebSocket connections.
     ///
-    /// When this limit is reached, new connections are rejected (TCP stream
-    /// dropped without WebSocket upgrade). A value of 0 rejects all connections.
+    /// When this limit is reached, new connections are rejected with HTTP 503
+    /// instead of being upgraded. A value of 0 rejects all connections.
     pub fn with_max_connections(mut self, max: usize) -> Self {
         self.max_connections = max;
         self
     }
 
+    /// Set the maximum concurrent connections per source IP (0 = unlimited).
+    pub fn with_max_connections_per_ip(mut self, max: usize) -> Self {
+        self.ip_limiter = Arc::new(IpLimiter::new(max, self.ip_limiter.max_registrations()));
+        self
+    }
+
+    /// Set the maximum registrations per client IP per minute (0 = unlimited).
+    pub fn with_max_registrations_per_minute(mut self, max: u32) -> Self {
+        self.ip_limiter = Arc::new(IpLimiter::new(self.ip_limiter.max_connections(), max));
+        self
+    }
+
     /// Try to acquire a connection slot. Returns a [`ConnectionGuard`] on
     /// success, or `None` if the limit has been reached.
-    ///
-    /// Uses `compare_exchange` in a loop to atomically check-and-increment,
-    /// ensuring no over-subscription.
     fn try_acquire_slot(&self) -> Option<ConnectionGuard> {
-        loop {
-            let current = self.active_connections.load(Ordering::Acquire);
-            if current >= self.max_connections {
-                return None;
+        try_acquire(&self.active_connections, self.max_connections)
+    }
+
+    /// Answer a refused connection with `status` in the background, or drop
+    /// it if [`MAX_PENDING_REJECTIONS`] answers are already in flight.
+    fn reject(
+        &self,
+        stream: TcpStream,
+        addr: SocketAddr,
+        status: StatusCode,
+        reason: RejectionReason,
+    ) {
+        match try_acquire(&self.pending_rejections, MAX_PENDING_REJECTIONS) {
+            Some(guard) => {
+                tokio::spawn(async move {
+                    reject_connection(stream, status, reason).await;
+                    drop(guard);
+                });
             }
-            match self.active_connections.compare_exchange(
-                current,
-                current + 1,
-                Ordering::AcqRel,
-                Ordering::Acquire,
-            ) {
-                Ok(_) => {
-                    return Some(ConnectionGuard {
-                        active: self.active_connections.clone(),
-                    });
-                }
-                Err(_) => continue, // CAS retry
+            None => {
+                warn!(addr = %addr, "too many pending rejections — dropping connection");
+                drop(stream);
             }
         }
     }
@@ -138,43 +237,91 @@ impl SignalingServer {
     /// connection. It runs indefinitely and only returns on a fatal bind/accept error.
     ///
     /// Connections beyond [`max_connections`](Self::with_max_connections) are
-    /// rejected by dropping the TCP stream immediately (no WebSocket upgrade).
+    /// answered with HTTP 503 instead of a WebSocket upgrade.
     pub async fn run(&self) -> Result<(), Box<dyn std::error::Error>> {
         let listener = TcpListener::bind(self.addr).await?;
+        self.serve(listener).await
+    }
+
+    /// Serve connections from an already-bound listener.
+    ///
+    /// Same as [`run`](Self::run) but lets the caller choose the socket,
+    /// e.g. binding port 0 in tests and reading back the assigned address.
+    pub async fn serve(&self, listener: TcpListener) -> Result<(), Box<dyn std::error::Error>> {
+        let addr = listener.local_addr()?;
+        let connection_config = Arc::new(self.connection_config.clone());
+        self.spawn_rejection_log();
 
         info!(
-            addr = %self.addr,
+            addr = %addr,
             max_connections = self.max_connections,
+            instance_id = %self.connection_config.instance_id,
             "LocalBolt signaling server listening on {}",
-            self.addr
+            addr
         );
 
         loop {
             match listener.accept().await {
                 Ok((stream, addr)) => {
                     // Acquire a connection slot before spawning the handler.
-                    // If the limit is reached, drop the TCP stream immediately.
+                    // If the limit is reached, answer 503 without a slot.
                     let guard = match self.try_acquire_slot() {
                         Some(g) => g,
                         None => {
+                            self.rejected_connections.fetch_add(1, Ordering::Relaxed);
                             warn!(
                                 addr = %addr,
                                 active = self.active_connections.load(Ordering::Relaxed),
                                 max = self.max_connections,
                                 "connection rejected — limit reached"
                             );
-                            // Drop `stream` — TCP RST to client.
-                            drop(stream);
+                            self.reject(
+                                stream,
+                                addr,
+                                StatusCode::SERVICE_UNAVAILABLE,
+                                RejectionReason::ServerFull,
+                            );
                             continue;
                         }
                     };
 
+                    // Connections that may come through a proxy are capped
+                    // during the handshake, once the client IP is known.
+                    let ip_guard = if connection_config.may_forward(addr.ip()) {
+                        None
+                    } else {
+                        match self.ip_limiter.try_connect(&addr.ip().to_string()) {
+                            Some(g) => Some(g),
+                            None => {
+                                drop(guard);
+                                self.reject(
+                                    stream,
+                                    addr,
+                                    StatusCode::TOO_MANY_REQUESTS,
+                                    RejectionReason::TooManyConnections,
+                                );
+                                continue;
+                            }
+                        }
+                    };
+
                     let room_manager = self.room_manager.clone();
-                    let trusted_proxies = self.trusted_proxies.clone();
+                    let pins = self.pins.clone();
+                    let ip_limiter = self.ip_limiter.clone();
+                    let connection_config = connection_config.clone();
                     tokio::spawn(async move {
-                        handle_connection(stream, addr, room_manager, trusted_proxies).await;
-                        // `guard` is moved into this future and dropped here,
-                        // releasing the connection slot.
+                        handle_connection(
+                            stream,
+                            addr,
+                            room_manager,
+                            pins,
+                            ip_limiter,
+                            connection_config,
+                        )
+                        .await;
+                        // The guards are moved into this future and dropped
+                        // here, releasing the connection slots.
+                        drop(ip_guard);
                         drop(guard);
                     });
                 }
@@ -198,10 +345,86 @@ impl SignalingServer {
         self.active_connections.load(Ordering::Relaxed)
     }
 
+    /// Per-IP limit state, including rejection counters.
+    pub fn ip_limiter(&self) -> Arc<IpLimiter> {
+        self.ip_limiter.clone()
+    }
+
+    /// Connections refused with 503 because the server was at its limit.
+    pub fn rejected_connections(&self) -> u64 {
+        self.rejected_connections.load(Ordering::Relaxed)
+    }
+
+    /// Connections refused with 429 by the per-IP cap.
+    pub fn rejected_ip_connections(&self) -> u64 {
+        self.ip_limiter.rejected_connections()
+    }
+
+    /// Registrations refused by the per-IP registration limit.
+    pub fn rejected_registrations(&self) -> u64 {
+        self.ip_limiter.rejected_registrations()
+    }
+
+    /// Log the rejection counters every [`REJECTION_LOG_INTERVAL`] while
+    /// any of them changed.
+    fn spawn_rejection_log(&self) {
+        let global = self.rejected_connections.clone();
+        let limiter = self.ip_limiter.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(REJECTION_LOG_INTERVAL);
+            let mut last = (0, 0, 0);
+            loop {
+                ticker.tick().await;
+                let now = (
+                    global.load(Ordering::Relaxed),
+                    limiter.rejected_connections(),
+                    limiter.rejected_registrations(),
+                );
+                if now != last {
+                    info!(
+                        rejected_connections = now.0,
+                        rejected_ip_connections = now.1,
+                        rejected_registrations = now.2,
+                        "connection limit rejections"
+                    );
+                    last = now;
+                }
+            }
+        });
+    }
+
     /// Configured maximum connections.
     pub fn max_connections(&self) -> usize {
         self.max_connections
     }
+
+    /// Random identifier of this server instance, reported to clients in
+    /// `server_info`.
+    pub fn instance_id(&self) -> &str {
+        &self.connection_config.instance_id
+    }
+}
+
+/// Increment `counter` if it is below `max`, returning a guard that
+/// decrements it again.
+///
+/// Uses `compare_exchange` in a loop to atomically check-and-increment,
+/// ensuring no over-subscription.
+fn try_acquire(counter: &Arc<AtomicUsize>, max: usize) -> Option<ConnectionGuard> {
+    loop {
+        let current = counter.load(Ordering::Acquire);
+        if current >= max {
+            return None;
+        }
+        match counter.compare_exchange(current, current + 1, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => {
+                return Some(ConnectionGuard {
+                    active: counter.clone(),
+                });
+            }
+            Err(_) => continue, // CAS retry
+        }
+    }
 }
 
 // ── Connection Limit Tests (AC-22) ──────────────────────────────────────
@@ -256,6 +479,7 @@ mod connection_limit_tests {
     }
 
     #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn default_limit_nonzero() {
         let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
         let server = SignalingServer::new(addr);
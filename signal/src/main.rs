@@ -20,6 +20,7 @@
 
 use std::net::{IpAddr, SocketAddr};
 
+use bolt_rendezvous::origin::{OriginPattern, OriginPolicy};
 use bolt_rendezvous::SignalingServer;
 use tracing_subscriber::EnvFilter;
 
@@ -98,6 +99,32 @@ async fn main() {
         tracing::info!(count = trusted_proxies.len(), "TRUSTED_PROXIES configured");
     }
 
+    // Parse ALLOWED_ORIGINS env var (comma-separated origin patterns).
+    // Unset → any origin. Set → only matching origins; invalid entries are
+    // skipped, so an allowlist with no valid entry refuses every browser.
+    let origin_policy = std::env::var("ALLOWED_ORIGINS").ok().map(|list| {
+        let patterns: Vec<OriginPattern> = list
+            .split(',')
+            .filter(|s| !s.trim().is_empty())
+            .filter_map(|s| match s.parse::<OriginPattern>() {
+                Ok(pattern) => Some(pattern),
+                Err(e) => {
+                    tracing::warn!(value = %s.trim(), error = %e, "invalid ALLOWED_ORIGINS entry — skipped");
+                    None
+                }
+            })
+            .collect();
+        tracing::info!(count = patterns.len(), "ALLOWED_ORIGINS configured");
+        let mut policy = OriginPolicy::allow_list(patterns);
+        if let Some(allow) = env_flag("ALLOW_NULL_ORIGIN") {
+            policy = policy.with_null(allow);
+        }
+        if let Some(allow) = env_flag("ALLOW_MISSING_ORIGIN") {
+            policy = policy.with_missing(allow);
+        }
+        policy
+    });
+
     // Parse MAX_WS_CONNECTIONS env var (optional). Default defined in lib.rs.
     let max_connections: Option<usize> = std::env::var("MAX_WS_CONNECTIONS")
         .ok()
@@ -109,11 +136,77 @@ async fn main() {
             }
         });
 
+    // Parse the per-IP limits (optional). Unset → defaults in ip_limit.rs; 0 → unlimited.
+    let max_connections_per_ip: Option<usize> = std::env::var("MAX_WS_CONNECTIONS_PER_IP")
+        .ok()
+        .and_then(|v| match v.trim().parse::<usize>() {
+            Ok(n) => Some(n),
+            Err(e) => {
+                tracing::warn!(value = %v, error = %e, "invalid MAX_WS_CONNECTIONS_PER_IP — using default");
+                None
+            }
+        });
+    let max_registrations_per_minute: Option<u32> = std::env::var("MAX_REGISTRATIONS_PER_MINUTE")
+        .ok()
+        .and_then(|v| match v.trim().parse::<u32>() {
+            Ok(n) => Some(n),
+            Err(e) => {
+                tracing::warn!(value = %v, error = %e, "invalid MAX_REGISTRATIONS_PER_MINUTE — using default");
+                None
+            }
+        });
+
+    // Parse AUTO_AWAY_SECS env var (optional). Unset or 0 → disabled.
+    let auto_away_secs: Option<u64> = std::env::var("AUTO_AWAY_SECS")
+        .ok()
+        .and_then(|v| match v.trim().parse::<u64>() {
+            Ok(n) => Some(n),
+            Err(e) => {
+                tracing::warn!(value = %v, error = %e, "invalid AUTO_AWAY_SECS — automatic away disabled");
+                None
+            }
+        });
+
+    // Parse the handshake deadlines (optional, seconds). Unset → defaults in server.rs.
+    let peek_timeout = timeout_secs("PEEK_TIMEOUT_SECS");
+    let upgrade_timeout = timeout_secs("UPGRADE_TIMEOUT_SECS");
+    let register_timeout = timeout_secs("REGISTER_TIMEOUT_SECS");
+
     let mut server = SignalingServer::new(addr).with_trusted_proxies(trusted_proxies);
+    if let Some(policy) = origin_policy {
+        server = server.with_origin_policy(policy);
+    }
     if let Some(max) = max_connections {
         tracing::info!(max_connections = max, "MAX_WS_CONNECTIONS configured");
         server = server.with_max_connections(max);
     }
+    if let Some(max) = max_connections_per_ip {
+        tracing::info!(
+            max_connections_per_ip = max,
+            "MAX_WS_CONNECTIONS_PER_IP configured"
+        );
+        server = server.with_max_connections_per_ip(max);
+    }
+    if let Some(max) = max_registrations_per_minute {
+        tracing::info!(
+            max_registrations_per_minute = max,
+            "MAX_REGISTRATIONS_PER_MINUTE configured"
+        );
+        server = server.with_max_registrations_per_minute(max);
+    }
+    if let Some(secs) = auto_away_secs {
+        tracing::info!(auto_away_secs = secs, "AUTO_AWAY_SECS configured");
+        server = server.with_auto_away(std::time::Duration::from_secs(secs));
+    }
+    if let Some(timeout) = peek_timeout {
+        server = server.with_peek_timeout(timeout);
+    }
+    if let Some(timeout) = upgrade_timeout {
+        server = server.with_upgrade_timeout(timeout);
+    }
+    if let Some(timeout) = register_timeout {
+        server = server.with_register_timeout(timeout);
+    }
 
     if let Err(e) = server.run().await {
         eprintln!("server error: {e}");
@@ -121,6 +214,40 @@ async fn main() {
     }
 }
 
+/// Read a positive number of seconds from the environment variable `name`.
+/// Invalid or zero values are logged and ignored.
+fn timeout_secs(name: &str) -> Option<std::time::Duration> {
+    let v = std::env::var(name).ok()?;
+    match v.trim().parse::<u64>() {
+        Ok(0) => {
+            tracing::warn!(value = %v, "{name} must be positive — using default");
+            None
+        }
+        Ok(secs) => {
+            tracing::info!(secs, "{name} configured");
+            Some(std::time::Duration::from_secs(secs))
+        }
+        Err(e) => {
+            tracing::warn!(value = %v, error = %e, "invalid {name} — using default");
+            None
+        }
+    }
+}
+
+/// Read a boolean (`true`/`false`/`1`/`0`) from the environment variable
+/// `name`. Invalid values are logged and ignored.
+fn env_flag(name: &str) -> Option<bool> {
+    let v = std::env::var(name).ok()?;
+    match v.trim().to_ascii_lowercase().as_str() {
+        "true" | "1" => Some(true),
+        "false" | "0" => Some(false),
+        _ => {
+            tracing::warn!(value = %v, "invalid {name} — using default");
+            None
+        }
+    }
+}
+
 /// Extract the value following a `--key` argument.
 fn get_arg(args: &[String], key: &str) -> Option<String> {
     args.iter()
@@ -0,0 +1,262 @@
+//! `Origin` checks for the WebSocket upgrade.
+//!
+//! Browsers attach an `Origin` header to every WebSocket handshake, and any
+//! page can open a socket to any address, including a LAN rendezvous. An
+//! [`OriginPolicy`] with an allowlist refuses upgrades from pages that are
+//! not on it, so an arbitrary site cannot enumerate the devices on the
+//! visitor's network. Native clients usually send no `Origin`; sandboxed
+//! frames and `file:` pages send the literal `null`. Both cases have their
+//! own switch.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// One allowlist entry: `scheme://host[:port]`, where the host may start
+/// with `*.` to match any subdomain (but not the domain itself), or `*` to
+/// match every non-`null` origin.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OriginPattern {
+    /// Any origin except `null`.
+    Any,
+    /// Exactly this origin.
+    Exact(String),
+    /// `scheme://*.suffix[:port]`: any subdomain of `suffix`.
+    Subdomains {
+        scheme: String,
+        suffix: String,
+        port: Option<u16>,
+    },
+}
+
+impl OriginPattern {
+    /// Whether the (already lowercased) `origin` matches this pattern.
+    fn matches(&self, origin: &str) -> bool {
+        match self {
+            OriginPattern::Any => true,
+            OriginPattern::Exact(exact) => origin == exact,
+            OriginPattern::Subdomains {
+                scheme,
+                suffix,
+                port,
+            } => {
+                let Ok((origin_scheme, host, origin_port)) = split_origin(origin) else {
+                    return false;
+                };
+                origin_scheme == scheme
+                    && origin_port == *port
+                    && host
+                        .strip_suffix(suffix.as_str())
+                        .and_then(|label| label.strip_suffix('.'))
+                        .is_some_and(|label| !label.is_empty())
+            }
+        }
+    }
+}
+
+impl FromStr for OriginPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_lowercase();
+        if s == "*" {
+            return Ok(OriginPattern::Any);
+        }
+        let (scheme, host, port) = split_origin(&s)?;
+        match host.strip_prefix("*.") {
+            Some(suffix) => {
+                if suffix.is_empty() || suffix.contains('*') {
+                    return Err(format!("invalid wildcard origin '{s}'"));
+                }
+                Ok(OriginPattern::Subdomains {
+                    scheme: scheme.to_string(),
+                    suffix: suffix.to_string(),
+                    port,
+                })
+            }
+            None if host.contains('*') => Err(format!(
+                "wildcards are only allowed as a leading '*.' in '{s}'"
+            )),
+            None => Ok(OriginPattern::Exact(s)),
+        }
+    }
+}
+
+impl fmt::Display for OriginPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OriginPattern::Any => write!(f, "*"),
+            OriginPattern::Exact(origin) => write!(f, "{origin}"),
+            OriginPattern::Subdomains {
+                scheme,
+                suffix,
+                port: Some(port),
+            } => write!(f, "{scheme}://*.{suffix}:{port}"),
+            OriginPattern::Subdomains { scheme, suffix, .. } => write!(f, "{scheme}://*.{suffix}"),
+        }
+    }
+}
+
+/// Split `scheme://host[:port]` into its parts. Paths, queries and user
+/// info are not part of an origin and are rejected.
+fn split_origin(origin: &str) -> Result<(&str, &str, Option<u16>), String> {
+    let (scheme, rest) = origin
+        .split_once("://")
+        .ok_or_else(|| format!("origin '{origin}' has no scheme"))?;
+    if scheme.is_empty() || rest.is_empty() || rest.contains(['/', '?', '#', '@']) {
+        return Err(format!("'{origin}' is not a scheme://host[:port] origin"));
+    }
+    // Bracketed IPv6 hosts contain colons of their own.
+    let port_sep = match rest.rfind(']') {
+        Some(end) => rest[end..].find(':').map(|i| end + i),
+        None => rest.rfind(':'),
+    };
+    match port_sep {
+        Some(i) => {
+            let port = rest[i + 1..]
+                .parse::<u16>()
+                .map_err(|_| format!("invalid port in origin '{origin}'"))?;
+            Ok((scheme, &rest[..i], Some(port)))
+        }
+        None => Ok((scheme, rest, None)),
+    }
+}
+
+/// Which `Origin` headers may open a WebSocket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OriginPolicy {
+    /// `None` accepts every origin (the default); `Some` only those
+    /// matching one of the patterns.
+    allowed: Option<Vec<OriginPattern>>,
+    allow_null: bool,
+    allow_missing: bool,
+}
+
+impl Default for OriginPolicy {
+    fn default() -> Self {
+        Self::any()
+    }
+}
+
+impl OriginPolicy {
+    /// Accept every origin, `null` and requests without `Origin`.
+    pub fn any() -> Self {
+        Self {
+            allowed: None,
+            allow_null: true,
+            allow_missing: true,
+        }
+    }
+
+    /// Accept only origins matching `patterns`. `null` origins are refused
+    /// and requests without `Origin` (native clients) accepted until
+    /// changed with [`with_null`](Self::with_null) and
+    /// [`with_missing`](Self::with_missing).
+    pub fn allow_list(patterns: Vec<OriginPattern>) -> Self {
+        Self {
+            allowed: Some(patterns),
+            allow_null: false,
+            allow_missing: true,
+        }
+    }
+
+    /// Whether `Origin: null` (sandboxed frames, `file:` pages) is accepted.
+    pub fn with_null(mut self, allow: bool) -> Self {
+        self.allow_null = allow;
+        self
+    }
+
+    /// Whether a handshake without an `Origin` header is accepted.
+    pub fn with_missing(mut self, allow: bool) -> Self {
+        self.allow_missing = allow;
+        self
+    }
+
+    /// Whether the policy restricts web origins at all.
+    pub fn is_restricted(&self) -> bool {
+        self.allowed.is_some()
+    }
+
+    /// Decide on the `Origin` header of a handshake, if it had one.
+    pub fn allows(&self, origin: Option<&str>) -> bool {
+        let Some(origin) = origin else {
+            return self.allow_missing;
+        };
+        let origin = origin.trim().to_ascii_lowercase();
+        if origin == "null" {
+            return self.allow_null;
+        }
+        match &self.allowed {
+            None => true,
+            Some(patterns) => patterns.iter().any(|pattern| pattern.matches(&origin)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(patterns: &[&str]) -> OriginPolicy {
+        OriginPolicy::allow_list(patterns.iter().map(|p| p.parse().unwrap()).collect())
+    }
+
+    #[test]
+    fn exact_origins_match_case_insensitively() {
+        let policy = policy(&["https://LocalBolt.app", "http://localhost:5173"]);
+        assert!(policy.allows(Some("https://localbolt.app")));
+        assert!(policy.allows(Some("http://localhost:5173")));
+        assert!(!policy.allows(Some("http://localbolt.app")));
+        assert!(!policy.allows(Some("http://localhost:5174")));
+        assert!(!policy.allows(Some("https://evil.example")));
+    }
+
+    #[test]
+    fn wildcards_match_subdomains_only() {
+        let policy = policy(&["https://*.localbolt.app"]);
+        assert!(policy.allows(Some("https://web.localbolt.app")));
+        assert!(policy.allows(Some("https://a.b.localbolt.app")));
+        assert!(!policy.allows(Some("https://localbolt.app")));
+        assert!(!policy.allows(Some("https://evillocalbolt.app")));
+        assert!(!policy.allows(Some("https://web.localbolt.app:8443")));
+        assert!(!policy.allows(Some("http://web.localbolt.app")));
+    }
+
+    #[test]
+    fn null_and_missing_origins_follow_their_switches() {
+        let strict = policy(&["https://localbolt.app"]);
+        assert!(!strict.allows(Some("null")));
+        assert!(strict.allows(None));
+        let strict = strict.with_null(true).with_missing(false);
+        assert!(strict.allows(Some("null")));
+        assert!(!strict.allows(None));
+
+        let any = policy(&["*"]);
+        assert!(any.allows(Some("https://anything.example")));
+        assert!(!any.allows(Some("null")));
+        assert!(OriginPolicy::default().allows(Some("null")));
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected() {
+        for bad in [
+            "localbolt.app",
+            "https://",
+            "https://localbolt.app/path",
+            "https://*.",
+            "https://web.*.app",
+            "https://*.*.app",
+            "https://localbolt.app:port",
+        ] {
+            assert!(bad.parse::<OriginPattern>().is_err(), "{bad}");
+        }
+        let ipv6: OriginPattern = "http://[::1]:3000".parse().unwrap();
+        assert_eq!(ipv6.to_string(), "http://[::1]:3000");
+        assert_eq!(
+            "HTTPS://*.Example.com:8443"
+                .parse::<OriginPattern>()
+                .unwrap()
+                .to_string(),
+            "https://*.example.com:8443"
+        );
+    }
+}
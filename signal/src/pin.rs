@@ -0,0 +1,422 @@
+//! Two-party PIN matchmaking for ad-hoc pairing.
+//!
+//! A peer submits a short numeric PIN with `match_pin`. If another peer is
+//! already waiting on the same PIN, the two are introduced (the caller links
+//! their sessions in the [`RoomManager`](crate::room::RoomManager)) and the
+//! PIN is burned: it cannot be used again until [`PIN_TTL`] has passed.
+//! Otherwise the submitter waits for up to [`PIN_TTL`].
+//!
+//! With [`PIN_DIGITS`] digits, [`PIN_TTL`] and the attempt limits below, an
+//! attacker guessing PINs from one address gets at most
+//! [`MAX_PIN_ATTEMPTS_PER_IP`] tries per [`PIN_ATTEMPT_WINDOW`] at a space
+//! of 10^6, and each connection at most [`MAX_PIN_ATTEMPTS_PER_CONNECTION`].
+
+use std::time::Duration;
+
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+
+use crate::ip_limit::limit_key;
+use crate::protocol::{ErrorCode, PeerData};
+use crate::room::{PeerSender, SessionRef};
+
+/// Number of decimal digits in a PIN.
+pub const PIN_DIGITS: usize = 6;
+
+/// How long a PIN waits for a partner, and how long a used PIN stays burned.
+pub const PIN_TTL: Duration = Duration::from_secs(60);
+
+/// `match_pin` attempts allowed per connection over its lifetime.
+pub const MAX_PIN_ATTEMPTS_PER_CONNECTION: u32 = 5;
+
+/// `match_pin` attempts allowed per client IP (or IPv6 /64) within
+/// [`PIN_ATTEMPT_WINDOW`].
+pub const MAX_PIN_ATTEMPTS_PER_IP: u32 = 20;
+
+/// Window for [`MAX_PIN_ATTEMPTS_PER_IP`].
+pub const PIN_ATTEMPT_WINDOW: Duration = Duration::from_secs(600);
+
+/// Maximum number of PINs waiting for a partner at once. Also bounds the
+/// burned-PIN and per-IP attempt tables.
+pub const MAX_PENDING_PINS: usize = 10_000;
+
+/// A session taking part in PIN matching.
+#[derive(Debug, Clone)]
+pub struct PinEntrant {
+    pub session: SessionRef,
+    pub peer: PeerData,
+    pub sender: PeerSender,
+    /// Correlation id of the entrant's `match_pin`.
+    pub request_id: Option<String>,
+}
+
+/// Result of a successful [`PinMatcher::submit`].
+#[derive(Debug)]
+pub enum PinOutcome {
+    /// No partner yet; the entrant waits until the PIN expires.
+    Waiting { expires_in: Duration },
+    /// Matched with this waiting entrant. The PIN is burned.
+    Matched(Box<PinEntrant>),
+}
+
+/// Reasons [`PinMatcher::submit`] can refuse a PIN.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PinError {
+    /// Not exactly [`PIN_DIGITS`] ASCII digits.
+    Invalid,
+    /// The PIN was used within the last [`PIN_TTL`].
+    Burned,
+    /// Too many attempts from this address; retry after the given time.
+    TooManyAttempts { retry_after: Duration },
+    /// [`MAX_PENDING_PINS`] PINs are already waiting.
+    Busy,
+}
+
+impl PinError {
+    /// Machine-readable error code reported to the client.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            PinError::Invalid => ErrorCode::InvalidField,
+            PinError::Burned => ErrorCode::Expired,
+            PinError::TooManyAttempts { .. } | PinError::Busy => ErrorCode::RateLimited,
+        }
+    }
+}
+
+impl std::fmt::Display for PinError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PinError::Invalid => write!(f, "pin must be {PIN_DIGITS} digits"),
+            PinError::Burned => write!(f, "pin already used"),
+            PinError::TooManyAttempts { .. } => write!(f, "too many pin attempts"),
+            PinError::Busy => write!(f, "too many pending pins ({MAX_PENDING_PINS})"),
+        }
+    }
+}
+
+impl std::error::Error for PinError {}
+
+struct Pending {
+    entrant: PinEntrant,
+    expires: Instant,
+}
+
+struct Attempts {
+    window_start: Instant,
+    count: u32,
+}
+
+/// Shared PIN matching state. All methods are safe to call concurrently.
+pub struct PinMatcher {
+    pending: DashMap<String, Pending>,
+    /// The PIN each session last waited on, so [`cancel`](Self::cancel)
+    /// need not scan `pending`. May be stale; `pending` is authoritative.
+    waiting: DashMap<u64, String>,
+    /// Used PINs and when they may be used again.
+    burned: DashMap<String, Instant>,
+    /// Attempt counters keyed by [`limit_key`] of the client IP.
+    attempts: DashMap<String, Attempts>,
+}
+
+impl PinMatcher {
+    /// Create an empty matcher.
+    pub fn new() -> Self {
+        Self {
+            pending: DashMap::new(),
+            waiting: DashMap::new(),
+            burned: DashMap::new(),
+            attempts: DashMap::new(),
+        }
+    }
+
+    /// Submit `pin` for `entrant`, counting one attempt against `ip`.
+    ///
+    /// A session has at most one waiting PIN: submitting again replaces it.
+    /// Waiting entrants whose connection is gone are skipped.
+    pub fn submit(&self, ip: &str, pin: &str, entrant: PinEntrant) -> Result<PinOutcome, PinError> {
+        if pin.len() != PIN_DIGITS || !pin.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(PinError::Invalid);
+        }
+        self.count_attempt(ip)?;
+        let session_id = entrant.session.session_id;
+        self.cancel(session_id);
+
+        let now = Instant::now();
+        if self.burned.len() >= MAX_PENDING_PINS {
+            self.burned.retain(|_, until| *until > now);
+        }
+        if self.burned.get(pin).is_some_and(|until| *until > now) {
+            debug!(peer_code = %entrant.session.peer_code, "burned pin submitted");
+            return Err(PinError::Burned);
+        }
+        if !self.pending.contains_key(pin) && self.pending.len() >= MAX_PENDING_PINS {
+            self.pending.retain(|_, p| p.expires > now);
+        }
+        let at_limit = self.pending.len() >= MAX_PENDING_PINS;
+
+        // `waiting` is only updated once the `pending` entry is released.
+        let (outcome, displaced) = match self.pending.entry(pin.to_string()) {
+            Entry::Occupied(entry)
+                if entry.get().expires > now && !entry.get().entrant.sender.is_closed() =>
+            {
+                let (pin, waiting) = entry.remove_entry();
+                self.burned.insert(pin, now + PIN_TTL);
+                info!(
+                    a = %waiting.entrant.session.peer_code,
+                    b = %entrant.session.peer_code,
+                    "pin matched"
+                );
+                let displaced = waiting.entrant.session.session_id;
+                (
+                    PinOutcome::Matched(Box::new(waiting.entrant)),
+                    Some(displaced),
+                )
+            }
+            Entry::Occupied(mut entry) => {
+                // Expired or disconnected: take the slot over.
+                let previous = entry.insert(Pending {
+                    entrant,
+                    expires: now + PIN_TTL,
+                });
+                let displaced = previous.entrant.session.session_id;
+                (
+                    PinOutcome::Waiting {
+                        expires_in: PIN_TTL,
+                    },
+                    Some(displaced),
+                )
+            }
+            Entry::Vacant(_) if at_limit => {
+                warn!("pending pin limit reached ({MAX_PENDING_PINS})");
+                return Err(PinError::Busy);
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(Pending {
+                    entrant,
+                    expires: now + PIN_TTL,
+                });
+                (
+                    PinOutcome::Waiting {
+                        expires_in: PIN_TTL,
+                    },
+                    None,
+                )
+            }
+        };
+        if let Some(displaced) = displaced {
+            self.waiting.remove_if(&displaced, |_, p| p == pin);
+        }
+        if matches!(outcome, PinOutcome::Waiting { .. }) {
+            self.waiting.insert(session_id, pin.to_string());
+        }
+        Ok(outcome)
+    }
+
+    /// Remove `session_id`'s waiting PIN, if any (e.g. on disconnect).
+    pub fn cancel(&self, session_id: u64) {
+        if let Some((_, pin)) = self.waiting.remove(&session_id) {
+            self.pending
+                .remove_if(&pin, |_, p| p.entrant.session.session_id == session_id);
+        }
+    }
+
+    /// Remove `pin` if `session_id` is still waiting on it and it has
+    /// expired. Returns the entrant so the caller can notify it.
+    pub fn expire(&self, pin: &str, session_id: u64) -> Option<PinEntrant> {
+        let now = Instant::now();
+        let (_, expired) = self.pending.remove_if(pin, |_, p| {
+            p.entrant.session.session_id == session_id && p.expires <= now
+        })?;
+        self.waiting.remove_if(&session_id, |_, p| p == pin);
+        Some(expired.entrant)
+    }
+
+    fn count_attempt(&self, ip: &str) -> Result<(), PinError> {
+        let now = Instant::now();
+        let key = limit_key(ip);
+        if !self.attempts.contains_key(&key) && self.attempts.len() >= MAX_PENDING_PINS {
+            self.attempts
+                .retain(|_, a| now.duration_since(a.window_start) < PIN_ATTEMPT_WINDOW);
+        }
+        let mut attempts = self.attempts.entry(key).or_insert(Attempts {
+            window_start: now,
+            count: 0,
+        });
+        if now.duration_since(attempts.window_start) >= PIN_ATTEMPT_WINDOW {
+            attempts.window_start = now;
+            attempts.count = 0;
+        }
+        if attempts.count >= MAX_PIN_ATTEMPTS_PER_IP {
+            warn!(ip = %ip, "pin attempt limit reached");
+            return Err(PinError::TooManyAttempts {
+                retry_after: PIN_ATTEMPT_WINDOW - now.duration_since(attempts.window_start),
+            });
+        }
+        attempts.count += 1;
+        Ok(())
+    }
+}
+
+impl Default for PinMatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{DeviceType, Presence};
+    use tokio::sync::mpsc;
+
+    fn entrant(
+        code: &str,
+        session_id: u64,
+    ) -> (
+        PinEntrant,
+        mpsc::UnboundedReceiver<crate::protocol::ServerMessage>,
+    ) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let entrant = PinEntrant {
+            session: SessionRef {
+                room: "room".into(),
+                peer_code: code.into(),
+                session_id,
+            },
+            peer: PeerData {
+                peer_code: code.into(),
+                device_name: code.into(),
+                device_type: DeviceType::Phone,
+                wt_url: None,
+                wt_cert_hash: None,
+                presence: Presence::Available,
+                capabilities: vec![],
+                metadata: Default::default(),
+            },
+            sender: tx,
+            request_id: None,
+        };
+        (entrant, rx)
+    }
+
+    #[test]
+    fn second_submission_matches_and_burns() {
+        let pins = PinMatcher::new();
+        let (a, _ra) = entrant("AAA", 1);
+        let (b, _rb) = entrant("BBB", 2);
+        let (c, _rc) = entrant("CCC", 3);
+        assert!(matches!(
+            pins.submit("ip", "123456", a),
+            Ok(PinOutcome::Waiting { .. })
+        ));
+        match pins.submit("ip", "123456", b) {
+            Ok(PinOutcome::Matched(waiting)) => assert_eq!(waiting.session.peer_code, "AAA"),
+            other => panic!("expected match, got {other:?}"),
+        }
+        assert_eq!(
+            pins.submit("ip", "123456", c).unwrap_err(),
+            PinError::Burned
+        );
+    }
+
+    #[test]
+    fn malformed_pins_rejected_without_counting() {
+        let pins = PinMatcher::new();
+        for pin in ["12345", "1234567", "12a456", "１２３４５６"] {
+            let (a, _ra) = entrant("AAA", 1);
+            assert_eq!(pins.submit("ip", pin, a).unwrap_err(), PinError::Invalid);
+        }
+        assert!(pins.attempts.is_empty());
+    }
+
+    #[test]
+    fn resubmitting_replaces_own_pin() {
+        let pins = PinMatcher::new();
+        let (a, _ra) = entrant("AAA", 1);
+        pins.submit("ip", "111111", a.clone()).unwrap();
+        pins.submit("ip", "222222", a).unwrap();
+        assert!(!pins.pending.contains_key("111111"));
+        assert!(pins.pending.contains_key("222222"));
+    }
+
+    #[test]
+    fn disconnected_waiter_is_replaced() {
+        let pins = PinMatcher::new();
+        let (a, ra) = entrant("AAA", 1);
+        let (b, _rb) = entrant("BBB", 2);
+        pins.submit("ip", "123456", a).unwrap();
+        drop(ra);
+        assert!(matches!(
+            pins.submit("ip", "123456", b),
+            Ok(PinOutcome::Waiting { .. })
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn attempts_limited_per_ip_window() {
+        let pins = PinMatcher::new();
+        for i in 0..MAX_PIN_ATTEMPTS_PER_IP {
+            let (a, _ra) = entrant("AAA", u64::from(i));
+            pins.submit("1.2.3.4", &format!("{i:06}"), a).unwrap();
+        }
+        let (a, _ra) = entrant("AAA", 99);
+        assert!(matches!(
+            pins.submit("1.2.3.4", "999999", a.clone()),
+            Err(PinError::TooManyAttempts { .. })
+        ));
+        assert!(pins.submit("5.6.7.8", "999999", a.clone()).is_ok());
+
+        tokio::time::advance(PIN_ATTEMPT_WINDOW).await;
+        assert!(pins.submit("1.2.3.4", "999998", a).is_ok());
+    }
+
+    #[test]
+    fn attempts_shared_across_an_ipv6_64() {
+        let pins = PinMatcher::new();
+        for i in 0..MAX_PIN_ATTEMPTS_PER_IP {
+            let (a, _ra) = entrant("AAA", u64::from(i));
+            let ip = format!("2001:db8:0:1::{:x}", i + 1);
+            pins.submit(&ip, &format!("{i:06}"), a).unwrap();
+        }
+        let (a, _ra) = entrant("AAA", 99);
+        assert!(matches!(
+            pins.submit("2001:db8:0:1:ffff::1", "999999", a.clone()),
+            Err(PinError::TooManyAttempts { .. })
+        ));
+        assert!(pins.submit("2001:db8:0:2::1", "999999", a).is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waiting_pin_expires() {
+        let pins = PinMatcher::new();
+        let (a, _ra) = entrant("AAA", 1);
+        pins.submit("ip", "123456", a).unwrap();
+        assert!(pins.expire("123456", 1).is_none(), "not expired yet");
+        tokio::time::advance(PIN_TTL).await;
+        assert!(pins.expire("123456", 2).is_none(), "other session");
+        assert_eq!(pins.expire("123456", 1).unwrap().session.peer_code, "AAA");
+        assert!(pins.pending.is_empty());
+        assert!(pins.waiting.is_empty());
+    }
+
+    #[test]
+    fn cancel_removes_only_that_sessions_pin() {
+        let pins = PinMatcher::new();
+        let (a, ra) = entrant("AAA", 1);
+        let (b, _rb) = entrant("BBB", 2);
+        let (c, _rc) = entrant("CCC", 3);
+        pins.submit("ip", "123456", a).unwrap();
+        drop(ra);
+        pins.submit("ip", "123456", b).unwrap();
+        pins.submit("ip", "654321", c).unwrap();
+        // AAA was displaced from the PIN BBB now waits on.
+        pins.cancel(1);
+        assert!(pins.pending.contains_key("123456"));
+        pins.cancel(2);
+        assert!(!pins.pending.contains_key("123456"));
+        assert!(pins.pending.contains_key("654321"));
+        assert_eq!(pins.waiting.len(), 1);
+    }
+}
@@ -34,7 +34,7 @@ mod tests {
         let json = r#"{"type":"signal","to":"XYZ789","payload":{"sdp":"..."}}"#;
         let msg: ClientMessage = serde_json::from_str(json).unwrap();
         match msg {
-            ClientMessage::Signal { to, payload } => {
+            ClientMessage::Signal { to, payload, .. } => {
                 assert_eq!(to, "XYZ789");
                 assert!(payload.get("sdp").is_some());
             }
@@ -47,7 +47,7 @@ mod tests {
         let json = r#"{"type":"manual_signal","to":"XYZ789","payload":{"sdp":"..."}}"#;
         let msg: ClientMessage = serde_json::from_str(json).unwrap();
         match msg {
-            ClientMessage::ManualSignal { to, payload } => {
+            ClientMessage::ManualSignal { to, payload, .. } => {
                 assert_eq!(to, "XYZ789");
                 assert!(payload.get("sdp").is_some());
             }
@@ -64,7 +64,14 @@ mod tests {
                 device_type: DeviceType::Laptop,
                 wt_url: None,
                 wt_cert_hash: None,
+                presence: Presence::Available,
+                capabilities: vec![],
+                metadata: Default::default(),
             }],
+            version: None,
+            id: None,
+            after: None,
+            next: None,
         };
         let json = serde_json::to_string(&msg).unwrap();
         assert!(json.contains(r#""type":"peers""#));
@@ -80,7 +87,11 @@ mod tests {
                 device_type: DeviceType::Tablet,
                 wt_url: None,
                 wt_cert_hash: None,
+                presence: Presence::Available,
+                capabilities: vec![],
+                metadata: Default::default(),
             },
+            version: None,
         };
         let json = serde_json::to_string(&msg).unwrap();
         assert!(json.contains(r#""type":"peer_joined""#));
@@ -90,6 +101,7 @@ mod tests {
     fn serialize_peer_left() {
         let msg = ServerMessage::PeerLeft {
             peer_code: "ABC123".into(),
+            version: None,
         };
         let json = serde_json::to_string(&msg).unwrap();
         assert!(json.contains(r#""type":"peer_left""#));
@@ -101,6 +113,7 @@ mod tests {
         let msg = ServerMessage::Signal {
             from: "ABC123".into(),
             payload: serde_json::json!({"sdp": "offer-data"}),
+            to: None,
         };
         let json = serde_json::to_string(&msg).unwrap();
         assert!(json.contains(r#""type":"signal""#));
@@ -111,6 +124,10 @@ mod tests {
     fn serialize_error() {
         let msg = ServerMessage::Error {
             message: "bad request".into(),
+            code: None,
+            field: None,
+            retry_after_ms: None,
+            id: None,
         };
         let json = serde_json::to_string(&msg).unwrap();
         assert!(json.contains(r#""type":"error""#));
@@ -3,14 +3,38 @@
 //! Peers connecting from the same IP address are grouped into the same "room",
 //! enabling local-network device discovery without any manual pairing. The
 //! [`RoomManager`] uses a [`DashMap`] for lock-free concurrent access.
-
+//!
+//! Peers may instead join a named room from any network by presenting the
+//! room's secret. Named rooms share the room table under keys built by
+//! [`named_room_key`], which can never collide with an IP address. The server
+//! keeps only a salted PBKDF2 verifier of each secret.
+//!
+//! Names are first come, first served, so anyone can claim a name before its
+//! intended users do. Claims are kept cheap to lose and costly to hoard: a
+//! name that never had a second member is released after
+//! [`UNSHARED_NAMED_ROOM_RETENTION`] instead of [`NAMED_ROOM_RETENTION`],
+//! and one client IP may claim at most [`MAX_NAMED_ROOMS_PER_IP`] names per
+//! [`NAMED_ROOM_CREATION_WINDOW`]. Users who need a name that cannot be
+//! taken should pick one that is hard to guess.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::ops::{Deref, DerefMut};
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
 
+use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
+use pbkdf2::pbkdf2_hmac_array;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
 use tokio::sync::mpsc;
+use tokio::time::Instant;
 use tracing::{debug, info, warn};
 
-use crate::protocol::{DeviceType, PeerData, ServerMessage};
+use crate::ip_limit::limit_key;
+use crate::protocol::{DeviceType, ErrorCode, PeerData, Presence, ServerMessage, Subscription};
 
 /// Channel sender type used to push messages to a connected peer's WebSocket.
 pub type PeerSender = mpsc::UnboundedSender<ServerMessage>;
@@ -18,11 +42,67 @@ pub type PeerSender = mpsc::UnboundedSender<ServerMessage>;
 /// Result for explicit manual peer-code lookup across rooms.
 #[derive(Debug, Clone)]
 pub enum ManualPeerLookup {
-    Found(PeerSender),
+    Found(PeerSender, SessionRef),
     NotFound,
     Ambiguous,
 }
 
+/// Reasons [`RoomManager::add_peer`] can refuse a registration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RoomError {
+    /// The target room already holds [`MAX_PEERS_PER_ROOM`] peers.
+    RoomFull,
+    /// The server already tracks [`MAX_ROOMS`] rooms, or [`MAX_NAMED_ROOMS`]
+    /// named room secrets.
+    RoomLimit,
+    /// The secret does not match the named room's verifier.
+    Unauthorized,
+    /// The client IP already claimed [`MAX_NAMED_ROOMS_PER_IP`] named rooms
+    /// in the current [`NAMED_ROOM_CREATION_WINDOW`].
+    TooManyNamedRooms,
+    /// The client IP got [`MAX_NAMED_ROOM_FAILURES_PER_IP`] secret checks
+    /// wrong in the current [`NAMED_ROOM_FAILURE_WINDOW`].
+    TooManySecretFailures,
+    /// [`RoomManager::rotate_code`]: another peer in the room uses the code.
+    CodeInUse,
+}
+
+impl RoomError {
+    /// Machine-readable error code reported to the client.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            RoomError::RoomFull => ErrorCode::RoomFull,
+            RoomError::RoomLimit => ErrorCode::RoomLimit,
+            RoomError::Unauthorized => ErrorCode::Unauthorized,
+            RoomError::TooManyNamedRooms | RoomError::TooManySecretFailures => {
+                ErrorCode::RateLimited
+            }
+            RoomError::CodeInUse => ErrorCode::CodeInUse,
+        }
+    }
+}
+
+impl std::fmt::Display for RoomError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RoomError::RoomFull => write!(f, "room full ({MAX_PEERS_PER_ROOM} peers)"),
+            RoomError::RoomLimit => write!(f, "room limit reached ({MAX_ROOMS})"),
+            RoomError::Unauthorized => write!(f, "room secret does not match"),
+            RoomError::TooManyNamedRooms => write!(
+                f,
+                "too many named rooms created from this address ({MAX_NAMED_ROOMS_PER_IP})"
+            ),
+            RoomError::TooManySecretFailures => write!(
+                f,
+                "too many wrong room secrets from this address ({MAX_NAMED_ROOM_FAILURES_PER_IP})"
+            ),
+            RoomError::CodeInUse => write!(f, "peer code already in use"),
+        }
+    }
+}
+
+impl std::error::Error for RoomError {}
+
 /// Monotonic session counter. Each `add_peer` call assigns a unique session ID
 /// so that `remove_peer` can distinguish the current connection from a stale one
 /// that was replaced (DP-5).
@@ -46,18 +126,182 @@ pub struct PeerInfo {
     pub wt_url: Option<String>,
     /// WebTransport TLS cert hash (optional, desktop peers only).
     pub wt_cert_hash: Option<String>,
+    /// Availability shown to the room.
+    pub presence: Presence,
+    /// Advertised feature capabilities.
+    pub capabilities: Vec<String>,
+    /// Advertised key/value metadata, without the WebTransport aliases
+    /// (see [`take_wt_aliases`]).
+    pub metadata: BTreeMap<String, String>,
+    /// `false` hides the peer from the rest of the room: no room events, no
+    /// place in peer lists, and no lookup by code until it signals first.
+    pub discoverable: bool,
+    /// Which peers this peer hears about, and how many events per second.
+    pub subscription: Subscription,
+    /// When the peer joined or last changed its data, code or presence.
+    /// Checked against [`Subscription::active_within_secs`].
+    pub last_active: Instant,
+    /// Room events delivered to this peer in the current second.
+    pub event_budget: EventBudget,
 }
 
 impl PeerInfo {
+    /// Whether this peer's subscription lets through a peer with the given
+    /// device type and capabilities.
+    fn subscribed_to(&self, device_type: &DeviceType, capabilities: &[String]) -> bool {
+        let sub = &self.subscription;
+        (sub.device_types.is_empty() || sub.device_types.contains(device_type))
+            && sub.capabilities.iter().all(|c| capabilities.contains(c))
+    }
+
+    /// Whether `other` belongs in this peer's peer lists: another,
+    /// discoverable peer that matches the subscription, including its
+    /// recency filter.
+    fn lists(&self, other: &PeerInfo) -> bool {
+        other.discoverable
+            && other.session_id != self.session_id
+            && self.subscribed_to(&other.device_type, &other.capabilities)
+            && self
+                .subscription
+                .active_within_secs
+                .is_none_or(|secs| other.last_active.elapsed() <= Duration::from_secs(secs))
+    }
+
+    /// Room events per second this peer accepts.
+    fn event_rate(&self) -> u32 {
+        self.subscription
+            .max_events_per_sec
+            .map_or(MAX_ROOM_EVENTS_PER_SECOND, |n| {
+                n.min(MAX_ROOM_EVENTS_PER_SECOND)
+            })
+    }
+
     /// Convert to the public [`PeerData`] representation (without the sender).
     pub fn to_peer_data(&self) -> PeerData {
+        let mut metadata = self.metadata.clone();
+        if let Some(url) = &self.wt_url {
+            metadata.insert(WT_URL_KEY.to_string(), url.clone());
+        }
+        if let Some(hash) = &self.wt_cert_hash {
+            metadata.insert(WT_CERT_HASH_KEY.to_string(), hash.clone());
+        }
         PeerData {
             peer_code: self.peer_code.clone(),
             device_name: self.device_name.clone(),
             device_type: self.device_type.clone(),
             wt_url: self.wt_url.clone(),
             wt_cert_hash: self.wt_cert_hash.clone(),
+            presence: self.presence,
+            capabilities: self.capabilities.clone(),
+            metadata,
+        }
+    }
+}
+
+/// Per-peer count of room events in one-second windows, enforcing
+/// [`MAX_ROOM_EVENTS_PER_SECOND`] or the peer's lower subscribed rate.
+#[derive(Debug, Clone)]
+pub struct EventBudget {
+    window_start: Instant,
+    sent: u32,
+}
+
+impl Default for EventBudget {
+    fn default() -> Self {
+        Self {
+            window_start: Instant::now(),
+            sent: 0,
+        }
+    }
+}
+
+impl EventBudget {
+    /// Count one event. `Ok` delivers it; `Err` drops it, carrying the
+    /// milliseconds left in the window for the first drop of a window (the
+    /// peer is told once) and `None` for the rest.
+    fn take(&mut self, per_sec: u32) -> Result<(), Option<u64>> {
+        let now = Instant::now();
+        if now.duration_since(self.window_start) >= Duration::from_secs(1) {
+            self.window_start = now;
+            self.sent = 0;
+        }
+        self.sent = self.sent.saturating_add(1);
+        if self.sent <= per_sec {
+            return Ok(());
+        }
+        if self.sent > per_sec.saturating_add(1) {
+            return Err(None);
+        }
+        let left = (self.window_start + Duration::from_secs(1)).saturating_duration_since(now);
+        Err(Some((left.as_millis() as u64).max(1)))
+    }
+}
+
+/// Metadata key that aliases [`PeerData::wt_url`].
+pub const WT_URL_KEY: &str = "wt_url";
+
+/// Metadata key that aliases [`PeerData::wt_cert_hash`].
+pub const WT_CERT_HASH_KEY: &str = "wt_cert_hash";
+
+/// Remove the WebTransport aliases from a client-supplied metadata map and
+/// return them as `(wt_url, wt_cert_hash)`. Callers let the explicit fields
+/// win over these.
+pub fn take_wt_aliases(
+    metadata: &mut BTreeMap<String, String>,
+) -> (Option<String>, Option<String>) {
+    (
+        metadata.remove(WT_URL_KEY),
+        metadata.remove(WT_CERT_HASH_KEY),
+    )
+}
+
+/// Metadata change requested by `ClientMessage::Update`.
+///
+/// `None` leaves a field unchanged. For the WebTransport fields, `Some(None)`
+/// clears the value; `capabilities` and `metadata` replace the old values.
+#[derive(Debug, Clone, Default)]
+pub struct PeerUpdate {
+    pub device_name: Option<String>,
+    pub wt_url: Option<Option<String>>,
+    pub wt_cert_hash: Option<Option<String>>,
+    pub capabilities: Option<Vec<String>>,
+    /// Without the WebTransport aliases.
+    pub metadata: Option<BTreeMap<String, String>>,
+}
+
+impl PeerUpdate {
+    /// Apply the change to `peer`. Returns whether anything changed.
+    fn apply(self, peer: &mut PeerInfo) -> bool {
+        let before = (
+            peer.device_name.clone(),
+            peer.wt_url.clone(),
+            peer.wt_cert_hash.clone(),
+            peer.capabilities.clone(),
+            peer.metadata.clone(),
+        );
+        if let Some(name) = self.device_name {
+            peer.device_name = name;
+        }
+        if let Some(url) = self.wt_url {
+            peer.wt_url = url;
+        }
+        if let Some(hash) = self.wt_cert_hash {
+            peer.wt_cert_hash = hash;
+        }
+        if let Some(capabilities) = self.capabilities {
+            peer.capabilities = capabilities;
         }
+        if let Some(metadata) = self.metadata {
+            peer.metadata = metadata;
+        }
+        before
+            != (
+                peer.device_name.clone(),
+                peer.wt_url.clone(),
+                peer.wt_cert_hash.clone(),
+                peer.capabilities.clone(),
+                peer.metadata.clone(),
+            )
     }
 }
 
@@ -69,13 +313,257 @@ pub const MAX_PEERS_PER_ROOM: usize = 256;
 /// Prevents room table memory exhaustion from many distinct IPs.
 pub const MAX_ROOMS: usize = 65_536;
 
-/// Manages rooms keyed by client IP address.
+/// Maximum number of named room secrets the server keeps.
+/// Prevents verifier table memory exhaustion from many distinct names.
+pub const MAX_NAMED_ROOMS: usize = 4_096;
+
+/// How long a named room's secret is kept after the room was last joined or
+/// emptied, once it has had two members at the same time. Until then, the
+/// name stays bound to its first secret.
+pub const NAMED_ROOM_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Retention of a named room that never had a second member. Short, so a
+/// name claimed and left alone is soon free again.
+pub const UNSHARED_NAMED_ROOM_RETENTION: Duration = Duration::from_secs(60 * 60);
+
+/// Named rooms one client IP (or IPv6 /64) may create per
+/// [`NAMED_ROOM_CREATION_WINDOW`]. Rebinding an expired name counts too.
+pub const MAX_NAMED_ROOMS_PER_IP: u32 = 8;
+
+/// Length of the named room creation counting window.
+pub const NAMED_ROOM_CREATION_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// Named room secret checks one client IP (or IPv6 /64) may get wrong per
+/// [`NAMED_ROOM_FAILURE_WINDOW`]. Each check runs the slow KDF, so this
+/// bounds the KDF work one address can cause without ever succeeding.
+pub const MAX_NAMED_ROOM_FAILURES_PER_IP: u32 = 10;
+
+/// Length of the named room secret failure counting window.
+pub const NAMED_ROOM_FAILURE_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// PBKDF2-HMAC-SHA256 iterations of a named room verifier. Each join pays
+/// this once; an offline guess against a leaked verifier pays it too.
+const NAMED_ROOM_KDF_ITERATIONS: u32 = 100_000;
+
+/// Minimum time between two scans of the named room tables.
+const NAMED_ROOM_PRUNE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often [`RoomManager::authorize_named_room`] re-runs the KDF when the
+/// verifier it checked against was replaced in the meantime.
+const NAMED_ROOM_AUTH_ATTEMPTS: usize = 3;
+
+/// Room table key for a named room. The prefix keeps named rooms apart from
+/// IP rooms (and from the shared `"local"` room).
+pub fn named_room_key(name: &str) -> String {
+    format!("name:{name}")
+}
+
+/// Maximum number of direct links (see [`RoomManager::link_sessions`]) kept
+/// per session. The oldest link is dropped first.
+pub const MAX_LINKS_PER_SESSION: usize = 32;
+
+/// Maximum number of unanswered pair requests a peer can hold.
+pub const MAX_PAIR_REQUESTS_PER_PEER: usize = 16;
+
+/// Number of recent changes each room remembers for
+/// [`RoomManager::list_peers`] deltas. Clients further behind get a snapshot.
+pub const MAX_ROOM_CHANGES: usize = 128;
+
+/// Room events (`peer_joined`, `peer_left`, `peer_updated`,
+/// `presence_changed`) delivered to one peer per second. A subscription may
+/// ask for fewer.
+pub const MAX_ROOM_EVENTS_PER_SECOND: u32 = 20;
+
+/// The peers of one room plus its presence version.
+///
+/// Every change announced to the room (join, leave, update, presence)
+/// advances `version` by one and records the peer code it touched, so a
+/// client that knows an older version can be sent just the net difference.
+/// A recreated room starts above every version its earlier incarnations
+/// reached (see `RoomManager::version_floor`), so their versions never match
+/// its change log. Dereferences to the peer list.
+#[derive(Default)]
+struct Room {
+    peers: Vec<PeerInfo>,
+    version: u64,
+    /// `(version, peer_code)` of the last [`MAX_ROOM_CHANGES`] changes,
+    /// oldest first.
+    changes: VecDeque<(u64, String)>,
+}
+
+impl Room {
+    /// Record a change to `peer_code` and return the new version.
+    fn bump(&mut self, peer_code: &str) -> u64 {
+        self.version += 1;
+        if self.changes.len() == MAX_ROOM_CHANGES {
+            self.changes.pop_front();
+        }
+        self.changes
+            .push_back((self.version, peer_code.to_string()));
+        self.version
+    }
+
+    /// Distinct peer codes changed after `since`, or `None` if some of those
+    /// changes are no longer remembered. A `since` from an earlier
+    /// incarnation of the room is below the first version this one
+    /// recorded, so it also gets `None`.
+    fn changed_since(&self, since: u64) -> Option<Vec<String>> {
+        if since > self.version {
+            return None;
+        }
+        if since < self.version && self.changes.front().is_none_or(|(v, _)| *v > since + 1) {
+            return None;
+        }
+        let mut codes: Vec<String> = Vec::new();
+        for (_, code) in self.changes.iter().filter(|(v, _)| *v > since) {
+            if !codes.contains(code) {
+                codes.push(code.clone());
+            }
+        }
+        Some(codes)
+    }
+
+    /// Send a room event to every peer except the session `subject` for
+    /// which `wants` holds, within each recipient's event rate. A recipient
+    /// over its rate gets one `presence_throttled` per second instead.
+    fn broadcast(&mut self, msg: &ServerMessage, subject: u64, wants: impl Fn(&PeerInfo) -> bool) {
+        for p in self.peers.iter_mut() {
+            if p.session_id == subject || !wants(p) {
+                continue;
+            }
+            let rate = p.event_rate();
+            let sent = match p.event_budget.take(rate) {
+                Ok(()) => p.sender.send(msg.clone()),
+                Err(Some(retry_after_ms)) => {
+                    debug!(peer_code = %p.peer_code, "room events throttled");
+                    p.sender
+                        .send(ServerMessage::PresenceThrottled { retry_after_ms })
+                }
+                Err(None) => Ok(()),
+            };
+            if sent.is_err() {
+                debug!(peer_code = %p.peer_code, "failed to send room event (receiver dropped)");
+            }
+        }
+    }
+
+    /// One page of the peers `me` lists, ordered by peer code when paged.
+    /// Returns the page and the `next` cursor if more peers follow.
+    fn page(&self, me: &PeerInfo, after: Option<&str>) -> (Vec<PeerData>, Option<String>) {
+        let mut listed: Vec<&PeerInfo> = self.peers.iter().filter(|p| me.lists(p)).collect();
+        let size = match me.subscription.page_size {
+            Some(n) => n as usize,
+            None if after.is_none() => {
+                return (
+                    listed.into_iter().map(PeerInfo::to_peer_data).collect(),
+                    None,
+                );
+            }
+            None => usize::MAX,
+        };
+        listed.sort_by(|a, b| a.peer_code.cmp(&b.peer_code));
+        if let Some(after) = after {
+            listed.retain(|p| p.peer_code.as_str() > after);
+        }
+        let next = (listed.len() > size).then(|| listed[size - 1].peer_code.clone());
+        let page = listed
+            .into_iter()
+            .take(size)
+            .map(PeerInfo::to_peer_data)
+            .collect();
+        (page, next)
+    }
+}
+
+impl Deref for Room {
+    type Target = Vec<PeerInfo>;
+
+    fn deref(&self) -> &Vec<PeerInfo> {
+        &self.peers
+    }
+}
+
+impl DerefMut for Room {
+    fn deref_mut(&mut self) -> &mut Vec<PeerInfo> {
+        &mut self.peers
+    }
+}
+
+/// One registered session: the room it joined, its peer code and session id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionRef {
+    pub room: String,
+    pub peer_code: String,
+    pub session_id: u64,
+}
+
+/// Salted PBKDF2-HMAC-SHA256 verifier of a named room's secret.
+struct RoomVerifier {
+    salt: [u8; 16],
+    digest: [u8; 32],
+    last_used: Instant,
+    /// Whether the room ever had two members at once.
+    shared: bool,
+}
+
+impl RoomVerifier {
+    fn new(salt: [u8; 16], digest: [u8; 32]) -> Self {
+        Self {
+            salt,
+            digest,
+            last_used: Instant::now(),
+            shared: false,
+        }
+    }
+
+    fn random_salt() -> [u8; 16] {
+        let mut salt = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut salt);
+        salt
+    }
+
+    /// Deliberately slow: call it without holding a map entry.
+    fn hash(salt: &[u8; 16], secret: &str) -> [u8; 32] {
+        pbkdf2_hmac_array::<Sha256, 32>(secret.as_bytes(), salt, NAMED_ROOM_KDF_ITERATIONS)
+    }
+
+    fn expired(&self) -> bool {
+        let retention = if self.shared {
+            NAMED_ROOM_RETENTION
+        } else {
+            UNSHARED_NAMED_ROOM_RETENTION
+        };
+        self.last_used.elapsed() >= retention
+    }
+}
+
+/// Manages rooms keyed by client IP address or room name.
 ///
 /// Each room contains a list of [`PeerInfo`] entries representing the peers
-/// currently connected from that IP. All methods are safe to call concurrently
-/// from multiple tasks.
+/// currently connected from that IP (or joined to that named room). All
+/// methods are safe to call concurrently from multiple tasks.
 pub struct RoomManager {
-    rooms: DashMap<String, Vec<PeerInfo>>,
+    rooms: DashMap<String, Room>,
+    /// Secret verifiers keyed by [`named_room_key`].
+    verifiers: DashMap<String, RoomVerifier>,
+    /// Named room creations per [`limit_key`]: window start and count.
+    creations: DashMap<String, (Instant, u32)>,
+    /// Failed (or in-flight) secret checks per [`limit_key`]: window start
+    /// and count.
+    failures: DashMap<String, (Instant, u32)>,
+    /// Earliest time [`prune_named_rooms`](Self::prune_named_rooms) scans again.
+    next_named_prune: Mutex<Instant>,
+    /// Sessions each session may signal outside its room, keyed by session id.
+    /// Links are created by a PIN match or an accepted pair request.
+    links: DashMap<u64, Vec<SessionRef>>,
+    /// Unanswered pair requests, keyed by the target's session id.
+    pair_requests: DashMap<u64, Vec<SessionRef>>,
+    /// Per-process salt for [`fingerprint`](Self::fingerprint).
+    fingerprint_salt: [u8; 16],
+    /// Version a new room starts at: above every version a removed room
+    /// reached, so a client's `since` from a room's earlier incarnation
+    /// always gets a snapshot.
+    version_floor: AtomicU64,
 }
 
 impl RoomManager {
@@ -83,24 +571,204 @@ impl RoomManager {
     pub fn new() -> Self {
         Self {
             rooms: DashMap::new(),
+            verifiers: DashMap::new(),
+            creations: DashMap::new(),
+            failures: DashMap::new(),
+            next_named_prune: Mutex::new(Instant::now()),
+            links: DashMap::new(),
+            pair_requests: DashMap::new(),
+            fingerprint_salt: rand::random(),
+            version_floor: AtomicU64::new(0),
         }
     }
 
+    /// Non-reversible identifier of a room key, stable while this manager
+    /// lives: 16 hex digits of a salted SHA-256. The salt keeps IP rooms
+    /// from being recovered by hashing every address.
+    pub fn fingerprint(&self, room: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.fingerprint_salt);
+        hasher.update(room.as_bytes());
+        hasher.finalize()[..8]
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+
+    /// Number of discoverable peers in a room. Hidden peers are left out:
+    /// the size is served to anyone over `/whoami`, and counting them would
+    /// reveal that they are there.
+    pub fn room_size(&self, room: &str) -> usize {
+        self.rooms
+            .get(room)
+            .map_or(0, |room| room.iter().filter(|p| p.discoverable).count())
+    }
+
+    /// Check `secret` against the named room `name` and return the room key
+    /// to pass to [`add_peer`](Self::add_peer).
+    ///
+    /// The first peer to use a name (or the first after the old secret
+    /// expired, see [`NAMED_ROOM_RETENTION`]) sets the secret; that counts
+    /// against [`MAX_NAMED_ROOMS_PER_IP`] for `ip`. The check runs a slow
+    /// KDF, so async callers should not call this on a runtime worker; it
+    /// is charged to `ip`'s [`MAX_NAMED_ROOM_FAILURES_PER_IP`] budget before
+    /// it runs and refunded if it succeeds.
+    pub fn authorize_named_room(
+        &self,
+        name: &str,
+        secret: &str,
+        ip: &str,
+    ) -> Result<String, RoomError> {
+        let key = named_room_key(name);
+        if self.creations.len() >= MAX_NAMED_ROOMS
+            || self.failures.len() >= MAX_NAMED_ROOMS
+            || (!self.verifiers.contains_key(&key) && self.verifiers.len() >= MAX_NAMED_ROOMS)
+        {
+            self.prune_named_rooms();
+        }
+        self.charge_secret_check(ip)?;
+        let result = self.check_named_room_secret(key, secret, ip);
+        if result.is_ok() {
+            self.refund_secret_check(ip);
+        }
+        result
+    }
+
+    fn check_named_room_secret(
+        &self,
+        key: String,
+        secret: &str,
+        ip: &str,
+    ) -> Result<String, RoomError> {
+        // The KDF runs without holding the entry, against the salt seen
+        // beforehand. A verifier replaced in the meantime has a new salt.
+        let mut salt = self
+            .verifiers
+            .get(&key)
+            .map_or_else(RoomVerifier::random_salt, |v| v.salt);
+        for _ in 0..NAMED_ROOM_AUTH_ATTEMPTS {
+            let digest = RoomVerifier::hash(&salt, secret);
+            // Checked before taking the entry: `rooms` and `verifiers` are
+            // separate maps, so this cannot deadlock.
+            let occupied = self.rooms.contains_key(&key);
+            let at_limit = self.verifiers.len() >= MAX_NAMED_ROOMS;
+            match self.verifiers.entry(key.clone()) {
+                Entry::Occupied(entry) if entry.get().salt != salt => {
+                    salt = entry.get().salt;
+                    continue;
+                }
+                Entry::Occupied(mut entry) => {
+                    let verifier = entry.get_mut();
+                    if verifier.expired() && !occupied {
+                        self.count_creation(ip)?;
+                        info!(room = %key, "named room secret expired, rebinding");
+                        *verifier = RoomVerifier::new(salt, digest);
+                    } else if bool::from(digest.ct_eq(&verifier.digest)) {
+                        verifier.last_used = Instant::now();
+                    } else {
+                        warn!(room = %key, "named room secret mismatch");
+                        return Err(RoomError::Unauthorized);
+                    }
+                }
+                Entry::Vacant(entry) => {
+                    if at_limit {
+                        warn!(room = %key, "named room limit reached ({MAX_NAMED_ROOMS})");
+                        return Err(RoomError::RoomLimit);
+                    }
+                    self.count_creation(ip)?;
+                    info!(room = %key, "named room created");
+                    entry.insert(RoomVerifier::new(salt, digest));
+                }
+            }
+            return Ok(key);
+        }
+        warn!(room = %key, "named room secret kept changing during the check");
+        Err(RoomError::Unauthorized)
+    }
+
+    /// Count a secret check against `ip`, see
+    /// [`MAX_NAMED_ROOM_FAILURES_PER_IP`].
+    fn charge_secret_check(&self, ip: &str) -> Result<(), RoomError> {
+        let now = Instant::now();
+        let mut window = self.failures.entry(limit_key(ip)).or_insert((now, 0));
+        if now.duration_since(window.0) >= NAMED_ROOM_FAILURE_WINDOW {
+            *window = (now, 0);
+        }
+        if window.1 >= MAX_NAMED_ROOM_FAILURES_PER_IP {
+            warn!(ip = %ip, "named room secret failure limit reached ({MAX_NAMED_ROOM_FAILURES_PER_IP})");
+            return Err(RoomError::TooManySecretFailures);
+        }
+        window.1 += 1;
+        Ok(())
+    }
+
+    /// Undo [`charge_secret_check`](Self::charge_secret_check) after a
+    /// successful check.
+    fn refund_secret_check(&self, ip: &str) {
+        if let Some(mut window) = self.failures.get_mut(&limit_key(ip)) {
+            window.1 = window.1.saturating_sub(1);
+        }
+    }
+
+    /// Count a named room creation against `ip`, see [`MAX_NAMED_ROOMS_PER_IP`].
+    fn count_creation(&self, ip: &str) -> Result<(), RoomError> {
+        let now = Instant::now();
+        let mut window = self.creations.entry(limit_key(ip)).or_insert((now, 0));
+        if now.duration_since(window.0) >= NAMED_ROOM_CREATION_WINDOW {
+            *window = (now, 0);
+        }
+        if window.1 >= MAX_NAMED_ROOMS_PER_IP {
+            warn!(ip = %ip, "named room creation limit reached ({MAX_NAMED_ROOMS_PER_IP})");
+            return Err(RoomError::TooManyNamedRooms);
+        }
+        window.1 += 1;
+        Ok(())
+    }
+
+    /// Drop verifiers of named rooms that are empty and past retention, and
+    /// creation and failure counts whose window has ended. Scans at most every
+    /// [`NAMED_ROOM_PRUNE_INTERVAL`]; callers that lose the race skip it.
+    fn prune_named_rooms(&self) {
+        let now = Instant::now();
+        let Ok(mut next_prune) = self.next_named_prune.try_lock() else {
+            return;
+        };
+        if now < *next_prune {
+            return;
+        }
+        *next_prune = now + NAMED_ROOM_PRUNE_INTERVAL;
+        drop(next_prune);
+        self.verifiers
+            .retain(|key, v| !v.expired() || self.rooms.contains_key(key));
+        self.creations
+            .retain(|_, (start, _)| now.duration_since(*start) < NAMED_ROOM_CREATION_WINDOW);
+        self.failures
+            .retain(|_, (start, _)| now.duration_since(*start) < NAMED_ROOM_FAILURE_WINDOW);
+    }
+
     /// Add a peer to the room for the given IP address.
     ///
-    /// Returns `(existing_peers, session_id)`: the list of peers that were
-    /// **already** in the room (before this peer was added), plus a monotonic
-    /// session ID that the caller must pass back to [`remove_peer`] on cleanup.
-    /// The session ID prevents a stale connection's teardown from removing a
-    /// replacement connection that reused the same peer code (DP-5).
+    /// Returns `(existing_peers, session_id, version, next)`: the first page
+    /// of peers that were **already** in the room (before this peer was
+    /// added) and match its subscription, a monotonic session ID that the
+    /// caller must pass back to [`remove_peer`] on cleanup, the room version
+    /// after the join, and the cursor for the next page, if any. The session
+    /// ID prevents a stale connection's teardown from removing a replacement
+    /// connection that reused the same peer code (DP-5).
     ///
-    /// Also broadcasts a `peer_joined` message to every existing peer in the room.
-    pub fn add_peer(&self, ip: &str, mut peer: PeerInfo) -> Result<(Vec<PeerData>, u64), String> {
+    /// Also broadcasts a `peer_joined` message to the existing peers whose
+    /// subscriptions match.
+    #[allow(clippy::type_complexity)]
+    pub fn add_peer(
+        &self,
+        ip: &str,
+        mut peer: PeerInfo,
+    ) -> Result<(Vec<PeerData>, u64, u64, Option<String>), RoomError> {
         let session_id = NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed);
         peer.session_id = session_id;
+        peer.last_active = Instant::now();
 
         let peer_data = peer.to_peer_data();
-        let mut existing_peers = Vec::new();
 
         // Check global room limit before creating a new room.
         if !self.rooms.contains_key(ip) && self.rooms.len() >= MAX_ROOMS {
@@ -110,10 +778,13 @@ impl RoomManager {
                 room_count = self.rooms.len(),
                 "room limit reached ({MAX_ROOMS}), rejecting new room"
             );
-            return Err(format!("room limit reached ({MAX_ROOMS})"));
+            return Err(RoomError::RoomLimit);
         }
 
-        let mut room = self.rooms.entry(ip.to_string()).or_default();
+        let mut room = self.rooms.entry(ip.to_string()).or_insert_with(|| Room {
+            version: self.version_floor.load(Ordering::Relaxed),
+            ..Room::default()
+        });
 
         // Replace stale peer with the same code (reconnection scenario).
         // The old WebSocket may not have been cleaned up yet when the client
@@ -125,7 +796,17 @@ impl RoomManager {
                 peer_code = %peer.peer_code,
                 "replacing stale peer connection (reconnect)"
             );
-            room.remove(pos);
+            let stale = room.remove(pos);
+            // A visible peer coming back hidden must disappear for the room.
+            if stale.discoverable && !peer.discoverable {
+                let leave_msg = ServerMessage::PeerLeft {
+                    peer_code: stale.peer_code.clone(),
+                    version: Some(room.bump(&stale.peer_code)),
+                };
+                room.broadcast(&leave_msg, stale.session_id, |p| {
+                    p.subscribed_to(&stale.device_type, &stale.capabilities)
+                });
+            }
         }
 
         // Check per-room peer limit (after stale replacement, so reconnects aren't blocked).
@@ -136,23 +817,27 @@ impl RoomManager {
                 room_size = room.len(),
                 "room full ({MAX_PEERS_PER_ROOM} peers), rejecting registration"
             );
-            return Err(format!("room full ({MAX_PEERS_PER_ROOM} peers)"));
+            return Err(RoomError::RoomFull);
         }
 
         // Snapshot existing peers for the "peers" response.
-        for p in room.iter() {
-            existing_peers.push(p.to_peer_data());
-        }
-
-        // Broadcast peer_joined to existing room members.
-        let join_msg = ServerMessage::PeerJoined {
-            peer: peer_data.clone(),
+        let (existing_peers, next) = room.page(&peer, None);
+
+        // Broadcast peer_joined to existing room members. A hidden peer's
+        // arrival is not a room change.
+        let version = if peer.discoverable {
+            let version = room.bump(&peer.peer_code);
+            let join_msg = ServerMessage::PeerJoined {
+                peer: peer_data,
+                version: Some(version),
+            };
+            room.broadcast(&join_msg, session_id, |p| {
+                p.subscribed_to(&peer.device_type, &peer.capabilities)
+            });
+            version
+        } else {
+            room.version
         };
-        for p in room.iter() {
-            if p.sender.send(join_msg.clone()).is_err() {
-                debug!(peer_code = %p.peer_code, "failed to send peer_joined (receiver dropped)");
-            }
-        }
 
         info!(
             ip = %ip,
@@ -168,7 +853,15 @@ impl RoomManager {
         );
 
         room.push(peer);
-        Ok((existing_peers, session_id))
+        let shared = room.len() > 1;
+        drop(room);
+        // A named room with two members is in use, not just claimed.
+        if shared {
+            if let Some(mut verifier) = self.verifiers.get_mut(ip) {
+                verifier.shared = true;
+            }
+        }
+        Ok((existing_peers, session_id, version, next))
     }
 
     /// Remove a peer from the room for the given IP address.
@@ -180,13 +873,14 @@ impl RoomManager {
     /// Broadcasts a `peer_left` message to all remaining peers in the room.
     /// Cleans up the room entry if it becomes empty.
     pub fn remove_peer(&self, ip: &str, peer_code: &str, session_id: u64) {
+        self.unlink_session(session_id);
+        self.drop_pair_requests(session_id);
         let should_remove_room = {
             if let Some(mut room) = self.rooms.get_mut(ip) {
-                let len_before = room.len();
-                room.retain(|p| !(p.peer_code == peer_code && p.session_id == session_id));
-                let removed = room.len() < len_before;
-
-                if !removed {
+                let Some(pos) = room
+                    .iter()
+                    .position(|p| p.peer_code == peer_code && p.session_id == session_id)
+                else {
                     // The peer was already replaced by a newer session (DP-5).
                     // Do not broadcast peer_left — the replacement is still active.
                     debug!(
@@ -196,16 +890,20 @@ impl RoomManager {
                         "skipping remove — peer was replaced by newer session"
                     );
                     return;
-                }
-
-                // Broadcast peer_left to remaining peers.
-                let leave_msg = ServerMessage::PeerLeft {
-                    peer_code: peer_code.to_string(),
                 };
-                for p in room.iter() {
-                    if p.sender.send(leave_msg.clone()).is_err() {
-                        debug!(peer_code = %p.peer_code, "failed to send peer_left (receiver dropped)");
-                    }
+                let removed = room.remove(pos);
+
+                // Broadcast peer_left to remaining peers, unless they never
+                // saw the peer.
+                if removed.discoverable {
+                    let version = room.bump(peer_code);
+                    let leave_msg = ServerMessage::PeerLeft {
+                        peer_code: peer_code.to_string(),
+                        version: Some(version),
+                    };
+                    room.broadcast(&leave_msg, session_id, |p| {
+                        p.subscribed_to(&removed.device_type, &removed.capabilities)
+                    });
                 }
 
                 info!(
@@ -223,11 +921,332 @@ impl RoomManager {
         };
 
         if should_remove_room {
-            self.rooms.remove(ip);
+            // The floor is raised under the shard lock, before a concurrent
+            // `add_peer` can recreate the room.
+            self.rooms.remove_if(ip, |_, room| {
+                let empty = room.is_empty();
+                if empty {
+                    self.version_floor
+                        .fetch_max(room.version + 1, Ordering::Relaxed);
+                }
+                empty
+            });
+            // Retention of a named room's secret runs from when it emptied.
+            if let Some(mut verifier) = self.verifiers.get_mut(ip) {
+                verifier.last_used = Instant::now();
+            }
             debug!(ip = %ip, "room cleaned up (empty)");
         }
     }
 
+    /// Update the metadata of a registered peer in place.
+    ///
+    /// Like [`remove_peer`], only the session identified by `session_id` is
+    /// affected. If anything changed, the peer itself is told with
+    /// `peer_updated`, and a discoverable peer's change is broadcast to the
+    /// room; subscribers get it if the peer matched their filters before or
+    /// after the change. A peer that is not discoverable is the only one
+    /// told, without a room version. Returns the peer's new public data, or
+    /// `None` if the session is no longer registered.
+    pub fn update_peer(
+        &self,
+        ip: &str,
+        peer_code: &str,
+        session_id: u64,
+        update: PeerUpdate,
+    ) -> Option<PeerData> {
+        let mut room = self.rooms.get_mut(ip)?;
+        let peer = room
+            .iter_mut()
+            .find(|p| p.peer_code == peer_code && p.session_id == session_id)?;
+        let before = (peer.device_type.clone(), peer.capabilities.clone());
+        if !update.apply(peer) {
+            return Some(peer.to_peer_data());
+        }
+        peer.last_active = Instant::now();
+        let peer_data = peer.to_peer_data();
+        if !peer.discoverable {
+            let _ = peer.sender.send(ServerMessage::PeerUpdated {
+                peer: peer_data.clone(),
+                version: None,
+            });
+            return Some(peer_data);
+        }
+        let sender = peer.sender.clone();
+        let after = (peer.device_type.clone(), peer.capabilities.clone());
+
+        let update_msg = ServerMessage::PeerUpdated {
+            peer: peer_data.clone(),
+            version: Some(room.bump(peer_code)),
+        };
+        // The peer learns the version its change produced; its own copy is
+        // not subject to its event rate.
+        if sender.send(update_msg.clone()).is_err() {
+            debug!(peer_code = %peer_code, "failed to send peer_updated (receiver dropped)");
+        }
+        room.broadcast(&update_msg, session_id, |p| {
+            p.subscribed_to(&before.0, &before.1) || p.subscribed_to(&after.0, &after.1)
+        });
+
+        info!(ip = %ip, peer_code = %peer_code, "peer updated");
+        debug!(
+            peer_code = %peer_code,
+            device_name = %peer_data.device_name,
+            "peer device details"
+        );
+        Some(peer_data)
+    }
+
+    /// Replace a registered peer's code in place.
+    ///
+    /// Under the room lock, the other peers receive `peer_left` for the old
+    /// code and then `peer_joined` for the new one, so no one observes both
+    /// codes at once or neither. The session keeps its id, but its links and
+    /// pair requests are dropped: the new code must not be traceable to the
+    /// old one through pairing state. The peer itself gets `code_rotated`
+    /// (answering `id`) in the same locked section, carrying the version of
+    /// the join.
+    ///
+    /// Returns the peer's new public data, `None` if the session is no longer
+    /// registered, or [`RoomError::CodeInUse`].
+    pub fn rotate_code(
+        &self,
+        ip: &str,
+        peer_code: &str,
+        session_id: u64,
+        new_code: &str,
+        id: Option<String>,
+    ) -> Result<Option<PeerData>, RoomError> {
+        let Some(mut room) = self.rooms.get_mut(ip) else {
+            return Ok(None);
+        };
+        if room
+            .iter()
+            .any(|p| p.peer_code == new_code && p.session_id != session_id)
+        {
+            return Err(RoomError::CodeInUse);
+        }
+        let Some(peer) = room
+            .iter_mut()
+            .find(|p| p.peer_code == peer_code && p.session_id == session_id)
+        else {
+            return Ok(None);
+        };
+        if peer_code == new_code {
+            return Ok(Some(peer.to_peer_data()));
+        }
+        peer.peer_code = new_code.to_string();
+        peer.last_active = Instant::now();
+        let peer_data = peer.to_peer_data();
+        let (sender, discoverable, device_type, capabilities) = (
+            peer.sender.clone(),
+            peer.discoverable,
+            peer.device_type.clone(),
+            peer.capabilities.clone(),
+        );
+
+        let mut version = None;
+        if discoverable {
+            let leave_msg = ServerMessage::PeerLeft {
+                peer_code: peer_code.to_string(),
+                version: Some(room.bump(peer_code)),
+            };
+            version = Some(room.bump(new_code));
+            let join_msg = ServerMessage::PeerJoined {
+                peer: peer_data.clone(),
+                version,
+            };
+            let wants = |p: &PeerInfo| p.subscribed_to(&device_type, &capabilities);
+            room.broadcast(&leave_msg, session_id, wants);
+            room.broadcast(&join_msg, session_id, wants);
+        }
+        let rotated = ServerMessage::CodeRotated {
+            peer_code: new_code.to_string(),
+            version,
+            id,
+        };
+        if sender.send(rotated).is_err() {
+            debug!(peer_code = %new_code, "failed to send code_rotated (receiver dropped)");
+        }
+        drop(room);
+
+        self.unlink_session(session_id);
+        self.drop_pair_requests(session_id);
+        info!(ip = %ip, old = %peer_code, new = %new_code, "peer code rotated");
+        Ok(Some(peer_data))
+    }
+
+    /// Change a registered peer's presence.
+    ///
+    /// With `only_from`, the change is applied only if the current state
+    /// matches (used for automatic away/back transitions so they never
+    /// override a state the peer chose). On change, the peer itself is told
+    /// and `presence_changed` is broadcast to the subscribers in the room; a
+    /// peer that is not discoverable is the only one told, without a room
+    /// version.
+    ///
+    /// Returns whether the presence changed; `false` also when the session is
+    /// no longer registered.
+    pub fn set_presence(
+        &self,
+        ip: &str,
+        peer_code: &str,
+        session_id: u64,
+        presence: Presence,
+        only_from: Option<Presence>,
+    ) -> bool {
+        let Some(mut room) = self.rooms.get_mut(ip) else {
+            return false;
+        };
+        let Some(peer) = room
+            .iter_mut()
+            .find(|p| p.peer_code == peer_code && p.session_id == session_id)
+        else {
+            return false;
+        };
+        if peer.presence == presence || only_from.is_some_and(|from| peer.presence != from) {
+            return false;
+        }
+        peer.presence = presence;
+        peer.last_active = Instant::now();
+
+        if !peer.discoverable {
+            let _ = peer.sender.send(ServerMessage::PresenceChanged {
+                peer_code: peer_code.to_string(),
+                presence,
+                automatic: only_from.is_some(),
+                version: None,
+            });
+            return true;
+        }
+        let (sender, device_type, capabilities) = (
+            peer.sender.clone(),
+            peer.device_type.clone(),
+            peer.capabilities.clone(),
+        );
+        let msg = ServerMessage::PresenceChanged {
+            peer_code: peer_code.to_string(),
+            presence,
+            automatic: only_from.is_some(),
+            version: Some(room.bump(peer_code)),
+        };
+        // The peer's own copy is not subject to its event rate.
+        if sender.send(msg.clone()).is_err() {
+            debug!(peer_code = %peer_code, "failed to send presence_changed (receiver dropped)");
+        }
+        room.broadcast(&msg, session_id, |p| {
+            p.subscribed_to(&device_type, &capabilities)
+        });
+        debug!(ip = %ip, peer_code = %peer_code, presence = ?presence, "presence changed");
+        true
+    }
+
+    /// Answer a session's `list_peers`.
+    ///
+    /// With `since` still covered by the room's change log the session gets a
+    /// `peers_delta`, otherwise a full `peers` snapshot, paged by the
+    /// session's subscription and starting after the code `after`. Only
+    /// peers the session's subscription lists are included; a changed peer
+    /// it no longer lists is reported as removed. The answer is queued on
+    /// the session's own channel while the room is locked, so it is ordered
+    /// with the room events around it. Returns `false` if the session is no
+    /// longer registered.
+    pub fn list_peers(
+        &self,
+        ip: &str,
+        peer_code: &str,
+        session_id: u64,
+        since: Option<u64>,
+        after: Option<String>,
+        id: Option<String>,
+    ) -> bool {
+        let Some(room) = self.rooms.get(ip) else {
+            return false;
+        };
+        let Some(me) = room
+            .iter()
+            .find(|p| p.peer_code == peer_code && p.session_id == session_id)
+        else {
+            return false;
+        };
+        let delta = since
+            .filter(|_| after.is_none())
+            .and_then(|since| Some((since, room.changed_since(since)?)));
+        let msg = match delta {
+            Some((since, codes)) => {
+                let mut peers = Vec::new();
+                let mut removed = Vec::new();
+                for code in codes.into_iter().filter(|c| c != peer_code) {
+                    match room.iter().find(|p| p.peer_code == code && me.lists(p)) {
+                        Some(p) => peers.push(p.to_peer_data()),
+                        None => removed.push(code),
+                    }
+                }
+                ServerMessage::PeersDelta {
+                    since,
+                    version: room.version,
+                    peers,
+                    removed,
+                    id,
+                }
+            }
+            None => {
+                let (peers, next) = room.page(me, after.as_deref());
+                ServerMessage::Peers {
+                    peers,
+                    version: Some(room.version),
+                    after,
+                    next,
+                    id,
+                }
+            }
+        };
+        if me.sender.send(msg).is_err() {
+            debug!(peer_code = %peer_code, "failed to send peer list (receiver dropped)");
+        }
+        true
+    }
+
+    /// Replace a session's subscription and send it the first page of a
+    /// fresh `peers` snapshot under it, queued while the room is locked like
+    /// [`list_peers`](Self::list_peers). Returns `false` if the session is no
+    /// longer registered.
+    pub fn subscribe(
+        &self,
+        ip: &str,
+        peer_code: &str,
+        session_id: u64,
+        subscription: Subscription,
+        id: Option<String>,
+    ) -> bool {
+        let Some(mut room) = self.rooms.get_mut(ip) else {
+            return false;
+        };
+        let Some(me) = room
+            .iter_mut()
+            .find(|p| p.peer_code == peer_code && p.session_id == session_id)
+        else {
+            return false;
+        };
+        me.subscription = subscription;
+        let Some(me) = room.iter().find(|p| p.session_id == session_id) else {
+            return false;
+        };
+        let (peers, next) = room.page(me, None);
+        let msg = ServerMessage::Peers {
+            peers,
+            version: Some(room.version),
+            after: None,
+            next,
+            id,
+        };
+        if me.sender.send(msg).is_err() {
+            debug!(peer_code = %peer_code, "failed to send peer list (receiver dropped)");
+        }
+        debug!(ip = %ip, peer_code = %peer_code, "subscription changed");
+        true
+    }
+
     /// Get the public peer data for all peers in the room at the given IP.
     pub fn get_room_peers(&self, ip: &str) -> Vec<PeerData> {
         self.rooms
@@ -239,11 +1258,12 @@ impl RoomManager {
     /// Look up a peer's sender channel by peer code within the caller's room.
     ///
     /// Enforces room isolation: only peers in the same room as the caller
-    /// can be resolved. Cross-room lookup returns `None`.
+    /// can be resolved. Cross-room lookup returns `None`, and so does a peer
+    /// that is not discoverable.
     pub fn find_peer(&self, caller_room: &str, peer_code: &str) -> Option<PeerSender> {
         if let Some(room) = self.rooms.get(caller_room) {
             for peer in room.value().iter() {
-                if peer.peer_code == peer_code {
+                if peer.peer_code == peer_code && peer.discoverable {
                     return Some(peer.sender.clone());
                 }
             }
@@ -251,6 +1271,144 @@ impl RoomManager {
         None
     }
 
+    /// Public data of one registered session, or `None` if it is gone.
+    pub fn peer_data(&self, ip: &str, peer_code: &str, session_id: u64) -> Option<PeerData> {
+        let room = self.rooms.get(ip)?;
+        room.iter()
+            .find(|p| p.peer_code == peer_code && p.session_id == session_id)
+            .map(|p| p.to_peer_data())
+    }
+
+    /// Let two sessions signal each other by peer code even though they are
+    /// not in the same room. Links end when either session is removed.
+    pub fn link_sessions(&self, a: &SessionRef, b: &SessionRef) {
+        for (from, to) in [(a, b), (b, a)] {
+            let mut links = self.links.entry(from.session_id).or_default();
+            links.retain(|l| l.peer_code != to.peer_code);
+            if links.len() >= MAX_LINKS_PER_SESSION {
+                links.remove(0);
+            }
+            links.push(to.clone());
+        }
+        debug!(a = %a.peer_code, b = %b.peer_code, "sessions linked");
+    }
+
+    /// Sender of a registered session, or `None` if it is gone.
+    pub fn session_sender(&self, session: &SessionRef) -> Option<PeerSender> {
+        let room = self.rooms.get(&session.room)?;
+        room.iter()
+            .find(|p| p.peer_code == session.peer_code && p.session_id == session.session_id)
+            .map(|p| p.sender.clone())
+    }
+
+    /// Look up a peer linked to `session_id` by [`link_sessions`](Self::link_sessions).
+    /// Returns `None` if there is no such link or the linked session is gone.
+    pub fn find_linked_peer(&self, session_id: u64, peer_code: &str) -> Option<PeerSender> {
+        let link = self
+            .links
+            .get(&session_id)?
+            .iter()
+            .find(|l| l.peer_code == peer_code)?
+            .clone();
+        self.session_sender(&link)
+    }
+
+    /// Record that `requester` asked to pair with `target`. A repeated
+    /// request replaces the earlier one. Returns `false` when the target
+    /// already holds [`MAX_PAIR_REQUESTS_PER_PEER`] requests.
+    pub fn add_pair_request(&self, requester: &SessionRef, target: &SessionRef) -> bool {
+        let mut pending = self.pair_requests.entry(target.session_id).or_default();
+        pending.retain(|r| r.peer_code != requester.peer_code);
+        if pending.len() >= MAX_PAIR_REQUESTS_PER_PEER {
+            warn!(target = %target.peer_code, "pair request limit reached");
+            return false;
+        }
+        pending.push(requester.clone());
+        true
+    }
+
+    /// Remove and return the pending request from `requester_code` to the
+    /// session `target_session_id`.
+    pub fn take_pair_request(
+        &self,
+        target_session_id: u64,
+        requester_code: &str,
+    ) -> Option<SessionRef> {
+        let mut pending = self.pair_requests.get_mut(&target_session_id)?;
+        let pos = pending.iter().position(|r| r.peer_code == requester_code)?;
+        let requester = pending.remove(pos);
+        let now_empty = pending.is_empty();
+        drop(pending);
+        if now_empty {
+            self.pair_requests
+                .remove_if(&target_session_id, |_, v| v.is_empty());
+        }
+        Some(requester)
+    }
+
+    /// Drop pair requests to and from a session.
+    fn drop_pair_requests(&self, session_id: u64) {
+        self.pair_requests.remove(&session_id);
+        self.pair_requests.retain(|_, pending| {
+            pending.retain(|r| r.session_id != session_id);
+            !pending.is_empty()
+        });
+    }
+
+    /// Look up a peer `session_id` may signal: a member of its room, or a
+    /// linked session.
+    ///
+    /// A caller that is not discoverable is linked to the room member it
+    /// looks up, so that peer can answer by code.
+    pub fn find_reachable_peer(
+        &self,
+        caller_room: &str,
+        session_id: u64,
+        peer_code: &str,
+    ) -> Option<PeerSender> {
+        let mut reveal = None;
+        let found = self.rooms.get(caller_room).and_then(|room| {
+            let target = room
+                .iter()
+                .find(|p| p.peer_code == peer_code && p.discoverable)?;
+            if let Some(caller) = room
+                .iter()
+                .find(|p| p.session_id == session_id && !p.discoverable)
+            {
+                let session = |p: &PeerInfo| SessionRef {
+                    room: caller_room.to_string(),
+                    peer_code: p.peer_code.clone(),
+                    session_id: p.session_id,
+                };
+                reveal = Some((session(caller), session(target)));
+            }
+            Some(target.sender.clone())
+        });
+        if let Some((caller, target)) = reveal {
+            self.link_sessions(&caller, &target);
+        }
+        found.or_else(|| self.find_linked_peer(session_id, peer_code))
+    }
+
+    /// Drop all links of a session, in both directions.
+    fn unlink_session(&self, session_id: u64) {
+        let Some((_, links)) = self.links.remove(&session_id) else {
+            return;
+        };
+        for link in links {
+            let now_empty = match self.links.get_mut(&link.session_id) {
+                Some(mut other) => {
+                    other.retain(|l| l.session_id != session_id);
+                    other.is_empty()
+                }
+                None => false,
+            };
+            if now_empty {
+                self.links.remove_if(&link.session_id, |_, v| v.is_empty());
+            }
+        }
+    }
+
     /// Look up a peer's sender channel by exact peer code across all rooms for
     /// explicit manual pairing.
     ///
@@ -259,7 +1417,7 @@ impl RoomManager {
     /// entering a peer code. If a code exists in multiple rooms, the lookup is
     /// rejected as ambiguous instead of guessing.
     pub fn find_peer_manual(&self, peer_code: &str) -> ManualPeerLookup {
-        let mut found: Option<PeerSender> = None;
+        let mut found: Option<(PeerSender, SessionRef)> = None;
 
         for room in self.rooms.iter() {
             for peer in room.value().iter() {
@@ -267,18 +1425,24 @@ impl RoomManager {
                     if found.is_some() {
                         return ManualPeerLookup::Ambiguous;
                     }
-                    found = Some(peer.sender.clone());
+                    let session = SessionRef {
+                        room: room.key().clone(),
+                        peer_code: peer.peer_code.clone(),
+                        session_id: peer.session_id,
+                    };
+                    found = Some((peer.sender.clone(), session));
                 }
             }
         }
 
         match found {
-            Some(sender) => ManualPeerLookup::Found(sender),
+            Some((sender, session)) => ManualPeerLookup::Found(sender, session),
             None => ManualPeerLookup::NotFound,
         }
     }
 
-    /// Return the total number of active rooms (unique IPs with at least one peer).
+    /// Return the total number of active rooms (unique IPs or named rooms with
+    /// at least one peer).
     pub fn room_count(&self) -> usize {
         self.rooms.len()
     }
@@ -314,10 +1478,662 @@ mod tests {
             session_id: 0, // assigned by add_peer
             wt_url: None,
             wt_cert_hash: None,
+            presence: Presence::Available,
+            capabilities: vec![],
+            metadata: BTreeMap::new(),
+            discoverable: true,
+            event_budget: Default::default(),
+            last_active: Instant::now(),
+            subscription: Default::default(),
         };
         (peer, rx)
     }
 
+    #[test]
+    fn fingerprints_are_stable_per_manager() {
+        let rm = RoomManager::new();
+        let fp = rm.fingerprint("203.0.113.7");
+        assert_eq!(fp.len(), 16);
+        assert_eq!(rm.fingerprint("203.0.113.7"), fp);
+        assert_ne!(rm.fingerprint("local"), fp);
+        // Another server instance salts differently.
+        assert_ne!(RoomManager::new().fingerprint("203.0.113.7"), fp);
+    }
+
+    // ─── named rooms ────────────────────────────────────────────────────
+
+    #[test]
+    fn named_room_first_secret_wins() {
+        let rm = RoomManager::new();
+        let key = rm
+            .authorize_named_room("team", "hunter22", "203.0.113.7")
+            .unwrap();
+        assert_eq!(key, named_room_key("team"));
+        assert_eq!(
+            rm.authorize_named_room("team", "hunter22", "198.51.100.9"),
+            Ok(key)
+        );
+        assert_eq!(
+            rm.authorize_named_room("team", "wrong-secret", "203.0.113.7"),
+            Err(RoomError::Unauthorized)
+        );
+        // A different name is an independent room.
+        assert!(rm
+            .authorize_named_room("other", "wrong-secret", "203.0.113.7")
+            .is_ok());
+    }
+
+    #[test]
+    fn named_room_stores_only_salted_digest() {
+        let (salt_a, salt_b) = (RoomVerifier::random_salt(), RoomVerifier::random_salt());
+        assert_ne!(salt_a, salt_b);
+        let a = RoomVerifier::hash(&salt_a, "same secret");
+        assert_ne!(a, RoomVerifier::hash(&salt_b, "same secret"));
+        assert_eq!(a, RoomVerifier::hash(&salt_a, "same secret"));
+        assert_ne!(a, RoomVerifier::hash(&salt_a, "same secret "));
+    }
+
+    #[test]
+    fn named_rooms_are_isolated_from_ip_rooms() {
+        let rm = RoomManager::new();
+        let key = rm
+            .authorize_named_room("local", "hunter22", "203.0.113.7")
+            .unwrap();
+        let (p1, _r1) = make_peer("AAA", "Named");
+        let (p2, _r2) = make_peer("BBB", "Local");
+        rm.add_peer(&key, p1).unwrap();
+        rm.add_peer("local", p2).unwrap();
+        assert!(rm.find_peer(&key, "BBB").is_none());
+        assert!(rm.find_peer("local", "AAA").is_none());
+        assert!(rm.find_peer(&key, "AAA").is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn named_room_secret_expires_only_when_empty() {
+        let rm = RoomManager::new();
+        let ip = "203.0.113.7";
+        let key = rm.authorize_named_room("team", "hunter22", ip).unwrap();
+        let (p1, _r1) = make_peer("AAA", "Member");
+        let (p2, _r2) = make_peer("BBB", "Member");
+        let (_, s1, _, _) = rm.add_peer(&key, p1).unwrap();
+        let (_, s2, _, _) = rm.add_peer(&key, p2).unwrap();
+
+        tokio::time::advance(NAMED_ROOM_RETENTION).await;
+        // Still occupied: the secret holds.
+        assert_eq!(
+            rm.authorize_named_room("team", "other-secret", ip),
+            Err(RoomError::Unauthorized)
+        );
+
+        rm.remove_peer(&key, "AAA", s1);
+        rm.remove_peer(&key, "BBB", s2);
+        tokio::time::advance(NAMED_ROOM_RETENTION - Duration::from_secs(1)).await;
+        assert_eq!(
+            rm.authorize_named_room("team", "other-secret", ip),
+            Err(RoomError::Unauthorized)
+        );
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(rm.authorize_named_room("team", "other-secret", ip).is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unshared_named_room_is_released_early() {
+        let rm = RoomManager::new();
+        let ip = "203.0.113.7";
+        let key = rm.authorize_named_room("team", "squatter", ip).unwrap();
+        let (p1, _r1) = make_peer("AAA", "Squatter");
+        let (_, s1, _, _) = rm.add_peer(&key, p1).unwrap();
+        rm.remove_peer(&key, "AAA", s1);
+
+        tokio::time::advance(UNSHARED_NAMED_ROOM_RETENTION - Duration::from_secs(1)).await;
+        assert_eq!(
+            rm.authorize_named_room("team", "hunter22", ip),
+            Err(RoomError::Unauthorized)
+        );
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(rm.authorize_named_room("team", "hunter22", ip).is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wrong_secrets_are_capped_per_ip() {
+        let rm = RoomManager::new();
+        rm.authorize_named_room("team", "hunter22", "198.51.100.9")
+            .unwrap();
+        for _ in 0..MAX_NAMED_ROOM_FAILURES_PER_IP {
+            assert_eq!(
+                rm.authorize_named_room("team", "guess", "2001:db8::1"),
+                Err(RoomError::Unauthorized)
+            );
+        }
+        // Same /64: refused before the KDF runs, even with the right secret.
+        assert_eq!(
+            rm.authorize_named_room("team", "hunter22", "2001:db8::2"),
+            Err(RoomError::TooManySecretFailures)
+        );
+        // Successful checks are refunded, so members are never locked out.
+        for _ in 0..=MAX_NAMED_ROOM_FAILURES_PER_IP {
+            assert!(rm
+                .authorize_named_room("team", "hunter22", "198.51.100.9")
+                .is_ok());
+        }
+
+        tokio::time::advance(NAMED_ROOM_FAILURE_WINDOW).await;
+        assert!(rm
+            .authorize_named_room("team", "hunter22", "2001:db8::2")
+            .is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn named_room_creations_are_capped_per_ip() {
+        let rm = RoomManager::new();
+        for i in 0..MAX_NAMED_ROOMS_PER_IP {
+            assert!(rm
+                .authorize_named_room(&format!("room-{i}"), "s", "2001:db8::1")
+                .is_ok());
+        }
+        // Same /64, new name: refused. Joining an existing name still works.
+        assert_eq!(
+            rm.authorize_named_room("one-more", "s", "2001:db8::2"),
+            Err(RoomError::TooManyNamedRooms)
+        );
+        assert!(rm
+            .authorize_named_room("room-0", "s", "2001:db8::2")
+            .is_ok());
+        // Other addresses are unaffected.
+        assert!(rm
+            .authorize_named_room("one-more", "s", "203.0.113.7")
+            .is_ok());
+
+        tokio::time::advance(NAMED_ROOM_CREATION_WINDOW).await;
+        assert!(rm
+            .authorize_named_room("another", "s", "2001:db8::2")
+            .is_ok());
+    }
+
+    // ─── rotate_code ────────────────────────────────────────────────────
+
+    #[test]
+    fn rotate_code_emits_left_then_joined() {
+        let rm = RoomManager::new();
+        let (p1, _r1) = make_peer("OLD", "Rotating");
+        let (p2, mut r2) = make_peer("WATCH", "Watcher");
+        let (_, s1, _, _) = rm.add_peer("10.0.0.1", p1).unwrap();
+        rm.add_peer("10.0.0.1", p2).unwrap();
+
+        let data = rm
+            .rotate_code("10.0.0.1", "OLD", s1, "NEW", None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(data.peer_code, "NEW");
+        assert!(matches!(
+            r2.try_recv(),
+            Ok(ServerMessage::PeerLeft { peer_code, .. }) if peer_code == "OLD"
+        ));
+        assert!(matches!(
+            r2.try_recv(),
+            Ok(ServerMessage::PeerJoined { peer, .. }) if peer.peer_code == "NEW"
+        ));
+        assert!(rm.find_peer("10.0.0.1", "OLD").is_none());
+        assert!(rm.find_peer("10.0.0.1", "NEW").is_some());
+
+        // The old code and session no longer match.
+        assert!(matches!(
+            rm.rotate_code("10.0.0.1", "OLD", s1, "X", None),
+            Ok(None)
+        ));
+        // Cleanup uses the new code.
+        rm.remove_peer("10.0.0.1", "NEW", s1);
+        assert_eq!(rm.get_room_peers("10.0.0.1").len(), 1);
+    }
+
+    #[test]
+    fn room_versions_are_consecutive_per_room() {
+        let rm = RoomManager::new();
+        let (p1, _r1) = make_peer("AAA", "A");
+        let (p2, mut r2) = make_peer("BBB", "B");
+        let (_, s1, v1, _) = rm.add_peer("10.0.0.1", p1).unwrap();
+        let (_, _, v2, _) = rm.add_peer("10.0.0.1", p2).unwrap();
+        assert_eq!((v1, v2), (1, 2));
+
+        assert!(rm.set_presence("10.0.0.1", "AAA", s1, Presence::Busy, None));
+        rm.rotate_code("10.0.0.1", "AAA", s1, "CCC", None).unwrap();
+        rm.remove_peer("10.0.0.1", "CCC", s1);
+        let versions: Vec<Option<u64>> = std::iter::from_fn(|| r2.try_recv().ok())
+            .map(|msg| msg.room_version())
+            .collect();
+        assert_eq!(versions, vec![Some(3), Some(4), Some(5), Some(6)]);
+
+        // Another room counts on its own.
+        let (p3, _r3) = make_peer("DDD", "D");
+        let (_, _, v3, _) = rm.add_peer("10.0.0.2", p3).unwrap();
+        assert_eq!(v3, 1);
+    }
+
+    #[test]
+    fn list_peers_sends_net_delta_or_snapshot() {
+        let rm = RoomManager::new();
+        let (me, mut me_rx) = make_peer("ME", "Me");
+        let (_, session, _, _) = rm.add_peer("10.0.0.1", me).unwrap();
+        let (gone, _gone_rx) = make_peer("GONE", "Gone");
+        let (_, s_gone, _, _) = rm.add_peer("10.0.0.1", gone).unwrap();
+        let (stay, _stay_rx) = make_peer("STAY", "Stay");
+        let (_, s_stay, _, _) = rm.add_peer("10.0.0.1", stay).unwrap();
+        rm.set_presence("10.0.0.1", "STAY", s_stay, Presence::Busy, None);
+        rm.remove_peer("10.0.0.1", "GONE", s_gone);
+        while me_rx.try_recv().is_ok() {}
+
+        // Since version 1 (only ME joined): GONE came and went, STAY joined
+        // and changed presence; each appears once.
+        assert!(rm.list_peers("10.0.0.1", "ME", session, Some(1), None, Some("r1".into())));
+        match me_rx.try_recv() {
+            Ok(ServerMessage::PeersDelta {
+                since,
+                version,
+                peers,
+                removed,
+                id,
+            }) => {
+                assert_eq!((since, version), (1, 5));
+                assert_eq!(peers.len(), 1);
+                assert_eq!(peers[0].peer_code, "STAY");
+                assert_eq!(peers[0].presence, Presence::Busy);
+                assert_eq!(removed, vec!["GONE".to_string()]);
+                assert_eq!(id.as_deref(), Some("r1"));
+            }
+            other => panic!("expected peers_delta, got {other:?}"),
+        }
+
+        // Up to date: an empty delta.
+        assert!(rm.list_peers("10.0.0.1", "ME", session, Some(5), None, None));
+        assert!(matches!(
+            me_rx.try_recv(),
+            Ok(ServerMessage::PeersDelta { peers, removed, .. }) if peers.is_empty() && removed.is_empty()
+        ));
+
+        // No `since`, or one the room cannot know: a snapshot without ME.
+        for since in [None, Some(99)] {
+            assert!(rm.list_peers("10.0.0.1", "ME", session, since, None, None));
+            match me_rx.try_recv() {
+                Ok(ServerMessage::Peers { peers, version, .. }) => {
+                    assert_eq!(version, Some(5));
+                    let codes: Vec<_> = peers.iter().map(|p| p.peer_code.as_str()).collect();
+                    assert_eq!(codes, vec!["STAY"]);
+                }
+                other => panic!("expected peers, got {other:?}"),
+            }
+        }
+
+        assert!(!rm.list_peers("10.0.0.1", "ME", session + 100, None, None, None));
+    }
+
+    #[test]
+    fn list_peers_falls_back_to_snapshot_past_change_log() {
+        let rm = RoomManager::new();
+        let (me, mut me_rx) = make_peer("ME", "Me");
+        let (_, session, _, _) = rm.add_peer("10.0.0.1", me).unwrap();
+        let (other, _other_rx) = make_peer("OTHER", "Other");
+        let (_, s_other, _, _) = rm.add_peer("10.0.0.1", other).unwrap();
+        for i in 0..MAX_ROOM_CHANGES {
+            let presence = if i % 2 == 0 {
+                Presence::Busy
+            } else {
+                Presence::Available
+            };
+            rm.set_presence("10.0.0.1", "OTHER", s_other, presence, None);
+        }
+        while me_rx.try_recv().is_ok() {}
+
+        // The log now starts at version 3: the join of OTHER is forgotten.
+        assert!(rm.list_peers("10.0.0.1", "ME", session, Some(1), None, None));
+        assert!(matches!(me_rx.try_recv(), Ok(ServerMessage::Peers { .. })));
+        assert!(rm.list_peers("10.0.0.1", "ME", session, Some(2), None, None));
+        assert!(matches!(
+            me_rx.try_recv(),
+            Ok(ServerMessage::PeersDelta { .. })
+        ));
+    }
+
+    #[test]
+    fn list_peers_snapshots_a_recreated_room() {
+        let rm = RoomManager::new();
+        let (me, _me_rx) = make_peer("ME", "Me");
+        let (_, s_me, _, _) = rm.add_peer("10.0.0.1", me).unwrap();
+        let (a, _a_rx) = make_peer("AAA", "A");
+        let (_, s_a, _, _) = rm.add_peer("10.0.0.1", a).unwrap();
+        rm.remove_peer("10.0.0.1", "AAA", s_a);
+        // ME last saw version 3, then the room emptied.
+        rm.remove_peer("10.0.0.1", "ME", s_me);
+
+        let (me, mut me_rx) = make_peer("ME", "Me");
+        let (_, s_me, _, _) = rm.add_peer("10.0.0.1", me).unwrap();
+        for code in ["BBB", "CCC", "DDD"] {
+            let (p, _rx) = make_peer(code, code);
+            rm.add_peer("10.0.0.1", p).unwrap();
+        }
+        while me_rx.try_recv().is_ok() {}
+
+        assert!(rm.list_peers("10.0.0.1", "ME", s_me, Some(3), None, None));
+        match me_rx.try_recv() {
+            Ok(ServerMessage::Peers { peers, version, .. }) => {
+                assert!(version > Some(3));
+                let codes: Vec<_> = peers.iter().map(|p| p.peer_code.as_str()).collect();
+                assert_eq!(codes, vec!["BBB", "CCC", "DDD"]);
+            }
+            other => panic!("expected peers, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn paged_snapshot_is_ordered_by_code() {
+        let rm = RoomManager::new();
+        for code in ["EEE", "BBB", "DDD", "AAA", "CCC"] {
+            let (p, _rx) = make_peer(code, code);
+            rm.add_peer("10.0.0.1", p).unwrap();
+        }
+        let (mut me, mut me_rx) = make_peer("ME", "Me");
+        me.subscription.page_size = Some(2);
+        let (first, session, _, next) = rm.add_peer("10.0.0.1", me).unwrap();
+        let codes: Vec<_> = first.iter().map(|p| p.peer_code.as_str()).collect();
+        assert_eq!(codes, vec!["AAA", "BBB"]);
+        assert_eq!(next.as_deref(), Some("BBB"));
+
+        let mut after = next;
+        let mut rest = Vec::new();
+        while let Some(cursor) = after.take() {
+            assert!(rm.list_peers("10.0.0.1", "ME", session, None, Some(cursor.clone()), None));
+            match me_rx.try_recv() {
+                Ok(ServerMessage::Peers {
+                    peers,
+                    after: page_after,
+                    next,
+                    ..
+                }) => {
+                    assert_eq!(page_after, Some(cursor));
+                    rest.extend(peers.into_iter().map(|p| p.peer_code));
+                    after = next;
+                }
+                other => panic!("expected peers, got {other:?}"),
+            }
+        }
+        assert_eq!(rest, vec!["CCC", "DDD", "EEE"]);
+    }
+
+    #[test]
+    fn subscription_filters_lists_and_events() {
+        let rm = RoomManager::new();
+        let (mut me, mut me_rx) = make_peer("ME", "Me");
+        me.subscription.device_types = vec![DeviceType::Phone];
+        me.subscription.capabilities = vec!["webrtc".into()];
+        let (_, session, _, _) = rm.add_peer("10.0.0.1", me).unwrap();
+
+        let (mut phone, _phone_rx) = make_peer("PHONE", "Phone");
+        phone.device_type = DeviceType::Phone;
+        phone.capabilities = vec!["webrtc".into(), "zstd".into()];
+        let (_, s_phone, _, _) = rm.add_peer("10.0.0.1", phone).unwrap();
+        let (mut bare, _bare_rx) = make_peer("BARE", "Bare");
+        bare.device_type = DeviceType::Phone;
+        let (_, s_bare, _, _) = rm.add_peer("10.0.0.1", bare).unwrap();
+        let (desk, _desk_rx) = make_peer("DESK", "Desk");
+        rm.add_peer("10.0.0.1", desk).unwrap();
+
+        // Only the phone with the capability is announced.
+        assert!(matches!(
+            me_rx.try_recv(),
+            Ok(ServerMessage::PeerJoined { peer, .. }) if peer.peer_code == "PHONE"
+        ));
+        assert!(me_rx.try_recv().is_err());
+
+        // Gaining the capability makes BARE match: it is announced as updated.
+        let update = PeerUpdate {
+            capabilities: Some(vec!["webrtc".into()]),
+            ..Default::default()
+        };
+        rm.update_peer("10.0.0.1", "BARE", s_bare, update);
+        assert!(matches!(
+            me_rx.try_recv(),
+            Ok(ServerMessage::PeerUpdated { peer, .. }) if peer.peer_code == "BARE"
+        ));
+
+        // Recency narrows lists only.
+        rm.subscribe(
+            "10.0.0.1",
+            "ME",
+            session,
+            Subscription {
+                active_within_secs: Some(0),
+                ..Default::default()
+            },
+            None,
+        );
+        assert!(matches!(
+            me_rx.try_recv(),
+            Ok(ServerMessage::Peers { peers, .. }) if peers.is_empty()
+        ));
+        rm.set_presence("10.0.0.1", "PHONE", s_phone, Presence::Busy, None);
+        assert!(matches!(
+            me_rx.try_recv(),
+            Ok(ServerMessage::PresenceChanged { peer_code, .. }) if peer_code == "PHONE"
+        ));
+    }
+
+    #[test]
+    fn room_events_are_capped_per_peer() {
+        let rm = RoomManager::new();
+        let (mut me, mut me_rx) = make_peer("ME", "Me");
+        me.subscription.max_events_per_sec = Some(2);
+        rm.add_peer("10.0.0.1", me).unwrap();
+        let (other, mut other_rx) = make_peer("OTHER", "Other");
+        let (_, s_other, _, _) = rm.add_peer("10.0.0.1", other).unwrap();
+        for presence in [Presence::Busy, Presence::Away, Presence::Available] {
+            rm.set_presence("10.0.0.1", "OTHER", s_other, presence, None);
+        }
+        for i in 0..30 {
+            let (p, _rx) = make_peer(&format!("P{i}"), "Peer");
+            rm.add_peer("10.0.0.1", p).unwrap();
+        }
+
+        // Join and one presence change fit; the rest are dropped after a
+        // single notice.
+        let mut received = Vec::new();
+        while let Ok(msg) = me_rx.try_recv() {
+            received.push(msg);
+        }
+        assert_eq!(received.len(), 3);
+        assert!(matches!(received[0], ServerMessage::PeerJoined { .. }));
+        assert!(matches!(received[1], ServerMessage::PresenceChanged { .. }));
+        match received[2] {
+            ServerMessage::PresenceThrottled { retry_after_ms } => {
+                assert!((1..=1000).contains(&retry_after_ms))
+            }
+            ref other => panic!("expected presence_throttled, got {other:?}"),
+        }
+
+        // The default cap applies without a subscription; the peer's own
+        // presence changes are not counted against it.
+        let mut own = 0;
+        let mut events = 0;
+        while let Ok(msg) = other_rx.try_recv() {
+            match msg {
+                ServerMessage::PresenceChanged { peer_code, .. } if peer_code == "OTHER" => {
+                    own += 1
+                }
+                ServerMessage::PresenceThrottled { .. } => {}
+                _ => events += 1,
+            }
+        }
+        assert_eq!(own, 3);
+        assert_eq!(events, MAX_ROOM_EVENTS_PER_SECOND as usize);
+    }
+
+    #[test]
+    fn hidden_peer_is_invisible_until_it_signals() {
+        let rm = RoomManager::new();
+        let (visible, mut visible_rx) = make_peer("SEEN", "Seen");
+        let (_, s_visible, _, _) = rm.add_peer("10.0.0.1", visible).unwrap();
+        let (mut hidden, mut hidden_rx) = make_peer("HIDDEN", "Hidden");
+        hidden.discoverable = false;
+        let (existing, s_hidden, version, _) = rm.add_peer("10.0.0.1", hidden).unwrap();
+        // The hidden peer still sees the room; the room is not told.
+        assert_eq!(existing.len(), 1);
+        assert_eq!(rm.room_size("10.0.0.1"), 1);
+        assert_eq!(version, 1);
+        assert!(visible_rx.try_recv().is_err());
+
+        assert!(rm.set_presence("10.0.0.1", "HIDDEN", s_hidden, Presence::Busy, None));
+        assert!(visible_rx.try_recv().is_err());
+        assert!(matches!(
+            hidden_rx.try_recv(),
+            Ok(ServerMessage::PresenceChanged { version: None, .. })
+        ));
+        assert!(rm.list_peers("10.0.0.1", "SEEN", s_visible, None, None, None));
+        assert!(matches!(
+            visible_rx.try_recv(),
+            Ok(ServerMessage::Peers { peers, version: Some(1), .. }) if peers.is_empty()
+        ));
+
+        // Not reachable by code until it signals first.
+        assert!(rm
+            .find_reachable_peer("10.0.0.1", s_visible, "HIDDEN")
+            .is_none());
+        assert!(rm
+            .find_reachable_peer("10.0.0.1", s_hidden, "SEEN")
+            .is_some());
+        assert!(rm
+            .find_reachable_peer("10.0.0.1", s_visible, "HIDDEN")
+            .is_some());
+        // Exact-code lookup still finds it.
+        assert!(matches!(
+            rm.find_peer_manual("HIDDEN"),
+            ManualPeerLookup::Found(..)
+        ));
+
+        rm.remove_peer("10.0.0.1", "HIDDEN", s_hidden);
+        assert!(visible_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn visible_peer_returning_hidden_leaves_the_room_view() {
+        let rm = RoomManager::new();
+        let (watcher, mut watcher_rx) = make_peer("WATCH", "Watcher");
+        rm.add_peer("10.0.0.1", watcher).unwrap();
+        let (first, _first_rx) = make_peer("AAA", "A");
+        rm.add_peer("10.0.0.1", first).unwrap();
+        while watcher_rx.try_recv().is_ok() {}
+
+        let (mut again, _again_rx) = make_peer("AAA", "A");
+        again.discoverable = false;
+        rm.add_peer("10.0.0.1", again).unwrap();
+        assert!(matches!(
+            watcher_rx.try_recv(),
+            Ok(ServerMessage::PeerLeft { peer_code, version: Some(3) }) if peer_code == "AAA"
+        ));
+        assert!(watcher_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn rotate_code_refuses_code_in_use() {
+        let rm = RoomManager::new();
+        let (p1, _r1) = make_peer("AAA", "A");
+        let (p2, _r2) = make_peer("BBB", "B");
+        let (_, s1, _, _) = rm.add_peer("10.0.0.1", p1).unwrap();
+        rm.add_peer("10.0.0.1", p2).unwrap();
+        assert!(matches!(
+            rm.rotate_code("10.0.0.1", "AAA", s1, "BBB", None),
+            Err(RoomError::CodeInUse)
+        ));
+        assert!(rm.find_peer("10.0.0.1", "AAA").is_some());
+    }
+
+    // ─── links ──────────────────────────────────────────────────────────
+
+    #[test]
+    fn linked_sessions_reach_each_other_until_removed() {
+        let rm = RoomManager::new();
+        let (p1, _r1) = make_peer("AAA", "A");
+        let (p2, mut r2) = make_peer("BBB", "B");
+        let (_, s1, _, _) = rm.add_peer("1.1.1.1", p1).unwrap();
+        let (_, s2, _, _) = rm.add_peer("2.2.2.2", p2).unwrap();
+        assert!(rm.find_reachable_peer("1.1.1.1", s1, "BBB").is_none());
+
+        let a = SessionRef {
+            room: "1.1.1.1".into(),
+            peer_code: "AAA".into(),
+            session_id: s1,
+        };
+        let b = SessionRef {
+            room: "2.2.2.2".into(),
+            peer_code: "BBB".into(),
+            session_id: s2,
+        };
+        rm.link_sessions(&a, &b);
+        let sender = rm.find_reachable_peer("1.1.1.1", s1, "BBB").unwrap();
+        sender
+            .send(ServerMessage::PeerLeft {
+                peer_code: "X".into(),
+                version: None,
+            })
+            .unwrap();
+        assert!(r2.try_recv().is_ok());
+        assert!(rm.find_reachable_peer("2.2.2.2", s2, "AAA").is_some());
+
+        rm.remove_peer("2.2.2.2", "BBB", s2);
+        assert!(rm.find_reachable_peer("1.1.1.1", s1, "BBB").is_none());
+        assert!(rm.links.is_empty());
+    }
+
+    #[test]
+    fn pair_requests_are_taken_once_and_dropped_on_disconnect() {
+        let rm = RoomManager::new();
+        let (p1, _r1) = make_peer("AAA", "A");
+        let (p2, _r2) = make_peer("BBB", "B");
+        let (_, s1, _, _) = rm.add_peer("1.1.1.1", p1).unwrap();
+        let (_, s2, _, _) = rm.add_peer("2.2.2.2", p2).unwrap();
+        let a = SessionRef {
+            room: "1.1.1.1".into(),
+            peer_code: "AAA".into(),
+            session_id: s1,
+        };
+        let b = SessionRef {
+            room: "2.2.2.2".into(),
+            peer_code: "BBB".into(),
+            session_id: s2,
+        };
+
+        assert!(rm.add_pair_request(&a, &b));
+        assert!(rm.add_pair_request(&a, &b));
+        assert_eq!(rm.take_pair_request(s2, "AAA"), Some(a.clone()));
+        assert_eq!(rm.take_pair_request(s2, "AAA"), None);
+
+        assert!(rm.add_pair_request(&a, &b));
+        rm.remove_peer("1.1.1.1", "AAA", s1);
+        assert_eq!(rm.take_pair_request(s2, "AAA"), None);
+        assert!(rm.pair_requests.is_empty());
+    }
+
+    #[test]
+    fn pair_requests_bounded_per_target() {
+        let rm = RoomManager::new();
+        let target = SessionRef {
+            room: "r".into(),
+            peer_code: "T".into(),
+            session_id: 1,
+        };
+        for i in 0..MAX_PAIR_REQUESTS_PER_PEER {
+            let requester = SessionRef {
+                room: "r".into(),
+                peer_code: format!("R{i}"),
+                session_id: 100 + i as u64,
+            };
+            assert!(rm.add_pair_request(&requester, &target));
+        }
+        let extra = SessionRef {
+            room: "r".into(),
+            peer_code: "EXTRA".into(),
+            session_id: 999,
+        };
+        assert!(!rm.add_pair_request(&extra, &target));
+    }
+
     // ─── add_peer ───────────────────────────────────────────────────────
 
     #[test]
@@ -329,7 +2145,7 @@ mod tests {
         assert!(result.is_ok());
 
         // First peer in room → existing list is empty
-        let (existing, _session_id) = result.unwrap();
+        let (existing, _session_id, _, _) = result.unwrap();
         assert!(existing.is_empty());
 
         assert_eq!(rm.room_count(), 1);
@@ -347,7 +2163,7 @@ mod tests {
         let (p2, _r2) = make_peer("BBB", "Second");
 
         rm.add_peer("10.0.0.1", p1).unwrap();
-        let (existing, _) = rm.add_peer("10.0.0.1", p2).unwrap();
+        let (existing, _, _, _) = rm.add_peer("10.0.0.1", p2).unwrap();
 
         // Second peer should see the first peer in the existing list
         assert_eq!(existing.len(), 1);
@@ -360,9 +2176,9 @@ mod tests {
         let (p1, mut rx1) = make_peer("DUP", "First");
         let (p2, _r2) = make_peer("DUP", "Second");
 
-        let (_, session1) = rm.add_peer("10.0.0.1", p1).unwrap();
+        let (_, session1, _, _) = rm.add_peer("10.0.0.1", p1).unwrap();
         // Second registration with same code replaces the first (reconnect).
-        let (_, session2) = rm.add_peer("10.0.0.1", p2).unwrap();
+        let (_, session2, _, _) = rm.add_peer("10.0.0.1", p2).unwrap();
         assert!(
             session2 > session1,
             "session IDs must be monotonically increasing"
@@ -400,8 +2216,8 @@ mod tests {
         let (p1, _r1) = make_peer("RECONNECT", "First");
         let (p2, _r2) = make_peer("RECONNECT", "Second");
 
-        let (_, session1) = rm.add_peer("10.0.0.1", p1).unwrap();
-        let (_, session2) = rm.add_peer("10.0.0.1", p2).unwrap();
+        let (_, session1, _, _) = rm.add_peer("10.0.0.1", p1).unwrap();
+        let (_, session2, _, _) = rm.add_peer("10.0.0.1", p2).unwrap();
         assert_eq!(rm.peer_count(), 1);
 
         // Old session's cleanup fires — must NOT remove the replacement.
@@ -428,7 +2244,7 @@ mod tests {
         // FIRST should have received a PeerJoined for SECOND
         let msg = rx1.try_recv().expect("should have received PeerJoined");
         match msg {
-            ServerMessage::PeerJoined { peer } => {
+            ServerMessage::PeerJoined { peer, .. } => {
                 assert_eq!(peer.peer_code, "SECOND");
             }
             other => panic!(
@@ -446,7 +2262,7 @@ mod tests {
         let (p1, _r1) = make_peer("RM1", "Device 1");
         let (p2, _r2) = make_peer("RM2", "Device 2");
 
-        let (_, s1) = rm.add_peer("10.0.0.1", p1).unwrap();
+        let (_, s1, _, _) = rm.add_peer("10.0.0.1", p1).unwrap();
         rm.add_peer("10.0.0.1", p2).unwrap();
         assert_eq!(rm.peer_count(), 2);
 
@@ -463,7 +2279,7 @@ mod tests {
         let rm = RoomManager::new();
         let (p1, _r1) = make_peer("SOLO", "Only One");
 
-        let (_, s1) = rm.add_peer("10.0.0.1", p1).unwrap();
+        let (_, s1, _, _) = rm.add_peer("10.0.0.1", p1).unwrap();
         assert_eq!(rm.room_count(), 1);
 
         rm.remove_peer("10.0.0.1", "SOLO", s1);
@@ -479,7 +2295,7 @@ mod tests {
         let (leave, _leave_rx) = make_peer("LEAVE", "Leaver");
 
         rm.add_peer("10.0.0.1", stay).unwrap();
-        let (_, s_leave) = rm.add_peer("10.0.0.1", leave).unwrap();
+        let (_, s_leave, _, _) = rm.add_peer("10.0.0.1", leave).unwrap();
 
         // Drain PeerJoined from STAY's channel
         let _ = stay_rx.try_recv();
@@ -488,7 +2304,7 @@ mod tests {
 
         let msg = stay_rx.try_recv().expect("should have received PeerLeft");
         match msg {
-            ServerMessage::PeerLeft { peer_code } => {
+            ServerMessage::PeerLeft { peer_code, .. } => {
                 assert_eq!(peer_code, "LEAVE");
             }
             other => panic!(
@@ -498,6 +2314,146 @@ mod tests {
         }
     }
 
+    // ─── update_peer ────────────────────────────────────────────────────
+
+    #[test]
+    fn update_peer_changes_metadata_and_broadcasts() {
+        let rm = RoomManager::new();
+        let (other, mut other_rx) = make_peer("OTHER", "Other");
+        let (me, mut me_rx) = make_peer("ME", "Old Name");
+        rm.add_peer("10.0.0.1", other).unwrap();
+        let (_, session, _, _) = rm.add_peer("10.0.0.1", me).unwrap();
+        let _ = other_rx.try_recv(); // PeerJoined
+
+        let update = PeerUpdate {
+            device_name: Some("New Name".into()),
+            wt_url: Some(Some("https://10.0.0.1:9948".into())),
+            ..Default::default()
+        };
+        let data = rm.update_peer("10.0.0.1", "ME", session, update).unwrap();
+        assert_eq!(data.device_name, "New Name");
+        assert_eq!(data.wt_url.as_deref(), Some("https://10.0.0.1:9948"));
+        // The WebTransport fields are mirrored into metadata.
+        assert_eq!(
+            data.metadata.get(WT_URL_KEY).map(String::as_str),
+            Some("https://10.0.0.1:9948")
+        );
+
+        let version = match other_rx
+            .try_recv()
+            .expect("should have received PeerUpdated")
+        {
+            ServerMessage::PeerUpdated { peer, version } => {
+                assert_eq!(peer.device_name, "New Name");
+                version
+            }
+            other => panic!("expected PeerUpdated, got {other:?}"),
+        };
+        // The sender gets the same version, so it sees no gap.
+        assert!(matches!(
+            me_rx.try_recv(),
+            Ok(ServerMessage::PeerUpdated { version: v, .. }) if v == version
+        ));
+        assert_eq!(rm.get_room_peers("10.0.0.1")[1].device_name, "New Name");
+        // No leave/join churn.
+        assert_eq!(rm.peer_count(), 2);
+    }
+
+    #[test]
+    fn update_peer_without_changes_is_silent() {
+        let rm = RoomManager::new();
+        let (other, mut other_rx) = make_peer("OTHER", "Other");
+        let (me, _me_rx) = make_peer("ME", "Name");
+        rm.add_peer("10.0.0.1", other).unwrap();
+        let (_, session, _, _) = rm.add_peer("10.0.0.1", me).unwrap();
+        let _ = other_rx.try_recv();
+
+        let update = PeerUpdate {
+            device_name: Some("Name".into()),
+            ..Default::default()
+        };
+        assert!(rm.update_peer("10.0.0.1", "ME", session, update).is_some());
+        assert!(other_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn update_peer_ignores_stale_session() {
+        let rm = RoomManager::new();
+        let (old, _r1) = make_peer("ME", "Old");
+        let (new, _r2) = make_peer("ME", "Replacement");
+        let (_, old_session, _, _) = rm.add_peer("10.0.0.1", old).unwrap();
+        rm.add_peer("10.0.0.1", new).unwrap();
+
+        let update = PeerUpdate {
+            device_name: Some("Hijack".into()),
+            ..Default::default()
+        };
+        assert!(rm
+            .update_peer("10.0.0.1", "ME", old_session, update)
+            .is_none());
+        assert_eq!(rm.get_room_peers("10.0.0.1")[0].device_name, "Replacement");
+    }
+
+    // ─── set_presence ───────────────────────────────────────────────────
+
+    #[test]
+    fn set_presence_broadcasts_to_whole_room() {
+        let rm = RoomManager::new();
+        let (other, mut other_rx) = make_peer("OTHER", "Other");
+        let (me, mut me_rx) = make_peer("ME", "Me");
+        rm.add_peer("10.0.0.1", other).unwrap();
+        let (_, session, _, _) = rm.add_peer("10.0.0.1", me).unwrap();
+        let _ = other_rx.try_recv(); // PeerJoined
+
+        assert!(rm.set_presence("10.0.0.1", "ME", session, Presence::Busy, None));
+        for rx in [&mut other_rx, &mut me_rx] {
+            match rx.try_recv().expect("should have received PresenceChanged") {
+                ServerMessage::PresenceChanged {
+                    peer_code,
+                    presence,
+                    automatic,
+                    ..
+                } => {
+                    assert_eq!(peer_code, "ME");
+                    assert_eq!(presence, Presence::Busy);
+                    assert!(!automatic);
+                }
+                other => panic!("expected PresenceChanged, got {other:?}"),
+            }
+        }
+        assert_eq!(rm.get_room_peers("10.0.0.1")[1].presence, Presence::Busy);
+
+        // Setting the same state again is a no-op.
+        assert!(!rm.set_presence("10.0.0.1", "ME", session, Presence::Busy, None));
+        assert!(other_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn automatic_presence_never_overrides_chosen_state() {
+        let rm = RoomManager::new();
+        let (me, _rx) = make_peer("ME", "Me");
+        let (_, session, _, _) = rm.add_peer("10.0.0.1", me).unwrap();
+
+        rm.set_presence("10.0.0.1", "ME", session, Presence::Busy, None);
+        let auto_away = Some(Presence::Available);
+        assert!(!rm.set_presence("10.0.0.1", "ME", session, Presence::Away, auto_away));
+        assert_eq!(rm.get_room_peers("10.0.0.1")[0].presence, Presence::Busy);
+
+        rm.set_presence("10.0.0.1", "ME", session, Presence::Available, None);
+        assert!(rm.set_presence("10.0.0.1", "ME", session, Presence::Away, auto_away));
+        assert_eq!(rm.get_room_peers("10.0.0.1")[0].presence, Presence::Away);
+    }
+
+    #[test]
+    fn set_presence_ignores_stale_session() {
+        let rm = RoomManager::new();
+        let (old, _r1) = make_peer("ME", "Old");
+        let (new, _r2) = make_peer("ME", "New");
+        let (_, old_session, _, _) = rm.add_peer("10.0.0.1", old).unwrap();
+        rm.add_peer("10.0.0.1", new).unwrap();
+        assert!(!rm.set_presence("10.0.0.1", "ME", old_session, Presence::Away, None));
+    }
+
     #[test]
     fn remove_peer_nonexistent_does_not_panic() {
         let rm = RoomManager::new();
@@ -579,7 +2535,7 @@ mod tests {
 
         assert!(matches!(
             rm.find_peer_manual("MANUAL"),
-            ManualPeerLookup::Found(_)
+            ManualPeerLookup::Found(_, session) if session.room == "10.0.0.2"
         ));
     }
 
@@ -615,7 +2571,7 @@ mod tests {
         let (pa, _ra) = make_peer("PEERA", "Device A");
         let (pb, _rb) = make_peer("PEERB", "Device B");
 
-        let (_, sa) = rm.add_peer("10.0.0.1", pa).unwrap();
+        let (_, sa, _, _) = rm.add_peer("10.0.0.1", pa).unwrap();
         rm.add_peer("10.0.0.1", pb).unwrap();
         assert_eq!(rm.peer_count(), 2);
 
@@ -642,8 +2598,8 @@ mod tests {
         let (p2, _r2) = make_peer("R1B", "Room1 B");
         let (p3, _r3) = make_peer("R2A", "Room2 A");
 
-        let (_, s1a) = rm.add_peer("10.0.0.1", p1).unwrap();
-        let (_, s1b) = rm.add_peer("10.0.0.1", p2).unwrap();
+        let (_, s1a, _, _) = rm.add_peer("10.0.0.1", p1).unwrap();
+        let (_, s1b, _, _) = rm.add_peer("10.0.0.1", p2).unwrap();
         rm.add_peer("10.0.0.2", p3).unwrap();
 
         assert_eq!(rm.room_count(), 2);
@@ -715,7 +2671,10 @@ mod tests {
             result.is_err(),
             "peer beyond MAX_PEERS_PER_ROOM must be rejected"
         );
-        assert!(result.unwrap_err().contains("room full"));
+        let err = result.unwrap_err();
+        assert_eq!(err, RoomError::RoomFull);
+        assert!(err.to_string().contains("room full"));
+        assert_eq!(err.code(), ErrorCode::RoomFull);
     }
 
     #[test]
@@ -770,8 +2729,8 @@ mod tests {
         assert_eq!(rm.rooms.len(), test_limit);
 
         // For the actual MAX_ROOMS enforcement, verify the constant is reasonable
-        assert!(MAX_ROOMS >= 1024, "MAX_ROOMS must be at least 1024");
-        assert!(MAX_ROOMS <= 1_000_000, "MAX_ROOMS must not be excessive");
+        const { assert!(MAX_ROOMS >= 1024, "MAX_ROOMS must be at least 1024") };
+        const { assert!(MAX_ROOMS <= 1_000_000, "MAX_ROOMS must not be excessive") };
     }
 
     #[test]
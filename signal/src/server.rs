@@ -1,10 +1,148 @@
 //! WebSocket connection handling for the signaling server.
 //!
 //! Each incoming TCP connection is upgraded to a WebSocket. The first message
-//! must be a `register` command; subsequent messages are either `signal` relays
-//! or invalid (producing an error response). Peer cleanup on disconnect is
+//! must be a `register` command; subsequent messages are `signal` relays,
+//! metadata `update`s, keepalive `ping`s, or invalid (producing an error
+//! response). Peer cleanup on disconnect is
 //! handled automatically.
 //!
+//! ## Version Negotiation
+//!
+//! Clients may offer `bolt-rendezvous.v{N}` in `Sec-WebSocket-Protocol`; the
+//! highest supported offer is echoed back, and an upgrade offering only
+//! unsupported versions is refused with HTTP 400. `Register.protocol_version`
+//! declares the same thing in-band. Once registered, the peer receives
+//! `server_info` (version, instance id, effective limits) before `peers`.
+//!
+//! ## Encodings
+//!
+//! Offering `bolt-rendezvous.v{N}.cbor` switches the connection's outbound
+//! messages to CBOR binary frames and allows CBOR binary frames inbound. Text
+//! frames are always parsed as JSON, and binary frames on a JSON connection
+//! are rejected. Relays go through typed [`ServerMessage`] values, so peers
+//! using different encodings can signal each other.
+//!
+//! ## Message Ids
+//!
+//! Any client message may carry an `id`. Errors caused by that message echo
+//! it, including rate-limit and parse errors when the id can still be read
+//! from the frame. `signal`/`manual_signal` with `ack: true` are answered with
+//! `signal_delivered` once the payload is queued on the target's connection.
+//!
+//! ## Presence
+//!
+//! Peers start `available` and change state with `set_presence`; the room
+//! (including the peer) receives `presence_changed`. When
+//! [`ConnectionConfig::auto_away`] is set, an `available` peer that sends no
+//! message other than `ping` for that long is marked `away` automatically
+//! and returns to `available` on its next message.
+//!
+//! ## Hidden Peers
+//!
+//! `register` with `discoverable: false` joins a room without announcing
+//! it: the peer receives the room's `peers` and events, but is left out of
+//! other members' lists and events and cannot be found by code in the room.
+//! It is reachable through `manual_signal` after pairing, and by any room
+//! member it signals first (the two sessions are linked).
+//!
+//! ## Room Versions
+//!
+//! Each room counts its announced changes; `peer_joined`, `peer_left`,
+//! `peer_updated` and `presence_changed` carry the resulting `version`, and
+//! `peers` carries the version it reflects. `list_peers` with `since` gets
+//! the net changes as `peers_delta` while the room's change log (see
+//! [`MAX_ROOM_CHANGES`](crate::room::MAX_ROOM_CHANGES)) reaches back that
+//! far, and a `peers` snapshot otherwise.
+//!
+//! ## Subscriptions
+//!
+//! A peer's `subscription` (from `register` or `subscribe`) filters which
+//! peers its `peers`, `peers_delta` and room events mention, and may page
+//! snapshots by peer code (`next`/`after`, at most [`MAX_PAGE_SIZE`]).
+//! Room events to each peer are capped at
+//! [`MAX_ROOM_EVENTS_PER_SECOND`]; the first event dropped in a second is
+//! replaced by `presence_throttled`, and the peer catches up with
+//! `list_peers`.
+//!
+//! ## PIN Matching
+//!
+//! `match_pin` pairs two registered peers that enter the same short PIN
+//! within [`PIN_TTL`](crate::pin::PIN_TTL); see [`crate::pin`] for the
+//! attempt limits. Matched sessions are linked so `signal` reaches across
+//! rooms until either side disconnects.
+//!
+//! ## Manual Pairing
+//!
+//! `manual_signal` is relayed only between linked sessions: the target must
+//! have answered the sender's `pair_request` with `pair_accept` (or the two
+//! matched a PIN). Links end when either session disconnects.
+//!
+//! ## Leave and Code Rotation
+//!
+//! `leave` removes the peer from its room and returns the connection to the
+//! registration phase; a new `register` must follow within [`IDLE_TIMEOUT`].
+//! `rotate_code` swaps the peer code in place (see
+//! [`RoomManager::rotate_code`](crate::room::RoomManager::rotate_code)).
+//!
+//! ## Multiple Identities
+//!
+//! A registered connection may send further `register` messages, each adding
+//! an identity with its own peer code, room and presence (up to
+//! [`MAX_IDENTITIES_PER_CONNECTION`]). Messages pick the acting identity with
+//! `as`, defaulting to the first; relayed `signal` and `pair_request` name the
+//! receiving identity in `to`. Room events are delivered once per identity in
+//! the room. Disconnecting removes every identity.
+//!
+//! ## Ping
+//!
+//! Every `ping`, including one sent before `register`, is answered with a
+//! `pong` echoing its `nonce` and carrying the server's wall-clock time and
+//! milliseconds since [`ConnectionConfig::started`].
+//!
+//! ## Handshake Deadlines
+//!
+//! Each stage before registration is bounded by a [`ConnectionConfig`]
+//! deadline, and each expiry closes differently: no bytes within
+//! `peek_timeout` gets `408 Request Timeout`, an upgrade slower than
+//! `upgrade_timeout` gets `408` with an `upgrade_timeout` [`HttpRejection`]
+//! (see [`reject_connection`]), and no `register` within
+//! `register_timeout` of the upgrade gets a `registration_timeout` error
+//! before the close.
+//!
+//! ## Per-IP Limits
+//!
+//! [`IpLimiter`] caps concurrent connections per client IP: at accept time
+//! in [`SignalingServer`](crate::SignalingServer) for public sources, or in
+//! the handshake callback for trusted proxies and private addresses (see
+//! [`ConnectionConfig::may_forward`]), which are charged to the client IP
+//! they forward instead of their own. Each `register`, added identity and
+//! `rotate_code` counts against the client IP's per-minute budget. An
+//! exhausted budget is answered with `rate_limited` and a
+//! `retry_after_ms` hint; the connection stays open.
+//!
+//! Connections refused before the upgrade (per-IP cap here, the global
+//! limit in [`SignalingServer`](crate::SignalingServer)) get an HTTP status
+//! with `Retry-After` and an [`HttpRejection`] body; see
+//! [`reject_connection`].
+//!
+//! ## Origin Checks
+//!
+//! With an allowlist in [`ConnectionConfig::origin_policy`], the handshake
+//! callback refuses browsers whose `Origin` matches no entry with `403` and
+//! an `origin_not_allowed` [`HttpRejection`], before any per-IP slot is
+//! taken. `null` origins and handshakes without `Origin` (native clients)
+//! are decided by separate switches on [`OriginPolicy`]. A 403 carries no
+//! `Retry-After`: retrying will not help.
+//!
+//! ## Room Diagnostics
+//!
+//! After `peers` (and after each `identity_added`) the server sends
+//! `room_info`: the room's [`RoomKind`], a salted fingerprint of the room key
+//! (see [`RoomManager::fingerprint`]), its size (discoverable peers only),
+//! and which [`IpSource`] chose the client IP. Plain HTTP `GET /whoami`
+//! answers with the same [`RoomInfo`] as JSON for the IP room the caller
+//! would join; any other plain HTTP request gets the health check body.
+//!
 //! ## Trust Boundary Limits (Phase 6A)
 //!
 //! All incoming data is untrusted. The following limits are enforced:
@@ -13,7 +151,16 @@
 //! |-------|-------|-------|
 //! | `MAX_MESSAGE_BYTES` | 1 MiB | Per WebSocket message (text + binary) |
 //! | `MAX_DEVICE_NAME_BYTES` | 256 | `Register.device_name` field |
+//! | `MAX_DEVICE_TYPE_BYTES` | 32 | Unrecognised `Register.device_type` values |
 //! | `MAX_PEER_CODE_BYTES` | 16 | `Register.peer_code` and `Signal.to` fields |
+//! | `MAX_MESSAGE_ID_BYTES` | 64 | Client-supplied message `id` and `ping` `nonce` |
+//! | `MAX_MULTICAST_TARGETS` | 16 | `MulticastSignal.to` entries |
+//! | `MAX_CAPABILITIES` × `MAX_CAPABILITY_BYTES` | 32 × 32 | `capabilities` tokens (`a-z`, `0-9`, `.`, `_`, `-`) |
+//! | `MAX_METADATA_ENTRIES` | 16 | `metadata` entries (keys like capabilities, max `MAX_METADATA_KEY_BYTES` = 32) |
+//! | `MAX_METADATA_VALUE_BYTES` | 256 | Each `metadata` value |
+//! | `MAX_IDENTITIES_PER_CONNECTION` | 8 | `register` messages held by one connection |
+//! | `MAX_ROOM_NAME_BYTES` | 64 | `Register.room` (`a-z`, `0-9`, `_`, `-`) |
+//! | `MIN_ROOM_SECRET_BYTES`..`MAX_ROOM_SECRET_BYTES` | 8..256 | `Register.room_secret` |
 //! | `RATE_LIMIT_PER_SECOND` | 50 | Per-connection message rate |
 //! | `RATE_LIMIT_CLOSE_THRESHOLD` | 3 | Consecutive violations before socket close |
 //!
@@ -21,20 +168,34 @@
 //! first-line defense. Application-level `validate_message_size()` provides
 //! defense-in-depth.
 
+use std::collections::BTreeMap;
 use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 use futures_util::{SinkExt, StreamExt};
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio::sync::mpsc;
+use tokio::time::Instant;
 use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::{header, HeaderValue, StatusCode};
 use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
 use tokio_tungstenite::tungstenite::Message;
 use tracing::{debug, error, info, warn};
 
-use crate::protocol::{ClientMessage, ServerMessage};
-use crate::room::{ManualPeerLookup, PeerInfo, RoomManager};
+use crate::ip_limit::IpLimiter;
+use crate::origin::OriginPolicy;
+use crate::pin::{PinEntrant, PinError, PinMatcher, PinOutcome, MAX_PIN_ATTEMPTS_PER_CONNECTION};
+use crate::protocol::{
+    parse_subprotocol, ClientMessage, CodecError, DeliveryResult, DeviceType, Encoding, ErrorCode,
+    HttpRejection, IpSource, Presence, RejectionReason, RoomInfo, RoomKind, ServerLimits,
+    ServerMessage, Subscription, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+use crate::room::{
+    take_wt_aliases, EventBudget, ManualPeerLookup, PeerInfo, PeerUpdate, RoomError, RoomManager,
+    SessionRef, MAX_ROOM_EVENTS_PER_SECOND,
+};
 
 // ── Trust Boundary Constants ────────────────────────────────────────────
 
@@ -45,9 +206,54 @@ pub const MAX_MESSAGE_BYTES: usize = 1_048_576;
 /// Maximum length of `Register.device_name` in bytes.
 pub const MAX_DEVICE_NAME_BYTES: usize = 256;
 
+/// Maximum length of an unrecognised `Register.device_type` value in bytes.
+/// Unknown kinds are relayed verbatim, so they are restricted to short
+/// lowercase tokens (`a-z`, `0-9`, `_`, `-`).
+pub const MAX_DEVICE_TYPE_BYTES: usize = 32;
+
+/// Maximum length of a named room (`Register.room`) in bytes.
+pub const MAX_ROOM_NAME_BYTES: usize = 64;
+
+/// Minimum length of a named room secret (`Register.room_secret`) in bytes.
+pub const MIN_ROOM_SECRET_BYTES: usize = 8;
+
+/// Maximum length of a named room secret (`Register.room_secret`) in bytes.
+pub const MAX_ROOM_SECRET_BYTES: usize = 256;
+
 /// Maximum length of peer code fields (`Register.peer_code`, `Signal.to`).
 pub const MAX_PEER_CODE_BYTES: usize = 16;
 
+/// Maximum length of a client-supplied message `id` in bytes.
+pub const MAX_MESSAGE_ID_BYTES: usize = 64;
+
+/// Maximum number of targets in one `MulticastSignal`.
+pub const MAX_MULTICAST_TARGETS: usize = 16;
+
+/// Maximum number of `capabilities` a peer may advertise.
+pub const MAX_CAPABILITIES: usize = 32;
+
+/// Maximum length of one capability token in bytes.
+pub const MAX_CAPABILITY_BYTES: usize = 32;
+
+/// Maximum number of `metadata` entries a peer may advertise.
+pub const MAX_METADATA_ENTRIES: usize = 16;
+
+/// Maximum length of a `metadata` key in bytes.
+pub const MAX_METADATA_KEY_BYTES: usize = 32;
+
+/// Maximum length of a `metadata` value in bytes.
+pub const MAX_METADATA_VALUE_BYTES: usize = 256;
+
+/// Maximum number of device types in a presence subscription filter.
+pub const MAX_SUBSCRIPTION_DEVICE_TYPES: usize = 8;
+
+/// Maximum `page_size` of a presence subscription: the most peers sent in
+/// one `peers` message when a client asks for paging.
+pub const MAX_PAGE_SIZE: u32 = 256;
+
+/// Maximum number of identities (peer codes) registered over one connection.
+pub const MAX_IDENTITIES_PER_CONNECTION: usize = 8;
+
 /// Maximum messages per second per connection.
 pub const RATE_LIMIT_PER_SECOND: u32 = 50;
 
@@ -60,6 +266,155 @@ pub const RATE_LIMIT_CLOSE_THRESHOLD: u32 = 3;
 /// clients send periodic pings or signals well within this window.
 pub const IDLE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(300);
 
+/// Default deadline for a new TCP connection's first bytes (the HTTP request
+/// that [`handle_connection`] peeks at). Expiry answers `408 Request Timeout`.
+pub const DEFAULT_PEEK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Default deadline for the WebSocket upgrade handshake once the request has
+/// started to arrive. Expiry answers `408 Request Timeout` with an
+/// `upgrade_timeout` body, or drops the socket if the handshake had already
+/// started its own response.
+pub const DEFAULT_UPGRADE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Default deadline from the upgrade to the first successful `register`.
+/// Expiry sends a `registration_timeout` error and closes the connection.
+pub const DEFAULT_REGISTER_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Upper bound for the automatic-away quiet period, so a peer is shown
+/// `away` well before [`IDLE_TIMEOUT`] would disconnect it.
+pub const MAX_AUTO_AWAY: std::time::Duration = std::time::Duration::from_secs(150);
+
+// ── Connection Config ───────────────────────────────────────────────────
+
+/// Server-wide settings shared by every connection handler.
+#[derive(Debug, Clone)]
+pub struct ConnectionConfig {
+    /// Proxy addresses whose `X-Forwarded-For` headers are honored.
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Which `Origin` headers may open a WebSocket.
+    pub origin_policy: OriginPolicy,
+    /// Random identifier of this server process, reported in `server_info`.
+    pub instance_id: String,
+    /// Quiet period after which an `available` peer is marked `away`.
+    /// `None` disables automatic away. See [`MAX_AUTO_AWAY`].
+    pub auto_away: Option<std::time::Duration>,
+    /// Origin of the monotonic clock reported in `pong`.
+    pub started: Instant,
+    /// See [`DEFAULT_PEEK_TIMEOUT`].
+    pub peek_timeout: std::time::Duration,
+    /// See [`DEFAULT_UPGRADE_TIMEOUT`].
+    pub upgrade_timeout: std::time::Duration,
+    /// See [`DEFAULT_REGISTER_TIMEOUT`].
+    pub register_timeout: std::time::Duration,
+}
+
+impl ConnectionConfig {
+    /// Whether a connection from `ip` may carry a forwarded client IP that
+    /// [`resolve_client_ip`] would honour.
+    pub fn may_forward(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.contains(&ip) || is_private_ip(&ip.to_string())
+    }
+
+    /// Create a config with no trusted proxies, a fresh instance id,
+    /// automatic away disabled and the default handshake deadlines.
+    pub fn new() -> Self {
+        Self {
+            trusted_proxies: Vec::new(),
+            origin_policy: OriginPolicy::any(),
+            instance_id: uuid::Uuid::new_v4().to_string(),
+            auto_away: None,
+            started: Instant::now(),
+            peek_timeout: DEFAULT_PEEK_TIMEOUT,
+            upgrade_timeout: DEFAULT_UPGRADE_TIMEOUT,
+            register_timeout: DEFAULT_REGISTER_TIMEOUT,
+        }
+    }
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Effective limits advertised to clients in `server_info`.
+pub fn server_limits(config: &ConnectionConfig) -> ServerLimits {
+    ServerLimits {
+        max_message_bytes: MAX_MESSAGE_BYTES as u64,
+        rate_limit_per_second: RATE_LIMIT_PER_SECOND,
+        idle_timeout_secs: IDLE_TIMEOUT.as_secs(),
+        max_peer_code_bytes: MAX_PEER_CODE_BYTES as u32,
+        max_device_name_bytes: MAX_DEVICE_NAME_BYTES as u32,
+        max_message_id_bytes: MAX_MESSAGE_ID_BYTES as u32,
+        max_multicast_targets: MAX_MULTICAST_TARGETS as u32,
+        max_identities: MAX_IDENTITIES_PER_CONNECTION as u32,
+        max_capabilities: MAX_CAPABILITIES as u32,
+        max_metadata_entries: MAX_METADATA_ENTRIES as u32,
+        max_metadata_value_bytes: MAX_METADATA_VALUE_BYTES as u32,
+        max_page_size: MAX_PAGE_SIZE,
+        max_room_events_per_sec: MAX_ROOM_EVENTS_PER_SECOND,
+        auto_away_secs: config.auto_away.map(|d| d.as_secs()),
+    }
+}
+
+// ── Version Negotiation (pure, testable) ────────────────────────────────
+
+/// Pick a protocol version and encoding from a `Sec-WebSocket-Protocol`
+/// header value.
+///
+/// Returns `Ok(None)` when the client offered no `bolt-rendezvous.v{N}`
+/// tokens, `Ok(Some(..))` for the highest supported version (using the
+/// client's first-listed encoding for that version), and `Err` when bolt
+/// tokens were offered but none is supported.
+///
+/// The chosen token is returned exactly as offered: RFC 6455 requires the
+/// response to echo one of the client's values, and aliases such as
+/// `bolt-rendezvous.v1.json` differ from the canonical
+/// [`subprotocol`](crate::protocol::subprotocol).
+pub fn select_subprotocol(offered: &str) -> Result<Option<(u32, Encoding, &str)>, String> {
+    let offers: Vec<(u32, Encoding, &str)> = offered
+        .split(',')
+        .map(str::trim)
+        .filter_map(|token| parse_subprotocol(token).map(|(v, e)| (v, e, token)))
+        .collect();
+    if offers.is_empty() {
+        return Ok(None);
+    }
+    let supported = |v: &u32| (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(v);
+    let Some(best) = offers.iter().map(|(v, ..)| *v).filter(supported).max() else {
+        let versions: Vec<u32> = offers.iter().map(|(v, ..)| *v).collect();
+        return Err(unsupported_version_message(&versions));
+    };
+    Ok(offers.into_iter().find(|(v, ..)| *v == best))
+}
+
+/// Resolve the effective protocol version from the negotiated subprotocol
+/// (if any) and the version declared in `Register` (if any).
+pub fn resolve_protocol_version(
+    negotiated: Option<u32>,
+    declared: Option<u32>,
+) -> Result<u32, String> {
+    match (negotiated, declared) {
+        (Some(n), Some(d)) if n != d => Err(format!(
+            "unsupported_protocol_version: protocol_version {d} does not match negotiated subprotocol version {n}"
+        )),
+        (Some(n), _) => Ok(n),
+        (None, Some(d)) if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&d) => {
+            Err(unsupported_version_message(&[d]))
+        }
+        (None, Some(d)) => Ok(d),
+        (None, None) => Ok(MIN_PROTOCOL_VERSION),
+    }
+}
+
+fn unsupported_version_message(offered: &[u32]) -> String {
+    let offered: Vec<String> = offered.iter().map(u32::to_string).collect();
+    format!(
+        "unsupported_protocol_version: offered {}, server supports {MIN_PROTOCOL_VERSION}..={PROTOCOL_VERSION}",
+        offered.join(", ")
+    )
+}
+
 // ── Validation Helpers (pure, testable) ─────────────────────────────────
 
 /// Reject messages exceeding `MAX_MESSAGE_BYTES`.
@@ -72,6 +427,117 @@ pub fn validate_message_size(len: usize) -> Result<(), String> {
     Ok(())
 }
 
+/// Validate the length of a client-supplied message `id`.
+pub fn validate_message_id(id: Option<&str>) -> Result<(), String> {
+    match id {
+        Some(id) if id.len() > MAX_MESSAGE_ID_BYTES => Err(format!(
+            "id too long ({} bytes, max {MAX_MESSAGE_ID_BYTES})",
+            id.len()
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Validate an unrecognised device type. Known kinds always pass.
+pub fn validate_device_type(device_type: &DeviceType) -> Result<(), String> {
+    let DeviceType::Unknown(kind) = device_type else {
+        return Ok(());
+    };
+    if kind.is_empty() || kind.len() > MAX_DEVICE_TYPE_BYTES {
+        return Err(format!(
+            "device_type must be 1..={MAX_DEVICE_TYPE_BYTES} bytes"
+        ));
+    }
+    if !kind
+        .bytes()
+        .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_' || b == b'-')
+    {
+        return Err("device_type may only contain a-z, 0-9, '_' and '-'".to_string());
+    }
+    Ok(())
+}
+
+/// Whether `token` is a valid capability or metadata key: 1..=`max` bytes of
+/// `a-z`, `0-9`, `.`, `_` and `-`.
+fn is_feature_token(token: &str, max: usize) -> bool {
+    !token.is_empty()
+        && token.len() <= max
+        && token.bytes().all(|b| {
+            b.is_ascii_lowercase() || b.is_ascii_digit() || matches!(b, b'.' | b'_' | b'-')
+        })
+}
+
+/// Validate a `capabilities` list: at most [`MAX_CAPABILITIES`] distinct
+/// tokens.
+pub fn validate_capabilities(capabilities: &[String]) -> Result<(), String> {
+    if capabilities.len() > MAX_CAPABILITIES {
+        return Err(format!("at most {MAX_CAPABILITIES} capabilities"));
+    }
+    for (i, capability) in capabilities.iter().enumerate() {
+        if !is_feature_token(capability, MAX_CAPABILITY_BYTES) {
+            return Err(format!(
+                "capability must be 1-{MAX_CAPABILITY_BYTES} bytes of a-z, 0-9, '.', '_' or '-'"
+            ));
+        }
+        if capabilities[..i].contains(capability) {
+            return Err(format!("duplicate capability '{capability}'"));
+        }
+    }
+    Ok(())
+}
+
+/// Validate a presence subscription: its capabilities like
+/// [`validate_capabilities`], at most [`MAX_SUBSCRIPTION_DEVICE_TYPES`]
+/// device types, a page size of 1..=[`MAX_PAGE_SIZE`] and an event rate of
+/// 1..=[`MAX_ROOM_EVENTS_PER_SECOND`].
+pub fn validate_subscription(subscription: &Subscription) -> Result<(), String> {
+    validate_capabilities(&subscription.capabilities)?;
+    if subscription.device_types.len() > MAX_SUBSCRIPTION_DEVICE_TYPES {
+        return Err(format!(
+            "at most {MAX_SUBSCRIPTION_DEVICE_TYPES} device types"
+        ));
+    }
+    for device_type in &subscription.device_types {
+        validate_device_type(device_type)?;
+    }
+    if subscription
+        .page_size
+        .is_some_and(|n| n == 0 || n > MAX_PAGE_SIZE)
+    {
+        return Err(format!("page_size must be 1-{MAX_PAGE_SIZE}"));
+    }
+    if subscription
+        .max_events_per_sec
+        .is_some_and(|n| n == 0 || n > MAX_ROOM_EVENTS_PER_SECOND)
+    {
+        return Err(format!(
+            "max_events_per_sec must be 1-{MAX_ROOM_EVENTS_PER_SECOND}"
+        ));
+    }
+    Ok(())
+}
+
+/// Validate a `metadata` map: entry count, key spelling and value size.
+pub fn validate_metadata(metadata: &BTreeMap<String, String>) -> Result<(), String> {
+    if metadata.len() > MAX_METADATA_ENTRIES {
+        return Err(format!("at most {MAX_METADATA_ENTRIES} metadata entries"));
+    }
+    for (key, value) in metadata {
+        if !is_feature_token(key, MAX_METADATA_KEY_BYTES) {
+            return Err(format!(
+                "metadata key must be 1-{MAX_METADATA_KEY_BYTES} bytes of a-z, 0-9, '.', '_' or '-'"
+            ));
+        }
+        if value.len() > MAX_METADATA_VALUE_BYTES {
+            return Err(format!(
+                "metadata '{key}' too long ({} bytes, max {MAX_METADATA_VALUE_BYTES})",
+                value.len()
+            ));
+        }
+    }
+    Ok(())
+}
+
 /// Validate device name length.
 pub fn validate_device_name(name: &str) -> Result<(), String> {
     if name.len() > MAX_DEVICE_NAME_BYTES {
@@ -83,6 +549,57 @@ pub fn validate_device_name(name: &str) -> Result<(), String> {
     Ok(())
 }
 
+/// Validate the named room fields of `Register`.
+///
+/// Returns the lowercased room name and the secret, `None` for the IP room,
+/// or the offending field name with an error.
+pub fn validate_room(
+    room: Option<&str>,
+    secret: Option<&str>,
+) -> Result<Option<(String, String)>, (&'static str, String)> {
+    let (room, secret) = match (room, secret) {
+        (None, None) => return Ok(None),
+        (Some(room), Some(secret)) => (room, secret),
+        (Some(_), None) => return Err(("room_secret", "room_secret is required with room".into())),
+        (None, Some(_)) => return Err(("room", "room is required with room_secret".into())),
+    };
+    let name = room.to_ascii_lowercase();
+    if name.is_empty() || name.len() > MAX_ROOM_NAME_BYTES {
+        return Err((
+            "room",
+            format!("room must be 1-{MAX_ROOM_NAME_BYTES} bytes"),
+        ));
+    }
+    if !name
+        .bytes()
+        .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_' || b == b'-')
+    {
+        return Err(("room", "room must be a-z, 0-9, '_' or '-'".into()));
+    }
+    if secret.len() < MIN_ROOM_SECRET_BYTES || secret.len() > MAX_ROOM_SECRET_BYTES {
+        return Err((
+            "room_secret",
+            format!("room_secret must be {MIN_ROOM_SECRET_BYTES}-{MAX_ROOM_SECRET_BYTES} bytes"),
+        ));
+    }
+    Ok(Some((name, secret.to_string())))
+}
+
+/// Validate the size of a `MulticastSignal.to` list. Individual codes are
+/// checked per target by [`relay_multicast`].
+pub fn validate_multicast_targets(to: &[String]) -> Result<(), String> {
+    if to.is_empty() {
+        return Err("multicast target list cannot be empty".to_string());
+    }
+    if to.len() > MAX_MULTICAST_TARGETS {
+        return Err(format!(
+            "too many multicast targets ({}, max {MAX_MULTICAST_TARGETS})",
+            to.len()
+        ));
+    }
+    Ok(())
+}
+
 /// Normalize and validate a signal target peer code (`Signal.to`).
 /// Same rules as `validate_peer_code`: strip hyphens, non-empty, max 16 chars,
 /// ASCII alphanumeric. Returns the normalized code on success.
@@ -157,6 +674,14 @@ impl RateLimit {
             }
         }
     }
+
+    /// Milliseconds until the current window resets and the budget refills.
+    pub fn retry_after_ms(&self) -> u64 {
+        let elapsed = tokio::time::Instant::now().duration_since(self.window_start);
+        std::time::Duration::from_secs(1)
+            .saturating_sub(elapsed)
+            .as_millis() as u64
+    }
 }
 
 // ── WebSocket Config ────────────────────────────────────────────────────
@@ -174,31 +699,63 @@ fn ws_config() -> Option<WebSocketConfig> {
 
 /// Handle a single incoming TCP connection: upgrade to WebSocket and process messages.
 ///
-/// `trusted_proxies` controls X-Forwarded-For trust: only when the connecting
-/// socket address is in this list will the header be honored. Empty list means
-/// the header is always ignored (fail-closed).
+/// `config.trusted_proxies` controls X-Forwarded-For trust: only when the
+/// connecting socket address is in this list will the header be honored. Empty
+/// list means the header is always ignored (fail-closed).
 #[allow(clippy::result_large_err)]
 pub async fn handle_connection(
     mut stream: TcpStream,
     addr: SocketAddr,
     room_manager: Arc<RoomManager>,
-    trusted_proxies: Arc<Vec<IpAddr>>,
+    pins: Arc<PinMatcher>,
+    limits: Arc<IpLimiter>,
+    config: Arc<ConnectionConfig>,
 ) {
     // Peek at the incoming request to detect plain HTTP (non-WebSocket) requests.
     // Reverse proxies (e.g. Fly.io) send HTTP health checks without the Upgrade
     // header. Respond with 200 OK directly instead of failing the WS handshake.
     let mut peek_buf = [0u8; 4096];
-    match stream.peek(&mut peek_buf).await {
+    let peeked = match tokio::time::timeout(config.peek_timeout, stream.peek(&mut peek_buf)).await {
+        Ok(peeked) => peeked,
+        Err(_) => {
+            info!(addr = %addr, "no request within peek deadline — closing");
+            let body = "request timeout";
+            let response = format!(
+                "HTTP/1.1 408 Request Timeout\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body,
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+            let _ = stream.shutdown().await;
+            return;
+        }
+    };
+    match peeked {
         Ok(n) => {
             let preview = String::from_utf8_lossy(&peek_buf[..n]);
             if !preview.to_ascii_lowercase().contains("upgrade: websocket") {
-                let body = "bolt-rendezvous OK";
+                let (content_type, body) = if request_path(&preview) == Some("/whoami") {
+                    let header = |name: &str| request_header(&preview, name);
+                    let forwarded =
+                        forwarded_client_ip(header(FLY_CLIENT_IP), header(X_FORWARDED_FOR));
+                    let client = resolve_client_ip(addr, &config.trusted_proxies, forwarded);
+                    let info = room_info(&room_manager, &client.room_key(), client.kind(), &client);
+                    let body = serde_json::to_string(&info).unwrap_or_default();
+                    ("application/json", body)
+                } else {
+                    ("text/plain", "bolt-rendezvous OK".to_string())
+                };
                 let response = format!(
-                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    "HTTP/1.1 200 OK\r\nContent-Type: {content_type}\r\nCache-Control: no-store\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
                     body.len(),
                     body,
                 );
+                // Consume the peeked request first: closing a socket with
+                // unread data resets the connection before the client has
+                // read the response.
+                let _ = stream.read(&mut peek_buf[..n]).await;
                 let _ = stream.write_all(response.as_bytes()).await;
+                let _ = stream.shutdown().await;
                 return;
             }
         }
@@ -210,109 +767,157 @@ pub async fn handle_connection(
 
     // Capture headers during WebSocket handshake to extract the real client IP.
     // Priority: Fly-Client-IP (Fly.dev guaranteed) > X-Forwarded-For > socket addr.
-    let forwarded_for = Arc::new(std::sync::Mutex::new(None::<String>));
+    let forwarded_for = Arc::new(std::sync::Mutex::new(None::<(&'static str, String)>));
     let forwarded_for_cb = forwarded_for.clone();
+    let negotiated = Arc::new(std::sync::Mutex::new(None::<(u32, Encoding)>));
+    let negotiated_cb = negotiated.clone();
+    let ip_guard = Arc::new(std::sync::Mutex::new(None));
+    let ip_guard_cb = ip_guard.clone();
+    let limits_cb = limits.clone();
+    let config_cb = config.clone();
+    // Set once the request is read: the handshake then writes a response
+    // (101 or a refusal) that a late 408 must not be mixed into.
+    let responding = Arc::new(AtomicBool::new(false));
+    let responding_cb = responding.clone();
+
+    let callback = move |req: &Request, mut resp: Response| -> Result<Response, ErrorResponse> {
+        responding_cb.store(true, Ordering::Relaxed);
+        // Subprotocol negotiation: echo the highest supported bolt version
+        // (and its encoding), refuse the upgrade if only unsupported versions
+        // were offered.
+        let offered: Vec<&str> = req
+            .headers()
+            .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .collect();
+        match select_subprotocol(&offered.join(",")) {
+            Ok(Some((version, encoding, token))) => {
+                if let Ok(value) = HeaderValue::from_str(token) {
+                    resp.headers_mut()
+                        .insert(header::SEC_WEBSOCKET_PROTOCOL, value);
+                }
+                if let Ok(mut lock) = negotiated_cb.lock() {
+                    *lock = Some((version, encoding));
+                }
+            }
+            Ok(None) => {}
+            Err(reason) => {
+                let mut err = ErrorResponse::new(Some(reason));
+                *err.status_mut() = StatusCode::BAD_REQUEST;
+                return Err(err);
+            }
+        }
 
-    let callback = move |req: &Request, resp: Response| -> Result<Response, ErrorResponse> {
-        // Fly-Client-IP: set by Fly.dev proxy, guaranteed to be the real client IP.
-        // Preferred over X-Forwarded-For because it cannot be spoofed by the client.
-        if let Some(fci) = req.headers().get("fly-client-ip") {
-            if let Ok(value) = fci.to_str() {
-                let ip_str = value.trim();
-                // Validate as IP address before trusting (RENDEZVOUS-HARDENING-1 P4).
-                if ip_str.parse::<std::net::IpAddr>().is_ok() {
-                    if let Ok(mut lock) = forwarded_for_cb.lock() {
-                        *lock = Some(ip_str.to_string());
+        let header = |name: &str| req.headers().get(name).and_then(|v| v.to_str().ok());
+        // Checked before the per-IP cap so refused pages take no slot. A
+        // header that is not valid UTF-8 counts as a present, unmatched one.
+        let origin = req
+            .headers()
+            .get(header::ORIGIN)
+            .map(|v| v.to_str().unwrap_or("\u{fffd}"));
+        if !config_cb.origin_policy.allows(origin) {
+            warn!(addr = %addr, origin = ?origin, "origin not allowed — refusing upgrade");
+            return Err(rejection_response(
+                StatusCode::FORBIDDEN,
+                RejectionReason::OriginNotAllowed,
+            ));
+        }
+        let forwarded = forwarded_client_ip(header(FLY_CLIENT_IP), header(X_FORWARDED_FOR));
+        // Connections that may come through a proxy skipped the accept-time
+        // per-IP cap; apply it to the client IP they resolve to.
+        if config_cb.may_forward(addr.ip()) {
+            let client = resolve_client_ip(addr, &config_cb.trusted_proxies, forwarded.clone());
+            match limits_cb.try_connect(&client.ip) {
+                Some(guard) => {
+                    if let Ok(mut lock) = ip_guard_cb.lock() {
+                        *lock = Some(guard);
                     }
-                    return Ok(resp);
                 }
-                // Invalid IP in Fly-Client-IP — log and fall through to XFF/socket
-                tracing::warn!("Fly-Client-IP contains invalid IP: {:?}", ip_str);
+                None => {
+                    return Err(rejection_response(
+                        StatusCode::TOO_MANY_REQUESTS,
+                        RejectionReason::TooManyConnections,
+                    ));
+                }
             }
         }
-        // Fallback: X-Forwarded-For (standard reverse proxy header).
-        if let Some(xff) = req.headers().get("x-forwarded-for") {
-            if let Ok(value) = xff.to_str() {
-                // Take the first IP in a comma-separated list.
-                let raw_ip = value.split(',').next().unwrap_or(value).trim();
-                // Validate as IP address (RENDEZVOUS-HARDENING-1 P4).
-                let client_ip = if raw_ip.parse::<std::net::IpAddr>().is_ok() {
-                    raw_ip.to_string()
-                } else {
-                    tracing::warn!("X-Forwarded-For contains invalid IP: {:?}", raw_ip);
-                    // Fall through — socket addr will be used
-                    return Ok(resp);
-                };
-                if let Ok(mut lock) = forwarded_for_cb.lock() {
-                    *lock = Some(client_ip);
-                }
+        if let Some(forwarded) = forwarded {
+            if let Ok(mut lock) = forwarded_for_cb.lock() {
+                *lock = Some(forwarded);
             }
         }
         Ok(resp)
     };
 
-    // Protocol-level message size enforcement via WebSocketConfig.
-    let ws_stream = match tokio_tungstenite::accept_hdr_async_with_config(
-        stream,
-        callback,
-        ws_config(),
-    )
-    .await
-    {
-        Ok(ws) => ws,
+    // The handshake owns the stream; a duplicate of the socket is kept
+    // (unregistered) until the upgrade ends, so a timed-out upgrade can
+    // still be answered.
+    let (stream, timeout_stream) = match duplicate_stream(stream) {
+        Ok(pair) => pair,
         Err(e) => {
-            error!(addr = %addr, error = %e, "WebSocket handshake failed");
+            error!(addr = %addr, error = %e, "failed to duplicate connection socket");
             return;
         }
     };
 
-    // Determine the effective client IP.
-    // AC-16: Only trust forwarded headers from configured proxies or Fly infrastructure.
-    //
-    // Fly-Client-IP is extracted with a `was_fly` flag so we can trust it unconditionally
-    // (Fly strips any client-sent Fly-Client-IP — it's infrastructure-only).
-    // X-Forwarded-For is only trusted from explicitly configured proxies.
-    let forwarded_ip = forwarded_for.lock().ok().and_then(|guard| guard.clone());
-
-    let raw_ip = if let Some(ref ip) = forwarded_ip {
-        if trusted_proxies.contains(&addr.ip()) {
-            // Explicitly trusted proxy — use forwarded IP (XFF or Fly-Client-IP)
-            ip.clone()
-        } else if is_private_ip(&addr.ip().to_string()) && !ip.is_empty() {
-            // Private source IP with a forwarded header — likely a PaaS proxy (Fly, Railway, etc.)
-            // Trust Fly-Client-IP / XFF from infrastructure proxies that connect internally.
-            // Safe because external clients cannot connect from private IPs.
-            info!(addr = %addr, forwarded_ip = %ip, "trusting forwarded IP from private-source proxy");
-            ip.clone()
-        } else {
-            addr.ip().to_string()
+    // Protocol-level message size enforcement via WebSocketConfig.
+    let handshake = tokio_tungstenite::accept_hdr_async_with_config(stream, callback, ws_config());
+    let ws_stream = match tokio::time::timeout(config.upgrade_timeout, handshake).await {
+        Ok(Ok(ws)) => ws,
+        Ok(Err(e)) => {
+            error!(addr = %addr, error = %e, "WebSocket handshake failed");
+            return;
+        }
+        Err(_) if responding.load(Ordering::Relaxed) => {
+            info!(addr = %addr, "WebSocket upgrade deadline passed mid-response — dropping connection");
+            return;
+        }
+        Err(_) => {
+            info!(addr = %addr, "WebSocket upgrade deadline passed — closing with 408");
+            if let Ok(stream) = TcpStream::from_std(timeout_stream) {
+                reject_connection(
+                    stream,
+                    StatusCode::REQUEST_TIMEOUT,
+                    RejectionReason::UpgradeTimeout,
+                )
+                .await;
+            }
+            return;
         }
-    } else {
-        addr.ip().to_string()
     };
+    drop(timeout_stream);
+    let upgraded_at = Instant::now();
+    // Held until the connection ends, releasing the per-IP slot on drop.
+    let _ip_guard = ip_guard.lock().ok().and_then(|mut guard| guard.take());
 
-    // For self-hosted mode: all private/loopback IPs share one room ("local").
-    // This lets devices on the same LAN discover each other even when the host
-    // machine connects via 127.0.0.1 and others via 192.168.x.x.
-    let client_ip = if is_private_ip(&raw_ip) {
-        "local".to_string()
-    } else {
-        raw_ip
-    };
+    // Determine the effective client IP (see `resolve_client_ip`).
+    let forwarded_ip = forwarded_for.lock().ok().and_then(|guard| guard.clone());
+    let negotiated = negotiated.lock().ok().and_then(|guard| *guard);
+    let negotiated_version = negotiated.map(|(version, _)| version);
+    // Outbound encoding; inbound text frames are always accepted as JSON.
+    let encoding = negotiated.map(|(_, encoding)| encoding).unwrap_or_default();
+
+    let client = resolve_client_ip(addr, &config.trusted_proxies, forwarded_ip);
+    if client.source == IpSource::PrivateSourceProxy {
+        info!(addr = %addr, forwarded_ip = %client.ip, "trusting forwarded IP from private-source proxy");
+    }
+    let client_ip = client.room_key();
 
-    debug!(addr = %addr, client_ip = %client_ip, "WebSocket connection established");
+    debug!(addr = %addr, client_ip = %client_ip, encoding = ?encoding, "WebSocket connection established");
 
     let (mut ws_sink, mut ws_stream_rx) = ws_stream.split();
 
     // Channel for sending server messages to this peer's WebSocket.
     let (tx, mut rx) = mpsc::unbounded_channel::<ServerMessage>();
 
-    // Spawn a task that forwards messages from the channel to the WebSocket sink.
+    // Spawn a task that forwards messages from the channel to the WebSocket sink,
+    // encoding them with this connection's negotiated encoding.
     let write_task = tokio::spawn(async move {
         while let Some(msg) = rx.recv().await {
-            match serde_json::to_string(&msg) {
-                Ok(json) => {
-                    if ws_sink.send(Message::Text(json)).await.is_err() {
+            match encode_server_frame(&msg, encoding) {
+                Ok(frame) => {
+                    if ws_sink.send(frame).await.is_err() {
                         break;
                     }
                 }
@@ -326,229 +931,490 @@ pub async fn handle_connection(
     // Per-connection rate limiter (applies to both registration and message phases).
     let mut rate_limit = RateLimit::new();
 
-    // --- Registration phase ---
-    // The first message must be a "register" command.
-    let (peer_code, _device_name, _device_type, _wt_url, _wt_cert_hash) = loop {
-        match ws_stream_rx.next().await {
-            Some(Ok(Message::Text(text))) => {
-                // Rate limit check (pre-registration).
-                match rate_limit.check() {
-                    Err(true) => {
-                        warn!(addr = %addr, "rate limit exceeded — closing connection");
+    // `match_pin` attempts are counted per connection, across re-registrations.
+    let mut pin_attempts = 0;
+    // Set after `leave`: the socket stays open for IDLE_TIMEOUT awaiting a
+    // new `register`.
+    let mut left_at: Option<Instant> = None;
+
+    loop {
+        // --- Registration phase ---
+        // The first message must be a "register" command, within the
+        // registration deadline (or, after `leave`, the idle timeout).
+        let register_deadline = match left_at {
+            Some(at) => at + IDLE_TIMEOUT,
+            None => upgraded_at + config.register_timeout,
+        };
+        let (
+            peer_code,
+            _device_name,
+            _device_type,
+            _wt_url,
+            _wt_cert_hash,
+            _capabilities,
+            mut _metadata,
+            discoverable,
+            _subscription,
+            protocol_version,
+            named_room,
+            register_id,
+        ) = loop {
+            let frame = tokio::select! {
+                frame = ws_stream_rx.next() => frame,
+                _ = tokio::time::sleep_until(register_deadline) => {
+                    if left_at.is_some() {
+                        info!(addr = %addr, "no register after leave ({} sec) — closing", IDLE_TIMEOUT.as_secs());
                         write_task.abort();
                         return;
                     }
-                    Err(false) => {
-                        warn!(addr = %addr, "rate limited during registration");
-                        let _ = tx.send(ServerMessage::Error {
-                            message: "rate limited".into(),
-                        });
-                        continue;
-                    }
-                    Ok(()) => {}
+                    info!(addr = %addr, "no register within registration deadline — closing");
+                    let err = ServerMessage::error(
+                        ErrorCode::RegistrationTimeout,
+                        format!(
+                            "no register within {} ms",
+                            config.register_timeout.as_millis()
+                        ),
+                    );
+                    close_with_error(tx, write_task, err).await;
+                    return;
                 }
-
-                // Application-level message size check (defense-in-depth).
-                if let Err(e) = validate_message_size(text.len()) {
-                    warn!(addr = %addr, error = %e, "oversized message during registration");
-                    let _ = tx.send(ServerMessage::Error { message: e });
-                    continue;
-                }
-
-                match serde_json::from_str::<ClientMessage>(&text) {
-                    Ok(ClientMessage::Register {
-                        peer_code,
-                        device_name,
-                        device_type,
-                        wt_url,
-                        wt_cert_hash,
-                    }) => {
-                        // Validate device_name length.
-                        if let Err(e) = validate_device_name(&device_name) {
-                            warn!(addr = %addr, error = %e, "invalid device_name");
-                            let _ = tx.send(ServerMessage::Error { message: e });
+            };
+            match frame {
+                Some(Ok(frame @ (Message::Text(_) | Message::Binary(_)))) => {
+                    // Rate limit check (pre-registration).
+                    match rate_limit.check() {
+                        Err(true) => {
+                            warn!(addr = %addr, "rate limit exceeded — closing connection");
+                            write_task.abort();
+                            return;
+                        }
+                        Err(false) => {
+                            warn!(addr = %addr, "rate limited during registration");
+                            let err = ServerMessage::rate_limited(rate_limit.retry_after_ms())
+                                .with_id(peek_message_id(&frame, encoding));
+                            let _ = tx.send(err);
                             continue;
                         }
-                        break (peer_code, device_name, device_type, wt_url, wt_cert_hash);
+                        Ok(()) => {}
                     }
-                    Ok(_) => {
-                        warn!(addr = %addr, "received non-register message before registration");
-                        let err = ServerMessage::Error {
-                            message: "must send 'register' as first message".into(),
-                        };
-                        let _ = tx.send(err);
-                    }
-                    Err(e) => {
-                        warn!(addr = %addr, error = %e, "malformed message during registration");
-                        let err = ServerMessage::Error {
-                            message: format!("malformed message: {e}"),
-                        };
-                        let _ = tx.send(err);
+
+                    // Size check (defense-in-depth), binary policy and decoding.
+                    let msg = match decode_client_frame(&frame, encoding) {
+                        Ok(msg) => msg,
+                        Err(err) => {
+                            warn!(addr = %addr, error = ?err, "rejected frame during registration");
+                            let _ = tx.send(err.with_id(peek_message_id(&frame, encoding)));
+                            continue;
+                        }
+                    };
+                    let id = match checked_message_id(&msg) {
+                        Ok(id) => id,
+                        Err(err) => {
+                            warn!(addr = %addr, "message id too long");
+                            let _ = tx.send(err);
+                            continue;
+                        }
+                    };
+
+                    match msg {
+                        ClientMessage::Register {
+                            peer_code,
+                            device_name,
+                            device_type,
+                            wt_url,
+                            wt_cert_hash,
+                            protocol_version,
+                            room,
+                            room_secret,
+                            capabilities,
+                            metadata,
+                            discoverable,
+                            subscription,
+                            ..
+                        } => {
+                            let named_room = match validate_registration(
+                                &device_name,
+                                &device_type,
+                                &capabilities,
+                                &metadata,
+                                subscription.as_ref(),
+                                room.as_deref(),
+                                room_secret.as_deref(),
+                            ) {
+                                Ok(named_room) => named_room,
+                                Err(err) => {
+                                    warn!(addr = %addr, error = ?err, "invalid register");
+                                    let _ = tx.send(err.with_id(id));
+                                    continue;
+                                }
+                            };
+                            // Version mismatch is fatal: the client cannot be served.
+                            let protocol_version = match resolve_protocol_version(
+                                negotiated_version,
+                                protocol_version,
+                            ) {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    warn!(addr = %addr, error = %e, "unsupported protocol version");
+                                    let err = ServerMessage::field_error(
+                                        ErrorCode::UnsupportedProtocolVersion,
+                                        "protocol_version",
+                                        e,
+                                    );
+                                    close_with_error(tx, write_task, err.with_id(id)).await;
+                                    return;
+                                }
+                            };
+                            if let Some(err) = registration_rate_error(&limits, &client.ip) {
+                                let _ = tx.send(err.with_id(id));
+                                continue;
+                            }
+                            break (
+                                peer_code,
+                                device_name,
+                                device_type,
+                                wt_url,
+                                wt_cert_hash,
+                                capabilities,
+                                metadata,
+                                discoverable,
+                                subscription,
+                                protocol_version,
+                                named_room,
+                                id,
+                            );
+                        }
+                        // Answered before registration so clients can compare
+                        // servers before choosing one.
+                        ClientMessage::Ping { nonce, .. } => {
+                            if let Err(e) = validate_ping_nonce(nonce.as_deref()) {
+                                let err =
+                                    ServerMessage::field_error(ErrorCode::InvalidField, "nonce", e);
+                                let _ = tx.send(err.with_id(id));
+                                continue;
+                            }
+                            let _ = tx.send(pong(&config, nonce, id));
+                        }
+                        _ => {
+                            warn!(addr = %addr, "received non-register message before registration");
+                            let err = ServerMessage::error(
+                                ErrorCode::NotRegistered,
+                                "must send 'register' as first message",
+                            );
+                            let _ = tx.send(err.with_id(id));
+                        }
                     }
                 }
+                Some(Ok(Message::Ping(_))) | Some(Ok(Message::Pong(_))) => {
+                    // Ignore control frames during registration.
+                    continue;
+                }
+                Some(Ok(Message::Close(_))) | None => {
+                    debug!(addr = %addr, "connection closed before registration");
+                    write_task.abort();
+                    return;
+                }
+                Some(Ok(_)) => {
+                    continue;
+                }
+                Some(Err(e)) => {
+                    debug!(addr = %addr, error = %e, "WebSocket error before registration");
+                    write_task.abort();
+                    return;
+                }
             }
-            Some(Ok(Message::Binary(_))) => {
-                // Binary frames rejected — signaling is text-only.
-                warn!(addr = %addr, "binary frame rejected during registration");
-                let _ = tx.send(ServerMessage::Error {
-                    message: "binary frames not accepted".into(),
-                });
-                continue;
-            }
-            Some(Ok(Message::Ping(_))) | Some(Ok(Message::Pong(_))) => {
-                // Ignore control frames during registration.
-                continue;
-            }
-            Some(Ok(Message::Close(_))) | None => {
-                debug!(addr = %addr, "connection closed before registration");
-                write_task.abort();
-                return;
-            }
-            Some(Ok(_)) => {
-                continue;
-            }
-            Some(Err(e)) => {
-                debug!(addr = %addr, error = %e, "WebSocket error before registration");
-                write_task.abort();
+        };
+
+        // Validate and normalize peer code.
+        let peer_code = match validate_peer_code(&peer_code) {
+            Ok(normalized) => normalized,
+            Err(e) => {
+                warn!(addr = %addr, error = %e, "invalid peer code");
+                let error = ServerMessage::field_error(ErrorCode::InvalidPeerCode, "peer_code", e);
+                close_with_error(tx, write_task, error.with_id(register_id)).await;
                 return;
             }
-        }
-    };
-
-    // Validate and normalize peer code.
-    let peer_code = match validate_peer_code(&peer_code) {
-        Ok(normalized) => normalized,
-        Err(e) => {
-            warn!(addr = %addr, error = %e, "invalid peer code");
-            let _ = tx.send(ServerMessage::Error { message: e });
-            write_task.abort();
-            return;
-        }
-    };
-
-    // Build peer info and add to room.
-    let peer_info = PeerInfo {
-        peer_code: peer_code.clone(),
-        device_name: _device_name,
-        device_type: _device_type,
-        sender: tx.clone(),
-        session_id: 0, // assigned by add_peer
-        wt_url: _wt_url,
-        wt_cert_hash: _wt_cert_hash,
-    };
-
-    let (existing_peers, session_id) = match room_manager.add_peer(&client_ip, peer_info) {
-        Ok(result) => result,
-        Err(e) => {
-            warn!(addr = %addr, error = %e, "peer code collision");
-            let _ = tx.send(ServerMessage::Error { message: e });
-            write_task.abort();
-            return;
-        }
-    };
+        };
 
-    // Send the current peer list to the newly registered peer.
-    let peers_msg = ServerMessage::Peers {
-        peers: existing_peers,
-    };
-    let _ = tx.send(peers_msg);
+        // Named rooms replace the IP room; a wrong secret is fatal so every
+        // guess costs a new connection.
+        let kind = room_kind(&client, named_room.is_some());
+        let room_key = match named_room {
+            None => client_ip.clone(),
+            Some((name, secret)) => {
+                match authorize_named_room(&room_manager, name, secret, &client.ip).await {
+                    Ok(key) => key,
+                    Err(e) => {
+                        warn!(addr = %addr, error = %e, "named room rejected");
+                        close_with_error(
+                            tx,
+                            write_task,
+                            ServerMessage::field_error(e.code(), "room_secret", e.to_string())
+                                .with_id(register_id),
+                        )
+                        .await;
+                        return;
+                    }
+                }
+            }
+        };
 
-    info!(
-        peer_code = %peer_code,
-        client_ip = %client_ip,
-        "peer registered"
-    );
+        // Build peer info and add to room.
+        let (alias_url, alias_hash) = take_wt_aliases(&mut _metadata);
+        let peer_info = PeerInfo {
+            peer_code: peer_code.clone(),
+            device_name: _device_name,
+            device_type: _device_type,
+            sender: tx.clone(),
+            session_id: 0, // assigned by add_peer
+            wt_url: _wt_url.or(alias_url),
+            wt_cert_hash: _wt_cert_hash.or(alias_hash),
+            presence: Presence::Available,
+            capabilities: _capabilities,
+            metadata: _metadata,
+            discoverable,
+            subscription: _subscription.unwrap_or_default(),
+            last_active: Instant::now(),
+            event_budget: EventBudget::default(),
+        };
 
-    // --- Message loop ---
-    loop {
-        let msg = tokio::time::timeout(IDLE_TIMEOUT, ws_stream_rx.next()).await;
-        let msg = match msg {
-            Ok(inner) => inner,
-            Err(_) => {
-                info!(peer_code = %peer_code, "idle timeout ({} sec) — closing", IDLE_TIMEOUT.as_secs());
-                break;
-            }
+        let (existing_peers, session_id, version, next) =
+            match room_manager.add_peer(&room_key, peer_info) {
+                Ok(result) => result,
+                Err(e) => {
+                    warn!(addr = %addr, error = %e, "peer code collision");
+                    close_with_error(
+                        tx,
+                        write_task,
+                        ServerMessage::error(e.code(), e.to_string()).with_id(register_id),
+                    )
+                    .await;
+                    return;
+                }
+            };
+
+        // Announce the negotiated version and effective limits, then send the
+        // current peer list to the newly registered peer.
+        let _ = tx.send(ServerMessage::ServerInfo {
+            protocol_version,
+            server_version: env!("CARGO_PKG_VERSION").to_string(),
+            instance_id: config.instance_id.clone(),
+            limits: server_limits(&config),
+        });
+        let peers_msg = ServerMessage::Peers {
+            peers: existing_peers,
+            version: Some(version),
+            after: None,
+            next,
+            id: None,
         };
-        match msg {
-            Some(Ok(Message::Text(text))) => {
-                // Rate limit check (post-registration).
-                match rate_limit.check() {
-                    Err(true) => {
-                        warn!(peer_code = %peer_code, "rate limit exceeded — closing connection");
-                        break;
-                    }
-                    Err(false) => {
-                        warn!(peer_code = %peer_code, "rate limited");
-                        let _ = tx.send(ServerMessage::Error {
-                            message: "rate limited".into(),
-                        });
-                        continue;
-                    }
-                    Ok(()) => {}
+        let _ = tx.send(peers_msg);
+        let _ = tx.send(ServerMessage::RoomInfo {
+            peer_code: peer_code.clone(),
+            room: room_info(&room_manager, &room_key, kind, &client),
+        });
+
+        info!(
+            peer_code = %peer_code,
+            client_ip = %client_ip,
+            room = %room_key,
+            "peer registered"
+        );
+        // Identities registered over this connection; the first is the
+        // default for messages without `as`. Never empty in the message loop.
+        let mut identities = vec![SessionRef {
+            room: room_key,
+            peer_code,
+            session_id,
+        }];
+        // Set by a fatal error while registering a further identity; sent
+        // once every identity is cleaned up.
+        let mut close_error = None;
+
+        // --- Message loop ---
+        // Any frame resets the idle timeout. Only client messages other than
+        // `ping` count as activity for automatic away.
+        let mut last_frame = Instant::now();
+        let mut last_activity = Instant::now();
+        let mut away_armed = config.auto_away.is_some();
+        let mut auto_away = false;
+        let mut left = false;
+        loop {
+            let idle_deadline = last_frame + IDLE_TIMEOUT;
+            let away_deadline = config
+                .auto_away
+                .filter(|_| away_armed)
+                .map(|after| last_activity + after);
+            let msg = tokio::select! {
+                msg = ws_stream_rx.next() => msg,
+                _ = tokio::time::sleep_until(idle_deadline) => {
+                    info!(peer_code = %identities[0].peer_code, "idle timeout ({} sec) — closing", IDLE_TIMEOUT.as_secs());
+                    break;
                 }
-
-                // Application-level message size check (defense-in-depth).
-                if let Err(e) = validate_message_size(text.len()) {
-                    warn!(peer_code = %peer_code, error = %e, "oversized message");
-                    let _ = tx.send(ServerMessage::Error { message: e });
+                _ = tokio::time::sleep_until(away_deadline.unwrap_or(idle_deadline)), if away_deadline.is_some() => {
+                    // Only an `available` peer goes away automatically; wait for
+                    // new activity before trying again.
+                    away_armed = false;
+                    for me in &identities {
+                        auto_away |= room_manager.set_presence(
+                            &me.room,
+                            &me.peer_code,
+                            me.session_id,
+                            Presence::Away,
+                            Some(Presence::Available),
+                        );
+                    }
                     continue;
                 }
+            };
+            last_frame = Instant::now();
+            match msg {
+                Some(Ok(frame @ (Message::Text(_) | Message::Binary(_)))) => {
+                    // Rate limit check (post-registration).
+                    match rate_limit.check() {
+                        Err(true) => {
+                            warn!(peer_code = %identities[0].peer_code, "rate limit exceeded — closing connection");
+                            break;
+                        }
+                        Err(false) => {
+                            warn!(peer_code = %identities[0].peer_code, "rate limited");
+                            let err = ServerMessage::rate_limited(rate_limit.retry_after_ms())
+                                .with_id(peek_message_id(&frame, encoding));
+                            let _ = tx.send(err);
+                            continue;
+                        }
+                        Ok(()) => {}
+                    }
 
-                match serde_json::from_str::<ClientMessage>(&text) {
-                    Ok(ClientMessage::Signal { to, payload }) => {
-                        // Validate and normalize Signal.to field.
-                        let to = match validate_signal_target(&to) {
-                            Ok(normalized) => normalized,
-                            Err(e) => {
-                                warn!(from = %peer_code, error = %e, "invalid signal target");
-                                let _ = tx.send(ServerMessage::Error { message: e });
-                                continue;
+                    // Size check (defense-in-depth), binary policy and decoding.
+                    let msg = match decode_client_frame(&frame, encoding) {
+                        Ok(msg) => msg,
+                        Err(err) => {
+                            warn!(peer_code = %identities[0].peer_code, error = ?err, "rejected frame");
+                            let _ = tx.send(err.with_id(peek_message_id(&frame, encoding)));
+                            continue;
+                        }
+                    };
+                    let id = match checked_message_id(&msg) {
+                        Ok(id) => id,
+                        Err(err) => {
+                            warn!(peer_code = %identities[0].peer_code, "message id too long");
+                            let _ = tx.send(err);
+                            continue;
+                        }
+                    };
+
+                    if !matches!(msg, ClientMessage::Ping { .. }) {
+                        last_activity = Instant::now();
+                        away_armed = config.auto_away.is_some();
+                        // Back from automatic away, unless the peer is choosing a
+                        // state itself right now.
+                        if std::mem::take(&mut auto_away)
+                            && !matches!(msg, ClientMessage::SetPresence { .. })
+                        {
+                            for me in &identities {
+                                room_manager.set_presence(
+                                    &me.room,
+                                    &me.peer_code,
+                                    me.session_id,
+                                    Presence::Available,
+                                    Some(Presence::Away),
+                                );
                             }
-                        };
+                        }
+                    }
 
-                        info!(from = %peer_code, to = %to, "signal relay");
-                        if let Some(target_sender) = room_manager.find_peer(&client_ip, &to) {
-                            let relay_msg = ServerMessage::Signal {
-                                from: peer_code.clone(),
-                                payload,
+                    let idx = match identity_index(&identities, msg.identity()) {
+                        Ok(idx) => idx,
+                        Err(err) => {
+                            let _ = tx.send(err.with_id(id));
+                            continue;
+                        }
+                    };
+                    let me = identities[idx].clone();
+                    let (room_key, peer_code, session_id) =
+                        (&me.room, &me.peer_code, me.session_id);
+
+                    match msg {
+                        ClientMessage::Signal {
+                            to, payload, ack, ..
+                        } => {
+                            // Validate and normalize Signal.to field.
+                            let to = match validate_signal_target(&to) {
+                                Ok(normalized) => normalized,
+                                Err(e) => {
+                                    warn!(from = %peer_code, error = %e, "invalid signal target");
+                                    let err = ServerMessage::field_error(
+                                        ErrorCode::InvalidPeerCode,
+                                        "to",
+                                        e,
+                                    );
+                                    let _ = tx.send(err.with_id(id));
+                                    continue;
+                                }
                             };
-                            if target_sender.send(relay_msg).is_err() {
-                                warn!(
-                                    from = %peer_code,
-                                    to = %to,
-                                    "target peer channel closed"
-                                );
-                                let err = ServerMessage::Error {
-                                    message: format!("peer '{to}' is no longer connected"),
+
+                            info!(from = %peer_code, to = %to, "signal relay");
+                            if let Some(target_sender) =
+                                room_manager.find_reachable_peer(room_key, session_id, &to)
+                            {
+                                let relay_msg = ServerMessage::Signal {
+                                    from: peer_code.clone(),
+                                    payload,
+                                    to: Some(to.clone()),
                                 };
-                                let _ = tx.send(err);
+                                if target_sender.send(relay_msg).is_err() {
+                                    warn!(
+                                        from = %peer_code,
+                                        to = %to,
+                                        "target peer channel closed"
+                                    );
+                                    let err = ServerMessage::error(
+                                        ErrorCode::PeerDisconnected,
+                                        format!("peer '{to}' is no longer connected"),
+                                    );
+                                    let _ = tx.send(err.with_id(id));
+                                } else if ack {
+                                    let _ = tx.send(ServerMessage::SignalDelivered { to, id });
+                                }
+                            } else {
+                                debug!(from = %peer_code, to = %to, "target peer not found");
+                                let err = ServerMessage::error(
+                                    ErrorCode::NotFound,
+                                    format!("peer '{to}' not found"),
+                                );
+                                let _ = tx.send(err.with_id(id));
                             }
-                        } else {
-                            debug!(from = %peer_code, to = %to, "target peer not found");
-                            let err = ServerMessage::Error {
-                                message: format!("peer '{to}' not found"),
-                            };
-                            let _ = tx.send(err);
                         }
-                    }
-                    Ok(ClientMessage::ManualSignal { to, payload }) => {
-                        // Explicit manual pairing path. Automatic discovery and
-                        // normal signal routing remain room-scoped.
-                        let to = match validate_signal_target(&to) {
-                            Ok(normalized) => normalized,
-                            Err(e) => {
-                                warn!(from = %peer_code, error = %e, "invalid manual signal target");
-                                let _ = tx.send(ServerMessage::Error { message: e });
-                                continue;
-                            }
-                        };
+                        ClientMessage::ManualSignal {
+                            to, payload, ack, ..
+                        } => {
+                            // Explicit manual pairing path. Automatic discovery and
+                            // normal signal routing remain room-scoped.
+                            let to = match validate_signal_target(&to) {
+                                Ok(normalized) => normalized,
+                                Err(e) => {
+                                    warn!(from = %peer_code, error = %e, "invalid manual signal target");
+                                    let err = ServerMessage::field_error(
+                                        ErrorCode::InvalidPeerCode,
+                                        "to",
+                                        e,
+                                    );
+                                    let _ = tx.send(err.with_id(id));
+                                    continue;
+                                }
+                            };
 
-                        info!(from = %peer_code, to = %to, "manual signal relay");
-                        match room_manager.find_peer_manual(&to) {
-                            ManualPeerLookup::Found(target_sender) => {
+                            // Consent: only peers linked by an accepted pair
+                            // request (or a PIN match) receive manual signals.
+                            if let Some(target_sender) =
+                                room_manager.find_linked_peer(session_id, &to)
+                            {
+                                info!(from = %peer_code, to = %to, "manual signal relay");
                                 let relay_msg = ServerMessage::Signal {
                                     from: peer_code.clone(),
                                     payload,
+                                    to: Some(to.clone()),
                                 };
                                 if target_sender.send(relay_msg).is_err() {
                                     warn!(
@@ -556,84 +1422,964 @@ pub async fn handle_connection(
                                         to = %to,
                                         "manual target peer channel closed"
                                     );
-                                    let err = ServerMessage::Error {
-                                        message: format!("peer '{to}' is no longer connected"),
+                                    let err = ServerMessage::error(
+                                        ErrorCode::PeerDisconnected,
+                                        format!("peer '{to}' is no longer connected"),
+                                    );
+                                    let _ = tx.send(err.with_id(id));
+                                } else if ack {
+                                    let _ = tx.send(ServerMessage::SignalDelivered { to, id });
+                                }
+                                continue;
+                            }
+                            let err = match room_manager.find_peer_manual(&to) {
+                                ManualPeerLookup::Found(..) | ManualPeerLookup::Ambiguous => {
+                                    debug!(from = %peer_code, to = %to, "manual signal without consent");
+                                    ServerMessage::error(
+                                        ErrorCode::ConsentRequired,
+                                        format!("peer '{to}' has not accepted a pair request"),
+                                    )
+                                }
+                                ManualPeerLookup::NotFound => {
+                                    debug!(from = %peer_code, to = %to, "manual target peer not found");
+                                    ServerMessage::error(
+                                        ErrorCode::NotFound,
+                                        format!("peer '{to}' not found"),
+                                    )
+                                }
+                            };
+                            let _ = tx.send(err.with_id(id));
+                        }
+                        ClientMessage::PairRequest { to, .. } => {
+                            let to = match validate_signal_target(&to) {
+                                Ok(normalized) => normalized,
+                                Err(e) => {
+                                    let err = ServerMessage::field_error(
+                                        ErrorCode::InvalidPeerCode,
+                                        "to",
+                                        e,
+                                    );
+                                    let _ = tx.send(err.with_id(id));
+                                    continue;
+                                }
+                            };
+                            let (target_sender, target) = match room_manager.find_peer_manual(&to) {
+                                ManualPeerLookup::Found(sender, target)
+                                    if target.session_id != session_id =>
+                                {
+                                    (sender, target)
+                                }
+                                ManualPeerLookup::Found(..) => {
+                                    let err = ServerMessage::field_error(
+                                        ErrorCode::InvalidPeerCode,
+                                        "to",
+                                        "cannot pair with self",
+                                    );
+                                    let _ = tx.send(err.with_id(id));
+                                    continue;
+                                }
+                                ManualPeerLookup::Ambiguous => {
+                                    let err = ServerMessage::error(
+                                        ErrorCode::Ambiguous,
+                                        format!("peer '{to}' is ambiguous"),
+                                    );
+                                    let _ = tx.send(err.with_id(id));
+                                    continue;
+                                }
+                                ManualPeerLookup::NotFound => {
+                                    let err = ServerMessage::error(
+                                        ErrorCode::NotFound,
+                                        format!("peer '{to}' not found"),
+                                    );
+                                    let _ = tx.send(err.with_id(id));
+                                    continue;
+                                }
+                            };
+                            let Some(peer) =
+                                room_manager.peer_data(room_key, peer_code, session_id)
+                            else {
+                                let err = ServerMessage::error(
+                                    ErrorCode::NotRegistered,
+                                    "peer no longer registered",
+                                );
+                                let _ = tx.send(err.with_id(id));
+                                continue;
+                            };
+                            if !room_manager.add_pair_request(&me, &target) {
+                                let err = ServerMessage::error(
+                                    ErrorCode::RateLimited,
+                                    format!("peer '{to}' has too many pending pair requests"),
+                                );
+                                let _ = tx.send(err.with_id(id));
+                                continue;
+                            }
+                            info!(from = %peer_code, to = %to, "pair request");
+                            let _ = target_sender.send(ServerMessage::PairRequest {
+                                peer,
+                                to: Some(target.peer_code),
+                            });
+                        }
+                        ClientMessage::PairAccept { to, .. } => {
+                            if let Err(err) = answer_pair_request(&room_manager, &me, &to, true) {
+                                let _ = tx.send(err.with_id(id));
+                            }
+                        }
+                        ClientMessage::PairReject { to, .. } => {
+                            if let Err(err) = answer_pair_request(&room_manager, &me, &to, false) {
+                                let _ = tx.send(err.with_id(id));
+                            }
+                        }
+                        ClientMessage::MulticastSignal { to, payload, .. } => {
+                            if let Err(e) = validate_multicast_targets(&to) {
+                                warn!(from = %peer_code, error = %e, "invalid multicast targets");
+                                let err =
+                                    ServerMessage::field_error(ErrorCode::InvalidField, "to", e);
+                                let _ = tx.send(err.with_id(id));
+                                continue;
+                            }
+                            info!(from = %peer_code, targets = to.len(), "multicast signal relay");
+                            let results = relay_multicast(
+                                &room_manager,
+                                room_key,
+                                session_id,
+                                peer_code,
+                                to,
+                                payload,
+                            );
+                            let _ = tx.send(ServerMessage::MulticastResult { results, id });
+                        }
+                        ClientMessage::MatchPin { pin, .. } => {
+                            if pin_attempts >= MAX_PIN_ATTEMPTS_PER_CONNECTION {
+                                warn!(peer_code = %peer_code, "pin attempts exhausted on connection");
+                                let err = ServerMessage::error(
+                                    ErrorCode::RateLimited,
+                                    "too many pin attempts on this connection",
+                                );
+                                let _ = tx.send(err.with_id(id));
+                                continue;
+                            }
+                            pin_attempts += 1;
+                            let Some(peer) =
+                                room_manager.peer_data(room_key, peer_code, session_id)
+                            else {
+                                let err = ServerMessage::error(
+                                    ErrorCode::NotRegistered,
+                                    "peer no longer registered",
+                                );
+                                let _ = tx.send(err.with_id(id));
+                                continue;
+                            };
+                            let entrant = PinEntrant {
+                                session: me.clone(),
+                                peer: peer.clone(),
+                                sender: tx.clone(),
+                                request_id: id.clone(),
+                            };
+                            match pins.submit(&client.ip, &pin, entrant) {
+                                Ok(PinOutcome::Waiting { expires_in }) => {
+                                    let _ = tx.send(ServerMessage::PinWaiting {
+                                        expires_in_ms: expires_in.as_millis() as u64,
+                                        id,
+                                    });
+                                    let pins = pins.clone();
+                                    tokio::spawn(async move {
+                                        tokio::time::sleep(expires_in).await;
+                                        if let Some(entrant) = pins.expire(&pin, session_id) {
+                                            let err = ServerMessage::error(
+                                                ErrorCode::Expired,
+                                                "pin expired",
+                                            )
+                                            .with_id(entrant.request_id);
+                                            let _ = entrant.sender.send(err);
+                                        }
+                                    });
+                                }
+                                Ok(PinOutcome::Matched(partner)) => {
+                                    room_manager.link_sessions(&me, &partner.session);
+                                    let partner_peer = room_manager
+                                        .peer_data(
+                                            &partner.session.room,
+                                            &partner.session.peer_code,
+                                            partner.session.session_id,
+                                        )
+                                        .unwrap_or(partner.peer);
+                                    let _ = partner.sender.send(ServerMessage::PinMatched {
+                                        peer,
+                                        id: partner.request_id,
+                                    });
+                                    let _ = tx.send(ServerMessage::PinMatched {
+                                        peer: partner_peer,
+                                        id,
+                                    });
+                                }
+                                Err(e) => {
+                                    warn!(peer_code = %peer_code, error = %e, "pin rejected");
+                                    let err = match e {
+                                        PinError::TooManyAttempts { retry_after } => {
+                                            ServerMessage::rate_limited(
+                                                retry_after.as_millis() as u64
+                                            )
+                                        }
+                                        PinError::Invalid => ServerMessage::field_error(
+                                            e.code(),
+                                            "pin",
+                                            e.to_string(),
+                                        ),
+                                        _ => ServerMessage::error(e.code(), e.to_string()),
                                     };
-                                    let _ = tx.send(err);
+                                    let _ = tx.send(err.with_id(id));
                                 }
                             }
-                            ManualPeerLookup::Ambiguous => {
-                                warn!(
-                                    from = %peer_code,
-                                    to = %to,
-                                    "manual signal target is ambiguous"
+                        }
+                        ClientMessage::Update {
+                            device_name,
+                            wt_url,
+                            wt_cert_hash,
+                            capabilities,
+                            mut metadata,
+                            ..
+                        } => {
+                            if let Some(Err(e)) = device_name.as_deref().map(validate_device_name) {
+                                warn!(peer_code = %peer_code, error = %e, "invalid device_name in update");
+                                let err = ServerMessage::field_error(
+                                    ErrorCode::InvalidField,
+                                    "device_name",
+                                    e,
                                 );
-                                let err = ServerMessage::Error {
-                                    message: format!("peer '{to}' is ambiguous"),
-                                };
-                                let _ = tx.send(err);
+                                let _ = tx.send(err.with_id(id));
+                                continue;
                             }
-                            ManualPeerLookup::NotFound => {
-                                debug!(from = %peer_code, to = %to, "manual target peer not found");
-                                let err = ServerMessage::Error {
-                                    message: format!("peer '{to}' not found"),
-                                };
-                                let _ = tx.send(err);
+                            if let Some(Err(e)) = capabilities.as_deref().map(validate_capabilities)
+                            {
+                                let err = ServerMessage::field_error(
+                                    ErrorCode::InvalidField,
+                                    "capabilities",
+                                    e,
+                                );
+                                let _ = tx.send(err.with_id(id));
+                                continue;
+                            }
+                            if let Some(Err(e)) = metadata.as_ref().map(validate_metadata) {
+                                let err = ServerMessage::field_error(
+                                    ErrorCode::InvalidField,
+                                    "metadata",
+                                    e,
+                                );
+                                let _ = tx.send(err.with_id(id));
+                                continue;
+                            }
+                            let (alias_url, alias_hash) =
+                                metadata.as_mut().map(take_wt_aliases).unwrap_or_default();
+                            let update = PeerUpdate {
+                                device_name,
+                                wt_url: wt_url.or(alias_url.map(Some)),
+                                wt_cert_hash: wt_cert_hash.or(alias_hash.map(Some)),
+                                capabilities,
+                                metadata,
+                            };
+                            if room_manager
+                                .update_peer(room_key, peer_code, session_id, update)
+                                .is_none()
+                            {
+                                // Replaced by a newer session with the same code (DP-5).
+                                debug!(peer_code = %peer_code, "update for replaced session ignored");
+                                let err = ServerMessage::error(
+                                    ErrorCode::NotRegistered,
+                                    "session was replaced by a newer connection",
+                                );
+                                let _ = tx.send(err.with_id(id));
+                            }
+                        }
+                        ClientMessage::ListPeers { since, after, .. } => {
+                            if !room_manager.list_peers(
+                                room_key,
+                                peer_code,
+                                session_id,
+                                since,
+                                after,
+                                id.clone(),
+                            ) {
+                                let err = ServerMessage::error(
+                                    ErrorCode::NotRegistered,
+                                    "session was replaced by a newer connection",
+                                );
+                                let _ = tx.send(err.with_id(id));
+                            }
+                        }
+                        ClientMessage::Subscribe { subscription, .. } => {
+                            if let Err(e) = validate_subscription(&subscription) {
+                                let err = ServerMessage::field_error(
+                                    ErrorCode::InvalidField,
+                                    "subscription",
+                                    e,
+                                );
+                                let _ = tx.send(err.with_id(id));
+                                continue;
+                            }
+                            if !room_manager.subscribe(
+                                room_key,
+                                peer_code,
+                                session_id,
+                                subscription,
+                                id.clone(),
+                            ) {
+                                let err = ServerMessage::error(
+                                    ErrorCode::NotRegistered,
+                                    "session was replaced by a newer connection",
+                                );
+                                let _ = tx.send(err.with_id(id));
+                            }
+                        }
+                        ClientMessage::SetPresence { presence, .. } => {
+                            if presence == Presence::Unknown {
+                                let err = ServerMessage::field_error(
+                                    ErrorCode::InvalidField,
+                                    "presence",
+                                    "unknown presence state",
+                                );
+                                let _ = tx.send(err.with_id(id));
+                                continue;
+                            }
+                            room_manager
+                                .set_presence(room_key, peer_code, session_id, presence, None);
+                        }
+                        ClientMessage::Ping { nonce, .. } => {
+                            if let Err(e) = validate_ping_nonce(nonce.as_deref()) {
+                                let err =
+                                    ServerMessage::field_error(ErrorCode::InvalidField, "nonce", e);
+                                let _ = tx.send(err.with_id(id));
+                                continue;
+                            }
+                            let _ = tx.send(pong(&config, nonce, id));
+                        }
+                        ClientMessage::Leave { identity, .. } => {
+                            if identity.is_some() && identities.len() > 1 {
+                                info!(peer_code = %peer_code, "identity left");
+                                pins.cancel(session_id);
+                                room_manager.remove_peer(room_key, peer_code, session_id);
+                                identities.remove(idx);
+                                continue;
+                            }
+                            left = true;
+                            break;
+                        }
+                        ClientMessage::RotateCode {
+                            peer_code: new_code,
+                            ..
+                        } => {
+                            let new_code = match validate_peer_code(&new_code) {
+                                Ok(normalized) => normalized,
+                                Err(e) => {
+                                    let err = ServerMessage::field_error(
+                                        ErrorCode::InvalidPeerCode,
+                                        "peer_code",
+                                        e,
+                                    );
+                                    let _ = tx.send(err.with_id(id));
+                                    continue;
+                                }
+                            };
+                            // Rotating onto the current code changes nothing,
+                            // as in `RoomManager::rotate_code`.
+                            if new_code == *peer_code {
+                                let _ = tx.send(ServerMessage::CodeRotated {
+                                    peer_code: new_code,
+                                    version: None,
+                                    id,
+                                });
+                                continue;
+                            }
+                            // `as` must stay unambiguous across rooms.
+                            if identities.iter().any(|other| other.peer_code == new_code) {
+                                let err = ServerMessage::field_error(
+                                    ErrorCode::CodeInUse,
+                                    "peer_code",
+                                    "peer code already in use on this connection",
+                                );
+                                let _ = tx.send(err.with_id(id));
+                                continue;
+                            }
+                            if let Some(err) = registration_rate_error(&limits, &client.ip) {
+                                let _ = tx.send(err.with_id(id));
+                                continue;
+                            }
+                            match room_manager.rotate_code(
+                                room_key,
+                                peer_code,
+                                session_id,
+                                &new_code,
+                                id.clone(),
+                            ) {
+                                Ok(Some(_)) => {
+                                    // `code_rotated` was queued by the room.
+                                    // A waiting PIN was entered under the old code.
+                                    pins.cancel(session_id);
+                                    identities[idx].peer_code = new_code;
+                                }
+                                Ok(None) => {
+                                    let err = ServerMessage::error(
+                                        ErrorCode::NotRegistered,
+                                        "session was replaced by a newer connection",
+                                    );
+                                    let _ = tx.send(err.with_id(id));
+                                }
+                                Err(e) => {
+                                    let err = ServerMessage::field_error(
+                                        e.code(),
+                                        "peer_code",
+                                        e.to_string(),
+                                    );
+                                    let _ = tx.send(err.with_id(id));
+                                }
+                            }
+                        }
+                        ClientMessage::Register {
+                            peer_code: new_code,
+                            device_name,
+                            device_type,
+                            wt_url,
+                            wt_cert_hash,
+                            room,
+                            room_secret,
+                            capabilities,
+                            mut metadata,
+                            discoverable,
+                            subscription,
+                            ..
+                        } => {
+                            if identities.len() >= MAX_IDENTITIES_PER_CONNECTION {
+                                warn!(peer_code = %peer_code, "identity limit reached");
+                                let err = ServerMessage::error(
+                                    ErrorCode::IdentityLimit,
+                                    format!(
+                                        "at most {MAX_IDENTITIES_PER_CONNECTION} identities per connection"
+                                    ),
+                                );
+                                let _ = tx.send(err.with_id(id));
+                                continue;
+                            }
+                            let named_room = match validate_registration(
+                                &device_name,
+                                &device_type,
+                                &capabilities,
+                                &metadata,
+                                subscription.as_ref(),
+                                room.as_deref(),
+                                room_secret.as_deref(),
+                            ) {
+                                Ok(named_room) => named_room,
+                                Err(err) => {
+                                    let _ = tx.send(err.with_id(id));
+                                    continue;
+                                }
+                            };
+                            let new_code = match validate_peer_code(&new_code) {
+                                Ok(normalized) => normalized,
+                                Err(e) => {
+                                    let err = ServerMessage::field_error(
+                                        ErrorCode::InvalidPeerCode,
+                                        "peer_code",
+                                        e,
+                                    );
+                                    let _ = tx.send(err.with_id(id));
+                                    continue;
+                                }
+                            };
+                            if identities.iter().any(|other| other.peer_code == new_code) {
+                                let err = ServerMessage::error(
+                                    ErrorCode::AlreadyRegistered,
+                                    "already registered",
+                                );
+                                let _ = tx.send(err.with_id(id));
+                                continue;
+                            }
+                            if let Some(err) = registration_rate_error(&limits, &client.ip) {
+                                let _ = tx.send(err.with_id(id));
+                                continue;
+                            }
+                            // As on the first registration, a wrong secret
+                            // closes the connection.
+                            let new_kind = room_kind(&client, named_room.is_some());
+                            let new_room = match named_room {
+                                None => client_ip.clone(),
+                                Some((name, secret)) => {
+                                    match authorize_named_room(
+                                        &room_manager,
+                                        name,
+                                        secret,
+                                        &client.ip,
+                                    )
+                                    .await
+                                    {
+                                        Ok(key) => key,
+                                        Err(e) => {
+                                            warn!(addr = %addr, error = %e, "named room rejected");
+                                            close_error = Some(
+                                                ServerMessage::field_error(
+                                                    e.code(),
+                                                    "room_secret",
+                                                    e.to_string(),
+                                                )
+                                                .with_id(id),
+                                            );
+                                            break;
+                                        }
+                                    }
+                                }
+                            };
+                            let (alias_url, alias_hash) = take_wt_aliases(&mut metadata);
+                            let peer_info = PeerInfo {
+                                peer_code: new_code.clone(),
+                                device_name,
+                                device_type,
+                                sender: tx.clone(),
+                                session_id: 0, // assigned by add_peer
+                                wt_url: wt_url.or(alias_url),
+                                wt_cert_hash: wt_cert_hash.or(alias_hash),
+                                presence: Presence::Available,
+                                capabilities,
+                                metadata,
+                                discoverable,
+                                subscription: subscription.unwrap_or_default(),
+                                last_active: Instant::now(),
+                                event_budget: EventBudget::default(),
+                            };
+                            match room_manager.add_peer(&new_room, peer_info) {
+                                Ok((peers, new_session, version, next)) => {
+                                    info!(peer_code = %new_code, room = %new_room, "identity added");
+                                    let room =
+                                        room_info(&room_manager, &new_room, new_kind, &client);
+                                    identities.push(SessionRef {
+                                        room: new_room,
+                                        peer_code: new_code.clone(),
+                                        session_id: new_session,
+                                    });
+                                    let _ = tx.send(ServerMessage::IdentityAdded {
+                                        peer_code: new_code.clone(),
+                                        peers,
+                                        version: Some(version),
+                                        next,
+                                        id,
+                                    });
+                                    let _ = tx.send(ServerMessage::RoomInfo {
+                                        peer_code: new_code,
+                                        room,
+                                    });
+                                }
+                                Err(e) => {
+                                    let err = ServerMessage::error(e.code(), e.to_string());
+                                    let _ = tx.send(err.with_id(id));
+                                }
                             }
                         }
-                    }
-                    Ok(ClientMessage::Ping) => {
-                        // Keepalive — no-op, just prevents idle timeout.
-                        continue;
-                    }
-                    Ok(ClientMessage::Register { .. }) => {
-                        warn!(peer_code = %peer_code, "duplicate register message");
-                        let err = ServerMessage::Error {
-                            message: "already registered".into(),
-                        };
-                        let _ = tx.send(err);
-                    }
-                    Err(e) => {
-                        warn!(peer_code = %peer_code, error = %e, "malformed message");
-                        let err = ServerMessage::Error {
-                            message: format!("malformed message: {e}"),
-                        };
-                        let _ = tx.send(err);
                     }
                 }
+                Some(Ok(Message::Ping(_))) | Some(Ok(Message::Pong(_))) => {
+                    // Control frames handled by tungstenite automatically.
+                    continue;
+                }
+                Some(Ok(Message::Close(_))) | None => {
+                    break;
+                }
+                Some(Ok(_)) => {
+                    continue;
+                }
+                Some(Err(e)) => {
+                    warn!(peer_code = %identities[0].peer_code, error = %e, "WebSocket error");
+                    break;
+                }
             }
-            Some(Ok(Message::Binary(_))) => {
-                // Binary frames rejected — signaling is text-only.
-                warn!(peer_code = %peer_code, "binary frame rejected");
-                let _ = tx.send(ServerMessage::Error {
-                    message: "binary frames not accepted".into(),
-                });
-                continue;
-            }
-            Some(Ok(Message::Ping(_))) | Some(Ok(Message::Pong(_))) => {
-                // Control frames handled by tungstenite automatically.
-                continue;
-            }
-            Some(Ok(Message::Close(_))) | None => {
-                break;
-            }
-            Some(Ok(_)) => {
-                continue;
+        }
+
+        // --- Cleanup ---
+        // Pass session_id so remove_peer skips removal if this connection was
+        // replaced by a newer session with the same peer_code (DP-5 race guard).
+        for me in &identities {
+            if left {
+                info!(peer_code = %me.peer_code, client_ip = %client_ip, "peer left (connection kept)");
+            } else {
+                info!(peer_code = %me.peer_code, client_ip = %client_ip, "peer disconnected");
             }
-            Some(Err(e)) => {
-                warn!(peer_code = %peer_code, error = %e, "WebSocket error");
-                break;
+            debug!(peer_code = %me.peer_code, session_id = me.session_id, "session details");
+            pins.cancel(me.session_id);
+            room_manager.remove_peer(&me.room, &me.peer_code, me.session_id);
+        }
+        if let Some(err) = close_error {
+            close_with_error(tx, write_task, err).await;
+            return;
+        }
+        if !left {
+            break;
+        }
+        left_at = Some(Instant::now());
+    }
+    write_task.abort();
+}
+
+/// Validate the per-identity fields of a `register`. Returns the named room
+/// and its secret, if one was requested.
+#[allow(clippy::result_large_err)] // the error is sent to the client as-is
+#[allow(clippy::too_many_arguments)]
+pub fn validate_registration(
+    device_name: &str,
+    device_type: &DeviceType,
+    capabilities: &[String],
+    metadata: &BTreeMap<String, String>,
+    subscription: Option<&Subscription>,
+    room: Option<&str>,
+    room_secret: Option<&str>,
+) -> Result<Option<(String, String)>, ServerMessage> {
+    validate_device_name(device_name)
+        .map_err(|e| ServerMessage::field_error(ErrorCode::InvalidField, "device_name", e))?;
+    validate_device_type(device_type)
+        .map_err(|e| ServerMessage::field_error(ErrorCode::InvalidField, "device_type", e))?;
+    validate_capabilities(capabilities)
+        .map_err(|e| ServerMessage::field_error(ErrorCode::InvalidField, "capabilities", e))?;
+    validate_metadata(metadata)
+        .map_err(|e| ServerMessage::field_error(ErrorCode::InvalidField, "metadata", e))?;
+    if let Some(Err(e)) = subscription.map(validate_subscription) {
+        return Err(ServerMessage::field_error(
+            ErrorCode::InvalidField,
+            "subscription",
+            e,
+        ));
+    }
+    validate_room(room, room_secret)
+        .map_err(|(field, e)| ServerMessage::field_error(ErrorCode::InvalidField, field, e))
+}
+
+/// Index of the identity named by a message's `as` (the first identity when
+/// absent).
+#[allow(clippy::result_large_err)] // the error is sent to the client as-is
+pub fn identity_index(
+    identities: &[SessionRef],
+    name: Option<&str>,
+) -> Result<usize, ServerMessage> {
+    let Some(name) = name else {
+        return Ok(0);
+    };
+    let code = validate_signal_target(name).unwrap_or_else(|_| name.to_string());
+    identities
+        .iter()
+        .position(|me| me.peer_code == code)
+        .ok_or_else(|| {
+            ServerMessage::field_error(
+                ErrorCode::NotRegistered,
+                "as",
+                format!("no identity '{code}' on this connection"),
+            )
+        })
+}
+
+/// Validate a `Ping.nonce`, which shares the message id size limit.
+pub fn validate_ping_nonce(nonce: Option<&str>) -> Result<(), String> {
+    match nonce {
+        Some(nonce) if nonce.len() > MAX_MESSAGE_ID_BYTES => Err(format!(
+            "nonce too long ({} bytes, max {MAX_MESSAGE_ID_BYTES})",
+            nonce.len()
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Build the `pong` answering a `ping`, stamped with the current time.
+pub fn pong(config: &ConnectionConfig, nonce: Option<String>, id: Option<String>) -> ServerMessage {
+    let server_time_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64);
+    ServerMessage::Pong {
+        nonce,
+        server_time_ms,
+        monotonic_ms: config.started.elapsed().as_millis() as u64,
+        id,
+    }
+}
+
+/// Answer the pending pair request from `requester_code` to `me`. Accepting
+/// links the two sessions so `ManualSignal` is relayed between them; the
+/// requester is told either way.
+#[allow(clippy::result_large_err)] // the error is sent to the client as-is
+pub fn answer_pair_request(
+    room_manager: &RoomManager,
+    me: &SessionRef,
+    requester_code: &str,
+    accept: bool,
+) -> Result<(), ServerMessage> {
+    let to = validate_signal_target(requester_code).unwrap_or_else(|_| requester_code.to_string());
+    let requester = room_manager
+        .take_pair_request(me.session_id, &to)
+        .ok_or_else(|| {
+            ServerMessage::error(
+                ErrorCode::NotFound,
+                format!("no pending pair request from '{to}'"),
+            )
+        })?;
+    let requester_sender = room_manager.session_sender(&requester).ok_or_else(|| {
+        ServerMessage::error(
+            ErrorCode::PeerDisconnected,
+            format!("peer '{to}' is no longer connected"),
+        )
+    })?;
+    info!(from = %to, to = %me.peer_code, accept, "pair request answered");
+    let reply = if accept {
+        room_manager.link_sessions(me, &requester);
+        let peer = room_manager
+            .peer_data(&me.room, &me.peer_code, me.session_id)
+            .ok_or_else(|| {
+                ServerMessage::error(ErrorCode::NotRegistered, "peer no longer registered")
+            })?;
+        ServerMessage::PairAccepted { peer }
+    } else {
+        ServerMessage::PairRejected {
+            peer_code: me.peer_code.clone(),
+        }
+    };
+    let _ = requester_sender.send(reply);
+    Ok(())
+}
+
+/// Relay a multicast payload to each target reachable from `room` and
+/// `session_id`, with the same scoping as `Signal`. Returns one result per
+/// requested target, in order; a target listed twice is only sent the
+/// payload once.
+pub fn relay_multicast(
+    room_manager: &RoomManager,
+    room: &str,
+    session_id: u64,
+    from: &str,
+    targets: Vec<String>,
+    payload: serde_json::Value,
+) -> Vec<DeliveryResult> {
+    let mut outcomes: Vec<(String, Option<ErrorCode>)> = Vec::new();
+    targets
+        .into_iter()
+        .map(|to| {
+            let outcome = match validate_signal_target(&to) {
+                Err(_) => Some(ErrorCode::InvalidPeerCode),
+                Ok(code) => match outcomes.iter().find(|(c, _)| *c == code) {
+                    Some((_, previous)) => *previous,
+                    None => {
+                        let outcome =
+                            match room_manager.find_reachable_peer(room, session_id, &code) {
+                                None => Some(ErrorCode::NotFound),
+                                Some(sender) => {
+                                    let relay_msg = ServerMessage::Signal {
+                                        from: from.to_string(),
+                                        payload: payload.clone(),
+                                        to: Some(code.clone()),
+                                    };
+                                    sender
+                                        .send(relay_msg)
+                                        .err()
+                                        .map(|_| ErrorCode::PeerDisconnected)
+                                }
+                            };
+                        outcomes.push((code, outcome));
+                        outcome
+                    }
+                },
+            };
+            DeliveryResult {
+                to,
+                delivered: outcome.is_none(),
+                code: outcome,
             }
+        })
+        .collect()
+}
+
+/// Decode one client data frame.
+///
+/// Text frames are always parsed as JSON. Binary frames are only accepted when
+/// the connection negotiated a binary encoding. On failure, returns the error
+/// message to send back to the client.
+#[allow(clippy::result_large_err)] // the error is sent to the client as-is
+fn decode_client_frame(
+    frame: &Message,
+    encoding: Encoding,
+) -> Result<ClientMessage, ServerMessage> {
+    let (data, binary) = match frame {
+        Message::Text(text) => (text.as_bytes(), false),
+        Message::Binary(data) => (data.as_slice(), true),
+        _ => {
+            return Err(ServerMessage::error(
+                ErrorCode::Malformed,
+                "not a data frame",
+            ))
+        }
+    };
+    // Application-level message size check (defense-in-depth).
+    validate_message_size(data.len()).map_err(|e| ServerMessage::error(ErrorCode::TooLarge, e))?;
+    let codec = if binary {
+        if !encoding.is_binary() {
+            return Err(ServerMessage::error(
+                ErrorCode::BinaryRejected,
+                "binary frames not accepted",
+            ));
         }
+        encoding
+    } else {
+        Encoding::Json
+    };
+    codec
+        .decode(data)
+        .map_err(|e| ServerMessage::error(ErrorCode::Malformed, format!("malformed message: {e}")))
+}
+
+/// Take the `id` of a decoded client message, rejecting ids over
+/// [`MAX_MESSAGE_ID_BYTES`]. An oversized id is not echoed back.
+#[allow(clippy::result_large_err)]
+fn checked_message_id(msg: &ClientMessage) -> Result<Option<String>, ServerMessage> {
+    validate_message_id(msg.id())
+        .map(|()| msg.id().map(str::to_owned))
+        .map_err(|e| ServerMessage::field_error(ErrorCode::InvalidField, "id", e))
+}
+
+/// Best-effort extraction of the `id` from a frame that was not (or could not
+/// be) decoded as a [`ClientMessage`], so the resulting error can still be
+/// correlated. Returns `None` unless `id` is a string within the size limit.
+fn peek_message_id(frame: &Message, encoding: Encoding) -> Option<String> {
+    #[derive(serde::Deserialize)]
+    struct IdOnly {
+        id: Option<String>,
     }
 
-    // --- Cleanup ---
-    // Pass session_id so remove_peer skips removal if this connection was
-    // replaced by a newer session with the same peer_code (DP-5 race guard).
-    info!(peer_code = %peer_code, client_ip = %client_ip, "peer disconnected");
-    debug!(peer_code = %peer_code, session_id = session_id, "session details");
-    room_manager.remove_peer(&client_ip, &peer_code, session_id);
-    write_task.abort();
+    let peeked: IdOnly = match frame {
+        Message::Text(text) => Encoding::Json.decode(text.as_bytes()).ok()?,
+        Message::Binary(data) if encoding.is_binary() => encoding.decode(data).ok()?,
+        _ => return None,
+    };
+    peeked.id.filter(|id| validate_message_id(Some(id)).is_ok())
+}
+
+/// Encode a server message as a WebSocket frame for the given encoding.
+fn encode_server_frame(msg: &ServerMessage, encoding: Encoding) -> Result<Message, CodecError> {
+    let bytes = encoding.encode(msg)?;
+    if encoding.is_binary() {
+        Ok(Message::Binary(bytes))
+    } else {
+        // JSON output is always valid UTF-8.
+        Ok(Message::Text(String::from_utf8_lossy(&bytes).into_owned()))
+    }
+}
+
+/// [`RoomManager::authorize_named_room`] on the blocking pool, since the
+/// secret check runs a deliberately slow KDF.
+async fn authorize_named_room(
+    room_manager: &Arc<RoomManager>,
+    name: String,
+    secret: String,
+    ip: &str,
+) -> Result<String, RoomError> {
+    let room_manager = Arc::clone(room_manager);
+    let ip = ip.to_string();
+    tokio::task::spawn_blocking(move || room_manager.authorize_named_room(&name, &secret, &ip))
+        .await
+        .unwrap_or(Err(RoomError::Unauthorized))
+}
+
+/// Count a registration against the client IP, returning the `rate_limited`
+/// error to send if [`IpLimiter::try_register`] refuses it.
+fn registration_rate_error(limits: &IpLimiter, ip: &str) -> Option<ServerMessage> {
+    let retry_after = limits.try_register(ip).err()?;
+    Some(ServerMessage::rate_limited(retry_after.as_millis() as u64))
+}
+
+/// Time allowed for [`reject_connection`] to read the request and write
+/// its response.
+pub const REJECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// `Retry-After` sent with connections refused for load (503 and 429).
+pub const REJECT_RETRY_AFTER_SECS: u64 = 5;
+
+/// The [`HttpRejection`] body for `reason`.
+pub fn rejection(reason: RejectionReason) -> HttpRejection {
+    let (message, retry_after_secs) = match reason {
+        RejectionReason::ServerFull => (
+            "server is at its connection limit",
+            Some(REJECT_RETRY_AFTER_SECS),
+        ),
+        RejectionReason::TooManyConnections => (
+            "too many connections from this address",
+            Some(REJECT_RETRY_AFTER_SECS),
+        ),
+        RejectionReason::OriginNotAllowed => ("origin not allowed", None),
+        RejectionReason::UpgradeTimeout => ("websocket upgrade timed out", None),
+        RejectionReason::Unknown => ("connection refused", None),
+    };
+    HttpRejection {
+        reason,
+        message: message.into(),
+        retry_after_secs,
+    }
+}
+
+/// Handshake-callback form of [`reject_connection`]: refuse the upgrade with
+/// `status` and the [`rejection`] body.
+fn rejection_response(status: StatusCode, reason: RejectionReason) -> ErrorResponse {
+    let rejection = rejection(reason);
+    let body = serde_json::to_string(&rejection).unwrap_or_default();
+    let mut err = ErrorResponse::new(Some(body));
+    *err.status_mut() = status;
+    let headers = err.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    if let Some(secs) = rejection.retry_after_secs {
+        headers.insert(header::RETRY_AFTER, HeaderValue::from(secs));
+    }
+    err
+}
+
+/// Answer a connection refused at accept time (or whose upgrade timed out)
+/// with `status`, the [`rejection`] body and its `Retry-After`, if any,
+/// instead of a TCP reset.
+///
+/// After the response, reads what the client sent until it closes, so the
+/// close does not reset the connection before the client sees the
+/// response. Everything is bounded by [`REJECT_TIMEOUT`]; callers also cap
+/// how many rejections run at once.
+pub async fn reject_connection(mut stream: TcpStream, status: StatusCode, reason: RejectionReason) {
+    let rejection = rejection(reason);
+    let body = serde_json::to_string(&rejection).unwrap_or_default();
+    let retry_after = rejection
+        .retry_after_secs
+        .map(|secs| format!("Retry-After: {secs}\r\n"))
+        .unwrap_or_default();
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\n{retry_after}Cache-Control: no-store\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len(),
+    );
+    let _ = tokio::time::timeout(REJECT_TIMEOUT, async {
+        let _ = stream.write_all(response.as_bytes()).await;
+        let _ = stream.shutdown().await;
+        let mut buf = [0u8; 1024];
+        while matches!(stream.read(&mut buf).await, Ok(n) if n > 0) {}
+    })
+    .await;
+}
+
+/// Split `stream` into itself and an unregistered duplicate of the same
+/// socket, for [`reject_connection`] once the original is gone. The
+/// duplicate is a second file descriptor, held only while the upgrade is in
+/// progress.
+fn duplicate_stream(stream: TcpStream) -> std::io::Result<(TcpStream, std::net::TcpStream)> {
+    let stream = stream.into_std()?;
+    let duplicate = stream.try_clone()?;
+    Ok((TcpStream::from_std(stream)?, duplicate))
+}
+
+/// Send a final error to the client and wait briefly for it to be flushed
+/// before the connection is torn down.
+async fn close_with_error(
+    tx: mpsc::UnboundedSender<ServerMessage>,
+    write_task: tokio::task::JoinHandle<()>,
+    error: ServerMessage,
+) {
+    let _ = tx.send(error);
+    drop(tx);
+    let _ = tokio::time::timeout(std::time::Duration::from_secs(1), write_task).await;
 }
 
 /// Normalize and validate a peer code: strip hyphens, then check non-empty,
@@ -657,6 +2403,156 @@ pub fn validate_peer_code(code: &str) -> Result<String, String> {
     Ok(normalized)
 }
 
+/// Header set by the Fly.io proxy to the real client IP. Fly strips any
+/// client-sent copy, so it cannot be spoofed through Fly.
+pub const FLY_CLIENT_IP: &str = "fly-client-ip";
+
+/// Standard reverse-proxy header; the first address is the client.
+pub const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// Effective client IP of a connection and where it was taken from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientIp {
+    pub ip: String,
+    pub source: IpSource,
+    /// The forwarded header `ip` was read from, unless `source` is
+    /// [`IpSource::Socket`].
+    pub header: Option<&'static str>,
+}
+
+impl ClientIp {
+    /// Key of the IP room. For self-hosted mode, all private, loopback,
+    /// link-local and CGNAT addresses share one room (`"local"`), so devices
+    /// on the same LAN find each other even when the host machine connects
+    /// via 127.0.0.1 and others via 192.168.x.x.
+    pub fn room_key(&self) -> String {
+        if is_private_ip(&self.ip) {
+            "local".to_string()
+        } else {
+            self.ip.clone()
+        }
+    }
+
+    /// Classification of the address, for `room_info`.
+    pub fn kind(&self) -> RoomKind {
+        if is_cgnat_ip(&self.ip) {
+            RoomKind::Cgnat
+        } else if is_private_ip(&self.ip) {
+            RoomKind::Private
+        } else {
+            RoomKind::Public
+        }
+    }
+}
+
+/// Client IP carried by forwarded headers, with the header it came from.
+/// `Fly-Client-IP` wins over `X-Forwarded-For`; values that are not an IP
+/// address are ignored (RENDEZVOUS-HARDENING-1 P4).
+pub fn forwarded_client_ip(
+    fly_client_ip: Option<&str>,
+    x_forwarded_for: Option<&str>,
+) -> Option<(&'static str, String)> {
+    if let Some(value) = fly_client_ip {
+        let ip = value.trim();
+        if ip.parse::<IpAddr>().is_ok() {
+            return Some((FLY_CLIENT_IP, ip.to_string()));
+        }
+        warn!("Fly-Client-IP contains invalid IP: {:?}", ip);
+    }
+    let value = x_forwarded_for?;
+    let ip = value.split(',').next().unwrap_or(value).trim();
+    if ip.parse::<IpAddr>().is_ok() {
+        return Some((X_FORWARDED_FOR, ip.to_string()));
+    }
+    warn!("X-Forwarded-For contains invalid IP: {:?}", ip);
+    None
+}
+
+/// Choose the effective client IP (AC-16).
+///
+/// A forwarded IP is used only from a configured trusted proxy, or from a
+/// private source address: that is a PaaS proxy connecting internally (Fly,
+/// Railway, etc.), since external clients cannot connect from private IPs.
+/// Otherwise the socket address is used (fail-closed).
+pub fn resolve_client_ip(
+    addr: SocketAddr,
+    trusted_proxies: &[IpAddr],
+    forwarded: Option<(&'static str, String)>,
+) -> ClientIp {
+    let socket = ClientIp {
+        ip: addr.ip().to_string(),
+        source: IpSource::Socket,
+        header: None,
+    };
+    let Some((header, ip)) = forwarded else {
+        return socket;
+    };
+    let source = if trusted_proxies.contains(&addr.ip()) {
+        IpSource::TrustedProxy
+    } else if is_private_ip(&socket.ip) {
+        IpSource::PrivateSourceProxy
+    } else {
+        return socket;
+    };
+    ClientIp {
+        ip,
+        source,
+        header: Some(header),
+    }
+}
+
+/// Room kind reported for a registration: [`RoomKind::Named`] for a named
+/// room, otherwise the classification of the client IP.
+fn room_kind(client: &ClientIp, named: bool) -> RoomKind {
+    if named {
+        RoomKind::Named
+    } else {
+        client.kind()
+    }
+}
+
+/// Diagnostics for the room `room_key`, as sent in `room_info` and served at
+/// `/whoami`.
+pub fn room_info(
+    room_manager: &RoomManager,
+    room_key: &str,
+    kind: RoomKind,
+    client: &ClientIp,
+) -> RoomInfo {
+    RoomInfo {
+        kind,
+        fingerprint: room_manager.fingerprint(room_key),
+        size: room_manager.room_size(room_key) as u32,
+        ip_source: client.source,
+        forwarded_header: client.header.map(str::to_string),
+    }
+}
+
+/// Path of a plain HTTP request, without the query string.
+fn request_path(request: &str) -> Option<&str> {
+    let target = request.lines().next()?.split_whitespace().nth(1)?;
+    Some(target.split('?').next().unwrap_or(target))
+}
+
+/// Value of the first header `name` (case-insensitive) in a plain HTTP
+/// request.
+fn request_header<'a>(request: &'a str, name: &str) -> Option<&'a str> {
+    request
+        .lines()
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim())
+}
+
+/// Check if an address is in the CGNAT / shared address space
+/// (100.64.0.0/10).
+fn is_cgnat_ip(ip: &str) -> bool {
+    ip.parse::<std::net::Ipv4Addr>()
+        .is_ok_and(|v4| v4.octets()[0] == 100 && (64..=127).contains(&v4.octets()[1]))
+}
+
 /// Check if an IP address is private (RFC 1918), loopback, or link-local.
 fn is_private_ip(ip: &str) -> bool {
     // IPv4 loopback
@@ -717,6 +2613,7 @@ fn is_private_ip(ip: &str) -> bool {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::protocol::subprotocol;
 
     // ── validate_message_size ───────────────────────────────────
 
@@ -876,6 +2773,84 @@ mod tests {
         );
     }
 
+    // ── Capabilities and metadata ───────────────────────────────
+
+    #[test]
+    fn capabilities_are_bounded_distinct_tokens() {
+        let caps = |list: &[&str]| list.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        assert!(validate_capabilities(&caps(&["webrtc", "bolt.v2", "zstd"])).is_ok());
+        assert!(validate_capabilities(&caps(&["WebRTC"])).is_err());
+        assert!(validate_capabilities(&caps(&[""])).is_err());
+        assert!(validate_capabilities(&caps(&["zstd", "zstd"])).is_err());
+        let many: Vec<String> = (0..=MAX_CAPABILITIES).map(|i| format!("c{i}")).collect();
+        assert!(validate_capabilities(&many).is_err());
+    }
+
+    #[test]
+    fn metadata_keys_and_values_are_bounded() {
+        let ok = BTreeMap::from([(
+            "bolt.inbox".to_string(),
+            "x".repeat(MAX_METADATA_VALUE_BYTES),
+        )]);
+        assert!(validate_metadata(&ok).is_ok());
+        let long = BTreeMap::from([("k".to_string(), "x".repeat(MAX_METADATA_VALUE_BYTES + 1))]);
+        assert!(validate_metadata(&long).is_err());
+        let bad_key = BTreeMap::from([("Key".to_string(), String::new())]);
+        assert!(validate_metadata(&bad_key).is_err());
+        let many: BTreeMap<String, String> = (0..=MAX_METADATA_ENTRIES)
+            .map(|i| (format!("k{i}"), String::new()))
+            .collect();
+        assert!(validate_metadata(&many).is_err());
+    }
+
+    #[test]
+    fn subscriptions_are_bounded() {
+        let sub = |page_size, max_events_per_sec| Subscription {
+            device_types: vec![DeviceType::Phone],
+            capabilities: vec!["webrtc".into()],
+            page_size,
+            max_events_per_sec,
+            ..Default::default()
+        };
+        assert!(validate_subscription(&Subscription::default()).is_ok());
+        assert!(validate_subscription(&sub(Some(MAX_PAGE_SIZE), Some(1))).is_ok());
+        assert!(validate_subscription(&sub(Some(0), None)).is_err());
+        assert!(validate_subscription(&sub(Some(MAX_PAGE_SIZE + 1), None)).is_err());
+        assert!(validate_subscription(&sub(None, Some(0))).is_err());
+        assert!(validate_subscription(&sub(None, Some(MAX_ROOM_EVENTS_PER_SECOND + 1))).is_err());
+        let bad_capability = Subscription {
+            capabilities: vec!["WebRTC".into()],
+            ..Default::default()
+        };
+        assert!(validate_subscription(&bad_capability).is_err());
+        let many_types = Subscription {
+            device_types: vec![DeviceType::Desktop; MAX_SUBSCRIPTION_DEVICE_TYPES + 1],
+            ..Default::default()
+        };
+        assert!(validate_subscription(&many_types).is_err());
+    }
+
+    // ── Identities ──────────────────────────────────────────────
+
+    #[test]
+    fn identity_index_defaults_to_first_and_normalizes() {
+        let identities: Vec<SessionRef> = ["DEVICE", "INBOX"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, code)| SessionRef {
+                room: "local".into(),
+                peer_code: code.into(),
+                session_id: i as u64,
+            })
+            .collect();
+        assert_eq!(identity_index(&identities, None).unwrap(), 0);
+        assert_eq!(identity_index(&identities, Some("IN-BOX")).unwrap(), 1);
+        assert!(matches!(
+            identity_index(&identities, Some("KIOSK")),
+            Err(ServerMessage::Error { field: Some(f), .. }) if f == "as"
+        ));
+    }
+
     // ── RateLimit ───────────────────────────────────────────────
 
     #[tokio::test]
@@ -932,6 +2907,17 @@ mod tests {
         assert!(rl.check().is_ok());
     }
 
+    #[tokio::test]
+    async fn rate_limit_retry_after_tracks_window() {
+        tokio::time::pause();
+        let rl = RateLimit::new();
+        assert_eq!(rl.retry_after_ms(), 1000);
+        tokio::time::advance(std::time::Duration::from_millis(400)).await;
+        assert_eq!(rl.retry_after_ms(), 600);
+        tokio::time::advance(std::time::Duration::from_secs(2)).await;
+        assert_eq!(rl.retry_after_ms(), 0);
+    }
+
     #[tokio::test]
     async fn rate_limit_violation_count_resets_on_success() {
         tokio::time::pause();
@@ -1009,6 +2995,86 @@ mod tests {
         assert_eq!(raw_ip, "198.51.100.1");
     }
 
+    #[test]
+    fn forwarded_client_ip_prefers_fly_and_skips_invalid_values() {
+        assert_eq!(
+            forwarded_client_ip(Some(" 203.0.113.7 "), Some("198.51.100.9")),
+            Some((FLY_CLIENT_IP, "203.0.113.7".to_string()))
+        );
+        assert_eq!(
+            forwarded_client_ip(Some("not-an-ip"), Some("198.51.100.9, 10.0.0.1")),
+            Some((X_FORWARDED_FOR, "198.51.100.9".to_string()))
+        );
+        assert_eq!(forwarded_client_ip(None, Some("spoofed.ip")), None);
+        assert_eq!(forwarded_client_ip(None, None), None);
+    }
+
+    #[test]
+    fn may_forward_covers_trusted_and_private_sources() {
+        let proxy: IpAddr = "198.51.100.1".parse().unwrap();
+        let config = ConnectionConfig {
+            trusted_proxies: vec![proxy],
+            ..ConnectionConfig::new()
+        };
+        assert!(config.may_forward(proxy));
+        assert!(config.may_forward("172.16.0.2".parse().unwrap()));
+        assert!(config.may_forward("::1".parse().unwrap()));
+        assert!(!config.may_forward("203.0.113.9".parse().unwrap()));
+    }
+
+    #[test]
+    fn resolve_client_ip_reports_its_source() {
+        let forwarded = || Some((X_FORWARDED_FOR, "203.0.113.7".to_string()));
+        let proxy: SocketAddr = "198.51.100.1:4000".parse().unwrap();
+
+        let client = resolve_client_ip(proxy, &[proxy.ip()], forwarded());
+        assert_eq!(client.ip, "203.0.113.7");
+        assert_eq!(client.source, IpSource::TrustedProxy);
+        assert_eq!(client.header, Some(X_FORWARDED_FOR));
+
+        let client = resolve_client_ip(proxy, &[], forwarded());
+        assert_eq!(client.ip, "198.51.100.1");
+        assert_eq!(client.source, IpSource::Socket);
+        assert_eq!(client.header, None);
+
+        let internal: SocketAddr = "172.16.0.2:4000".parse().unwrap();
+        let client = resolve_client_ip(internal, &[], forwarded());
+        assert_eq!(client.ip, "203.0.113.7");
+        assert_eq!(client.source, IpSource::PrivateSourceProxy);
+    }
+
+    #[test]
+    fn client_ip_kind_and_room_key() {
+        let client = |ip: &str| ClientIp {
+            ip: ip.to_string(),
+            source: IpSource::Socket,
+            header: None,
+        };
+        for (ip, kind, key) in [
+            ("192.168.1.20", RoomKind::Private, "local"),
+            ("::1", RoomKind::Private, "local"),
+            ("100.101.102.103", RoomKind::Cgnat, "local"),
+            ("100.128.0.1", RoomKind::Public, "100.128.0.1"),
+            ("203.0.113.7", RoomKind::Public, "203.0.113.7"),
+        ] {
+            assert_eq!(client(ip).kind(), kind, "{ip}");
+            assert_eq!(client(ip).room_key(), key, "{ip}");
+        }
+    }
+
+    #[test]
+    fn plain_http_requests_are_parsed_for_whoami() {
+        let request =
+            "GET /whoami?x=1 HTTP/1.1\r\nHost: a\r\nX-Forwarded-For: 203.0.113.7\r\n\r\nbody: no";
+        assert_eq!(request_path(request), Some("/whoami"));
+        assert_eq!(
+            request_header(request, X_FORWARDED_FOR),
+            Some("203.0.113.7")
+        );
+        assert_eq!(request_header(request, "body"), None);
+        assert_eq!(request_path(""), None);
+    }
+
     #[test]
     fn xff_empty_trusted_list_always_ignores_header() {
         // Default: empty trusted_proxies list → header always ignored.
@@ -1025,6 +3091,367 @@ mod tests {
         assert_eq!(raw_ip, "127.0.0.1");
     }
 
+    // ── Version negotiation ─────────────────────────────────────
+
+    #[test]
+    fn subprotocol_absent_is_not_negotiated() {
+        assert_eq!(select_subprotocol(""), Ok(None));
+        assert_eq!(select_subprotocol("graphql-ws, chat"), Ok(None));
+    }
+
+    #[test]
+    fn subprotocol_picks_highest_supported() {
+        assert_eq!(
+            select_subprotocol("chat, bolt-rendezvous.v1"),
+            Ok(Some((
+                PROTOCOL_VERSION,
+                Encoding::Json,
+                "bolt-rendezvous.v1"
+            )))
+        );
+        let offered = format!(
+            "bolt-rendezvous.v1, {}",
+            subprotocol(PROTOCOL_VERSION + 1, Encoding::Json)
+        );
+        assert_eq!(
+            select_subprotocol(&offered),
+            Ok(Some((
+                PROTOCOL_VERSION,
+                Encoding::Json,
+                "bolt-rendezvous.v1"
+            )))
+        );
+    }
+
+    #[test]
+    fn subprotocol_echoes_the_offered_alias() {
+        assert_eq!(
+            select_subprotocol("bolt-rendezvous.v01 , chat"),
+            Ok(Some((1, Encoding::Json, "bolt-rendezvous.v01")))
+        );
+        assert_eq!(
+            select_subprotocol("bolt-rendezvous.v1.json"),
+            Ok(Some((1, Encoding::Json, "bolt-rendezvous.v1.json")))
+        );
+    }
+
+    #[test]
+    fn subprotocol_keeps_client_encoding_preference() {
+        assert_eq!(
+            select_subprotocol("bolt-rendezvous.v1.cbor, bolt-rendezvous.v1"),
+            Ok(Some((1, Encoding::Cbor, "bolt-rendezvous.v1.cbor")))
+        );
+        assert_eq!(
+            select_subprotocol("bolt-rendezvous.v1, bolt-rendezvous.v1.cbor"),
+            Ok(Some((1, Encoding::Json, "bolt-rendezvous.v1")))
+        );
+    }
+
+    #[test]
+    fn subprotocol_only_unsupported_is_rejected() {
+        let offered = subprotocol(PROTOCOL_VERSION + 1, Encoding::Cbor);
+        let err = select_subprotocol(&offered).unwrap_err();
+        assert!(err.starts_with("unsupported_protocol_version:"), "{err}");
+    }
+
+    #[test]
+    fn protocol_version_defaults_for_legacy_clients() {
+        assert_eq!(
+            resolve_protocol_version(None, None),
+            Ok(MIN_PROTOCOL_VERSION)
+        );
+        assert_eq!(resolve_protocol_version(None, Some(1)), Ok(1));
+        assert_eq!(resolve_protocol_version(Some(1), None), Ok(1));
+    }
+
+    #[test]
+    fn protocol_version_rejects_unsupported_or_conflicting() {
+        assert!(resolve_protocol_version(None, Some(PROTOCOL_VERSION + 1)).is_err());
+        assert!(resolve_protocol_version(None, Some(0)).is_err());
+        assert!(resolve_protocol_version(Some(1), Some(PROTOCOL_VERSION + 1)).is_err());
+    }
+
+    #[test]
+    fn server_limits_match_constants() {
+        let limits = server_limits(&ConnectionConfig::new());
+        assert_eq!(limits.max_message_bytes, MAX_MESSAGE_BYTES as u64);
+        assert_eq!(limits.rate_limit_per_second, RATE_LIMIT_PER_SECOND);
+        assert_eq!(limits.idle_timeout_secs, IDLE_TIMEOUT.as_secs());
+        assert_eq!(limits.max_peer_code_bytes, MAX_PEER_CODE_BYTES as u32);
+    }
+
+    // ── Frame encoding ──────────────────────────────────────────
+
+    #[test]
+    fn text_frames_decode_as_json_on_any_connection() {
+        let frame = Message::Text(r#"{"type":"ping"}"#.into());
+        assert!(matches!(
+            decode_client_frame(&frame, Encoding::Json),
+            Ok(ClientMessage::Ping { .. })
+        ));
+        assert!(matches!(
+            decode_client_frame(&frame, Encoding::Cbor),
+            Ok(ClientMessage::Ping { .. })
+        ));
+    }
+
+    #[test]
+    fn binary_frames_rejected_without_binary_encoding() {
+        let frame = Message::Binary(
+            Encoding::Cbor
+                .encode(&ClientMessage::Ping {
+                    nonce: None,
+                    id: None,
+                })
+                .unwrap(),
+        );
+        match decode_client_frame(&frame, Encoding::Json) {
+            Err(ServerMessage::Error { code, .. }) => {
+                assert_eq!(code, Some(ErrorCode::BinaryRejected))
+            }
+            other => panic!("expected binary_rejected, got {other:?}"),
+        }
+        assert!(matches!(
+            decode_client_frame(&frame, Encoding::Cbor),
+            Ok(ClientMessage::Ping { .. })
+        ));
+    }
+
+    #[test]
+    fn oversized_and_malformed_frames_rejected() {
+        let frame = Message::Binary(vec![0u8; MAX_MESSAGE_BYTES + 1]);
+        match decode_client_frame(&frame, Encoding::Cbor) {
+            Err(ServerMessage::Error { code, .. }) => assert_eq!(code, Some(ErrorCode::TooLarge)),
+            other => panic!("expected too_large, got {other:?}"),
+        }
+        let frame = Message::Text("{not json".into());
+        match decode_client_frame(&frame, Encoding::Json) {
+            Err(ServerMessage::Error { code, message, .. }) => {
+                assert_eq!(code, Some(ErrorCode::Malformed));
+                assert!(message.starts_with("malformed message:"));
+            }
+            other => panic!("expected malformed, got {other:?}"),
+        }
+    }
+
+    // ── Ping ────────────────────────────────────────────────────
+
+    #[test]
+    fn pong_echoes_nonce_with_timestamps() {
+        let config = ConnectionConfig::new();
+        match pong(&config, Some("n".into()), Some("p1".into())) {
+            ServerMessage::Pong {
+                nonce,
+                server_time_ms,
+                monotonic_ms,
+                id,
+            } => {
+                assert_eq!(nonce.as_deref(), Some("n"));
+                assert_eq!(id.as_deref(), Some("p1"));
+                assert!(server_time_ms > 1_600_000_000_000);
+                assert!(monotonic_ms < 60_000);
+            }
+            other => panic!("expected pong, got {other:?}"),
+        }
+        assert!(validate_ping_nonce(None).is_ok());
+        assert!(validate_ping_nonce(Some(&"a".repeat(MAX_MESSAGE_ID_BYTES))).is_ok());
+        assert!(validate_ping_nonce(Some(&"a".repeat(MAX_MESSAGE_ID_BYTES + 1))).is_err());
+    }
+
+    // ── Multicast ───────────────────────────────────────────────
+
+    #[test]
+    fn multicast_target_count_bounded() {
+        assert!(validate_multicast_targets(&[]).is_err());
+        assert!(validate_multicast_targets(&vec!["A".to_string(); MAX_MULTICAST_TARGETS]).is_ok());
+        assert!(
+            validate_multicast_targets(&vec!["A".to_string(); MAX_MULTICAST_TARGETS + 1]).is_err()
+        );
+    }
+
+    #[test]
+    fn multicast_reports_per_target_results_room_scoped() {
+        let rm = RoomManager::new();
+        let peer = |code: &str| {
+            let (tx, rx) = mpsc::unbounded_channel();
+            let info = PeerInfo {
+                peer_code: code.to_string(),
+                device_name: code.to_string(),
+                device_type: DeviceType::Desktop,
+                sender: tx,
+                session_id: 0,
+                wt_url: None,
+                wt_cert_hash: None,
+                presence: Presence::Available,
+                capabilities: vec![],
+                metadata: BTreeMap::new(),
+                discoverable: true,
+                event_budget: Default::default(),
+                last_active: Instant::now(),
+                subscription: Default::default(),
+            };
+            (info, rx)
+        };
+        let (a, mut a_rx) = peer("AAA");
+        let (b, mut b_rx) = peer("BBB");
+        let (c, mut c_rx) = peer("CCC");
+        rm.add_peer("room1", a).unwrap();
+        rm.add_peer("room1", b).unwrap();
+        rm.add_peer("room2", c).unwrap();
+        while a_rx.try_recv().is_ok() {}
+
+        let targets = ["AAA", "B-BB", "CCC", "bad!", "AAA"]
+            .iter()
+            .map(|t| t.to_string())
+            .collect();
+        let results = relay_multicast(&rm, "room1", 0, "ME", targets, serde_json::json!({"x": 1}));
+        let summary: Vec<(&str, bool, Option<ErrorCode>)> = results
+            .iter()
+            .map(|r| (r.to.as_str(), r.delivered, r.code))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("AAA", true, None),
+                ("B-BB", true, None),
+                ("CCC", false, Some(ErrorCode::NotFound)),
+                ("bad!", false, Some(ErrorCode::InvalidPeerCode)),
+                ("AAA", true, None),
+            ]
+        );
+
+        // One copy per distinct target; nothing crosses rooms.
+        assert!(matches!(a_rx.try_recv(), Ok(ServerMessage::Signal { .. })));
+        assert!(a_rx.try_recv().is_err());
+        assert!(matches!(b_rx.try_recv(), Ok(ServerMessage::Signal { .. })));
+        assert!(c_rx.try_recv().is_err());
+    }
+
+    // ── Named rooms ─────────────────────────────────────────────
+
+    #[test]
+    fn room_fields_validated_together() {
+        assert_eq!(validate_room(None, None), Ok(None));
+        assert_eq!(
+            validate_room(Some("Design-Team"), Some("hunter22")),
+            Ok(Some(("design-team".into(), "hunter22".into())))
+        );
+        assert_eq!(
+            validate_room(Some("team"), None).unwrap_err().0,
+            "room_secret"
+        );
+        assert_eq!(validate_room(None, Some("hunter22")).unwrap_err().0, "room");
+        assert_eq!(
+            validate_room(Some(""), Some("hunter22")).unwrap_err().0,
+            "room"
+        );
+        assert_eq!(
+            validate_room(Some("a b"), Some("hunter22")).unwrap_err().0,
+            "room"
+        );
+        let long = "a".repeat(MAX_ROOM_NAME_BYTES + 1);
+        assert_eq!(
+            validate_room(Some(&long), Some("hunter22")).unwrap_err().0,
+            "room"
+        );
+        assert_eq!(
+            validate_room(Some("team"), Some("short")).unwrap_err().0,
+            "room_secret"
+        );
+        let long = "s".repeat(MAX_ROOM_SECRET_BYTES + 1);
+        assert_eq!(
+            validate_room(Some("team"), Some(&long)).unwrap_err().0,
+            "room_secret"
+        );
+    }
+
+    // ── Device types ────────────────────────────────────────────
+
+    #[test]
+    fn device_type_known_kinds_always_valid() {
+        for kind in DeviceType::KNOWN {
+            assert!(validate_device_type(&DeviceType::from(*kind)).is_ok());
+        }
+    }
+
+    #[test]
+    fn device_type_unknown_kinds_bounded() {
+        assert!(validate_device_type(&DeviceType::from("smart-fridge_2")).is_ok());
+        assert!(validate_device_type(&DeviceType::Unknown(String::new())).is_err());
+        assert!(validate_device_type(&DeviceType::from(
+            "x".repeat(MAX_DEVICE_TYPE_BYTES + 1).as_str()
+        ))
+        .is_err());
+        assert!(validate_device_type(&DeviceType::from("Fridge")).is_err());
+        assert!(validate_device_type(&DeviceType::from("<script>")).is_err());
+    }
+
+    // ── Message ids ─────────────────────────────────────────────
+
+    #[test]
+    fn message_id_length_is_bounded() {
+        assert!(validate_message_id(None).is_ok());
+        assert!(validate_message_id(Some(&"a".repeat(MAX_MESSAGE_ID_BYTES))).is_ok());
+        assert!(validate_message_id(Some(&"a".repeat(MAX_MESSAGE_ID_BYTES + 1))).is_err());
+
+        let msg = ClientMessage::Ping {
+            nonce: None,
+            id: Some("a".repeat(MAX_MESSAGE_ID_BYTES + 1)),
+        };
+        match checked_message_id(&msg) {
+            Err(ServerMessage::Error { field, id, .. }) => {
+                assert_eq!(field.as_deref(), Some("id"));
+                assert_eq!(id, None, "oversized ids are not echoed");
+            }
+            other => panic!("expected invalid_field, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn message_id_peeked_from_undecodable_frames() {
+        // Unknown message type, but the id is still readable.
+        let frame = Message::Text(r#"{"type":"frobnicate","id":"m-1"}"#.into());
+        assert!(decode_client_frame(&frame, Encoding::Json).is_err());
+        assert_eq!(
+            peek_message_id(&frame, Encoding::Json).as_deref(),
+            Some("m-1")
+        );
+
+        let frame = Message::Text(r#"{"type":"signal","id":42}"#.into());
+        assert_eq!(peek_message_id(&frame, Encoding::Json), None);
+        let frame = Message::Text("{not json".into());
+        assert_eq!(peek_message_id(&frame, Encoding::Json), None);
+
+        let bytes = Encoding::Cbor
+            .encode(&serde_json::json!({"type": "bogus", "id": "m-2"}))
+            .unwrap();
+        let frame = Message::Binary(bytes);
+        assert_eq!(
+            peek_message_id(&frame, Encoding::Cbor).as_deref(),
+            Some("m-2")
+        );
+        assert_eq!(peek_message_id(&frame, Encoding::Json), None);
+    }
+
+    #[test]
+    fn server_frames_follow_connection_encoding() {
+        let msg = ServerMessage::PeerLeft {
+            peer_code: "ABC".into(),
+            version: None,
+        };
+        match encode_server_frame(&msg, Encoding::Json).unwrap() {
+            Message::Text(text) => assert!(text.contains(r#""type":"peer_left""#)),
+            other => panic!("expected text frame, got {other:?}"),
+        }
+        match encode_server_frame(&msg, Encoding::Cbor).unwrap() {
+            Message::Binary(bytes) => {
+                let decoded: ServerMessage = Encoding::Cbor.decode(&bytes).unwrap();
+                assert!(matches!(decoded, ServerMessage::PeerLeft { .. }));
+            }
+            other => panic!("expected binary frame, got {other:?}"),
+        }
+    }
+
     // ── Constants sanity ────────────────────────────────────────
 
     #[test]
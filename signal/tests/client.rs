@@ -0,0 +1,1254 @@
+//! End-to-end tests for the `client` feature of the protocol crate against a
+//! real server bound to an ephemeral port.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use bolt_rendezvous::SignalingServer;
+use bolt_rendezvous_protocol::client::{ClientConfig, ClientError, ClientEvent, RendezvousClient};
+use bolt_rendezvous_protocol::{
+    ClientMessage, DeviceType, Encoding, ErrorCode, HttpRejection, IpSource, Presence,
+    RejectionReason, RoomInfo, RoomKind, ServerMessage, Subscription,
+};
+use futures_util::StreamExt;
+use serde_json::json;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::tungstenite::Message;
+
+async fn start_server() -> SocketAddr {
+    start_server_with(|server| server).await
+}
+
+async fn start_server_with(
+    configure: impl FnOnce(SignalingServer) -> SignalingServer,
+) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = configure(SignalingServer::new(addr));
+    tokio::spawn(async move {
+        let _ = server.serve(listener).await;
+    });
+    addr
+}
+
+fn config(addr: SocketAddr, code: &str) -> ClientConfig {
+    ClientConfig::new(format!("ws://{addr}"), code, code, DeviceType::Desktop)
+}
+
+/// Wait for the next relayed signal, skipping other events.
+async fn next_signal(client: &mut RendezvousClient) -> (String, serde_json::Value) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            match client.next_event().await {
+                Some(ClientEvent::Message(ServerMessage::Signal { from, payload, .. })) => {
+                    return (from, payload)
+                }
+                Some(_) => continue,
+                None => panic!("client stopped"),
+            }
+        }
+    })
+    .await
+    .expect("timed out waiting for signal")
+}
+
+/// Wait until `client` sees `code` in its peer list.
+async fn wait_for_peer(client: &RendezvousClient, code: &str) {
+    let mut peers = client.peers();
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        peers.wait_for(|list| list.iter().any(|p| p.peer_code == code)),
+    )
+    .await
+    .expect("timed out waiting for peer")
+    .unwrap();
+}
+
+#[tokio::test]
+async fn clients_discover_each_other_and_relay_signals() {
+    let addr = start_server().await;
+    let mut alice = RendezvousClient::connect(config(addr, "ALICE1"))
+        .await
+        .unwrap();
+    let mut bob = RendezvousClient::connect(config(addr, "BOB123"))
+        .await
+        .unwrap();
+
+    wait_for_peer(&alice, "BOB123").await;
+    wait_for_peer(&bob, "ALICE1").await;
+
+    alice
+        .send_signal("BOB123", json!({"sdp": "offer"}))
+        .unwrap();
+    let (from, payload) = next_signal(&mut bob).await;
+    assert_eq!(from, "ALICE1");
+    assert_eq!(payload, json!({"sdp": "offer"}));
+
+    bob.send_signal("ALICE1", json!({"sdp": "answer"})).unwrap();
+    let (from, _) = next_signal(&mut alice).await;
+    assert_eq!(from, "BOB123");
+}
+
+#[tokio::test]
+async fn json_and_cbor_clients_interoperate() {
+    let addr = start_server().await;
+    let mut json_client = RendezvousClient::connect(config(addr, "JSON01"))
+        .await
+        .unwrap();
+    let mut cbor_client =
+        RendezvousClient::connect(config(addr, "CBOR01").with_encoding(Encoding::Cbor))
+            .await
+            .unwrap();
+
+    wait_for_peer(&json_client, "CBOR01").await;
+
+    let payload = json!({"candidate": "a=1", "sdpMLineIndex": 0});
+    json_client.send_signal("CBOR01", payload.clone()).unwrap();
+    assert_eq!(next_signal(&mut cbor_client).await.1, payload);
+
+    cbor_client.send_signal("JSON01", payload.clone()).unwrap();
+    assert_eq!(next_signal(&mut json_client).await.1, payload);
+}
+
+#[tokio::test]
+async fn invalid_peer_code_is_rejected() {
+    let addr = start_server().await;
+    let err = RendezvousClient::connect(config(addr, "not valid!"))
+        .await
+        .err()
+        .expect("registration should fail");
+    assert!(matches!(
+        err,
+        bolt_rendezvous_protocol::client::ClientError::Rejected { .. }
+    ));
+}
+
+/// Wait for the first event matching `pick`.
+async fn next_matching<T>(
+    client: &mut RendezvousClient,
+    mut pick: impl FnMut(ServerMessage) -> Option<T>,
+) -> T {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            match client.next_event().await {
+                Some(ClientEvent::Message(msg)) => {
+                    if let Some(found) = pick(msg) {
+                        return found;
+                    }
+                }
+                Some(_) => continue,
+                None => panic!("client stopped"),
+            }
+        }
+    })
+    .await
+    .expect("timed out waiting for message")
+}
+
+#[tokio::test]
+async fn acks_and_errors_carry_message_ids() {
+    let addr = start_server().await;
+    let mut alice = RendezvousClient::connect(config(addr, "ALICE2"))
+        .await
+        .unwrap();
+    let bob = RendezvousClient::connect(config(addr, "BOB456"))
+        .await
+        .unwrap();
+    wait_for_peer(&alice, "BOB456").await;
+
+    alice
+        .send(ClientMessage::Signal {
+            to: "BOB456".into(),
+            payload: json!({"candidate": "c1"}),
+            id: Some("c1".into()),
+            ack: true,
+            identity: None,
+        })
+        .unwrap();
+    let (to, id) = next_matching(&mut alice, |msg| match msg {
+        ServerMessage::SignalDelivered { to, id } => Some((to, id)),
+        _ => None,
+    })
+    .await;
+    assert_eq!(to, "BOB456");
+    assert_eq!(id.as_deref(), Some("c1"));
+
+    alice
+        .send(ClientMessage::Signal {
+            to: "NOBODY".into(),
+            payload: json!({}),
+            id: Some("c2".into()),
+            ack: true,
+            identity: None,
+        })
+        .unwrap();
+    let (code, id) = next_matching(&mut alice, |msg| match msg {
+        ServerMessage::Error { code, id, .. } => Some((code, id)),
+        _ => None,
+    })
+    .await;
+    assert_eq!(code, Some(ErrorCode::NotFound));
+    assert_eq!(id.as_deref(), Some("c2"));
+    drop(bob);
+}
+
+#[tokio::test]
+async fn update_renames_without_leave_join() {
+    let addr = start_server().await;
+    let alice = RendezvousClient::connect(config(addr, "ALICE3"))
+        .await
+        .unwrap();
+    let mut bob = RendezvousClient::connect(config(addr, "BOB789"))
+        .await
+        .unwrap();
+    wait_for_peer(&bob, "ALICE3").await;
+
+    alice
+        .update(
+            Some("Alice's Desk".into()),
+            Some(Some("https://wt".into())),
+            None,
+        )
+        .unwrap();
+    let peer = next_matching(&mut bob, |msg| match msg {
+        ServerMessage::PeerUpdated { peer, .. } => Some(peer),
+        ServerMessage::PeerLeft { .. } => panic!("update must not cause peer_left"),
+        _ => None,
+    })
+    .await;
+    assert_eq!(peer.peer_code, "ALICE3");
+    assert_eq!(peer.device_name, "Alice's Desk");
+    assert_eq!(peer.wt_url.as_deref(), Some("https://wt"));
+    let listed = bob.peers().borrow().clone();
+    assert_eq!(listed[0].device_name, "Alice's Desk");
+}
+
+#[tokio::test]
+async fn presence_changes_reach_the_room_and_auto_away_reverts_on_activity() {
+    let addr = start_server_with(|s| s.with_auto_away(Duration::from_millis(300))).await;
+    let alice = RendezvousClient::connect(config(addr, "ALICE4"))
+        .await
+        .unwrap();
+    let mut bob = RendezvousClient::connect(config(addr, "BOB000"))
+        .await
+        .unwrap();
+    wait_for_peer(&bob, "ALICE4").await;
+
+    let presence_of = |code: &'static str| {
+        move |msg| match msg {
+            ServerMessage::PresenceChanged {
+                peer_code,
+                presence,
+                automatic,
+                ..
+            } if peer_code == code => Some((presence, automatic)),
+            _ => None,
+        }
+    };
+
+    alice.set_presence(Presence::Busy).unwrap();
+    assert_eq!(
+        next_matching(&mut bob, presence_of("ALICE4")).await,
+        (Presence::Busy, false)
+    );
+
+    // A busy peer is never marked away; an available one is after the quiet
+    // period, and comes back on its next message.
+    alice.set_presence(Presence::Available).unwrap();
+    assert_eq!(
+        next_matching(&mut bob, presence_of("ALICE4")).await,
+        (Presence::Available, false)
+    );
+    assert_eq!(
+        next_matching(&mut bob, presence_of("ALICE4")).await,
+        (Presence::Away, true)
+    );
+    alice.update(Some("Alice".into()), None, None).unwrap();
+    assert_eq!(
+        next_matching(&mut bob, presence_of("ALICE4")).await,
+        (Presence::Available, true)
+    );
+    let listed = bob.peers().borrow().clone();
+    assert_eq!(listed[0].presence, Presence::Available);
+}
+
+#[tokio::test]
+async fn multicast_reports_each_target() {
+    let addr = start_server().await;
+    let mut alice = RendezvousClient::connect(config(addr, "ALICE3"))
+        .await
+        .unwrap();
+    let mut bob = RendezvousClient::connect(config(addr, "BOB789"))
+        .await
+        .unwrap();
+    let mut carol = RendezvousClient::connect(config(addr, "CAROL1"))
+        .await
+        .unwrap();
+    wait_for_peer(&alice, "BOB789").await;
+    wait_for_peer(&alice, "CAROL1").await;
+
+    alice
+        .send(ClientMessage::MulticastSignal {
+            to: vec!["BOB789".into(), "CAROL1".into(), "NOBODY".into()],
+            payload: json!({"hello": true}),
+            id: Some("m1".into()),
+            identity: None,
+        })
+        .unwrap();
+    let (results, id) = next_matching(&mut alice, |msg| match msg {
+        ServerMessage::MulticastResult { results, id } => Some((results, id)),
+        _ => None,
+    })
+    .await;
+    assert_eq!(id.as_deref(), Some("m1"));
+    let outcomes: Vec<_> = results
+        .iter()
+        .map(|r| (r.to.as_str(), r.delivered, r.code))
+        .collect();
+    assert_eq!(
+        outcomes,
+        vec![
+            ("BOB789", true, None),
+            ("CAROL1", true, None),
+            ("NOBODY", false, Some(ErrorCode::NotFound)),
+        ]
+    );
+    assert_eq!(next_signal(&mut bob).await.0, "ALICE3");
+    assert_eq!(next_signal(&mut carol).await.0, "ALICE3");
+}
+
+#[tokio::test]
+async fn ping_is_answered_with_pong() {
+    let addr = start_server().await;
+    let mut alice = RendezvousClient::connect(config(addr, "ALICE4"))
+        .await
+        .unwrap();
+    alice.ping("rtt-1").unwrap();
+    let (nonce, server_time_ms) = next_matching(&mut alice, |msg| match msg {
+        ServerMessage::Pong {
+            nonce,
+            server_time_ms,
+            ..
+        } => Some((nonce, server_time_ms)),
+        _ => None,
+    })
+    .await;
+    assert_eq!(nonce.as_deref(), Some("rtt-1"));
+    let now_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    assert!(now_ms.abs_diff(server_time_ms) < 5_000);
+}
+
+#[tokio::test]
+async fn named_rooms_require_the_secret_and_scope_signals() {
+    let addr = start_server().await;
+    let mut alice = RendezvousClient::connect(config(addr, "ALICE5").with_room("team", "hunter22"))
+        .await
+        .unwrap();
+    let bob = RendezvousClient::connect(config(addr, "BOB555").with_room("Team", "hunter22"))
+        .await
+        .unwrap();
+    wait_for_peer(&alice, "BOB555").await;
+
+    // Same IP, but outside the named room: neither side sees the other.
+    let outsider = RendezvousClient::connect(config(addr, "EVE555"))
+        .await
+        .unwrap();
+    assert!(outsider.peers().borrow().is_empty());
+    alice
+        .send(ClientMessage::Signal {
+            to: "EVE555".into(),
+            payload: json!({}),
+            id: Some("s1".into()),
+            ack: false,
+            identity: None,
+        })
+        .unwrap();
+    let code = next_matching(&mut alice, |msg| match msg {
+        ServerMessage::Error { code, .. } => Some(code),
+        _ => None,
+    })
+    .await;
+    assert_eq!(code, Some(ErrorCode::NotFound));
+
+    match RendezvousClient::connect(config(addr, "MALLRY").with_room("team", "guessing")).await {
+        Err(ClientError::Rejected { code, .. }) => assert_eq!(code, Some(ErrorCode::Unauthorized)),
+        other => panic!("expected unauthorized, got {:?}", other.err()),
+    }
+    drop(bob);
+}
+
+#[tokio::test]
+async fn pin_match_introduces_peers_across_rooms() {
+    let addr = start_server().await;
+    let mut alice =
+        RendezvousClient::connect(config(addr, "ALICE6").with_room("north", "hunter22"))
+            .await
+            .unwrap();
+    let mut bob = RendezvousClient::connect(config(addr, "BOB666").with_room("south", "hunter22"))
+        .await
+        .unwrap();
+
+    alice.match_pin("314159").unwrap();
+    next_matching(&mut alice, |msg| match msg {
+        ServerMessage::PinWaiting { .. } => Some(()),
+        _ => None,
+    })
+    .await;
+    bob.match_pin("314159").unwrap();
+    let matched = next_matching(&mut bob, |msg| match msg {
+        ServerMessage::PinMatched { peer, .. } => Some(peer.peer_code),
+        _ => None,
+    })
+    .await;
+    assert_eq!(matched, "ALICE6");
+    let matched = next_matching(&mut alice, |msg| match msg {
+        ServerMessage::PinMatched { peer, .. } => Some(peer.peer_code),
+        _ => None,
+    })
+    .await;
+    assert_eq!(matched, "BOB666");
+
+    // Matched peers can signal each other although their rooms differ.
+    alice
+        .send_signal("BOB666", json!({"sdp": "offer"}))
+        .unwrap();
+    assert_eq!(next_signal(&mut bob).await.0, "ALICE6");
+
+    // The PIN is burned.
+    bob.match_pin("314159").unwrap();
+    let code = next_matching(&mut bob, |msg| match msg {
+        ServerMessage::Error { code, .. } => Some(code),
+        _ => None,
+    })
+    .await;
+    assert_eq!(code, Some(ErrorCode::Expired));
+}
+
+#[tokio::test]
+async fn manual_signals_require_an_accepted_pair_request() {
+    let addr = start_server().await;
+    let mut alice = RendezvousClient::connect(config(addr, "ALICE7").with_room("east", "hunter22"))
+        .await
+        .unwrap();
+    let mut bob = RendezvousClient::connect(config(addr, "BOB777").with_room("west", "hunter22"))
+        .await
+        .unwrap();
+
+    let manual = |id: &str| ClientMessage::ManualSignal {
+        to: "BOB777".into(),
+        payload: json!({"sdp": "offer"}),
+        id: Some(id.into()),
+        ack: false,
+        identity: None,
+    };
+    alice.send(manual("m1")).unwrap();
+    let code = next_matching(&mut alice, |msg| match msg {
+        ServerMessage::Error { code, .. } => Some(code),
+        _ => None,
+    })
+    .await;
+    assert_eq!(code, Some(ErrorCode::ConsentRequired));
+
+    alice.pair_request("BOB777").unwrap();
+    let requester = next_matching(&mut bob, |msg| match msg {
+        ServerMessage::PairRequest { peer, .. } => Some(peer.peer_code),
+        _ => None,
+    })
+    .await;
+    assert_eq!(requester, "ALICE7");
+    bob.answer_pair_request(&requester, true).unwrap();
+    let accepted = next_matching(&mut alice, |msg| match msg {
+        ServerMessage::PairAccepted { peer } => Some(peer.peer_code),
+        _ => None,
+    })
+    .await;
+    assert_eq!(accepted, "BOB777");
+
+    alice.send(manual("m2")).unwrap();
+    assert_eq!(next_signal(&mut bob).await.0, "ALICE7");
+
+    // Consent ends with the session.
+    drop(bob);
+    let _bob = RendezvousClient::connect(config(addr, "BOB777").with_room("west", "hunter22"))
+        .await
+        .unwrap();
+    alice.send(manual("m3")).unwrap();
+    let code = next_matching(&mut alice, |msg| match msg {
+        ServerMessage::Error { code, .. } => Some(code),
+        _ => None,
+    })
+    .await;
+    assert_eq!(code, Some(ErrorCode::ConsentRequired));
+}
+
+#[tokio::test]
+async fn own_update_and_rotation_keep_the_room_version() {
+    let addr = start_server().await;
+    let mut alice = RendezvousClient::connect(config(addr, "ALICE8"))
+        .await
+        .unwrap();
+
+    alice.update(Some("Renamed".into()), None, None).unwrap();
+    let version = next_matching(&mut alice, |msg| match msg {
+        ServerMessage::PeerUpdated { peer, version } if peer.peer_code == "ALICE8" => Some(version),
+        _ => None,
+    })
+    .await;
+    assert!(version.is_some());
+    alice.rotate_code("ALICE9").unwrap();
+    let rotated = next_matching(&mut alice, |msg| match msg {
+        ServerMessage::CodeRotated { version, .. } => Some(version),
+        _ => None,
+    })
+    .await;
+    assert_eq!(rotated, version.map(|v| v + 2));
+
+    let _bob = RendezvousClient::connect(config(addr, "BOB888"))
+        .await
+        .unwrap();
+    next_matching(&mut alice, |msg| match msg {
+        ServerMessage::PeerJoined { peer, .. } if peer.peer_code == "BOB888" => Some(()),
+        _ => None,
+    })
+    .await;
+    // A `list_peers` resync would be answered with `peers_delta` or `peers`.
+    let resync = tokio::time::timeout(
+        Duration::from_millis(500),
+        next_matching(&mut alice, |msg| match msg {
+            ServerMessage::PeersDelta { .. } | ServerMessage::Peers { .. } => Some(()),
+            _ => None,
+        }),
+    )
+    .await;
+    assert!(
+        resync.is_err(),
+        "client sent list_peers after its own changes"
+    );
+}
+
+#[tokio::test]
+async fn rotating_to_the_current_code_is_a_no_op() {
+    let addr = start_server().await;
+    let mut alice = RendezvousClient::connect(config(addr, "ALICE8"))
+        .await
+        .unwrap();
+    let mut bob = RendezvousClient::connect(config(addr, "BOB888"))
+        .await
+        .unwrap();
+    wait_for_peer(&bob, "ALICE8").await;
+
+    alice.rotate_code("ALICE8").unwrap();
+    let rotated = next_matching(&mut alice, |msg| match msg {
+        ServerMessage::CodeRotated { peer_code, .. } => Some(peer_code),
+        ServerMessage::Error { code, .. } => panic!("expected code_rotated, got {code:?}"),
+        _ => None,
+    })
+    .await;
+    assert_eq!(rotated, "ALICE8");
+
+    // Bob sees no leave/join; the next room event is from a real rotation.
+    alice.rotate_code("ALICE9").unwrap();
+    let left = next_matching(&mut bob, |msg| match msg {
+        ServerMessage::PeerLeft { peer_code, .. } => Some(peer_code),
+        ServerMessage::PeerJoined { peer, .. } => panic!("unexpected join of {}", peer.peer_code),
+        _ => None,
+    })
+    .await;
+    assert_eq!(left, "ALICE8");
+}
+
+#[tokio::test]
+async fn rotate_code_and_leave_keep_the_socket() {
+    let addr = start_server().await;
+    let mut alice = RendezvousClient::connect(config(addr, "ALICE8"))
+        .await
+        .unwrap();
+    let mut bob = RendezvousClient::connect(config(addr, "BOB888"))
+        .await
+        .unwrap();
+    wait_for_peer(&bob, "ALICE8").await;
+
+    alice.rotate_code("ALICE9").unwrap();
+    let rotated = next_matching(&mut alice, |msg| match msg {
+        ServerMessage::CodeRotated { peer_code, .. } => Some(peer_code),
+        _ => None,
+    })
+    .await;
+    assert_eq!(rotated, "ALICE9");
+    let left = next_matching(&mut bob, |msg| match msg {
+        ServerMessage::PeerLeft { peer_code, .. } => Some(peer_code),
+        ServerMessage::PeerJoined { .. } => panic!("peer_joined before peer_left"),
+        _ => None,
+    })
+    .await;
+    assert_eq!(left, "ALICE8");
+    let joined = next_matching(&mut bob, |msg| match msg {
+        ServerMessage::PeerJoined { peer, .. } => Some(peer.peer_code),
+        _ => None,
+    })
+    .await;
+    assert_eq!(joined, "ALICE9");
+
+    // Rotating onto a code that is in use fails without side effects.
+    alice.rotate_code("BOB888").unwrap();
+    let code = next_matching(&mut alice, |msg| match msg {
+        ServerMessage::Error { code, .. } => Some(code),
+        _ => None,
+    })
+    .await;
+    assert_eq!(code, Some(ErrorCode::CodeInUse));
+
+    alice
+        .send(ClientMessage::Leave {
+            id: None,
+            identity: None,
+        })
+        .unwrap();
+    let left = next_matching(&mut bob, |msg| match msg {
+        ServerMessage::PeerLeft { peer_code, .. } => Some(peer_code),
+        _ => None,
+    })
+    .await;
+    assert_eq!(left, "ALICE9");
+
+    // The same socket can register again.
+    alice
+        .send(ClientMessage::Register {
+            peer_code: "ALICE0".into(),
+            device_name: "Alice".into(),
+            device_type: DeviceType::Desktop,
+            wt_url: None,
+            wt_cert_hash: None,
+            protocol_version: None,
+            room: None,
+            room_secret: None,
+            id: None,
+            capabilities: vec![],
+            metadata: BTreeMap::new(),
+            discoverable: true,
+            subscription: None,
+        })
+        .unwrap();
+    let joined = next_matching(&mut bob, |msg| match msg {
+        ServerMessage::PeerJoined { peer, .. } => Some(peer.peer_code),
+        _ => None,
+    })
+    .await;
+    assert_eq!(joined, "ALICE0");
+}
+
+#[tokio::test]
+async fn one_connection_hosts_several_identities() {
+    let addr = start_server().await;
+    let mut daemon = RendezvousClient::connect(config(addr, "DEVICE1"))
+        .await
+        .unwrap();
+    let mut bob = RendezvousClient::connect(config(addr, "BOB999"))
+        .await
+        .unwrap();
+    wait_for_peer(&bob, "DEVICE1").await;
+
+    let register = |code: &str| ClientMessage::Register {
+        peer_code: code.into(),
+        device_name: "Inbox".into(),
+        device_type: DeviceType::Desktop,
+        wt_url: None,
+        wt_cert_hash: None,
+        protocol_version: None,
+        room: None,
+        room_secret: None,
+        id: Some(code.into()),
+        capabilities: vec![],
+        metadata: BTreeMap::new(),
+        discoverable: true,
+        subscription: None,
+    };
+    daemon.send(register("INBOX1")).unwrap();
+    let (code, peers) = next_matching(&mut daemon, |msg| match msg {
+        ServerMessage::IdentityAdded {
+            peer_code, peers, ..
+        } => Some((peer_code, peers)),
+        _ => None,
+    })
+    .await;
+    assert_eq!(code, "INBOX1");
+    assert!(peers.iter().any(|p| p.peer_code == "BOB999"));
+    wait_for_peer(&bob, "INBOX1").await;
+
+    // Inbound signals name the receiving identity.
+    bob.send_signal("INBOX1", json!({"sdp": "offer"})).unwrap();
+    let to = next_matching(&mut daemon, |msg| match msg {
+        ServerMessage::Signal { to, .. } => Some(to),
+        _ => None,
+    })
+    .await;
+    assert_eq!(to.as_deref(), Some("INBOX1"));
+
+    // Outbound messages pick the identity with `as`.
+    daemon
+        .send(ClientMessage::Signal {
+            to: "BOB999".into(),
+            payload: json!({"sdp": "answer"}),
+            id: None,
+            ack: false,
+            identity: Some("INBOX1".into()),
+        })
+        .unwrap();
+    assert_eq!(next_signal(&mut bob).await.0, "INBOX1");
+
+    for n in 2..8 {
+        daemon.send(register(&format!("INBOX{n}"))).unwrap();
+    }
+    daemon.send(register("INBOX8")).unwrap();
+    let code = next_matching(&mut daemon, |msg| match msg {
+        ServerMessage::Error { code, id, .. } if id.as_deref() == Some("INBOX8") => Some(code),
+        _ => None,
+    })
+    .await;
+    assert_eq!(code, Some(ErrorCode::IdentityLimit));
+
+    // Disconnecting removes every identity.
+    drop(daemon);
+    let mut peers = bob.peers();
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        peers.wait_for(|list| list.is_empty()),
+    )
+    .await
+    .expect("identities were not removed")
+    .unwrap();
+}
+
+#[tokio::test]
+async fn capabilities_and_metadata_reach_the_room() {
+    let addr = start_server().await;
+    let mut alice = RendezvousClient::connect(
+        config(addr, "ALICEC")
+            .with_capabilities(["webrtc", "webtransport"])
+            .with_metadata("wt_url", "https://10.0.0.1:9948")
+            .with_metadata("bolt.inbox", "1"),
+    )
+    .await
+    .unwrap();
+    let mut bob = RendezvousClient::connect(config(addr, "BOBCCC"))
+        .await
+        .unwrap();
+    wait_for_peer(&bob, "ALICEC").await;
+
+    let listed = bob.peers().borrow().clone();
+    let peer = listed.iter().find(|p| p.peer_code == "ALICEC").unwrap();
+    assert_eq!(peer.capabilities, ["webrtc", "webtransport"]);
+    // The metadata alias fills the legacy field, and the field is mirrored.
+    assert_eq!(peer.wt_url.as_deref(), Some("https://10.0.0.1:9948"));
+    assert_eq!(peer.metadata.len(), 2);
+
+    alice
+        .send(ClientMessage::Update {
+            device_name: None,
+            wt_url: Some(None),
+            wt_cert_hash: None,
+            capabilities: Some(vec!["webrtc".into()]),
+            metadata: Some(BTreeMap::new()),
+            id: None,
+            identity: None,
+        })
+        .unwrap();
+    let peer = next_matching(&mut bob, |msg| match msg {
+        ServerMessage::PeerUpdated { peer, .. } => Some(peer),
+        _ => None,
+    })
+    .await;
+    assert_eq!(peer.capabilities, ["webrtc"]);
+    assert_eq!(peer.wt_url, None);
+    assert!(peer.metadata.is_empty());
+
+    alice
+        .send(ClientMessage::Update {
+            device_name: None,
+            wt_url: None,
+            wt_cert_hash: None,
+            capabilities: Some(vec!["Not A Token".into()]),
+            metadata: None,
+            id: Some("u1".into()),
+            identity: None,
+        })
+        .unwrap();
+    let field = next_matching(&mut alice, |msg| match msg {
+        ServerMessage::Error { field, .. } => Some(field),
+        _ => None,
+    })
+    .await;
+    assert_eq!(field.as_deref(), Some("capabilities"));
+}
+
+#[tokio::test]
+async fn list_peers_answers_with_deltas_and_own_changes_are_versioned() {
+    let addr = start_server().await;
+    let alice = RendezvousClient::connect(config(addr, "ALICED"))
+        .await
+        .unwrap();
+    let mut bob = RendezvousClient::connect(config(addr, "BOBDDD"))
+        .await
+        .unwrap();
+    let since = next_matching(&mut bob, |msg| match msg {
+        ServerMessage::Peers { version, .. } => version,
+        _ => None,
+    })
+    .await;
+    assert_eq!(since, 2);
+
+    let carol = RendezvousClient::connect(config(addr, "CAROLD"))
+        .await
+        .unwrap();
+    drop(carol);
+    let left = next_matching(&mut bob, |msg| match msg {
+        ServerMessage::PeerLeft { version, .. } => version,
+        _ => None,
+    })
+    .await;
+    assert_eq!(left, 4);
+
+    bob.send(ClientMessage::ListPeers {
+        since: Some(since),
+        id: Some("l1".into()),
+        identity: None,
+        after: None,
+    })
+    .unwrap();
+    let (version, peers, removed) = next_matching(&mut bob, |msg| match msg {
+        ServerMessage::PeersDelta {
+            version,
+            peers,
+            removed,
+            id: Some(id),
+            ..
+        } if id == "l1" => Some((version, peers, removed)),
+        _ => None,
+    })
+    .await;
+    assert_eq!(version, 4);
+    assert!(peers.is_empty());
+    assert_eq!(removed, ["CAROLD"]);
+
+    // Bob's own update is answered with version 5, so Alice's presence
+    // change (version 6) follows without a gap.
+    bob.update(Some("Bob 2".into()), None, None).unwrap();
+    let own = next_matching(&mut bob, |msg| match msg {
+        ServerMessage::PeerUpdated { version, .. } => Some(version),
+        _ => None,
+    })
+    .await;
+    assert_eq!(own, Some(5));
+    alice.set_presence(Presence::Busy).unwrap();
+    let presence = next_matching(&mut bob, |msg| match msg {
+        ServerMessage::PresenceChanged { version, .. } => Some(version),
+        ServerMessage::PeersDelta { .. } => panic!("unexpected resync"),
+        _ => None,
+    })
+    .await;
+    assert_eq!(presence, Some(6));
+    assert_eq!(bob.peers().borrow()[0].presence, Presence::Busy);
+}
+
+#[tokio::test]
+async fn hidden_peers_see_the_room_but_are_not_listed() {
+    let addr = start_server().await;
+    let mut host = RendezvousClient::connect(config(addr, "HOSTEE"))
+        .await
+        .unwrap();
+    let mut lurker = RendezvousClient::connect(config(addr, "LURKER").with_discoverable(false))
+        .await
+        .unwrap();
+    wait_for_peer(&lurker, "HOSTEE").await;
+
+    // The host cannot reach the hidden peer by code yet.
+    host.send_signal("LURKER", json!({"sdp": "offer"})).unwrap();
+    let code = next_matching(&mut host, |msg| match msg {
+        ServerMessage::Error { code, .. } => code,
+        _ => None,
+    })
+    .await;
+    assert_eq!(code, ErrorCode::NotFound);
+
+    // Once the hidden peer signals, the host can answer.
+    lurker
+        .send_signal("HOSTEE", json!({"sdp": "offer"}))
+        .unwrap();
+    assert_eq!(next_signal(&mut host).await.0, "LURKER");
+    host.send_signal("LURKER", json!({"sdp": "answer"}))
+        .unwrap();
+    let (from, payload) = next_signal(&mut lurker).await;
+    assert_eq!(from, "HOSTEE");
+    assert_eq!(payload, json!({"sdp": "answer"}));
+    assert!(host.peers().borrow().is_empty());
+}
+
+#[tokio::test]
+async fn subscriptions_page_and_filter_the_peer_list() {
+    let addr = start_server().await;
+    let mut others = Vec::new();
+    for code in ["PEERA", "PEERB", "PEERC", "PEERD", "PEERE"] {
+        others.push(RendezvousClient::connect(config(addr, code)).await.unwrap());
+    }
+    let mut phone = config(addr, "PHONE1");
+    phone.device_type = DeviceType::Phone;
+    others.push(RendezvousClient::connect(phone).await.unwrap());
+
+    // Pages of two are fetched until the list is complete.
+    let paged = Subscription {
+        page_size: Some(2),
+        ..Default::default()
+    };
+    let watcher = RendezvousClient::connect(config(addr, "WATCH").with_subscription(paged))
+        .await
+        .unwrap();
+    let mut peers = watcher.peers();
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        peers.wait_for(|list| list.len() == 6),
+    )
+    .await
+    .expect("timed out waiting for all pages")
+    .unwrap();
+
+    // A device-type filter narrows the list to phones.
+    watcher
+        .subscribe(Subscription {
+            device_types: vec![DeviceType::Phone],
+            ..Default::default()
+        })
+        .unwrap();
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        peers.wait_for(|list| list.len() == 1 && list[0].peer_code == "PHONE1"),
+    )
+    .await
+    .expect("timed out waiting for the filtered list")
+    .unwrap();
+
+    // Desktop joins are not announced to it.
+    let late = RendezvousClient::connect(config(addr, "LATE"))
+        .await
+        .unwrap();
+    wait_for_peer(&late, "PHONE1").await;
+    assert_eq!(watcher.peers().borrow().len(), 1);
+}
+
+#[tokio::test]
+async fn room_info_and_whoami_describe_the_room() {
+    let addr = start_server().await;
+    let mut first = RendezvousClient::connect(config(addr, "FIRST"))
+        .await
+        .unwrap();
+    let info = next_matching(&mut first, |msg| match msg {
+        ServerMessage::RoomInfo { peer_code, room } => Some((peer_code, room)),
+        _ => None,
+    })
+    .await;
+    assert_eq!(info.0, "FIRST");
+    assert_eq!(info.1.kind, RoomKind::Private);
+    assert_eq!(info.1.ip_source, IpSource::Socket);
+    assert_eq!(info.1.size, 1);
+
+    // The HTTP endpoint reports the same room, now with the peer in it.
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"GET /whoami HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.contains("Content-Type: application/json"));
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    let whoami: RoomInfo = serde_json::from_str(body).unwrap();
+    assert_eq!(whoami.fingerprint, info.1.fingerprint);
+    assert_eq!(whoami.size, 1);
+    assert_eq!(whoami.forwarded_header, None);
+}
+
+#[tokio::test]
+async fn handshake_deadlines_close_stalled_connections() {
+    let addr = start_server_with(|s| {
+        s.with_peek_timeout(Duration::from_millis(200))
+            .with_upgrade_timeout(Duration::from_millis(200))
+            .with_register_timeout(Duration::from_millis(200))
+    })
+    .await;
+
+    // Silent connection: 408 once the peek deadline passes.
+    let mut silent = TcpStream::connect(addr).await.unwrap();
+    let mut response = String::new();
+    tokio::time::timeout(Duration::from_secs(5), silent.read_to_string(&mut response))
+        .await
+        .expect("silent connection was not closed")
+        .unwrap();
+    assert!(response.starts_with("HTTP/1.1 408 Request Timeout"));
+
+    // Partial upgrade request: 408 with an upgrade_timeout body.
+    let mut partial = TcpStream::connect(addr).await.unwrap();
+    partial
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        partial.read_to_string(&mut response),
+    )
+    .await
+    .expect("stalled upgrade was not closed")
+    .unwrap();
+    assert!(response.starts_with("HTTP/1.1 408 Request Timeout"));
+    assert!(!response.contains("Retry-After"));
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    let rejection: HttpRejection = serde_json::from_str(body).unwrap();
+    assert_eq!(rejection.reason, RejectionReason::UpgradeTimeout);
+
+    // Upgraded but never registered: registration_timeout, then close.
+    let (mut ws, _) = tokio_tungstenite::connect_async(format!("ws://{addr}"))
+        .await
+        .unwrap();
+    let mut codes = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while let Some(Ok(msg)) = ws.next().await {
+            if let Message::Text(text) = msg {
+                if let Ok(ServerMessage::Error { code, .. }) = serde_json::from_str(&text) {
+                    codes.push(code);
+                }
+            }
+        }
+    })
+    .await
+    .expect("unregistered connection was not closed");
+    assert_eq!(codes, vec![Some(ErrorCode::RegistrationTimeout)]);
+}
+
+#[tokio::test]
+async fn per_ip_limits_refuse_extra_connections_and_registrations() {
+    let addr = start_server_with(|s| {
+        s.with_max_connections_per_ip(2)
+            .with_max_registrations_per_minute(3)
+    })
+    .await;
+    let first = RendezvousClient::connect(config(addr, "FIRST"))
+        .await
+        .unwrap();
+    let mut second = RendezvousClient::connect(config(addr, "SECOND"))
+        .await
+        .unwrap();
+
+    // A third connection from the same address is refused during the upgrade.
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(
+            b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+              Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let mut response = Vec::new();
+    let _ = stream.read_to_end(&mut response).await;
+    assert!(String::from_utf8_lossy(&response).starts_with("HTTP/1.1 429"));
+
+    // Two registrations are used; the first rotation fits, the second does not.
+    second
+        .send(ClientMessage::RotateCode {
+            peer_code: "THIRD".into(),
+            id: None,
+            identity: None,
+        })
+        .unwrap();
+    next_matching(&mut second, |msg| match msg {
+        ServerMessage::CodeRotated { .. } => Some(()),
+        _ => None,
+    })
+    .await;
+    second
+        .send(ClientMessage::RotateCode {
+            peer_code: "FOURTH".into(),
+            id: None,
+            identity: None,
+        })
+        .unwrap();
+    let retry_after_ms = next_matching(&mut second, |msg| match msg {
+        ServerMessage::Error {
+            code: Some(ErrorCode::RateLimited),
+            retry_after_ms,
+            ..
+        } => retry_after_ms,
+        _ => None,
+    })
+    .await;
+    assert!(retry_after_ms > 0 && retry_after_ms <= 60_000);
+
+    // Closing a connection frees its slot.
+    drop(first);
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if tokio_tungstenite::connect_async(format!("ws://{addr}"))
+                .await
+                .is_ok()
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("slot was not released");
+}
+
+#[tokio::test]
+async fn connection_limit_answers_503_with_retry_after() {
+    let addr = start_server_with(|s| s.with_max_connections(0)).await;
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 503 Service Unavailable"));
+    assert!(response.contains("Retry-After: 5\r\n"));
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    let rejection: HttpRejection = serde_json::from_str(body).unwrap();
+    assert_eq!(rejection.reason, RejectionReason::ServerFull);
+    assert_eq!(rejection.retry_after_secs, Some(5));
+
+    // The client surfaces the refusal with its retry hint.
+    let err = RendezvousClient::connect(config(addr, "FULL"))
+        .await
+        .err()
+        .unwrap();
+    match err {
+        ClientError::Refused {
+            status,
+            retry_after,
+            rejection,
+        } => {
+            assert_eq!(status, 503);
+            assert_eq!(retry_after, Some(Duration::from_secs(5)));
+            assert_eq!(rejection.unwrap().reason, RejectionReason::ServerFull);
+        }
+        other => panic!("expected Refused, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn origin_allowlist_refuses_other_pages() {
+    use bolt_rendezvous::origin::OriginPolicy;
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    let policy = OriginPolicy::allow_list(vec!["https://*.localbolt.app".parse().unwrap()]);
+    let addr = start_server_with(|s| s.with_origin_policy(policy)).await;
+    let handshake = |origin: Option<&'static str>| async move {
+        let mut request = format!("ws://{addr}").into_client_request().unwrap();
+        if let Some(origin) = origin {
+            request
+                .headers_mut()
+                .insert("origin", origin.parse().unwrap());
+        }
+        tokio_tungstenite::connect_async(request).await
+    };
+
+    assert!(handshake(Some("https://web.localbolt.app")).await.is_ok());
+    // Native clients send no Origin and are accepted by default.
+    assert!(handshake(None).await.is_ok());
+
+    for origin in ["https://evil.example", "null"] {
+        match handshake(Some(origin)).await {
+            Err(tokio_tungstenite::tungstenite::Error::Http(response)) => {
+                assert_eq!(response.status().as_u16(), 403);
+                assert!(response.headers().get("retry-after").is_none());
+                let rejection: HttpRejection =
+                    serde_json::from_slice(response.body().as_deref().unwrap()).unwrap();
+                assert_eq!(rejection.reason, RejectionReason::OriginNotAllowed);
+            }
+            other => panic!("expected 403 for {origin}, got {other:?}"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn private_sources_are_capped_per_forwarded_ip() {
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    let addr = start_server_with(|s| s.with_max_connections_per_ip(2)).await;
+    let connect = |forwarded: &'static str| async move {
+        let mut request = format!("ws://{addr}").into_client_request().unwrap();
+        request
+            .headers_mut()
+            .insert("x-forwarded-for", forwarded.parse().unwrap());
+        tokio_tungstenite::connect_async(request).await
+    };
+
+    // Loopback is a private source, like a PaaS edge: its clients are
+    // capped by the IP they forward, not by the shared socket address.
+    let _a = connect("203.0.113.1").await.unwrap();
+    let _b = connect("203.0.113.1").await.unwrap();
+    let _c = connect("203.0.113.2").await.unwrap();
+    match connect("203.0.113.1").await {
+        Err(tokio_tungstenite::tungstenite::Error::Http(response)) => {
+            assert_eq!(response.status().as_u16(), 429);
+        }
+        other => panic!("expected 429, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn hidden_peers_are_not_counted_in_room_size() {
+    let addr = start_server().await;
+    let _visible = RendezvousClient::connect(config(addr, "SEEN"))
+        .await
+        .unwrap();
+    let mut hidden = RendezvousClient::connect(config(addr, "HIDDEN").with_discoverable(false))
+        .await
+        .unwrap();
+    let room = next_matching(&mut hidden, |msg| match msg {
+        ServerMessage::RoomInfo { room, .. } => Some(room),
+        _ => None,
+    })
+    .await;
+    assert_eq!(room.size, 1);
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"GET /whoami HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    let whoami: RoomInfo = serde_json::from_str(body).unwrap();
+    assert_eq!(whoami.size, 1);
+}
+
+#[tokio::test]
+async fn handshake_echoes_an_offered_subprotocol_alias() {
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    let addr = start_server().await;
+    for alias in ["bolt-rendezvous.v1.json", "bolt-rendezvous.v01"] {
+        let mut request = format!("ws://{addr}").into_client_request().unwrap();
+        request
+            .headers_mut()
+            .insert("sec-websocket-protocol", alias.parse().unwrap());
+        // The client itself rejects a response naming a protocol it did not offer.
+        let (_, response) = tokio_tungstenite::connect_async(request)
+            .await
+            .unwrap_or_else(|e| panic!("handshake offering {alias} failed: {e}"));
+        assert_eq!(
+            response.headers()["sec-websocket-protocol"]
+                .to_str()
+                .unwrap(),
+            alias
+        );
+    }
+}